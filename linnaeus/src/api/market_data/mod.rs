@@ -7,7 +7,7 @@ pub use structs::*;
 use linnaeus_request::*;
 
 pub async fn server_time(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
 ) -> Result<ServerTime, error::RequestError> {
     do_request_no_params(
         client,
@@ -19,7 +19,7 @@ pub async fn server_time(
 }
 
 pub async fn system_status(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
 ) -> Result<SystemStatus, error::RequestError> {
     do_request_no_params(
         client,
@@ -31,7 +31,7 @@ pub async fn system_status(
 }
 
 pub async fn asset_info(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &AssetInfoParams
 ) -> Result<AssetInfo, error::RequestError> {
     do_request_with_query(
@@ -45,7 +45,7 @@ pub async fn asset_info(
 }
 
 pub async fn all_asset_info(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
 ) -> Result<AssetInfo, error::RequestError> {
     do_request_no_params(
         client,
@@ -58,7 +58,7 @@ pub async fn all_asset_info(
 
 
 pub async fn tradable_asset_pairs(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &TradableAssetPairsParams
 ) -> Result<TradingAssetPairs, error::RequestError> {
     do_request_with_query(
@@ -72,7 +72,7 @@ pub async fn tradable_asset_pairs(
 }
 
 pub async fn all_tradable_asset_pairs(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
 ) -> Result<TradingAssetPairs, error::RequestError> {
     do_request_no_params(
         client,
@@ -84,7 +84,7 @@ pub async fn all_tradable_asset_pairs(
 }
 
 pub async fn ticker_information(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &TickerInfoParams
 ) -> Result<MultiTickerInformation, error::RequestError> {
     do_request_with_query(
@@ -98,7 +98,7 @@ pub async fn ticker_information(
 }
 
 pub async fn ohlc(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &OHLCDataParams
 ) -> Result<OHLCData, error::RequestError> {
     do_request_with_query(
@@ -112,7 +112,7 @@ pub async fn ohlc(
 }
 
 pub async fn order_book(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &OrderBookParams
 ) -> Result<OrderBooks, error::RequestError> {
     do_request_with_query(
@@ -126,7 +126,7 @@ pub async fn order_book(
 }
 
 pub async fn recent_trades(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &RecentTradesParams
 ) -> Result<RecentTrades, error::RequestError> {
     do_request_with_query(
@@ -140,7 +140,7 @@ pub async fn recent_trades(
 }
 
 pub async fn recent_spreads(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &RecentSpreadsParams
 ) -> Result<RecentSpreads, error::RequestError> {
     do_request_with_query(
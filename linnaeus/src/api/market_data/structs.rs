@@ -6,6 +6,7 @@ use derive_getters::Getters;
 use derive_new::new;
 use derive_setters::Setters;
 use display_json::{DebugAsJson, DisplayAsJsonPretty};
+use linnaeus_types as types;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_with::formats::CommaSeparator;
@@ -307,6 +308,22 @@ pub struct TickData {
     count: i64,
 }
 
+impl TickData {
+    /// A candle for an interval without trades where every price is `price`
+    pub(crate) fn flat(time: chrono::DateTime<Utc>, price: Decimal) -> Self {
+        Self {
+            time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume_weighted_average_price: price,
+            volume: Decimal::ZERO,
+            count: 0,
+        }
+    }
+}
+
 #[skip_serializing_none]
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
 pub struct OHLCData {
@@ -376,6 +393,14 @@ impl RecentTradesParams {
             },
         }
     }
+
+    /// Continue from the `last` cursor of a previous response
+    pub fn new_with_cursor(pair: &str, since: u64) -> Self {
+        Self {
+            pair: Some(pair.to_string()),
+            since: Some(since),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, EnumDisplay, Clone)]
@@ -441,3 +466,103 @@ pub struct RecentSpreads {
     #[serde(flatten)]
     spread_data: HashMap<String, Vec<SpreadData>>,
 }
+
+impl From<&TickerInformation> for types::Ticker {
+    fn from(ticker: &TickerInformation) -> Self {
+        Self::new(
+            ticker.bid.price,
+            ticker.bid.lot_volume,
+            ticker.ask.price,
+            ticker.ask.lot_volume,
+            ticker.last_trade_closed.price,
+            ticker.open,
+            ticker.high.last_24h,
+            ticker.low.last_24h,
+            ticker.volume.last_24h,
+            ticker.volume_weighted_average_price.last_24h,
+        )
+    }
+}
+
+impl From<Interval> for types::Interval {
+    fn from(interval: Interval) -> Self {
+        match interval {
+            Interval::OneMin => Self::OneMin,
+            Interval::FiveMin => Self::FiveMin,
+            Interval::FifteenMin => Self::FifteenMin,
+            Interval::ThirtyMin => Self::ThirtyMin,
+            Interval::OneHour => Self::OneHour,
+            Interval::FourHour => Self::FourHour,
+            Interval::OneDay => Self::OneDay,
+            Interval::OneWeek => Self::OneWeek,
+            Interval::FifteenDay => Self::FifteenDay,
+        }
+    }
+}
+
+impl From<&TickData> for types::Candle {
+    fn from(tick: &TickData) -> Self {
+        Self::new(
+            tick.time,
+            tick.open,
+            tick.high,
+            tick.low,
+            tick.close,
+            tick.volume_weighted_average_price,
+            tick.volume,
+            tick.count.max(0) as u64,
+        )
+    }
+}
+
+impl From<&types::Candle> for TickData {
+    fn from(candle: &types::Candle) -> Self {
+        Self {
+            time: *candle.time(),
+            open: *candle.open(),
+            high: *candle.high(),
+            low: *candle.low(),
+            close: *candle.close(),
+            volume_weighted_average_price: *candle.vwap(),
+            volume: *candle.volume(),
+            count: *candle.trades() as i64,
+        }
+    }
+}
+
+impl From<&OrderBookAsk> for types::PriceLevel {
+    fn from(level: &OrderBookAsk) -> Self {
+        Self::new(level.price, level.volume, Some(level.timestamp))
+    }
+}
+
+impl From<Side> for types::Side {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Buy => Self::Buy,
+            Side::Sell => Self::Sell,
+        }
+    }
+}
+
+impl From<TradeType> for types::TradeType {
+    fn from(trade_type: TradeType) -> Self {
+        match trade_type {
+            TradeType::Market => Self::Market,
+            TradeType::Limit => Self::Limit,
+        }
+    }
+}
+
+impl From<&TradeData> for types::Trade {
+    fn from(trade: &TradeData) -> Self {
+        Self::new(
+            trade.price,
+            trade.volume,
+            trade.time,
+            trade.side.clone().into(),
+            trade.trade_type.clone().into(),
+            None,
+        )
+    }
+}
@@ -1,9 +1,7 @@
 use chrono::Utc;
 use derive_getters::Getters;
 use display_json::{DebugAsJson, DisplayAsJsonPretty};
-use linnaeus_request::{
-    do_request_no_params, error, EndpointSecurityType, RequestClient, RequestHelpers,
-};
+use linnaeus_request::{do_request_no_params, error, EndpointSecurityType, RequestHelpers};
 use serde::{Deserialize, Serialize};
 
 use serde_with::serde_as;
@@ -35,8 +33,15 @@ impl WebsocketToken {
     }
 }
 
+impl From<WebsocketToken> for linnaeus_ws::auth::AuthToken {
+    fn from(token: WebsocketToken) -> Self {
+        let expiry_time = token.expiry_time();
+        Self::new(token.token, expiry_time)
+    }
+}
+
 pub async fn authenticate_websocket(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
 ) -> Result<WebsocketToken, error::RequestError> {
     do_request_no_params(
         client,
@@ -9,7 +9,7 @@ pub use structs::*;
 use linnaeus_request::*;
 
 pub async fn account_balances(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
 ) -> Result<AccountBalances, error::RequestError> {
     do_request_no_params(
         client,
@@ -21,7 +21,7 @@ pub async fn account_balances(
 }
 
 pub async fn trade_balances(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &TradeBalancesParams,
 ) -> Result<TradeBalances, error::RequestError> {
     do_request_with_body(
@@ -35,7 +35,7 @@ pub async fn trade_balances(
 }
 
 pub async fn open_orders(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &OpenOrdersParams,
 ) -> Result<HashMap<String, OrderBase>, error::RequestError> {
     let wrapper: OpenOrdersWrapper = do_request_with_body(
@@ -50,7 +50,7 @@ pub async fn open_orders(
 }
 
 pub async fn closed_orders(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &ClosedOrdersParams,
 ) -> Result<ClosedOrders, error::RequestError> {
     do_request_with_body(
@@ -64,7 +64,7 @@ pub async fn closed_orders(
 }
 
 pub async fn query_orders(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &QueryOrderParams,
 ) -> Result<Orders, error::RequestError> {
     do_request_with_body(
@@ -78,7 +78,7 @@ pub async fn query_orders(
 }
 
 pub async fn trade_history(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &TradeHistoryParams,
 ) -> Result<TradeHistory, error::RequestError> {
     do_request_with_body(
@@ -92,7 +92,7 @@ pub async fn trade_history(
 }
 
 pub async fn query_trade_info(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &QueryTradeInfoParams,
 ) -> Result<TradeInfo, error::RequestError> {
     do_request_with_body(
@@ -106,7 +106,7 @@ pub async fn query_trade_info(
 }
 
 pub async fn open_positions(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &OpenPositionParams,
 ) -> Result<OpenPositions, error::RequestError> {
     do_request_with_body(
@@ -120,7 +120,7 @@ pub async fn open_positions(
 }
 
 pub async fn get_ledger_info(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &LedgerInfoParams,
 ) -> Result<LedgerInfo, error::RequestError> {
     do_request_with_body(
@@ -134,7 +134,7 @@ pub async fn get_ledger_info(
 }
 
 pub async fn query_ledger(
-    client: &(impl RequestClient + RequestHelpers),
+    client: &impl RequestHelpers,
     params: &QueryLedgerParams,
 ) -> Result<Ledgers, error::RequestError> {
     do_request_with_body(
@@ -146,3 +146,76 @@ pub async fn query_ledger(
     )
     .await
 }
+
+pub async fn trade_volume(
+    client: &impl RequestHelpers,
+    params: &TradeVolumeParams,
+) -> Result<TradeVolume, error::RequestError> {
+    do_request_with_body(
+        client,
+        "/0/private/TradeVolume",
+        http::Method::POST,
+        EndpointSecurityType::Private,
+        params,
+    )
+    .await
+}
+
+/// Request a bulk trades or ledgers report. Reports are processed in the background, see
+/// [crate::report::ReportExport] for the whole workflow
+pub async fn add_export(
+    client: &impl RequestHelpers,
+    params: &AddExportParams,
+) -> Result<AddExport, error::RequestError> {
+    do_request_with_body(
+        client,
+        "/0/private/AddExport",
+        http::Method::POST,
+        EndpointSecurityType::Private,
+        params,
+    )
+    .await
+}
+
+pub async fn export_status(
+    client: &impl RequestHelpers,
+    params: &ExportStatusParams,
+) -> Result<Vec<ExportReport>, error::RequestError> {
+    do_request_with_body(
+        client,
+        "/0/private/ExportStatus",
+        http::Method::POST,
+        EndpointSecurityType::Private,
+        params,
+    )
+    .await
+}
+
+/// Download a processed report. The report is a zip archive
+pub async fn retrieve_export(
+    client: &impl RequestHelpers,
+    params: &RetrieveExportParams,
+) -> Result<Vec<u8>, error::RequestError> {
+    do_request_with_body_bytes(
+        client,
+        "/0/private/RetrieveExport",
+        http::Method::POST,
+        EndpointSecurityType::Private,
+        params,
+    )
+    .await
+}
+
+pub async fn remove_export(
+    client: &impl RequestHelpers,
+    params: &RemoveExportParams,
+) -> Result<RemoveExport, error::RequestError> {
+    do_request_with_body(
+        client,
+        "/0/private/RemoveExport",
+        http::Method::POST,
+        EndpointSecurityType::Private,
+        params,
+    )
+    .await
+}
@@ -4,6 +4,7 @@ use derive_getters::Getters;
 use derive_new::new;
 use derive_setters::Setters;
 use display_json::{DebugAsJson, DisplayAsJsonPretty};
+use linnaeus_ws::messages::private_messages as ws;
 use rust_decimal::Decimal;
 use serde_with::formats::CommaSeparator;
 use serde_with::{
@@ -61,9 +62,32 @@ pub struct TradeBalances {
 pub struct OpenOrdersParams {
     trades: bool,
     userref: Option<i32>,
+    cl_ord_id: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, EnumDisplay, Clone)]
+impl OpenOrdersParams {
+    /// Whether an order passes the user reference and client order id filters
+    pub(crate) fn matches(&self, order: &OrderBase) -> bool {
+        matches_tags(self.userref, &self.cl_ord_id, order)
+    }
+}
+
+fn matches_tags(userref: Option<i32>, cl_ord_id: &Option<String>, order: &OrderBase) -> bool {
+    userref.is_none_or(|userref| order.user_reference_id == Some(i64::from(userref)))
+        && cl_ord_id
+            .as_ref()
+            .is_none_or(|id| order.client_order_id.as_ref() == Some(id))
+}
+
+fn within(
+    start: Option<chrono::DateTime<Utc>>,
+    end: Option<chrono::DateTime<Utc>>,
+    time: chrono::DateTime<Utc>,
+) -> bool {
+    start.is_none_or(|start| time >= start) && end.is_none_or(|end| time <= end)
+}
+
+#[derive(Debug, Serialize, Deserialize, EnumDisplay, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum OrderStatus {
     Pending,
@@ -73,6 +97,34 @@ pub enum OrderStatus {
     Expired,
 }
 
+impl OrderStatus {
+    /// Closed, canceled and expired orders can't change anymore
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Closed | Self::Canceled | Self::Expired)
+    }
+
+    /// Orders only move forward: pending orders can open or end and open orders can end
+    pub fn can_transition_to(&self, next: &Self) -> bool {
+        match self {
+            Self::Pending => *next != Self::Pending,
+            Self::Open => next.is_terminal(),
+            _ => false,
+        }
+    }
+}
+
+impl From<&OrderStatus> for ws::OrderStatus {
+    fn from(status: &OrderStatus) -> Self {
+        match status {
+            OrderStatus::Pending => Self::Pending,
+            OrderStatus::Open => Self::Open,
+            OrderStatus::Closed => Self::Closed,
+            OrderStatus::Canceled => Self::Canceled,
+            OrderStatus::Expired => Self::Expired,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, EnumDisplay, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum Trigger {
@@ -106,6 +158,15 @@ pub enum Side {
     Sell,
 }
 
+impl From<&Side> for ws::Side {
+    fn from(side: &Side) -> Self {
+        match side {
+            Side::Buy => Self::Buy,
+            Side::Sell => Self::Sell,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, EnumDisplay, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub enum OrderType {
@@ -118,6 +179,20 @@ pub enum OrderType {
     SettlePosition,
 }
 
+impl From<&OrderType> for ws::OrderType {
+    fn from(order_type: &OrderType) -> Self {
+        match order_type {
+            OrderType::Market => Self::Market,
+            OrderType::Limit => Self::Limit,
+            OrderType::StopLoss => Self::StopLoss,
+            OrderType::TakeProfit => Self::TakeProfit,
+            OrderType::StopLossLimit => Self::StopLossLimit,
+            OrderType::TakeProfitLimit => Self::TakeProfitLimit,
+            OrderType::SettlePosition => Self::SettlePosition,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, EnumDisplay, EnumString, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum OrderFlags {
@@ -140,11 +215,15 @@ pub enum OrderFlags {
 
 #[skip_serializing_none]
 #[serde_as]
-#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Setters, Clone)]
+#[setters(prefix = "with_")]
 pub struct OrderDescription {
+    #[setters(skip)]
     pair: String,
     #[serde(rename = "type")]
+    #[setters(skip)]
     side: Side,
+    #[setters(skip)]
     order_type: Option<OrderType>,
     price: Decimal,
     #[serde(rename = "price2")]
@@ -152,19 +231,43 @@ pub struct OrderDescription {
     #[serde_as(deserialize_as = "DefaultOnError")]
     leverage: Option<Decimal>,
     #[serde(rename = "order")]
+    #[setters(skip)]
     order_description: String,
     #[serde(rename = "close")]
     conditional_close_description: Option<String>,
 }
 
+impl OrderDescription {
+    /// Unpriced and without a conditional close
+    pub fn new(pair: String, side: Side, order_type: OrderType, order_description: String) -> Self {
+        Self {
+            pair,
+            side,
+            order_type: Some(order_type),
+            price: Decimal::ZERO,
+            secondary_price: Decimal::ZERO,
+            leverage: None,
+            order_description,
+            conditional_close_description: None,
+        }
+    }
+}
+
 #[serde_as]
-#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Setters, Clone)]
+#[setters(prefix = "with_")]
 pub struct OrderBase {
     #[serde(rename = "refid")]
     referral_order_transaction_id: Option<String>,
+    #[serde(rename = "userref")]
+    user_reference_id: Option<i64>,
+    #[serde(rename = "cl_ord_id")]
+    client_order_id: Option<String>,
+    #[setters(skip)]
     status: OrderStatus,
     #[serde(rename = "opentm")]
     #[serde_as(as = "TimestampSecondsWithFrac<f64>")]
+    #[setters(skip)]
     open_time: chrono::DateTime<Utc>,
     #[serde(rename = "starttm")]
     #[serde_as(as = "TimestampSecondsWithFrac<f64>")]
@@ -175,6 +278,7 @@ pub struct OrderBase {
     #[serde(rename = "descr")]
     description: OrderDescription,
     #[serde(rename = "vol")]
+    #[setters(skip)]
     volume: Decimal,
     #[serde(rename = "vol_exec")]
     vol_executed: Decimal,
@@ -196,6 +300,38 @@ pub struct OrderBase {
     trades: Vec<String>,
 }
 
+impl OrderBase {
+    /// An order that hasn't been filled yet, without a start or expire time
+    pub fn new(
+        status: OrderStatus,
+        open_time: chrono::DateTime<Utc>,
+        description: OrderDescription,
+        volume: Decimal,
+    ) -> Self {
+        Self {
+            referral_order_transaction_id: None,
+            user_reference_id: None,
+            client_order_id: None,
+            status,
+            open_time,
+            start_time: chrono::DateTime::UNIX_EPOCH,
+            expire_time: chrono::DateTime::UNIX_EPOCH,
+            description,
+            volume,
+            vol_executed: Decimal::ZERO,
+            cost: Decimal::ZERO,
+            fee: Decimal::ZERO,
+            price: Decimal::ZERO,
+            stop_price: Decimal::ZERO,
+            limit_price: Decimal::ZERO,
+            trigger: Trigger::default(),
+            misc: Vec::new(),
+            order_flags: Vec::new(),
+            trades: Vec::new(),
+        }
+    }
+}
+
 pub type OpenOrder = OrderBase;
 
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Clone)]
@@ -224,6 +360,7 @@ impl Default for TimeType {
 pub struct ClosedOrdersParams {
     trades: bool,
     userref: Option<i32>,
+    cl_ord_id: Option<String>,
     #[serde_as(as = "Option<TimestampSeconds<i64>>")]
     start: Option<chrono::DateTime<Utc>>,
     #[serde_as(as = "Option<TimestampSeconds<i64>>")]
@@ -233,6 +370,21 @@ pub struct ClosedOrdersParams {
     close_time: TimeType,
 }
 
+impl ClosedOrdersParams {
+    /// Whether an order closed at `closed_at` passes the filters. The offset isn't applied
+    pub(crate) fn matches(&self, order: &OrderBase, closed_at: chrono::DateTime<Utc>) -> bool {
+        let time = match self.close_time {
+            TimeType::Open => order.open_time,
+            TimeType::Close | TimeType::Both => closed_at,
+        };
+        matches_tags(self.userref, &self.cl_ord_id, order) && within(self.start, self.end, time)
+    }
+
+    pub(crate) fn page_offset(&self) -> usize {
+        self.offset.unwrap_or_default()
+    }
+}
+
 #[serde_as]
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
 pub struct ClosedOrder {
@@ -243,7 +395,7 @@ pub struct ClosedOrder {
     reason: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, new, Clone)]
 pub struct ClosedOrders {
     closed: HashMap<String, OrderBase>,
     count: usize,
@@ -350,6 +502,17 @@ pub struct TradeHistoryParams {
     offset: usize,
 }
 
+impl TradeHistoryParams {
+    /// Whether a trade passes the time filters. The offset isn't applied
+    pub(crate) fn matches(&self, trade: &Trade) -> bool {
+        within(self.start, self.end, trade.time)
+    }
+
+    pub(crate) fn page_offset(&self) -> usize {
+        self.offset
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, EnumDisplay, EnumString, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum TradeMiscInfo {
@@ -365,21 +528,29 @@ pub enum PositionStatus {
 }
 
 #[serde_as]
-#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Setters, Clone)]
+#[setters(prefix = "with_")]
 pub struct Trade {
     #[serde(rename = "ordertxid")]
+    #[setters(skip)]
     order_id: String,
+    #[setters(skip)]
     pair: String,
     #[serde_as(as = "TimestampSecondsWithFrac<f64>")]
+    #[setters(skip)]
     time: chrono::DateTime<Utc>,
     #[serde(rename = "type")]
+    #[setters(skip)]
     side: Side,
     #[serde(rename = "ordertype")]
+    #[setters(skip)]
     order_type: OrderType,
+    #[setters(skip)]
     price: Decimal,
     cost: Decimal,
     fee: Decimal,
     #[serde(rename = "vol")]
+    #[setters(skip)]
     volume: Decimal,
     margin: Decimal,
     #[serde_as(as = "StringWithSeparator::<CommaSeparator, TradeMiscInfo>")]
@@ -402,7 +573,42 @@ pub struct Trade {
     trades: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+impl Trade {
+    /// A spot trade without fees that cost `price * volume`
+    pub fn new(
+        order_id: String,
+        pair: String,
+        time: chrono::DateTime<Utc>,
+        side: Side,
+        order_type: OrderType,
+        price: Decimal,
+        volume: Decimal,
+    ) -> Self {
+        Self {
+            order_id,
+            pair,
+            time,
+            side,
+            order_type,
+            price,
+            cost: price * volume,
+            fee: Decimal::ZERO,
+            volume,
+            margin: Decimal::ZERO,
+            misc: Vec::new(),
+            position_status: None,
+            close_price: None,
+            close_cost: None,
+            close_fee: None,
+            close_volume: None,
+            close_margin: None,
+            net: None,
+            trades: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, new, Clone)]
 pub struct TradeHistory {
     trades: HashMap<String, Trade>,
     count: usize,
@@ -511,7 +717,7 @@ pub struct OpenPosition {
 
 pub type OpenPositions = HashMap<String, OpenPosition>;
 
-#[derive(Debug, Serialize, Deserialize, EnumDisplay, Clone)]
+#[derive(Debug, Serialize, Deserialize, EnumDisplay, Clone, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum LedgerType {
     All,
@@ -571,29 +777,72 @@ impl LedgerInfoParams {
     pub fn add_asset(&mut self, asset: &str) {
         self.assets.push(asset.to_string());
     }
+
+    /// Whether a ledger entry passes the asset, type and time filters. The offset isn't applied
+    pub(crate) fn matches(&self, ledger: &Ledger) -> bool {
+        (self.assets.is_empty() || self.assets.contains(&ledger.asset))
+            && (self.ledger_type == LedgerType::All || self.ledger_type == ledger.ledger_type)
+            && within(self.start, self.end, ledger.time)
+    }
+
+    pub(crate) fn page_offset(&self) -> usize {
+        self.offset
+    }
 }
 
 #[serde_as]
-#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Setters, Clone)]
+#[setters(prefix = "with_")]
 pub struct Ledger {
     #[serde(rename = "refid")]
+    #[setters(skip)]
     reference_id: String,
     #[serde_as(as = "TimestampSecondsWithFrac<f64>")]
+    #[setters(skip)]
     time: chrono::DateTime<Utc>,
     #[serde(rename = "type")]
+    #[setters(skip)]
     ledger_type: LedgerType,
     #[serde(rename = "subtype")]
+    #[serde(default)]
     sub_type: String,
     #[serde(rename = "aclass")]
     class: String,
+    #[setters(skip)]
     asset: String,
+    #[setters(skip)]
     amount: Decimal,
     fee: Decimal,
+    #[setters(skip)]
     balance: Decimal,
 }
 
+impl Ledger {
+    /// A currency entry without a fee or sub type
+    pub fn new(
+        reference_id: String,
+        time: chrono::DateTime<Utc>,
+        ledger_type: LedgerType,
+        asset: String,
+        amount: Decimal,
+        balance: Decimal,
+    ) -> Self {
+        Self {
+            reference_id,
+            time,
+            ledger_type,
+            sub_type: String::new(),
+            class: "currency".to_string(),
+            asset,
+            amount,
+            fee: Decimal::ZERO,
+            balance,
+        }
+    }
+}
+
 #[serde_as]
-#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, new, Clone)]
 pub struct LedgerInfo {
     ledger: HashMap<String, Ledger>,
     count: usize,
@@ -616,3 +865,180 @@ impl QueryLedgerParams {
 }
 
 pub type Ledgers = HashMap<String, Ledger>;
+
+#[serde_as]
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Setters, Default, Clone)]
+pub struct TradeVolumeParams {
+    #[serde(rename = "pair")]
+    #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
+    pairs: Vec<String>,
+}
+
+impl TradeVolumeParams {
+    pub fn add_pair(&mut self, pair: &str) {
+        self.pairs.push(pair.to_string());
+    }
+}
+
+/// The fee tier the account is in for a pair
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct FeeTierInfo {
+    /// Current fee in percent
+    fee: Decimal,
+    #[serde(rename = "minfee")]
+    min_fee: Decimal,
+    #[serde(rename = "maxfee")]
+    max_fee: Decimal,
+    /// Fee in percent of the next tier. None at the lowest fee
+    #[serde(rename = "nextfee")]
+    next_fee: Option<Decimal>,
+    /// Volume needed to reach the next tier
+    #[serde(rename = "nextvolume")]
+    next_volume: Option<Decimal>,
+    /// Volume of the current tier
+    #[serde(rename = "tiervolume")]
+    tier_volume: Option<Decimal>,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct TradeVolume {
+    /// Currency the volume is measured in
+    currency: String,
+    /// 30 day trading volume
+    volume: Decimal,
+    /// Taker fees keyed by the pairs in the request
+    #[serde(default)]
+    fees: HashMap<String, FeeTierInfo>,
+    /// Maker fees keyed by the pairs in the request. Pairs without a maker/taker schedule are
+    /// missing
+    #[serde(default)]
+    fees_maker: HashMap<String, FeeTierInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, EnumDisplay, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportReportType {
+    Trades,
+    Ledgers,
+}
+
+#[derive(Debug, Serialize, Deserialize, EnumDisplay, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[serde(rename = "CSV")]
+    Csv,
+    #[serde(rename = "TSV")]
+    Tsv,
+}
+
+#[serde_as]
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Setters, Clone)]
+pub struct AddExportParams {
+    report: ExportReportType,
+    format: ExportFormat,
+    description: String,
+    /// Columns to include. Kraken includes every column when this is None
+    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, String>>")]
+    fields: Option<Vec<String>>,
+    #[serde(rename = "starttm")]
+    #[serde_as(as = "Option<TimestampSeconds<i64>>")]
+    start_time: Option<chrono::DateTime<Utc>>,
+    #[serde(rename = "endtm")]
+    #[serde_as(as = "Option<TimestampSeconds<i64>>")]
+    end_time: Option<chrono::DateTime<Utc>>,
+}
+
+impl AddExportParams {
+    /// A CSV report of every column over the whole account history
+    pub fn new(report: ExportReportType, description: &str) -> Self {
+        Self {
+            report,
+            format: ExportFormat::Csv,
+            description: description.to_string(),
+            fields: None,
+            start_time: None,
+            end_time: None,
+        }
+    }
+
+    pub fn report_type(&self) -> ExportReportType {
+        self.report
+    }
+
+    pub fn export_format(&self) -> ExportFormat {
+        self.format
+    }
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct AddExport {
+    id: String,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, new, Clone)]
+pub struct ExportStatusParams {
+    report: ExportReportType,
+}
+
+#[derive(Debug, Serialize, Deserialize, EnumDisplay, Clone, Copy, PartialEq, Eq)]
+pub enum ExportReportStatus {
+    Queued,
+    Processing,
+    Processed,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct ExportReport {
+    id: String,
+    #[serde(rename = "descr")]
+    description: String,
+    format: ExportFormat,
+    report: ExportReportType,
+    status: ExportReportStatus,
+    fields: String,
+    #[serde(rename = "createdtm")]
+    #[serde_as(as = "TimestampSeconds<String>")]
+    created_time: chrono::DateTime<Utc>,
+    #[serde(rename = "completedtm")]
+    #[serde_as(as = "Option<TimestampSeconds<String>>")]
+    #[serde(default)]
+    completed_time: Option<chrono::DateTime<Utc>>,
+    #[serde(rename = "datastarttm")]
+    #[serde_as(as = "Option<TimestampSeconds<String>>")]
+    #[serde(default)]
+    data_start_time: Option<chrono::DateTime<Utc>>,
+    #[serde(rename = "dataendtm")]
+    #[serde_as(as = "Option<TimestampSeconds<String>>")]
+    #[serde(default)]
+    data_end_time: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, new, Clone)]
+pub struct RetrieveExportParams {
+    id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, EnumDisplay, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoveExportType {
+    /// Cancel a report that is still queued or processing
+    Cancel,
+    /// Delete a processed report
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, new, Clone)]
+pub struct RemoveExportParams {
+    id: String,
+    #[serde(rename = "type")]
+    remove_type: RemoveExportType,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct RemoveExport {
+    delete: Option<bool>,
+    cancel: Option<bool>,
+}
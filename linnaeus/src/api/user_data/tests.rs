@@ -166,3 +166,13 @@ async fn test_query_ledger_info() -> Result<()> {
     info!("ledger info is {:?}", ledger_info);
     Ok(())
 }
+
+#[tokio::test]
+async fn test_trade_volume() -> Result<()> {
+    let bin = setup();
+    let mut params = TradeVolumeParams::default();
+    params.add_pair("XXBTZUSD");
+    let trade_volume = trade_volume(&bin, &params).await.error()?;
+    info!("trade volume is {:?}", trade_volume);
+    Ok(())
+}
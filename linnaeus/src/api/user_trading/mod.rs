@@ -1,3 +1,35 @@
 mod structs;
 #[cfg(test)]
 mod tests;
+
+pub use structs::*;
+
+use linnaeus_request::*;
+
+pub async fn add_order(
+    client: &impl RequestHelpers,
+    params: &AddOrderParams,
+) -> Result<AddOrderResponse, error::RequestError> {
+    do_request_with_body(
+        client,
+        "/0/private/AddOrder",
+        http::Method::POST,
+        EndpointSecurityType::Private,
+        params,
+    )
+    .await
+}
+
+pub async fn cancel_order(
+    client: &impl RequestHelpers,
+    params: &CancelOrderParams,
+) -> Result<CancelOrderResponse, error::RequestError> {
+    do_request_with_body(
+        client,
+        "/0/private/CancelOrder",
+        http::Method::POST,
+        EndpointSecurityType::Private,
+        params,
+    )
+    .await
+}
@@ -0,0 +1,220 @@
+use crate::api::user_data::{OrderFlags, OrderType, Side, Trigger};
+use crate::{Deserialize, Serialize};
+use derive_getters::Getters;
+use derive_new::new;
+use derive_setters::Setters;
+use display_json::{DebugAsJson, DisplayAsJsonPretty};
+use rust_decimal::Decimal;
+use serde_with::formats::CommaSeparator;
+use serde_with::{serde_as, skip_serializing_none, StringWithSeparator};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TimeInForce {
+    #[serde(rename = "GTC")]
+    GoodTillCancelled,
+    #[serde(rename = "IOC")]
+    ImmediateOrCancel,
+    #[serde(rename = "GTD")]
+    GoodTillDate,
+}
+
+#[serde_as]
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Setters, Clone)]
+#[setters(strip_option, prefix = "with_")]
+pub struct AddOrderParams {
+    #[serde(rename = "ordertype")]
+    #[setters(skip)]
+    order_type: OrderType,
+    #[serde(rename = "type")]
+    #[setters(skip)]
+    side: Side,
+    #[setters(skip)]
+    pair: String,
+    #[setters(skip)]
+    volume: Decimal,
+    price: Option<Decimal>,
+    price2: Option<Decimal>,
+    trigger: Option<Trigger>,
+    leverage: Option<Decimal>,
+    reduce_only: Option<bool>,
+    #[serde(rename = "oflags")]
+    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, OrderFlags>>")]
+    order_flags: Option<Vec<OrderFlags>>,
+    #[serde(rename = "timeinforce")]
+    time_in_force: Option<TimeInForce>,
+    ///Scheduled start time, either a unix timestamp or `+<n>` seconds from now
+    #[serde(rename = "starttm")]
+    start_time: Option<String>,
+    ///Expiration time, either a unix timestamp or `+<n>` seconds from now
+    #[serde(rename = "expiretm")]
+    expire_time: Option<String>,
+    ///Numeric tag for the order, can't be combined with a client order id
+    #[serde(rename = "userref")]
+    user_reference_id: Option<i32>,
+    ///Free form tag for the order, can't be combined with a user reference id
+    #[serde(rename = "cl_ord_id")]
+    client_order_id: Option<String>,
+    ///Only validate the inputs, the order isn't submitted
+    validate: Option<bool>,
+    ///RFC3339 timestamp after which the matching engine should reject the order
+    deadline: Option<String>,
+    #[serde(rename = "close[ordertype]")]
+    #[setters(skip)]
+    #[getter(skip)]
+    close_order_type: Option<OrderType>,
+    #[serde(rename = "close[price]")]
+    #[setters(skip)]
+    #[getter(skip)]
+    close_price: Option<Decimal>,
+    #[serde(rename = "close[price2]")]
+    #[setters(skip)]
+    #[getter(skip)]
+    close_price2: Option<Decimal>,
+}
+
+impl AddOrderParams {
+    pub fn new(order_type: OrderType, side: Side, pair: String, volume: Decimal) -> Self {
+        Self {
+            order_type,
+            side,
+            pair,
+            volume,
+            price: None,
+            price2: None,
+            trigger: None,
+            leverage: None,
+            reduce_only: None,
+            order_flags: None,
+            time_in_force: None,
+            start_time: None,
+            expire_time: None,
+            user_reference_id: None,
+            client_order_id: None,
+            validate: None,
+            deadline: None,
+            close_order_type: None,
+            close_price: None,
+            close_price2: None,
+        }
+    }
+
+    /// Have kraken place the close order once this order is filled
+    pub fn with_close(mut self, close: ConditionalClose) -> Self {
+        self.close_order_type = Some(close.order_type);
+        self.close_price = Some(close.price);
+        self.close_price2 = close.price2;
+        self
+    }
+
+    pub fn close(&self) -> Option<ConditionalClose> {
+        Some(ConditionalClose {
+            order_type: self.close_order_type.clone()?,
+            price: self.close_price?,
+            price2: self.close_price2,
+        })
+    }
+}
+
+/// Order that kraken places, with the entry's volume and the opposite side, once the entry order
+/// it's attached to is filled
+#[derive(Debug, Clone, Getters)]
+pub struct ConditionalClose {
+    order_type: OrderType,
+    price: Decimal,
+    price2: Option<Decimal>,
+}
+
+impl ConditionalClose {
+    pub fn limit(price: Decimal) -> Self {
+        Self {
+            order_type: OrderType::Limit,
+            price,
+            price2: None,
+        }
+    }
+
+    /// Market order once `trigger` is reached in the profitable direction
+    pub fn take_profit(trigger: Decimal) -> Self {
+        Self {
+            order_type: OrderType::TakeProfit,
+            price: trigger,
+            price2: None,
+        }
+    }
+
+    /// Market order once `trigger` is reached in the losing direction
+    pub fn stop_loss(trigger: Decimal) -> Self {
+        Self {
+            order_type: OrderType::StopLoss,
+            price: trigger,
+            price2: None,
+        }
+    }
+
+    /// Limit order at `limit` once `trigger` is reached in the profitable direction
+    pub fn take_profit_limit(trigger: Decimal, limit: Decimal) -> Self {
+        Self {
+            order_type: OrderType::TakeProfitLimit,
+            price: trigger,
+            price2: Some(limit),
+        }
+    }
+
+    /// Limit order at `limit` once `trigger` is reached in the losing direction
+    pub fn stop_loss_limit(trigger: Decimal, limit: Decimal) -> Self {
+        Self {
+            order_type: OrderType::StopLossLimit,
+            price: trigger,
+            price2: Some(limit),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, new, Clone)]
+pub struct AddOrderDescription {
+    order: String,
+    close: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, new, Clone)]
+pub struct AddOrderResponse {
+    #[serde(rename = "descr")]
+    description: AddOrderDescription,
+    #[serde(rename = "txid", default)]
+    transaction_ids: Vec<String>,
+}
+
+/// Identifies the order to cancel by either its txid, user reference or client order id
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct CancelOrderParams {
+    #[serde(rename = "txid")]
+    transaction_id: Option<String>,
+    #[serde(rename = "cl_ord_id")]
+    client_order_id: Option<String>,
+}
+
+impl CancelOrderParams {
+    /// A txid, or a user reference to cancel every order tagged with it
+    pub fn by_transaction_id(transaction_id: String) -> Self {
+        Self {
+            transaction_id: Some(transaction_id),
+            client_order_id: None,
+        }
+    }
+
+    pub fn by_client_order_id(client_order_id: String) -> Self {
+        Self {
+            transaction_id: None,
+            client_order_id: Some(client_order_id),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, new, Clone)]
+pub struct CancelOrderResponse {
+    count: usize,
+    #[serde(default)]
+    pending: bool,
+}
@@ -0,0 +1,37 @@
+use super::*;
+use crate::api::user_data::{OrderFlags, OrderType, Side};
+use anyhow::Result;
+use pretty_assertions::{assert_eq, assert_str_eq};
+use rust_decimal_macros::dec;
+
+#[test]
+fn test_add_order_params_encoding() -> Result<()> {
+    let params = AddOrderParams::new(OrderType::Limit, Side::Buy, "XBTUSD".into(), dec!(1.25))
+        .with_price(dec!(37500))
+        .with_order_flags(vec![OrderFlags::Post, OrderFlags::Fciq])
+        .with_time_in_force(TimeInForce::GoodTillCancelled)
+        .with_client_order_id("6d1b345e-2821-40e2-ad83-4ecb18a06876".into());
+    assert_str_eq!(
+        serde_urlencoded::to_string(&params)?,
+        "ordertype=limit&type=buy&pair=XBTUSD&volume=1.25&price=37500&oflags=post%2Cfciq\
+         &timeinforce=GTC&cl_ord_id=6d1b345e-2821-40e2-ad83-4ecb18a06876"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_add_order_params_with_close() -> Result<()> {
+    let params = AddOrderParams::new(OrderType::Limit, Side::Buy, "XBTUSD".into(), dec!(1))
+        .with_price(dec!(20000))
+        .with_close(ConditionalClose::stop_loss_limit(dec!(19000), dec!(18900)));
+    assert_str_eq!(
+        serde_urlencoded::to_string(&params)?,
+        "ordertype=limit&type=buy&pair=XBTUSD&volume=1&price=20000\
+         &close%5Bordertype%5D=stop-loss-limit&close%5Bprice%5D=19000&close%5Bprice2%5D=18900"
+    );
+    assert_eq!(
+        params.close().map(|close| *close.price()),
+        Some(dec!(19000))
+    );
+    Ok(())
+}
@@ -0,0 +1,416 @@
+use crate::api::market_data::{recent_trades, RecentTradesParams, TradeData};
+use crate::Linnaeus;
+use chrono::{DateTime, Utc};
+use derive_setters::Setters;
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use linnaeus_request::error::RequestError;
+use linnaeus_request::RequestHelpers;
+use linnaeus_types as types;
+use linnaeus_ws::messages::public_messages::Trades;
+use linnaeus_ws::messages::Pair;
+use log::{debug, warn};
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Walks the public trade tape of a pair from a start time.
+///
+/// The stream follows the `last` cursor of the Trades endpoint and ends at `end` or, without an
+/// end, once it has caught up with live trading. That is when a page comes back short or its last
+/// trade is within `live_window` of now. Rate limit errors are retried after a pause. Any other
+/// error is yielded and ends the stream.
+#[derive(Debug, Clone, Setters)]
+#[setters(prefix = "with_")]
+pub struct TradeBackfill {
+    #[setters(skip)]
+    pair: String,
+    #[setters(skip)]
+    start: DateTime<Utc>,
+    #[setters(strip_option)]
+    end: Option<DateTime<Utc>>,
+    /// Pause between pages to stay under the public rate limit
+    page_delay: Duration,
+    /// Pause after kraken rejected a request for exceeding the rate limit
+    rate_limit_delay: Duration,
+    /// How close to now the last trade of a page has to be to count as caught up
+    live_window: Duration,
+}
+
+/// Number of trades in a full page of the Trades endpoint
+const PAGE_SIZE: usize = 1000;
+
+impl TradeBackfill {
+    pub fn new(pair: &str, start: DateTime<Utc>) -> Self {
+        Self {
+            pair: pair.to_string(),
+            start,
+            end: None,
+            page_delay: Duration::from_secs(1),
+            rate_limit_delay: Duration::from_secs(5),
+            live_window: Duration::from_secs(10),
+        }
+    }
+
+    pub fn stream<C: RequestHelpers + Send + Sync + 'static>(
+        self,
+        client: C,
+    ) -> impl Stream<Item = Result<TradeData, RequestError>> {
+        let state = BackfillState {
+            cursor: self.start.timestamp_nanos_opt().unwrap_or(0) as u64,
+            backfill: self,
+            client,
+            buffered: VecDeque::new(),
+            first_page: true,
+            done: false,
+        };
+        futures::stream::unfold(state, |mut state| async move {
+            let item = state.next().await?;
+            Some((item, state))
+        })
+    }
+
+    /// Backfill up to live trading then continue with the trades from a websocket subscription.
+    ///
+    /// Subscribe before calling this so that no trades are missed while backfilling. Websocket
+    /// trades the backfill already returned are skipped. The stream ends after the first error
+    pub fn with_live<C, S>(
+        self,
+        client: C,
+        live: S,
+    ) -> impl Stream<Item = Result<types::Trade, RequestError>>
+    where
+        C: RequestHelpers + Send + Sync + 'static,
+        S: Stream<Item = (Pair, Trades)> + Send + Unpin + 'static,
+    {
+        hand_off(self.stream(client).boxed(), live)
+    }
+}
+
+struct BackfillState<C> {
+    backfill: TradeBackfill,
+    client: C,
+    cursor: u64,
+    buffered: VecDeque<TradeData>,
+    first_page: bool,
+    done: bool,
+}
+
+impl<C: RequestHelpers> BackfillState<C> {
+    async fn next(&mut self) -> Option<Result<TradeData, RequestError>> {
+        loop {
+            if let Some(trade) = self.buffered.pop_front() {
+                return Some(Ok(trade));
+            }
+            if self.done {
+                return None;
+            }
+            if !self.first_page {
+                tokio::time::sleep(self.backfill.page_delay).await;
+            }
+            self.first_page = false;
+            let params = RecentTradesParams::new_with_cursor(&self.backfill.pair, self.cursor);
+            let trades = match recent_trades(&self.client, &params).await {
+                Ok(trades) => trades,
+                Err(err) if err.is_rate_limit() => {
+                    warn!(
+                        "rate limited while backfilling {}. Retrying in {:?}",
+                        self.backfill.pair, self.backfill.rate_limit_delay
+                    );
+                    tokio::time::sleep(self.backfill.rate_limit_delay).await;
+                    continue;
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            };
+            let page: Vec<&TradeData> = trades.trade_data().values().flatten().collect();
+            for &trade in &page {
+                if matches!(self.backfill.end, Some(end) if *trade.time() >= end) {
+                    self.done = true;
+                    break;
+                }
+                self.buffered.push_back(trade.clone());
+            }
+            debug!(
+                "backfilled {} trades for {} at cursor {}",
+                self.buffered.len(),
+                self.backfill.pair,
+                trades.last()
+            );
+            let live_since = Utc::now()
+                - chrono::Duration::from_std(self.backfill.live_window).unwrap_or_default();
+            if self.buffered.is_empty()
+                || *trades.last() <= self.cursor
+                || page.len() < PAGE_SIZE
+                || page.last().is_some_and(|trade| *trade.time() >= live_since)
+            {
+                // caught up with live trading
+                self.done = true;
+            }
+            self.cursor = *trades.last();
+        }
+    }
+}
+
+/// Trades are matched on their time to the microsecond, price, volume and side. REST times are
+/// floats so they are rounded rather than compared exactly
+type TradeKey = (i64, Decimal, Decimal, types::Side);
+
+fn trade_key(trade: &types::Trade) -> TradeKey {
+    let nanos = trade.time().timestamp_nanos_opt().unwrap_or(0);
+    (
+        (nanos + 500).div_euclid(1000),
+        *trade.price(),
+        *trade.volume(),
+        *trade.side(),
+    )
+}
+
+struct HandOff {
+    backfill: Option<BoxStream<'static, Result<TradeData, RequestError>>>,
+    live: mpsc::UnboundedReceiver<types::Trade>,
+    /// Time of the last backfilled trade and the trades that happened at that time
+    last: Option<(i64, Vec<TradeKey>)>,
+    failed: bool,
+}
+
+impl HandOff {
+    async fn next(&mut self) -> Option<Result<types::Trade, RequestError>> {
+        if self.failed {
+            return None;
+        }
+        if let Some(backfill) = &mut self.backfill {
+            match backfill.next().await {
+                Some(Ok(trade)) => {
+                    let trade = types::Trade::from(&trade);
+                    let key = trade_key(&trade);
+                    match &mut self.last {
+                        Some((time, keys)) if *time == key.0 => keys.push(key),
+                        _ => self.last = Some((key.0, vec![key])),
+                    }
+                    return Some(Ok(trade));
+                }
+                Some(Err(err)) => {
+                    self.failed = true;
+                    return Some(Err(err));
+                }
+                None => self.backfill = None,
+            }
+        }
+        loop {
+            let trade = self.live.recv().await?;
+            let key = trade_key(&trade);
+            match &mut self.last {
+                Some((time, _)) if key.0 < *time => continue,
+                Some((time, keys)) if key.0 == *time => {
+                    if let Some(position) = keys.iter().position(|seen| *seen == key) {
+                        keys.swap_remove(position);
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            return Some(Ok(trade));
+        }
+    }
+}
+
+fn hand_off<S>(
+    backfill: BoxStream<'static, Result<TradeData, RequestError>>,
+    mut live: S,
+) -> impl Stream<Item = Result<types::Trade, RequestError>>
+where
+    S: Stream<Item = (Pair, Trades)> + Send + Unpin + 'static,
+{
+    // drain the subscription while backfilling so that it doesn't fall behind
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some((_, trades)) = live.next().await {
+            for trade in &trades {
+                if sender.send(types::Trade::from(trade)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    let state = HandOff {
+        backfill: Some(backfill),
+        live: receiver,
+        last: None,
+        failed: false,
+    };
+    futures::stream::unfold(state, |mut state| async move {
+        let item = state.next().await?;
+        Some((item, state))
+    })
+}
+
+impl Linnaeus {
+    /// Walk the public trade tape. See [TradeBackfill]
+    pub fn backfill_trades(
+        &self,
+        backfill: TradeBackfill,
+    ) -> impl Stream<Item = Result<TradeData, RequestError>> {
+        backfill.stream(self.rest_client())
+    }
+
+    /// Backfill then continue with a websocket trade subscription. See [TradeBackfill::with_live]
+    pub fn backfill_trades_with_live<S>(
+        &self,
+        backfill: TradeBackfill,
+        live: S,
+    ) -> impl Stream<Item = Result<types::Trade, RequestError>>
+    where
+        S: Stream<Item = (Pair, Trades)> + Send + Unpin + 'static,
+    {
+        backfill.with_live(self.rest_client(), live)
+    }
+}
+
+#[cfg(test)]
+mod backfill_tests {
+    use super::*;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn rest_trade(price: &str, seconds: f64) -> Result<TradeData, RequestError> {
+        Ok(
+            serde_json::from_value(json!([price, "1", seconds, "b", "l", ""]))
+                .expect("couldn't deserialize trade"),
+        )
+    }
+
+    fn live_trades(trades: &[(&str, &str)]) -> (Pair, Trades) {
+        let trades = trades
+            .iter()
+            .map(|(price, time)| json!([price, "1", time, "b", "l", ""]))
+            .collect::<Vec<_>>();
+        (
+            "XBT/USD".to_string(),
+            serde_json::from_value(json!(trades)).expect("couldn't deserialize trades"),
+        )
+    }
+
+    #[tokio::test]
+    async fn live_trades_already_backfilled_are_skipped() {
+        let backfill = futures::stream::iter(vec![
+            rest_trade("1", 100.5),
+            rest_trade("2", 101.25),
+            rest_trade("3", 101.25),
+        ])
+        .boxed();
+        let live = futures::stream::iter(vec![
+            live_trades(&[("1", "100.500000"), ("2", "101.250000")]),
+            live_trades(&[("4", "101.250000"), ("5", "102.000000")]),
+        ]);
+        let prices: Vec<Decimal> = hand_off(backfill, live)
+            .map(|trade| *trade.expect("unexpected error").price())
+            .collect()
+            .await;
+        assert_eq!(prices, vec![dec!(1), dec!(2), dec!(3), dec!(4), dec!(5)]);
+    }
+
+    struct TestClient {
+        client: reqwest::Client,
+        keys: linnaeus_request::KrakenKeyPair,
+        base_url: String,
+    }
+
+    impl linnaeus_request::RequestClient for TestClient {
+        fn get_client(&self) -> &reqwest::Client {
+            &self.client
+        }
+
+        fn get_keys(&self) -> &linnaeus_request::KrakenKeyPair {
+            &self.keys
+        }
+
+        fn get_base_url(&self) -> &str {
+            &self.base_url
+        }
+    }
+
+    impl RequestHelpers for TestClient {}
+
+    /// Serve `body` to every request and count the requests
+    async fn serve(body: serde_json::Value) -> (TestClient, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("couldn't bind");
+        let address = listener.local_addr().expect("no address");
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            let body = body.to_string();
+            while let Ok((mut socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+                    match socket.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        let client = TestClient {
+            client: reqwest::Client::new(),
+            keys: linnaeus_request::KrakenKeyPair::new("", ""),
+            base_url: format!("http://{}", address),
+        };
+        (client, requests)
+    }
+
+    #[tokio::test]
+    async fn short_page_hands_off_to_live_trades() {
+        let (client, requests) = serve(json!({
+            "error": [],
+            "result": {
+                "XXBTZUSD": [["1", "1", 100.5, "b", "l", ""], ["2", "1", 101.25, "b", "l", ""]],
+                "last": "101250000000"
+            }
+        }))
+        .await;
+        let live = futures::stream::iter(vec![
+            live_trades(&[("2", "101.250000")]),
+            live_trades(&[("3", "102.000000")]),
+        ]);
+        let prices: Vec<Decimal> = TradeBackfill::new("XBTUSD", Utc.timestamp_opt(100, 0).unwrap())
+            .with_page_delay(Duration::ZERO)
+            .with_live(client, live)
+            .map(|trade| *trade.expect("unexpected error").price())
+            .collect()
+            .await;
+        assert_eq!(prices, vec![dec!(1), dec!(2), dec!(3)]);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn errors_end_the_stream() {
+        let backfill = futures::stream::iter(vec![
+            rest_trade("1", 100.0),
+            Err(RequestError::Other("down".to_string())),
+        ])
+        .boxed();
+        let live = futures::stream::iter(vec![live_trades(&[("5", "102.000000")])]);
+        let results: Vec<_> = hand_off(backfill, live).collect().await;
+        assert_eq!(results.len(), 2);
+        assert!(results[1].is_err());
+    }
+}
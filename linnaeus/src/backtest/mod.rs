@@ -0,0 +1,546 @@
+mod stats;
+
+pub use stats::Summary;
+
+use crate::api::market_data::TickData;
+use crate::api::user_data::{AccountBalances, Trade};
+use crate::fees::FeeCalculator;
+use crate::order_tracker::OrderTracker;
+use crate::paper::{PaperExchange, Slippage};
+use crate::registry::AssetRegistry;
+use crate::strategy::{MarketEvent, OrderRequest, Strategy, StrategyContext};
+use crate::trading::TradingClient;
+use chrono::{DateTime, Duration, Utc};
+use derive_getters::Getters;
+use derive_new::new;
+use derive_setters::Setters;
+use linnaeus_request::error::RequestError;
+use linnaeus_types::{AssetId, PairId};
+use linnaeus_ws::book::LocalBook;
+use linnaeus_ws::messages::general_messages::Depth;
+use linnaeus_ws::messages::private_messages::OpenOrders;
+use linnaeus_ws::messages::{ChannelMessage, ChannelMessageWrapper};
+use linnaeus_ws::recorder::read_recording;
+use log::{debug, warn};
+use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+#[derive(Debug, Error)]
+pub enum BacktestError {
+    #[error("unknown currency {0}")]
+    UnknownCurrency(String),
+    #[error("the sample interval must be positive, got {0}")]
+    SampleInterval(Duration),
+    #[error("couldn't fund the simulated account -> {0}")]
+    Funding(#[from] RequestError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Getters, new)]
+pub struct EquityPoint {
+    time: DateTime<Utc>,
+    equity: Decimal,
+}
+
+#[derive(Debug, Clone, Setters)]
+#[setters(prefix = "with_")]
+pub struct BacktestConfig {
+    /// Asset the equity curve and statistics are measured in
+    #[setters(skip)]
+    currency: String,
+    /// Starting balances keyed by any asset name the registry knows
+    balances: HashMap<String, Decimal>,
+    /// Time between the strategy sending a request and the exchange receiving it
+    latency: Duration,
+    slippage: Slippage,
+    /// The equity curve gets a point every interval. The Sharpe ratio is computed from the
+    /// returns between points
+    sample_interval: Duration,
+}
+
+impl BacktestConfig {
+    /// No balances, latency or slippage and an equity point every hour. `currency` can be any
+    /// asset name the registry knows
+    pub fn new(currency: &str) -> Self {
+        Self {
+            currency: currency.to_string(),
+            balances: HashMap::new(),
+            latency: Duration::zero(),
+            slippage: Slippage::None,
+            sample_interval: Duration::hours(1),
+        }
+    }
+
+    pub fn with_balance(mut self, asset: &str, amount: Decimal) -> Self {
+        self.balances.insert(asset.to_string(), amount);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Getters)]
+pub struct BacktestReport {
+    equity_curve: Vec<EquityPoint>,
+    /// Every fill keyed by its trade id, oldest first
+    fills: Vec<(String, Trade)>,
+    /// Balances at the end of the run
+    balances: AccountBalances,
+    summary: Summary,
+}
+
+/// Replays market data through a [Strategy] on a simulated clock.
+///
+/// The strategy's orders are matched by a [PaperExchange] running on the replay's clock, so the
+/// fee model is the [FeeCalculator] it's built with, either the account's tiers or
+/// [FeeCalculator::flat]. Requests reach the exchange after the configured latency and takers
+/// pay the configured slippage. Candles are replayed as four trades at their open, low, high and
+/// close, or open, high, low and close for falling candles, spread evenly from the start to the
+/// end of their interval. The strategy sees a candle once its interval has ended.
+///
+/// Nothing depends on the system clock or on hash ordering so the same data and strategy always
+/// produce the same report
+#[derive(Debug, Clone)]
+pub struct Backtester {
+    fees: FeeCalculator,
+    config: BacktestConfig,
+}
+
+impl Backtester {
+    pub fn new(fees: FeeCalculator, config: BacktestConfig) -> Self {
+        Self { fees, config }
+    }
+
+    /// Replay `events`, which must be in time order, as fast as they can be handled
+    pub async fn run<S: Strategy>(
+        &self,
+        strategy: &mut S,
+        events: impl IntoIterator<Item = MarketEvent>,
+    ) -> Result<BacktestReport, BacktestError> {
+        if self.config.sample_interval <= Duration::zero() {
+            return Err(BacktestError::SampleInterval(self.config.sample_interval));
+        }
+        let registry = self.fees.registry();
+        let currency = registry
+            .asset_id(&self.config.currency)
+            .cloned()
+            .ok_or_else(|| BacktestError::UnknownCurrency(self.config.currency.clone()))?;
+        let exchange = PaperExchange::new(self.fees.clone()).with_slippage(self.config.slippage);
+        let mut events = events.into_iter().peekable();
+        let start = events.peek().map(MarketEvent::time).unwrap_or_default();
+        exchange.set_time(events.peek().map(first_trade_time).unwrap_or_default());
+        let mut balances: Vec<_> = self.config.balances.iter().collect();
+        balances.sort();
+        for (asset, amount) in balances {
+            exchange.deposit(asset, *amount)?;
+        }
+
+        let mut replay = Replay {
+            feed: exchange.subscribe(),
+            exchange,
+            strategy,
+            tracker: OrderTracker::new(),
+            pending: VecDeque::new(),
+            latency: self.config.latency,
+        };
+        let mut prices = HashMap::new();
+        let mut curve = Vec::new();
+        let mut next_sample = start;
+        let mut end = start;
+        for event in events {
+            let time = event.time();
+            end = time;
+            replay.apply(&event).await;
+            if let (Some(pair), Some(price)) = (registry.pair_id(event.pair()), event.price()) {
+                prices.insert(pair.clone(), price);
+            }
+            while next_sample <= time {
+                let equity = equity(registry, &replay.exchange.balances(), &prices, &currency);
+                curve.push(EquityPoint::new(next_sample, equity));
+                next_sample += self.config.sample_interval;
+            }
+            let mut context = StrategyContext::new(time);
+            replay.notify(&mut context);
+            replay.strategy.on_market(&event, &mut context);
+            replay.send(context);
+            replay.deliver(time).await;
+        }
+        let balances = replay.exchange.balances();
+        let equity = equity(registry, &balances, &prices, &currency);
+        if curve.last().map(|point| point.time) != Some(end) {
+            curve.push(EquityPoint::new(end, equity));
+        }
+
+        let fills = replay.exchange.fills();
+        let (mut traded, mut fees) = (Decimal::ZERO, Decimal::ZERO);
+        for (_, fill) in &fills {
+            if let Some((_, quote)) = registry.pair_assets(&PairId::new(fill.pair())) {
+                traded += value(registry, &prices, quote, *fill.cost(), &currency);
+                fees += value(registry, &prices, quote, *fill.fee(), &currency);
+            }
+        }
+        let periods_per_year = Duration::days(365).num_seconds() as f64
+            / self.config.sample_interval.num_seconds().max(1) as f64;
+        Ok(BacktestReport {
+            summary: Summary::new(&curve, traded, fees, fills.len(), periods_per_year),
+            equity_curve: curve,
+            fills,
+            balances: balances
+                .into_iter()
+                .map(|(asset, balance)| (asset.to_string(), balance))
+                .collect(),
+        })
+    }
+}
+
+struct Replay<'a, S> {
+    exchange: PaperExchange,
+    feed: broadcast::Receiver<OpenOrders>,
+    strategy: &'a mut S,
+    tracker: OrderTracker,
+    /// Requests on their way to the exchange and the time they arrive
+    pending: VecDeque<(DateTime<Utc>, OrderRequest)>,
+    latency: Duration,
+}
+
+impl<S: Strategy> Replay<'_, S> {
+    /// Pass an event to the exchange, delivering the requests that arrive before each of its
+    /// trades first
+    async fn apply(&mut self, event: &MarketEvent) {
+        match event {
+            MarketEvent::Candle {
+                pair,
+                candle,
+                interval,
+            } => {
+                let volume = candle.volume() / Decimal::from(4);
+                for (step, price) in (0..).zip(candle_prices(candle)) {
+                    self.advance(*candle.time() + *interval * step / 3).await;
+                    self.exchange.apply_trade(pair, price, volume);
+                }
+            }
+            MarketEvent::Trade {
+                pair,
+                time,
+                price,
+                volume,
+            } => {
+                self.advance(*time).await;
+                self.exchange.apply_trade(pair, *price, *volume);
+            }
+            MarketEvent::Book { time, book, .. } => {
+                self.advance(*time).await;
+                self.exchange.apply_book(book);
+            }
+        }
+    }
+
+    async fn advance(&mut self, time: DateTime<Utc>) {
+        self.deliver(time).await;
+        self.exchange.set_time(time);
+    }
+
+    /// Pass the exchange's order updates to the strategy
+    fn notify(&mut self, context: &mut StrategyContext) {
+        loop {
+            match self.feed.try_recv() {
+                Ok(orders) => {
+                    for event in self.tracker.apply_open_orders(&orders) {
+                        self.strategy.on_order(&event, context);
+                    }
+                }
+                Err(TryRecvError::Lagged(skipped)) => {
+                    warn!("backtest lost {} open order updates", skipped)
+                }
+                Err(_) => return,
+            }
+        }
+    }
+
+    fn send(&mut self, context: StrategyContext) {
+        let arrival = context.now() + self.latency;
+        self.pending.extend(
+            context
+                .into_requests()
+                .into_iter()
+                .map(|request| (arrival, request)),
+        );
+    }
+
+    /// Hand every request that arrives by `until` to the exchange, at the time it arrives
+    async fn deliver(&mut self, until: DateTime<Utc>) {
+        while self
+            .pending
+            .front()
+            .is_some_and(|(arrival, _)| *arrival <= until)
+        {
+            let Some((arrival, request)) = self.pending.pop_front() else {
+                return;
+            };
+            self.exchange.set_time(arrival);
+            let mut context = StrategyContext::new(arrival);
+            match request {
+                OrderRequest::Add(params) => {
+                    if let Err(err) = self.exchange.add_order(&params).await {
+                        self.strategy.on_rejected(&params, &err, &mut context);
+                    }
+                }
+                OrderRequest::Cancel(params) => {
+                    // the order may have been filled while the request was on its way
+                    if let Err(err) = self.exchange.cancel_order(&params).await {
+                        debug!("backtest cancel failed -> {}", err);
+                    }
+                }
+            }
+            self.notify(&mut context);
+            self.send(context);
+        }
+    }
+}
+
+/// When the exchange sees the first of an event's trades
+fn first_trade_time(event: &MarketEvent) -> DateTime<Utc> {
+    match event {
+        MarketEvent::Candle { candle, .. } => *candle.time(),
+        _ => event.time(),
+    }
+}
+
+fn candle_prices(candle: &TickData) -> [Decimal; 4] {
+    if candle.close() >= candle.open() {
+        [
+            *candle.open(),
+            *candle.low(),
+            *candle.high(),
+            *candle.close(),
+        ]
+    } else {
+        [
+            *candle.open(),
+            *candle.high(),
+            *candle.low(),
+            *candle.close(),
+        ]
+    }
+}
+
+fn equity(
+    registry: &AssetRegistry,
+    balances: &HashMap<AssetId, Decimal>,
+    prices: &HashMap<PairId, Decimal>,
+    currency: &AssetId,
+) -> Decimal {
+    balances
+        .iter()
+        .map(|(asset, amount)| value(registry, prices, asset, *amount, currency))
+        .sum()
+}
+
+/// `amount` of `asset` in `currency` at the last price of a pair between them. Zero until such a
+/// pair has a price
+fn value(
+    registry: &AssetRegistry,
+    prices: &HashMap<PairId, Decimal>,
+    asset: &AssetId,
+    amount: Decimal,
+    currency: &AssetId,
+) -> Decimal {
+    if asset == currency {
+        return amount;
+    }
+    let price = |base, quote| prices.get(registry.pair_by_assets(base, quote)?).copied();
+    if let Some(price) = price(asset, currency) {
+        return amount * price;
+    }
+    match price(currency, asset) {
+        Some(price) if !price.is_zero() => amount / price,
+        _ => Decimal::ZERO,
+    }
+}
+
+/// Trade and book events from a recording made with [linnaeus_ws::recorder::Recorder], timed by
+/// when their frame was received. Other frames are skipped
+pub fn recorded_events(path: impl AsRef<Path>) -> io::Result<impl Iterator<Item = MarketEvent>> {
+    let mut books: HashMap<String, LocalBook> = HashMap::new();
+    Ok(read_recording(path)?.flat_map(move |frame| {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(err) => {
+                warn!("skipping unreadable recorded frame -> {}", err);
+                return Vec::new();
+            }
+        };
+        let Ok(message) = serde_json::from_str::<ChannelMessageWrapper>(frame.frame()) else {
+            return Vec::new();
+        };
+        let (Some(pair), time) = (message.pair().clone(), *frame.received_at()) else {
+            return Vec::new();
+        };
+        match message.message() {
+            ChannelMessage::Trade(trades) => trades
+                .iter()
+                .map(|trade| MarketEvent::Trade {
+                    pair: pair.clone(),
+                    time,
+                    price: *trade.price(),
+                    volume: *trade.volume(),
+                })
+                .collect(),
+            ChannelMessage::Book(update) => {
+                let book = books
+                    .entry(pair.clone())
+                    .or_insert_with(|| LocalBook::new(pair.clone(), Depth::default()));
+                if book.apply(update) {
+                    vec![MarketEvent::Book {
+                        pair,
+                        time,
+                        book: book.clone(),
+                    }]
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => Vec::new(),
+        }
+    }))
+}
+
+#[cfg(test)]
+mod backtest_tests {
+    use super::*;
+    use crate::api::user_data::{OrderType, Side};
+    use crate::api::user_trading::AddOrderParams;
+    use crate::order_tracker::OrderEvent;
+    use crate::registry::test_registry;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    fn time(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    fn candle(hour: i64, open: &str, close: &str) -> MarketEvent {
+        let candle = serde_json::from_value(json!([
+            time(hour * 3600).timestamp(),
+            open,
+            open.max(close),
+            open.min(close),
+            close,
+            close,
+            "4",
+            10
+        ]))
+        .expect("couldn't deserialize candle");
+        MarketEvent::candle("XBT/USD", candle, Duration::hours(1))
+    }
+
+    fn trade(seconds: i64, price: Decimal) -> MarketEvent {
+        MarketEvent::Trade {
+            pair: "XBT/USD".to_string(),
+            time: time(seconds),
+            price,
+            volume: dec!(1),
+        }
+    }
+
+    /// Buys once on the first event and records what happened to the order
+    #[derive(Default)]
+    struct BuyOnce {
+        placed: bool,
+        filled_at: Option<Decimal>,
+    }
+
+    impl Strategy for BuyOnce {
+        fn on_market(&mut self, _event: &MarketEvent, context: &mut StrategyContext) {
+            if !self.placed {
+                self.placed = true;
+                context.add_order(AddOrderParams::new(
+                    OrderType::Market,
+                    Side::Buy,
+                    "XBTUSD".to_string(),
+                    dec!(1),
+                ));
+            }
+        }
+
+        fn on_order(&mut self, event: &OrderEvent, _context: &mut StrategyContext) {
+            if let OrderEvent::Fill { average_price, .. } = event {
+                self.filled_at = *average_price;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn buy_and_hold_over_candles() -> Result<(), BacktestError> {
+        let fees = FeeCalculator::flat(test_registry(), Decimal::ZERO, Decimal::ZERO);
+        let backtester = Backtester::new(
+            fees,
+            BacktestConfig::new("USD").with_balance("USD", dec!(1000)),
+        );
+        let candles = vec![
+            candle(0, "95", "100"),
+            candle(1, "100", "110"),
+            candle(2, "110", "99"),
+        ];
+        let mut strategy = BuyOnce::default();
+        let report = backtester.run(&mut strategy, candles).await?;
+
+        // the first candle is seen once it has closed and the order fills at that close
+        assert_eq!(strategy.filled_at, Some(dec!(100)));
+        assert_eq!(*report.fills()[0].1.time(), time(3600));
+        let equity: Vec<Decimal> = report
+            .equity_curve()
+            .iter()
+            .map(|point| *point.equity())
+            .collect();
+        assert_eq!(equity, vec![dec!(1000), dec!(1010), dec!(999)]);
+        let summary = report.summary();
+        assert_eq!(*summary.total_return(), dec!(-0.001));
+        assert_eq!(*summary.max_drawdown(), dec!(11) / dec!(1010));
+        assert_eq!(*summary.fills(), 1);
+        assert_eq!(report.balances()["XXBT"], dec!(1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sample_interval_must_be_positive() {
+        let fees = FeeCalculator::flat(test_registry(), Decimal::ZERO, Decimal::ZERO);
+        let config = BacktestConfig::new("USD").with_sample_interval(Duration::zero());
+        let result = Backtester::new(fees, config)
+            .run(&mut BuyOnce::default(), vec![candle(0, "95", "100")])
+            .await;
+        assert!(matches!(result, Err(BacktestError::SampleInterval(_))));
+    }
+
+    #[tokio::test]
+    async fn orders_arrive_after_the_latency_and_pay_slippage() -> Result<(), BacktestError> {
+        let fees = FeeCalculator::flat(test_registry(), dec!(0.1), dec!(0.2));
+        let config = BacktestConfig::new("USD")
+            .with_balance("USD", dec!(1000))
+            .with_latency(Duration::seconds(2))
+            .with_slippage(Slippage::BasisPoints(dec!(100)));
+        let trades = vec![
+            trade(0, dec!(100)),
+            trade(1, dec!(101)),
+            trade(3, dec!(105)),
+        ];
+        let mut strategy = BuyOnce::default();
+        let report = Backtester::new(fees, config)
+            .run(&mut strategy, trades)
+            .await?;
+
+        let (_, fill) = &report.fills()[0];
+        assert_eq!(*fill.time(), time(2));
+        assert_eq!(*fill.price(), dec!(102.01));
+        assert_eq!(*fill.fee(), dec!(0.20402));
+        assert_eq!(*report.summary().fees(), dec!(0.20402));
+        assert_eq!(
+            report.balances()["ZUSD"],
+            dec!(1000) - dec!(102.01) - dec!(0.20402)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use super::EquityPoint;
+use derive_getters::Getters;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+/// Statistics of a backtest, measured in the currency of its equity curve
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct Summary {
+    start_equity: Decimal,
+    end_equity: Decimal,
+    /// Gain over the whole run as a fraction of the starting equity
+    total_return: Decimal,
+    /// Largest fall of the equity curve from a previous peak, as a fraction of the peak
+    max_drawdown: Decimal,
+    /// Annualized from the returns between points of the equity curve, without a risk free rate.
+    /// None if there are fewer than two returns or they never vary
+    sharpe: Option<f64>,
+    /// Value traded divided by the average equity
+    turnover: Decimal,
+    fees: Decimal,
+    fills: usize,
+}
+
+impl Summary {
+    pub(crate) fn new(
+        curve: &[EquityPoint],
+        traded: Decimal,
+        fees: Decimal,
+        fills: usize,
+        periods_per_year: f64,
+    ) -> Self {
+        let start_equity = curve.first().map(|point| point.equity).unwrap_or_default();
+        let end_equity = curve.last().map(|point| point.equity).unwrap_or_default();
+        let total_return = if start_equity.is_zero() {
+            Decimal::ZERO
+        } else {
+            end_equity / start_equity - Decimal::ONE
+        };
+        let average_equity = if curve.is_empty() {
+            Decimal::ZERO
+        } else {
+            curve.iter().map(|point| point.equity).sum::<Decimal>() / Decimal::from(curve.len())
+        };
+        let turnover = if average_equity.is_zero() {
+            Decimal::ZERO
+        } else {
+            traded / average_equity
+        };
+        Self {
+            start_equity,
+            end_equity,
+            total_return,
+            max_drawdown: max_drawdown(curve),
+            sharpe: sharpe(curve, periods_per_year),
+            turnover,
+            fees,
+            fills,
+        }
+    }
+}
+
+fn max_drawdown(curve: &[EquityPoint]) -> Decimal {
+    let mut peak = Decimal::ZERO;
+    let mut drawdown = Decimal::ZERO;
+    for point in curve {
+        peak = peak.max(point.equity);
+        if peak > Decimal::ZERO {
+            drawdown = drawdown.max((peak - point.equity) / peak);
+        }
+    }
+    drawdown
+}
+
+fn sharpe(curve: &[EquityPoint], periods_per_year: f64) -> Option<f64> {
+    let returns: Vec<f64> = curve
+        .windows(2)
+        .filter(|pair| pair[0].equity > Decimal::ZERO)
+        .filter_map(|pair| (pair[1].equity / pair[0].equity - Decimal::ONE).to_f64())
+        .collect();
+    if returns.len() < 2 {
+        return None;
+    }
+    let count = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / count;
+    let variance = returns
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / (count - 1.0);
+    let deviation = variance.sqrt();
+    if deviation == 0.0 {
+        return None;
+    }
+    Some(mean / deviation * periods_per_year.sqrt())
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+
+    fn curve(equity: &[Decimal]) -> Vec<EquityPoint> {
+        equity
+            .iter()
+            .enumerate()
+            .map(|(hour, equity)| {
+                EquityPoint::new(Utc.timestamp_opt(hour as i64 * 3600, 0).unwrap(), *equity)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drawdown_is_measured_from_the_highest_peak() {
+        let curve = curve(&[dec!(100), dec!(120), dec!(90), dec!(130), dec!(117)]);
+        assert_eq!(max_drawdown(&curve), dec!(0.25));
+
+        let summary = Summary::new(&curve, dec!(222.8), dec!(1), 2, 8766.0);
+        assert_eq!(*summary.total_return(), dec!(0.17));
+        assert_eq!(*summary.turnover(), dec!(2));
+    }
+
+    #[test]
+    fn sharpe_needs_varying_returns() {
+        assert_eq!(
+            sharpe(&curve(&[dec!(100), dec!(100), dec!(100)]), 365.0),
+            None
+        );
+        let sharpe = sharpe(&curve(&[dec!(100), dec!(110), dec!(121), dec!(121)]), 4.0)
+            .expect("returns vary");
+        // mean 0.0667, sample deviation 0.0577, twice for four periods a year
+        assert!((sharpe - 2.309).abs() < 0.001, "sharpe was {}", sharpe);
+    }
+}
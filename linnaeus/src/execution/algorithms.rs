@@ -0,0 +1,557 @@
+use super::{Action, ChildOrder};
+use crate::api::user_data::{OrderStatus, OrderType, Side};
+use chrono::{DateTime, Utc};
+use derive_getters::Getters;
+use derive_new::new;
+use rust_decimal::prelude::RoundingStrategy;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Decimal places used when splitting a volume into slices
+const VOLUME_DECIMALS: u32 = 8;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailOffset {
+    /// Distance from the best price in the quote currency
+    Price(Decimal),
+    /// Distance from the best price in percent of it
+    Percent(Decimal),
+}
+
+/// Client side order logic that is carried out with native orders
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub enum Algorithm {
+    TrailingStop(TrailingStop),
+    OneCancelsOther(OneCancelsOther),
+    Iceberg(Iceberg),
+    Twap(Twap),
+}
+
+/// The decisions of an algorithm given what happened to the orders it placed so far
+trait Strategy {
+    fn pair(&self) -> &str;
+    fn side(&self) -> &Side;
+    fn volume(&self) -> Decimal;
+    fn start(&mut self, now: DateTime<Utc>) -> Vec<Action>;
+
+    fn on_price(&mut self, _price: Decimal, _children: &[ChildOrder]) -> Vec<Action> {
+        Vec::new()
+    }
+
+    fn on_time(&mut self, _now: DateTime<Utc>, _children: &[ChildOrder]) -> Vec<Action> {
+        Vec::new()
+    }
+
+    fn on_child(&mut self, _children: &[ChildOrder]) -> Vec<Action> {
+        Vec::new()
+    }
+
+    fn is_finished(&self, children: &[ChildOrder]) -> bool;
+}
+
+impl Algorithm {
+    fn strategy(&self) -> &dyn Strategy {
+        match self {
+            Self::TrailingStop(algorithm) => algorithm,
+            Self::OneCancelsOther(algorithm) => algorithm,
+            Self::Iceberg(algorithm) => algorithm,
+            Self::Twap(algorithm) => algorithm,
+        }
+    }
+
+    fn strategy_mut(&mut self) -> &mut dyn Strategy {
+        match self {
+            Self::TrailingStop(algorithm) => algorithm,
+            Self::OneCancelsOther(algorithm) => algorithm,
+            Self::Iceberg(algorithm) => algorithm,
+            Self::Twap(algorithm) => algorithm,
+        }
+    }
+
+    /// Websocket name of the pair, which is also used when placing orders
+    pub fn pair(&self) -> &str {
+        self.strategy().pair()
+    }
+
+    pub fn side(&self) -> &Side {
+        self.strategy().side()
+    }
+
+    pub fn volume(&self) -> Decimal {
+        self.strategy().volume()
+    }
+
+    pub(super) fn start(&mut self, now: DateTime<Utc>) -> Vec<Action> {
+        self.strategy_mut().start(now)
+    }
+
+    pub(super) fn on_price(&mut self, price: Decimal, children: &[ChildOrder]) -> Vec<Action> {
+        self.strategy_mut().on_price(price, children)
+    }
+
+    pub(super) fn on_time(&mut self, now: DateTime<Utc>, children: &[ChildOrder]) -> Vec<Action> {
+        self.strategy_mut().on_time(now, children)
+    }
+
+    pub(super) fn on_child(&mut self, children: &[ChildOrder]) -> Vec<Action> {
+        self.strategy_mut().on_child(children)
+    }
+
+    pub(super) fn is_finished(&self, children: &[ChildOrder]) -> bool {
+        self.strategy().is_finished(children)
+    }
+}
+
+impl From<TrailingStop> for Algorithm {
+    fn from(algorithm: TrailingStop) -> Self {
+        Self::TrailingStop(algorithm)
+    }
+}
+
+impl From<OneCancelsOther> for Algorithm {
+    fn from(algorithm: OneCancelsOther) -> Self {
+        Self::OneCancelsOther(algorithm)
+    }
+}
+
+impl From<Iceberg> for Algorithm {
+    fn from(algorithm: Iceberg) -> Self {
+        Self::Iceberg(algorithm)
+    }
+}
+
+impl From<Twap> for Algorithm {
+    fn from(algorithm: Twap) -> Self {
+        Self::Twap(algorithm)
+    }
+}
+
+fn place(order_type: OrderType, volume: Decimal, price: Option<Decimal>) -> Action {
+    Action::Place {
+        order_type,
+        volume,
+        price,
+        price2: None,
+    }
+}
+
+fn executed(children: &[ChildOrder]) -> Decimal {
+    children.iter().map(|child| child.executed).sum()
+}
+
+fn all_done(children: &[ChildOrder]) -> bool {
+    children.iter().all(ChildOrder::is_done)
+}
+
+/// Market order placed once the price moves against the best price seen by the offset. A sell
+/// stop trails below the highest price and a buy stop above the lowest price
+#[derive(Debug, Clone, Serialize, Deserialize, Getters)]
+pub struct TrailingStop {
+    pair: String,
+    side: Side,
+    volume: Decimal,
+    offset: TrailOffset,
+    /// Highest price seen for a sell stop and lowest price for a buy stop
+    best_price: Option<Decimal>,
+    triggered: bool,
+}
+
+impl TrailingStop {
+    pub fn new(pair: &str, side: Side, volume: Decimal, offset: TrailOffset) -> Self {
+        Self {
+            pair: pair.to_string(),
+            side,
+            volume,
+            offset,
+            best_price: None,
+            triggered: false,
+        }
+    }
+
+    /// None until the first price is seen
+    pub fn stop_price(&self) -> Option<Decimal> {
+        let best_price = self.best_price?;
+        let distance = match self.offset {
+            TrailOffset::Price(distance) => distance,
+            TrailOffset::Percent(percent) => best_price * percent / dec!(100),
+        };
+        Some(match self.side {
+            Side::Sell => best_price - distance,
+            Side::Buy => best_price + distance,
+        })
+    }
+}
+
+impl Strategy for TrailingStop {
+    fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    fn side(&self) -> &Side {
+        &self.side
+    }
+
+    fn volume(&self) -> Decimal {
+        self.volume
+    }
+
+    fn start(&mut self, _now: DateTime<Utc>) -> Vec<Action> {
+        Vec::new()
+    }
+
+    fn on_price(&mut self, price: Decimal, _children: &[ChildOrder]) -> Vec<Action> {
+        if self.triggered {
+            return Vec::new();
+        }
+        self.best_price = Some(match (self.best_price, &self.side) {
+            (None, _) => price,
+            (Some(best), Side::Sell) => best.max(price),
+            (Some(best), Side::Buy) => best.min(price),
+        });
+        let Some(stop) = self.stop_price() else {
+            return Vec::new();
+        };
+        self.triggered = match self.side {
+            Side::Sell => price <= stop,
+            Side::Buy => price >= stop,
+        };
+        if self.triggered {
+            vec![place(OrderType::Market, self.volume, None)]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn is_finished(&self, children: &[ChildOrder]) -> bool {
+        self.triggered && !children.is_empty() && all_done(children)
+    }
+}
+
+/// A native order used as one side of a [OneCancelsOther]
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, new)]
+pub struct OcoLeg {
+    order_type: OrderType,
+    price: Decimal,
+    price2: Option<Decimal>,
+}
+
+/// Two native orders where the first to be filled, even partially, cancels the other. Canceling
+/// either order outside the algorithm cancels both
+#[derive(Debug, Clone, Serialize, Deserialize, Getters)]
+pub struct OneCancelsOther {
+    pair: String,
+    side: Side,
+    volume: Decimal,
+    first: OcoLeg,
+    second: OcoLeg,
+}
+
+impl OneCancelsOther {
+    pub fn new(pair: &str, side: Side, volume: Decimal, first: OcoLeg, second: OcoLeg) -> Self {
+        Self {
+            pair: pair.to_string(),
+            side,
+            volume,
+            first,
+            second,
+        }
+    }
+}
+
+impl Strategy for OneCancelsOther {
+    fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    fn side(&self) -> &Side {
+        &self.side
+    }
+
+    fn volume(&self) -> Decimal {
+        self.volume
+    }
+
+    fn start(&mut self, _now: DateTime<Utc>) -> Vec<Action> {
+        [&self.first, &self.second]
+            .into_iter()
+            .map(|leg| Action::Place {
+                order_type: leg.order_type.clone(),
+                volume: self.volume,
+                price: Some(leg.price),
+                price2: leg.price2,
+            })
+            .collect()
+    }
+
+    fn on_child(&mut self, children: &[ChildOrder]) -> Vec<Action> {
+        let [first, second] = children else {
+            return Vec::new();
+        };
+        let triggered =
+            |leg: &ChildOrder| !leg.executed.is_zero() || (leg.is_done() && !leg.canceled);
+        [(first, second), (second, first)]
+            .into_iter()
+            .filter(|(leg, other)| triggered(leg) && !other.canceled && !other.is_done())
+            .map(|(_, other)| Action::Cancel {
+                client_order_id: other.client_order_id.clone(),
+            })
+            .collect()
+    }
+
+    fn is_finished(&self, children: &[ChildOrder]) -> bool {
+        children.len() == 2 && all_done(children)
+    }
+}
+
+/// Limit order that only shows part of its volume. A new slice is placed each time the visible
+/// one is filled. Canceling a slice outside the algorithm ends it
+#[derive(Debug, Clone, Serialize, Deserialize, Getters)]
+pub struct Iceberg {
+    pair: String,
+    side: Side,
+    volume: Decimal,
+    price: Decimal,
+    visible_volume: Decimal,
+}
+
+impl Iceberg {
+    pub fn new(
+        pair: &str,
+        side: Side,
+        volume: Decimal,
+        price: Decimal,
+        visible_volume: Decimal,
+    ) -> Self {
+        Self {
+            pair: pair.to_string(),
+            side,
+            volume,
+            price,
+            visible_volume,
+        }
+    }
+
+    fn next_slice(&self, children: &[ChildOrder]) -> Action {
+        let remaining = self.volume - executed(children);
+        place(
+            OrderType::Limit,
+            self.visible_volume.min(remaining),
+            Some(self.price),
+        )
+    }
+}
+
+impl Strategy for Iceberg {
+    fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    fn side(&self) -> &Side {
+        &self.side
+    }
+
+    fn volume(&self) -> Decimal {
+        self.volume
+    }
+
+    fn start(&mut self, _now: DateTime<Utc>) -> Vec<Action> {
+        vec![self.next_slice(&[])]
+    }
+
+    fn on_child(&mut self, children: &[ChildOrder]) -> Vec<Action> {
+        if self.is_finished(children) {
+            return Vec::new();
+        }
+        match children.last() {
+            Some(last) if last.is_done() => vec![self.next_slice(children)],
+            _ => Vec::new(),
+        }
+    }
+
+    fn is_finished(&self, children: &[ChildOrder]) -> bool {
+        let Some(last) = children.last() else {
+            return false;
+        };
+        last.is_done()
+            && (executed(children) >= self.volume || last.status != Some(OrderStatus::Closed))
+    }
+}
+
+/// Market orders of equal volume spread evenly over the horizon. The first slice is placed when
+/// the algorithm starts and the last one horizon / slices before the end
+#[derive(Debug, Clone, Serialize, Deserialize, Getters)]
+pub struct Twap {
+    pair: String,
+    side: Side,
+    volume: Decimal,
+    horizon: Duration,
+    slices: u32,
+    started_at: Option<DateTime<Utc>>,
+}
+
+impl Twap {
+    pub fn new(pair: &str, side: Side, volume: Decimal, horizon: Duration, slices: u32) -> Self {
+        Self {
+            pair: pair.to_string(),
+            side,
+            volume,
+            horizon,
+            slices: slices.max(1),
+            started_at: None,
+        }
+    }
+
+    /// Every slice has the same volume except the last which takes what rounding left over
+    fn slice_volume(&self, index: u32) -> Decimal {
+        let slice = (self.volume / Decimal::from(self.slices))
+            .round_dp_with_strategy(VOLUME_DECIMALS, RoundingStrategy::ToZero);
+        if index + 1 == self.slices {
+            self.volume - slice * Decimal::from(self.slices - 1)
+        } else {
+            slice
+        }
+    }
+
+    fn slices_due(&self, now: DateTime<Utc>) -> u32 {
+        let Some(started_at) = self.started_at else {
+            return 0;
+        };
+        let interval = self.horizon / self.slices;
+        let elapsed = (now - started_at).to_std().unwrap_or_default();
+        if interval.is_zero() {
+            return self.slices;
+        }
+        let due = elapsed.as_nanos() / interval.as_nanos() + 1;
+        due.min(self.slices as u128) as u32
+    }
+}
+
+impl Strategy for Twap {
+    fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    fn side(&self) -> &Side {
+        &self.side
+    }
+
+    fn volume(&self) -> Decimal {
+        self.volume
+    }
+
+    fn start(&mut self, now: DateTime<Utc>) -> Vec<Action> {
+        self.started_at = Some(now);
+        self.on_time(now, &[])
+    }
+
+    fn on_time(&mut self, now: DateTime<Utc>, children: &[ChildOrder]) -> Vec<Action> {
+        (children.len() as u32..self.slices_due(now))
+            .map(|index| place(OrderType::Market, self.slice_volume(index), None))
+            .collect()
+    }
+
+    fn is_finished(&self, children: &[ChildOrder]) -> bool {
+        children.len() as u32 == self.slices && all_done(children)
+    }
+}
+
+#[cfg(test)]
+mod algorithm_tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn placed_volumes(actions: &[Action]) -> Vec<Decimal> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Place { volume, .. } => Some(*volume),
+                Action::Cancel { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trailing_stop_follows_the_best_price() {
+        let mut stop = TrailingStop::new(
+            "XBT/USD",
+            Side::Sell,
+            dec!(1),
+            TrailOffset::Percent(dec!(5)),
+        );
+        assert!(stop.on_price(dec!(20000), &[]).is_empty());
+        assert!(stop.on_price(dec!(22000), &[]).is_empty());
+        assert_eq!(stop.stop_price(), Some(dec!(20900)));
+        // a fall that stays above the stop doesn't move it
+        assert!(stop.on_price(dec!(21000), &[]).is_empty());
+        assert_eq!(stop.stop_price(), Some(dec!(20900)));
+
+        let actions = stop.on_price(dec!(20850), &[]);
+        assert_eq!(placed_volumes(&actions), vec![dec!(1)]);
+        assert!(stop.on_price(dec!(20000), &[]).is_empty());
+    }
+
+    #[test]
+    fn twap_spreads_slices_over_the_horizon() {
+        let start = DateTime::from_timestamp(1_000, 0).expect("valid time");
+        let mut twap = Twap::new("XBT/USD", Side::Buy, dec!(1), Duration::from_secs(90), 3);
+        let mut children = Vec::new();
+        let slices = |actions: Vec<Action>, children: &mut Vec<ChildOrder>| {
+            let volumes = placed_volumes(&actions);
+            for volume in &volumes {
+                children.push(ChildOrder::new(
+                    children.len().to_string(),
+                    OrderType::Market,
+                    *volume,
+                    None,
+                ));
+            }
+            volumes
+        };
+        assert_eq!(
+            slices(twap.start(start), &mut children),
+            vec![dec!(0.33333333)]
+        );
+        let at = |seconds| start + chrono::Duration::seconds(seconds);
+        assert!(slices(twap.on_time(at(29), &children), &mut children).is_empty());
+        assert_eq!(
+            slices(twap.on_time(at(100), &children), &mut children),
+            vec![dec!(0.33333333), dec!(0.33333334)]
+        );
+        assert!(slices(twap.on_time(at(200), &children), &mut children).is_empty());
+    }
+
+    #[test]
+    fn oco_cancels_the_other_leg_on_a_fill() {
+        let mut oco = OneCancelsOther::new(
+            "XBT/USD",
+            Side::Sell,
+            dec!(1),
+            OcoLeg::new(OrderType::Limit, dec!(22000), None),
+            OcoLeg::new(OrderType::StopLoss, dec!(19000), None),
+        );
+        assert_eq!(placed_volumes(&oco.start(Utc::now())).len(), 2);
+        let mut children = vec![
+            ChildOrder::new("take".into(), OrderType::Limit, dec!(1), Some(dec!(22000))),
+            ChildOrder::new(
+                "stop".into(),
+                OrderType::StopLoss,
+                dec!(1),
+                Some(dec!(19000)),
+            ),
+        ];
+        assert!(oco.on_child(&children).is_empty());
+
+        children[0].executed = dec!(0.2);
+        let actions = oco.on_child(&children);
+        assert!(matches!(
+            actions.as_slice(),
+            [Action::Cancel { client_order_id }] if client_order_id == "stop"
+        ));
+        children[1].canceled = true;
+        assert!(oco.on_child(&children).is_empty());
+    }
+}
@@ -0,0 +1,1130 @@
+//! Client side order types carried out with native orders driven by live websocket data.
+//!
+//! An [ExecutionEngine] runs [Algorithm]s, places and cancels their orders, follows those orders
+//! through the open orders feed and saves every change to an [ExecutionStore] so that running
+//! algorithms are resumed when the engine is created again.
+
+mod algorithms;
+mod store;
+
+pub use algorithms::*;
+pub use store::*;
+
+use crate::api::user_data::{OrderBase, OrderStatus, OrderType};
+use crate::api::user_trading::{AddOrderParams, CancelOrderParams};
+use crate::order_tracker::{OrderEvent, OrderTracker};
+use crate::submission::{find_order, OrderSubmitter, SubmitError};
+use crate::trading::TradingClient;
+use crate::Linnaeus;
+use chrono::{DateTime, Utc};
+use derive_getters::Getters;
+use futures::stream::{select_all, BoxStream};
+use futures::{Stream, StreamExt};
+use linnaeus_ws::error::LinnaeusWebsocketError;
+use linnaeus_ws::messages::private_messages::OpenOrders;
+use linnaeus_ws::messages::Pair;
+use linnaeus_ws::subscription::SubscriptionOptions;
+use log::{debug, error, warn};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum ExecutionError {
+    #[error("no execution with id {0}")]
+    UnknownExecution(String),
+    #[error("couldn't access the execution store -> {0}")]
+    Io(#[from] std::io::Error),
+    #[error("couldn't serialize execution state -> {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("the execution engine has stopped")]
+    Stopped,
+}
+
+/// What an algorithm wants done next
+#[derive(Debug, Clone)]
+pub(crate) enum Action {
+    Place {
+        order_type: OrderType,
+        volume: Decimal,
+        price: Option<Decimal>,
+        price2: Option<Decimal>,
+    },
+    Cancel {
+        client_order_id: String,
+    },
+}
+
+/// A native order placed by an algorithm
+#[derive(Debug, Clone, Serialize, Deserialize, Getters)]
+pub struct ChildOrder {
+    client_order_id: String,
+    transaction_id: Option<String>,
+    order_type: OrderType,
+    volume: Decimal,
+    price: Option<Decimal>,
+    executed: Decimal,
+    average_price: Option<Decimal>,
+    /// None until kraken reports the order
+    status: Option<OrderStatus>,
+    /// The algorithm asked for the order to be canceled
+    canceled: bool,
+}
+
+impl ChildOrder {
+    pub(crate) fn new(
+        client_order_id: String,
+        order_type: OrderType,
+        volume: Decimal,
+        price: Option<Decimal>,
+    ) -> Self {
+        Self {
+            client_order_id,
+            transaction_id: None,
+            order_type,
+            volume,
+            price,
+            executed: Decimal::ZERO,
+            average_price: None,
+            status: None,
+            canceled: false,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.status.is_some_and(|status| status.is_terminal())
+    }
+
+    /// Placed without knowing whether kraken accepted it
+    fn is_unresolved(&self) -> bool {
+        self.transaction_id.is_none() && self.status.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecutionStatus {
+    Running,
+    Completed,
+    Canceled,
+    /// An order couldn't be placed
+    Failed(String),
+}
+
+/// An algorithm and the orders it placed
+#[derive(Debug, Clone, Serialize, Deserialize, Getters)]
+pub struct Execution {
+    id: String,
+    algorithm: Algorithm,
+    children: Vec<ChildOrder>,
+    status: ExecutionStatus,
+    started_at: DateTime<Utc>,
+}
+
+impl Execution {
+    pub fn executed(&self) -> Decimal {
+        self.children.iter().map(|child| child.executed).sum()
+    }
+
+    pub fn remaining(&self) -> Decimal {
+        (self.algorithm.volume() - self.executed()).max(Decimal::ZERO)
+    }
+
+    /// Volume weighted over every child, None until something was executed
+    pub fn average_price(&self) -> Option<Decimal> {
+        let executed = self.executed();
+        if executed.is_zero() {
+            return None;
+        }
+        let cost: Decimal = self
+            .children
+            .iter()
+            .filter_map(|child| Some(child.average_price? * child.executed))
+            .sum();
+        Some(cost / executed)
+    }
+
+    fn is_running(&self) -> bool {
+        self.status == ExecutionStatus::Running
+    }
+
+    fn child_mut(&mut self, client_order_id: &str) -> Option<&mut ChildOrder> {
+        self.children
+            .iter_mut()
+            .find(|child| child.client_order_id == client_order_id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionEvent {
+    Started {
+        id: String,
+    },
+    OrderPlaced {
+        id: String,
+        client_order_id: String,
+        transaction_id: Option<String>,
+        volume: Decimal,
+    },
+    OrderCanceled {
+        id: String,
+        client_order_id: String,
+    },
+    /// More of the algorithm's volume was executed
+    Progress {
+        id: String,
+        executed: Decimal,
+        remaining: Decimal,
+    },
+    Finished {
+        id: String,
+        status: ExecutionStatus,
+    },
+}
+
+/// Runs algorithms and keeps their state in a store.
+///
+/// Orders are followed through an internal [OrderTracker] fed by [ExecutionEngine::apply_open_orders]
+/// and [ExecutionEngine::apply_rest_orders]. After a restart the open orders feed only reports
+/// orders that are still open, so closed orders placed by resumed algorithms should be applied
+/// from the closed orders endpoint. Orders are placed through an [OrderSubmitter]. An order that
+/// was rejected fails the algorithm and cancels its other orders. An order that may or may not
+/// have been placed is kept and looked up by its client order id on every tick until it's found
+/// or known to be missing
+#[derive(Debug)]
+pub struct ExecutionEngine<S> {
+    executions: HashMap<String, Execution>,
+    /// Txid of every child order to its execution and client order id
+    transactions: HashMap<String, (String, String)>,
+    tracker: OrderTracker,
+    submitter: OrderSubmitter,
+    store: S,
+    /// The state last written to the store of every running execution
+    saved: HashMap<String, Vec<u8>>,
+    events: broadcast::Sender<ExecutionEvent>,
+}
+
+impl<S: ExecutionStore> ExecutionEngine<S> {
+    /// Resumes the running executions saved in the store
+    pub fn new(store: S) -> Result<Self, ExecutionError> {
+        let mut executions = HashMap::new();
+        let mut transactions = HashMap::new();
+        let mut saved = HashMap::new();
+        for execution in store.load()? {
+            if !execution.is_running() {
+                continue;
+            }
+            for child in &execution.children {
+                if let Some(transaction_id) = &child.transaction_id {
+                    transactions.insert(
+                        transaction_id.clone(),
+                        (execution.id.clone(), child.client_order_id.clone()),
+                    );
+                }
+            }
+            debug!("resuming execution {}", execution.id);
+            saved.insert(execution.id.clone(), serde_json::to_vec(&execution)?);
+            executions.insert(execution.id.clone(), execution);
+        }
+        Ok(Self {
+            executions,
+            transactions,
+            tracker: OrderTracker::new(),
+            submitter: OrderSubmitter::new(),
+            store,
+            saved,
+            events: broadcast::channel(100).0,
+        })
+    }
+
+    /// Place orders through `submitter` instead of one with the default settings
+    pub fn with_submitter(mut self, submitter: OrderSubmitter) -> Self {
+        self.submitter = submitter;
+        self
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ExecutionEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Execution> {
+        self.executions.get(id)
+    }
+
+    pub fn executions(&self) -> impl Iterator<Item = &Execution> {
+        self.executions.values()
+    }
+
+    /// Start an algorithm and return the id of its execution
+    pub async fn start(
+        &mut self,
+        client: &impl TradingClient,
+        mut algorithm: Algorithm,
+    ) -> Result<String, ExecutionError> {
+        let now = Utc::now();
+        let id = Uuid::new_v4().to_string();
+        let actions = algorithm.start(now);
+        self.executions.insert(
+            id.clone(),
+            Execution {
+                id: id.clone(),
+                algorithm,
+                children: Vec::new(),
+                status: ExecutionStatus::Running,
+                started_at: now,
+            },
+        );
+        self.publish(ExecutionEvent::Started { id: id.clone() });
+        self.perform(client, &id, actions).await?;
+        Ok(id)
+    }
+
+    /// Cancel every open order of the execution and stop it
+    pub async fn cancel(
+        &mut self,
+        client: &impl TradingClient,
+        id: &str,
+    ) -> Result<(), ExecutionError> {
+        let execution = self
+            .executions
+            .get_mut(id)
+            .ok_or_else(|| ExecutionError::UnknownExecution(id.to_string()))?;
+        if !execution.is_running() {
+            return Ok(());
+        }
+        let canceled = cancel_children(client, execution).await;
+        execution.status = ExecutionStatus::Canceled;
+        for client_order_id in canceled {
+            self.publish(ExecutionEvent::OrderCanceled {
+                id: id.to_string(),
+                client_order_id,
+            });
+        }
+        self.finish(id)
+    }
+
+    /// The last trade price of a pair, using its websocket name
+    pub async fn apply_price(
+        &mut self,
+        client: &impl TradingClient,
+        pair: &str,
+        price: Decimal,
+    ) -> Result<(), ExecutionError> {
+        let mut pending = Vec::new();
+        for execution in self.executions.values_mut() {
+            if execution.is_running() && execution.algorithm.pair() == pair {
+                let actions = execution.algorithm.on_price(price, &execution.children);
+                pending.push((execution.id.clone(), actions));
+            }
+        }
+        for (id, actions) in pending {
+            self.perform(client, &id, actions).await?;
+        }
+        Ok(())
+    }
+
+    /// Look up orders that may not have been placed and let time based algorithms place orders
+    /// that are due
+    pub async fn apply_time(
+        &mut self,
+        client: &impl TradingClient,
+        now: DateTime<Utc>,
+    ) -> Result<(), ExecutionError> {
+        let unresolved: Vec<String> = self
+            .executions()
+            .filter(|execution| {
+                execution.is_running() && execution.children.iter().any(ChildOrder::is_unresolved)
+            })
+            .map(|execution| execution.id.clone())
+            .collect();
+        for id in unresolved {
+            if self.resolve_children(client, &id).await {
+                let Some(execution) = self.executions.get_mut(&id) else {
+                    continue;
+                };
+                let actions = execution.algorithm.on_child(&execution.children);
+                self.perform(client, &id, actions).await?;
+            }
+        }
+
+        let mut pending = Vec::new();
+        for execution in self.executions.values_mut() {
+            if execution.is_running() {
+                let actions = execution.algorithm.on_time(now, &execution.children);
+                if !actions.is_empty() {
+                    pending.push((execution.id.clone(), actions));
+                }
+            }
+        }
+        for (id, actions) in pending {
+            self.perform(client, &id, actions).await?;
+        }
+        Ok(())
+    }
+
+    /// A message from the websocket open orders feed
+    pub async fn apply_open_orders(
+        &mut self,
+        client: &impl TradingClient,
+        orders: &OpenOrders,
+    ) -> Result<(), ExecutionError> {
+        let events = self.tracker.apply_open_orders(orders);
+        self.apply_order_events(client, events).await
+    }
+
+    /// Orders from the open orders or closed orders endpoints
+    pub async fn apply_rest_orders(
+        &mut self,
+        client: &impl TradingClient,
+        orders: &HashMap<String, OrderBase>,
+    ) -> Result<(), ExecutionError> {
+        let events = self.tracker.apply_rest_orders(orders);
+        self.apply_order_events(client, events).await
+    }
+
+    async fn apply_order_events(
+        &mut self,
+        client: &impl TradingClient,
+        events: Vec<OrderEvent>,
+    ) -> Result<(), ExecutionError> {
+        let mut changed: Vec<String> = Vec::new();
+        for event in events {
+            let transaction_id = match &event {
+                OrderEvent::Transition { id, .. } | OrderEvent::Fill { id, .. } => id,
+                OrderEvent::CloseLinked { .. } => continue,
+            };
+            if let Some(id) = self.sync_child(transaction_id) {
+                if !changed.contains(&id) {
+                    changed.push(id);
+                }
+            }
+        }
+        for id in changed {
+            let Some(execution) = self.executions.get_mut(&id) else {
+                continue;
+            };
+            let actions = execution.algorithm.on_child(&execution.children);
+            self.perform(client, &id, actions).await?;
+        }
+        Ok(())
+    }
+
+    /// Copy what the tracker knows about an order to its child. Returns the id of the execution
+    /// if the child changed
+    fn sync_child(&mut self, transaction_id: &str) -> Option<String> {
+        let (id, client_order_id) = self.transactions.get(transaction_id)?;
+        let order = self.tracker.get(transaction_id)?;
+        let execution = self.executions.get_mut(id)?;
+        let child = execution.child_mut(client_order_id)?;
+        let filled = child.executed != *order.executed();
+        if !filled && child.status == Some(*order.status()) {
+            return None;
+        }
+        child.executed = *order.executed();
+        child.average_price = order.average_price();
+        child.status = Some(*order.status());
+        if filled {
+            let event = ExecutionEvent::Progress {
+                id: id.clone(),
+                executed: execution.executed(),
+                remaining: execution.remaining(),
+            };
+            // nobody may be listening
+            let _ = self.events.send(event);
+        }
+        Some(id.clone())
+    }
+
+    /// Find the orders of an execution that may not have been placed by their client order id.
+    /// Returns true if any of them was resolved
+    async fn resolve_children(&mut self, client: &impl TradingClient, id: &str) -> bool {
+        let Some(execution) = self.executions.get(id) else {
+            return false;
+        };
+        // leave room for clock differences when searching closed orders
+        let submitted_after = execution.started_at - chrono::Duration::minutes(1);
+        let unresolved: Vec<(String, Decimal)> = execution
+            .children
+            .iter()
+            .filter(|child| child.is_unresolved())
+            .map(|child| (child.client_order_id.clone(), child.volume))
+            .collect();
+        let mut resolved = false;
+        for (client_order_id, volume) in unresolved {
+            let transaction_ids = match find_order(client, &client_order_id, submitted_after).await
+            {
+                Ok(transaction_ids) => transaction_ids,
+                Err(err) => {
+                    warn!("couldn't look up order {} -> {}", client_order_id, err);
+                    continue;
+                }
+            };
+            let Some(execution) = self.executions.get_mut(id) else {
+                return resolved;
+            };
+            resolved = true;
+            let Some(transaction_id) = transaction_ids.into_iter().next() else {
+                debug!(
+                    "order {} of execution {} wasn't placed",
+                    client_order_id, id
+                );
+                execution
+                    .children
+                    .retain(|child| child.client_order_id != client_order_id);
+                continue;
+            };
+            if let Some(child) = execution.child_mut(&client_order_id) {
+                child.transaction_id = Some(transaction_id.clone());
+            }
+            self.publish(ExecutionEvent::OrderPlaced {
+                id: id.to_string(),
+                client_order_id: client_order_id.clone(),
+                transaction_id: Some(transaction_id.clone()),
+                volume,
+            });
+            self.transactions
+                .insert(transaction_id.clone(), (id.to_string(), client_order_id));
+            self.sync_child(&transaction_id);
+        }
+        resolved
+    }
+
+    /// Carry out the actions of an algorithm, and those that follow from them, then save it
+    async fn perform(
+        &mut self,
+        client: &impl TradingClient,
+        id: &str,
+        actions: Vec<Action>,
+    ) -> Result<(), ExecutionError> {
+        let mut queue = VecDeque::from(actions);
+        while let Some(action) = queue.pop_front() {
+            let Some(execution) = self.executions.get_mut(id) else {
+                return Err(ExecutionError::UnknownExecution(id.to_string()));
+            };
+            if !execution.is_running() {
+                break;
+            }
+            match action {
+                Action::Place {
+                    order_type,
+                    volume,
+                    price,
+                    price2,
+                } => {
+                    let client_order_id = Uuid::new_v4().to_string();
+                    let mut params = AddOrderParams::new(
+                        order_type.clone(),
+                        execution.algorithm.side().clone(),
+                        execution.algorithm.pair().to_string(),
+                        volume,
+                    )
+                    .with_client_order_id(client_order_id.clone());
+                    if let Some(price) = price {
+                        params = params.with_price(price);
+                    }
+                    if let Some(price2) = price2 {
+                        params = params.with_price2(price2);
+                    }
+                    execution.children.push(ChildOrder::new(
+                        client_order_id.clone(),
+                        order_type,
+                        volume,
+                        price,
+                    ));
+                    // saved first so a restart looks the order up instead of placing it again
+                    self.save(id)?;
+                    let Some(execution) = self.executions.get_mut(id) else {
+                        return Err(ExecutionError::UnknownExecution(id.to_string()));
+                    };
+                    let transaction_id = match self.submitter.submit(client, params).await {
+                        Ok(submitted) => submitted.transaction_ids().first().cloned(),
+                        Err(err @ SubmitError::Unknown { .. }) => {
+                            // kept until it's found on a later tick
+                            warn!("execution {} may have placed an order -> {}", id, err);
+                            continue;
+                        }
+                        Err(err) => {
+                            warn!("execution {} couldn't place an order -> {}", id, err);
+                            execution
+                                .children
+                                .retain(|child| child.client_order_id != client_order_id);
+                            cancel_children(client, execution).await;
+                            execution.status = ExecutionStatus::Failed(err.to_string());
+                            break;
+                        }
+                    };
+                    if let Some(child) = execution.child_mut(&client_order_id) {
+                        child.transaction_id = transaction_id.clone();
+                    }
+                    self.publish(ExecutionEvent::OrderPlaced {
+                        id: id.to_string(),
+                        client_order_id: client_order_id.clone(),
+                        transaction_id: transaction_id.clone(),
+                        volume,
+                    });
+                    let Some(transaction_id) = transaction_id else {
+                        continue;
+                    };
+                    self.transactions
+                        .insert(transaction_id.clone(), (id.to_string(), client_order_id));
+                    // the websocket may have reported the order before the response arrived
+                    if self.sync_child(&transaction_id).is_some() {
+                        if let Some(execution) = self.executions.get_mut(id) {
+                            queue.extend(execution.algorithm.on_child(&execution.children));
+                        }
+                    }
+                }
+                Action::Cancel { client_order_id } => {
+                    let Some(child) = execution.child_mut(&client_order_id) else {
+                        continue;
+                    };
+                    child.canceled = true;
+                    let params = CancelOrderParams::by_client_order_id(client_order_id.clone());
+                    if let Err(err) = client.cancel_order(&params).await {
+                        // the order may have been closed in the meantime
+                        warn!("couldn't cancel order {} -> {}", client_order_id, err);
+                    }
+                    self.publish(ExecutionEvent::OrderCanceled {
+                        id: id.to_string(),
+                        client_order_id,
+                    });
+                }
+            }
+        }
+        if let Some(execution) = self.executions.get_mut(id) {
+            if execution.is_running() && execution.algorithm.is_finished(&execution.children) {
+                execution.status = ExecutionStatus::Completed;
+            }
+        }
+        self.finish(id)
+    }
+
+    /// Save a running execution if it changed, or announce a finished one and remove it from the
+    /// store
+    fn finish(&mut self, id: &str) -> Result<(), ExecutionError> {
+        let Some(execution) = self.executions.get(id) else {
+            return Ok(());
+        };
+        if execution.is_running() {
+            return self.save(id);
+        }
+        let status = execution.status.clone();
+        self.store.remove(id)?;
+        self.saved.remove(id);
+        self.transactions
+            .retain(|_, (execution_id, _)| execution_id != id);
+        self.publish(ExecutionEvent::Finished {
+            id: id.to_string(),
+            status,
+        });
+        Ok(())
+    }
+
+    /// Save an execution if it changed since it was last saved
+    fn save(&mut self, id: &str) -> Result<(), ExecutionError> {
+        let Some(execution) = self.executions.get(id) else {
+            return Ok(());
+        };
+        let state = serde_json::to_vec(execution)?;
+        if self.saved.get(id) != Some(&state) {
+            self.store.save(execution)?;
+            self.saved.insert(id.to_string(), state);
+        }
+        Ok(())
+    }
+
+    fn publish(&self, event: ExecutionEvent) {
+        // nobody may be listening
+        let _ = self.events.send(event);
+    }
+}
+
+/// Request the cancellation of every order of the execution that may still be open. Returns the
+/// client order ids of those orders
+async fn cancel_children(client: &impl TradingClient, execution: &mut Execution) -> Vec<String> {
+    let mut canceled = Vec::new();
+    for child in &mut execution.children {
+        if child.is_done() || child.canceled {
+            continue;
+        }
+        child.canceled = true;
+        let params = CancelOrderParams::by_client_order_id(child.client_order_id.clone());
+        if let Err(err) = client.cancel_order(&params).await {
+            warn!("couldn't cancel order {} -> {}", child.client_order_id, err);
+        }
+        canceled.push(child.client_order_id.clone());
+    }
+    canceled
+}
+
+enum Command {
+    Start(Algorithm, oneshot::Sender<Result<String, ExecutionError>>),
+    Cancel(String, oneshot::Sender<Result<(), ExecutionError>>),
+}
+
+impl std::fmt::Debug for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Start(algorithm, _) => write!(f, "Start({:?})", algorithm),
+            Self::Cancel(id, _) => write!(f, "Cancel({})", id),
+        }
+    }
+}
+
+enum Update {
+    Command(Command),
+    OpenOrders(OpenOrders),
+    Price(Pair, Decimal),
+    Tick,
+}
+
+impl<S: ExecutionStore + Send + 'static> ExecutionEngine<S> {
+    /// Drive the engine from a background task. Prices are last trade prices keyed by the
+    /// websocket name of their pair. Time based algorithms are checked every `tick`
+    pub fn run<C, O, P>(self, client: C, open_orders: O, prices: P, tick: Duration) -> LiveExecution
+    where
+        C: TradingClient + 'static,
+        O: Stream<Item = OpenOrders> + Send + 'static,
+        P: Stream<Item = (Pair, Decimal)> + Send + 'static,
+    {
+        let (commands, receiver) = mpsc::channel(16);
+        let updates: Vec<BoxStream<'static, Update>> = vec![
+            futures::stream::unfold(receiver, |mut receiver| async move {
+                let command = receiver.recv().await?;
+                Some((Update::Command(command), receiver))
+            })
+            .boxed(),
+            open_orders.map(Update::OpenOrders).boxed(),
+            prices
+                .map(|(pair, price)| Update::Price(pair, price))
+                .boxed(),
+            futures::stream::unfold((), move |_| async move {
+                tokio::time::sleep(tick).await;
+                Some((Update::Tick, ()))
+            })
+            .boxed(),
+        ];
+        let events = self.events.clone();
+        let mut engine = self;
+        let task = tokio::spawn(async move {
+            let mut updates = select_all(updates);
+            while let Some(update) = updates.next().await {
+                let result = match update {
+                    Update::Command(Command::Start(algorithm, reply)) => {
+                        let _ = reply.send(engine.start(&client, algorithm).await);
+                        Ok(())
+                    }
+                    Update::Command(Command::Cancel(id, reply)) => {
+                        let _ = reply.send(engine.cancel(&client, &id).await);
+                        Ok(())
+                    }
+                    Update::OpenOrders(orders) => engine.apply_open_orders(&client, &orders).await,
+                    Update::Price(pair, price) => engine.apply_price(&client, &pair, price).await,
+                    Update::Tick => engine.apply_time(&client, Utc::now()).await,
+                };
+                if let Err(err) = result {
+                    error!("execution engine update failed -> {}", err);
+                }
+            }
+        });
+        LiveExecution {
+            commands,
+            events,
+            task,
+        }
+    }
+}
+
+/// An [ExecutionEngine] running in a background task
+#[derive(Debug)]
+pub struct LiveExecution {
+    commands: mpsc::Sender<Command>,
+    events: broadcast::Sender<ExecutionEvent>,
+    task: JoinHandle<()>,
+}
+
+impl LiveExecution {
+    pub async fn start(&self, algorithm: Algorithm) -> Result<String, ExecutionError> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .send(Command::Start(algorithm, reply))
+            .await
+            .map_err(|_| ExecutionError::Stopped)?;
+        result.await.map_err(|_| ExecutionError::Stopped)?
+    }
+
+    pub async fn cancel(&self, id: &str) -> Result<(), ExecutionError> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .send(Command::Cancel(id.to_string(), reply))
+            .await
+            .map_err(|_| ExecutionError::Stopped)?;
+        result.await.map_err(|_| ExecutionError::Stopped)?
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ExecutionEvent> {
+        self.events.subscribe()
+    }
+
+    /// False once the task has stopped
+    pub fn is_running(&self) -> bool {
+        !self.task.is_finished()
+    }
+}
+
+impl Drop for LiveExecution {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Linnaeus {
+    /// Run the engine over the private open orders feed and the public trade feed of `pairs`.
+    /// Algorithms can only follow the prices of these pairs
+    pub async fn run_executions<S: ExecutionStore + Send + 'static>(
+        &mut self,
+        engine: ExecutionEngine<S>,
+        pairs: &[Pair],
+        options: SubscriptionOptions,
+    ) -> Result<LiveExecution, LinnaeusWebsocketError> {
+        let private = self.get_private_websocket_client().await?;
+        let open_orders = private.subscribe_open_orders(options.clone()).await?;
+        let prices = if pairs.is_empty() {
+            futures::stream::empty().boxed()
+        } else {
+            let public = self.get_websocket_client().await?;
+            public
+                .subscribe_trades(pairs, options)
+                .await?
+                .filter_map(|(pair, trades)| async move {
+                    trades.last().map(|trade| (pair, *trade.price()))
+                })
+                .boxed()
+        };
+        Ok(engine.run(
+            self.rest_client(),
+            open_orders,
+            prices,
+            Duration::from_secs(1),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod execution_tests {
+    use super::*;
+    use crate::api::user_data::{
+        AccountBalances, ClosedOrders, ClosedOrdersParams, LedgerInfo, LedgerInfoParams,
+        OpenOrdersParams, Side, TradeHistory, TradeHistoryParams,
+    };
+    use crate::api::user_trading::{AddOrderResponse, CancelOrderResponse};
+    use crate::order_tracker::{test_rest_order, test_websocket_order};
+    use futures::future::BoxFuture;
+    use linnaeus_request::error::RequestError;
+    use linnaeus_ws::messages::private_messages::{
+        self as ws, OpenOrderOrStatusChange, OrderStatusChange,
+    };
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    /// Accepts every order and numbers their txids O1, O2, ...
+    #[derive(Default)]
+    struct MockClient {
+        placed: Mutex<Vec<AddOrderParams>>,
+        canceled: Mutex<Vec<String>>,
+        /// Returned by the next AddOrder request instead of accepting the order
+        add_order_error: Mutex<Option<RequestError>>,
+        /// Open orders requests fail while set
+        lookups_fail: Mutex<bool>,
+        /// AddOrder requests never get a response while set
+        unanswered: Mutex<bool>,
+    }
+
+    impl TradingClient for MockClient {
+        fn add_order<'a>(
+            &'a self,
+            params: &'a AddOrderParams,
+        ) -> BoxFuture<'a, Result<AddOrderResponse, RequestError>> {
+            Box::pin(async move {
+                let placed_count = {
+                    let mut placed = self.placed.lock().expect("poisoned");
+                    placed.push(params.clone());
+                    placed.len()
+                };
+                if *self.unanswered.lock().expect("poisoned") {
+                    std::future::pending::<()>().await;
+                }
+                if let Some(err) = self.add_order_error.lock().expect("poisoned").take() {
+                    return Err(err);
+                }
+                Ok(serde_json::from_value(json!({
+                    "descr": {"order": ""},
+                    "txid": [format!("O{}", placed_count)]
+                }))
+                .expect("valid response"))
+            })
+        }
+
+        fn cancel_order<'a>(
+            &'a self,
+            params: &'a CancelOrderParams,
+        ) -> BoxFuture<'a, Result<CancelOrderResponse, RequestError>> {
+            Box::pin(async move {
+                let id = params.client_order_id().clone().unwrap_or_default();
+                self.canceled.lock().expect("poisoned").push(id);
+                Ok(CancelOrderResponse::new(1, false))
+            })
+        }
+
+        fn account_balances(&self) -> BoxFuture<'_, Result<AccountBalances, RequestError>> {
+            Box::pin(async { Ok(AccountBalances::new()) })
+        }
+
+        /// Every placed order is listed as open
+        fn open_orders<'a>(
+            &'a self,
+            _params: &'a OpenOrdersParams,
+        ) -> BoxFuture<'a, Result<HashMap<String, OrderBase>, RequestError>> {
+            Box::pin(async move {
+                if *self.lookups_fail.lock().expect("poisoned") {
+                    return Err(RequestError::Other("down".to_string()));
+                }
+                let placed = self.placed.lock().expect("poisoned");
+                Ok(placed
+                    .iter()
+                    .enumerate()
+                    .map(|(index, params)| {
+                        let order = test_rest_order(OrderStatus::Open, *params.volume())
+                            .with_client_order_id(params.client_order_id().clone());
+                        (format!("O{}", index + 1), order)
+                    })
+                    .collect())
+            })
+        }
+
+        fn closed_orders<'a>(
+            &'a self,
+            _params: &'a ClosedOrdersParams,
+        ) -> BoxFuture<'a, Result<ClosedOrders, RequestError>> {
+            Box::pin(async { Ok(ClosedOrders::new(HashMap::new(), 0)) })
+        }
+
+        fn trade_history<'a>(
+            &'a self,
+            _params: &'a TradeHistoryParams,
+        ) -> BoxFuture<'a, Result<TradeHistory, RequestError>> {
+            Box::pin(async { Ok(TradeHistory::new(HashMap::new(), 0)) })
+        }
+
+        fn ledgers<'a>(
+            &'a self,
+            _params: &'a LedgerInfoParams,
+        ) -> BoxFuture<'a, Result<LedgerInfo, RequestError>> {
+            Box::pin(async { Ok(LedgerInfo::new(HashMap::new(), 0)) })
+        }
+    }
+
+    fn filled(id: &str, volume: Decimal, cost: Decimal) -> OpenOrders {
+        let order = test_websocket_order(ws::OrderStatus::Open, volume);
+        let fill = OrderStatusChange::new(ws::OrderStatus::Closed)
+            .with_volume_executed(Some(volume))
+            .with_cost(Some(cost))
+            .with_fee(Some(Decimal::ZERO));
+        vec![
+            OpenOrderOrStatusChange::OpenOrder((id.to_string(), order)),
+            OpenOrderOrStatusChange::StatusChange((id.to_string(), fill)),
+        ]
+    }
+
+    fn placed_volumes(client: &MockClient) -> Vec<Decimal> {
+        let placed = client.placed.lock().expect("poisoned");
+        placed.iter().map(|params| *params.volume()).collect()
+    }
+
+    #[tokio::test]
+    async fn iceberg_places_slices_until_filled() -> Result<(), ExecutionError> {
+        let client = MockClient::default();
+        let mut engine = ExecutionEngine::new(MemoryStore::default())?;
+        let mut events = engine.subscribe();
+        let iceberg = Iceberg::new("XBT/USD", Side::Buy, dec!(1), dec!(20000), dec!(0.4));
+        let id = engine.start(&client, iceberg.into()).await?;
+
+        engine
+            .apply_open_orders(&client, &filled("O1", dec!(0.4), dec!(8000)))
+            .await?;
+        engine
+            .apply_open_orders(&client, &filled("O2", dec!(0.4), dec!(8000)))
+            .await?;
+        assert_eq!(
+            *engine.get(&id).expect("execution").status(),
+            ExecutionStatus::Running
+        );
+        engine
+            .apply_open_orders(&client, &filled("O3", dec!(0.2), dec!(4000)))
+            .await?;
+
+        assert_eq!(
+            placed_volumes(&client),
+            vec![dec!(0.4), dec!(0.4), dec!(0.2)]
+        );
+        let execution = engine.get(&id).expect("execution");
+        assert_eq!(*execution.status(), ExecutionStatus::Completed);
+        assert_eq!(execution.remaining(), dec!(0));
+        assert_eq!(execution.average_price(), Some(dec!(20000)));
+        assert!(engine.store.load()?.is_empty());
+
+        let mut progress = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let ExecutionEvent::Progress { executed, .. } = event {
+                progress.push(executed);
+            }
+        }
+        assert_eq!(progress, vec![dec!(0.4), dec!(0.8), dec!(1)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn indeterminate_orders_are_resolved_by_client_order_id() -> Result<(), ExecutionError> {
+        let client = MockClient::default();
+        *client.add_order_error.lock().expect("poisoned") =
+            Some(RequestError::ParsingError("truncated".to_string()));
+        *client.lookups_fail.lock().expect("poisoned") = true;
+        let submitter = OrderSubmitter::new()
+            .with_retries(0)
+            .with_lookup_delay(Duration::ZERO);
+        let mut engine = ExecutionEngine::new(MemoryStore::default())?.with_submitter(submitter);
+        let iceberg = Iceberg::new("XBT/USD", Side::Buy, dec!(1), dec!(20000), dec!(0.4));
+        let id = engine.start(&client, iceberg.into()).await?;
+
+        let execution = engine.get(&id).expect("execution");
+        assert_eq!(*execution.status(), ExecutionStatus::Running);
+        assert_eq!(execution.children().len(), 1);
+        assert_eq!(*execution.children()[0].transaction_id(), None);
+
+        *client.lookups_fail.lock().expect("poisoned") = false;
+        engine.apply_time(&client, Utc::now()).await?;
+        let execution = engine.get(&id).expect("execution");
+        assert_eq!(
+            execution.children()[0].transaction_id().as_deref(),
+            Some("O1")
+        );
+        engine
+            .apply_open_orders(&client, &filled("O1", dec!(0.4), dec!(8000)))
+            .await?;
+        assert_eq!(placed_volumes(&client), vec![dec!(0.4), dec!(0.4)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn orders_in_flight_are_looked_up_after_a_restart() -> Result<(), ExecutionError> {
+        let directory = std::env::temp_dir().join(format!("linnaeus-{}", Uuid::new_v4()));
+        let client = MockClient::default();
+        *client.unanswered.lock().expect("poisoned") = true;
+        let iceberg = Iceberg::new("XBT/USD", Side::Buy, dec!(1), dec!(20000), dec!(0.4));
+        let mut engine = ExecutionEngine::new(FileStore::new(&directory)?)?;
+        let started = engine.start(&client, iceberg.into());
+        // the process stops while waiting for the AddOrder response
+        assert!(tokio::time::timeout(Duration::from_millis(50), started)
+            .await
+            .is_err());
+        drop(engine);
+
+        *client.unanswered.lock().expect("poisoned") = false;
+        let mut engine = ExecutionEngine::new(FileStore::new(&directory)?)?;
+        let execution = engine.executions().next().expect("execution");
+        let id = execution.id().clone();
+        assert_eq!(execution.children().len(), 1);
+        assert_eq!(*execution.children()[0].transaction_id(), None);
+        engine.apply_time(&client, Utc::now()).await?;
+        let execution = engine.get(&id).expect("execution");
+        assert_eq!(
+            execution.children()[0].transaction_id().as_deref(),
+            Some("O1")
+        );
+        assert_eq!(placed_volumes(&client), vec![dec!(0.4)]);
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unchanged_executions_are_not_saved_again() -> Result<(), ExecutionError> {
+        let client = MockClient::default();
+        let mut engine = ExecutionEngine::new(CountingStore::default())?;
+        let stop = TrailingStop::new(
+            "XBT/USD",
+            Side::Sell,
+            dec!(1),
+            TrailOffset::Price(dec!(100)),
+        );
+        engine.start(&client, stop.into()).await?;
+        engine.apply_price(&client, "XBT/USD", dec!(20000)).await?;
+        let saves = engine.store.saves;
+        engine.apply_price(&client, "XBT/USD", dec!(19950)).await?;
+        engine.apply_time(&client, Utc::now()).await?;
+        assert_eq!(engine.store.saves, saves);
+        engine.apply_price(&client, "XBT/USD", dec!(20100)).await?;
+        assert_eq!(engine.store.saves, saves + 1);
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct CountingStore {
+        saves: usize,
+    }
+
+    impl ExecutionStore for CountingStore {
+        fn load(&self) -> Result<Vec<Execution>, ExecutionError> {
+            Ok(Vec::new())
+        }
+
+        fn save(&mut self, _execution: &Execution) -> Result<(), ExecutionError> {
+            self.saves += 1;
+            Ok(())
+        }
+
+        fn remove(&mut self, _id: &str) -> Result<(), ExecutionError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn executions_resume_from_the_store() -> Result<(), ExecutionError> {
+        let directory = std::env::temp_dir().join(format!("linnaeus-{}", Uuid::new_v4()));
+        let client = MockClient::default();
+        let stop = TrailingStop::new(
+            "XBT/USD",
+            Side::Sell,
+            dec!(1),
+            TrailOffset::Price(dec!(100)),
+        );
+        let mut engine = ExecutionEngine::new(FileStore::new(&directory)?)?;
+        let id = engine.start(&client, stop.into()).await?;
+        engine.apply_price(&client, "XBT/USD", dec!(20000)).await?;
+        engine.apply_price(&client, "XBT/USD", dec!(20500)).await?;
+        drop(engine);
+
+        let mut engine = ExecutionEngine::new(FileStore::new(&directory)?)?;
+        let Algorithm::TrailingStop(stop) = engine.get(&id).expect("execution").algorithm() else {
+            panic!("expected a trailing stop");
+        };
+        assert_eq!(stop.stop_price(), Some(dec!(20400)));
+        engine.apply_price(&client, "ETH/USD", dec!(1000)).await?;
+        engine.apply_price(&client, "XBT/USD", dec!(20400)).await?;
+        assert_eq!(placed_volumes(&client), vec![dec!(1)]);
+        let order = client.placed.lock().expect("poisoned")[0].clone();
+        assert!(matches!(order.order_type(), OrderType::Market));
+        assert!(matches!(order.side(), Side::Sell));
+
+        engine.cancel(&client, &id).await?;
+        assert_eq!(client.canceled.lock().expect("poisoned").len(), 1);
+        assert!(engine.store.load()?.is_empty());
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use super::{Execution, ExecutionError};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Keeps the state of running executions so they can be resumed after a restart
+pub trait ExecutionStore {
+    /// Every execution that was saved and not removed since
+    fn load(&self) -> Result<Vec<Execution>, ExecutionError>;
+    fn save(&mut self, execution: &Execution) -> Result<(), ExecutionError>;
+    fn remove(&mut self, id: &str) -> Result<(), ExecutionError>;
+}
+
+/// Keeps nothing across restarts
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    executions: HashMap<String, Execution>,
+}
+
+impl ExecutionStore for MemoryStore {
+    fn load(&self) -> Result<Vec<Execution>, ExecutionError> {
+        Ok(self.executions.values().cloned().collect())
+    }
+
+    fn save(&mut self, execution: &Execution) -> Result<(), ExecutionError> {
+        self.executions
+            .insert(execution.id().clone(), execution.clone());
+        Ok(())
+    }
+
+    fn remove(&mut self, id: &str) -> Result<(), ExecutionError> {
+        self.executions.remove(id);
+        Ok(())
+    }
+}
+
+/// One json file per execution in a directory
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    directory: PathBuf,
+}
+
+impl FileStore {
+    /// Creates the directory if it doesn't exist
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self, ExecutionError> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self { directory })
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.directory.join(format!("{}.json", id))
+    }
+}
+
+impl ExecutionStore for FileStore {
+    fn load(&self) -> Result<Vec<Execution>, ExecutionError> {
+        let mut executions = Vec::new();
+        for entry in std::fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                executions.push(serde_json::from_slice(&std::fs::read(path)?)?);
+            }
+        }
+        Ok(executions)
+    }
+
+    /// Written to a temporary file first so a crash can't leave a partial file behind
+    fn save(&mut self, execution: &Execution) -> Result<(), ExecutionError> {
+        let path = self.path(execution.id());
+        let temporary = path.with_extension("json.tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(execution)?)?;
+        std::fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, id: &str) -> Result<(), ExecutionError> {
+        match std::fs::remove_file(self.path(id)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use super::{ExportError, ExportRecord, Exporter, Value};
+use chrono::SecondsFormat;
+use std::io::Write;
+use std::marker::PhantomData;
+
+/// Writes records as CSV with a header row. Decimals are written exactly as they were received
+/// and times as RFC 3339 in UTC
+pub struct CsvExporter<W: Write, R> {
+    writer: csv::Writer<W>,
+    header_written: bool,
+    record: PhantomData<fn(&R)>,
+}
+
+impl<W: Write, R: ExportRecord> CsvExporter<W, R> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            header_written: false,
+            record: PhantomData,
+        }
+    }
+
+    fn write_header(&mut self) -> Result<(), ExportError> {
+        if !self.header_written {
+            self.writer
+                .write_record(R::columns().iter().map(|column| column.name))?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Finish and get the underlying writer back
+    pub fn into_inner(mut self) -> Result<W, ExportError> {
+        self.write_header()?;
+        self.writer
+            .into_inner()
+            .map_err(|err| ExportError::Io(err.into_error()))
+    }
+}
+
+impl<W: Write, R: ExportRecord> Exporter<R> for CsvExporter<W, R> {
+    fn write(&mut self, record: &R) -> Result<(), ExportError> {
+        self.write_header()?;
+        self.writer.write_record(record.values().iter().map(cell))?;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), ExportError> {
+        self.into_inner().map(|_| ())
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Timestamp(time) => time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        Value::Decimal(decimal) => decimal.to_string(),
+        Value::Integer(integer) => integer.to_string(),
+        Value::Text(text) => text.clone(),
+    }
+}
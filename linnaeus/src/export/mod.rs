@@ -0,0 +1,168 @@
+//! Columnar export of market and account data to CSV and Apache Parquet.
+//!
+//! Decimals keep their full precision and times are written in UTC. Records are written as they
+//! arrive so histories can be exported while they are paged without holding them in memory.
+
+mod csv_writer;
+mod parquet_writer;
+mod records;
+
+pub use csv_writer::CsvExporter;
+pub use parquet_writer::{DecimalEncoding, ParquetExporter, ParquetOptions};
+
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Timestamp,
+    Decimal,
+    Integer,
+    Text,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    pub name: &'static str,
+    pub kind: ColumnKind,
+}
+
+impl Column {
+    pub const fn new(name: &'static str, kind: ColumnKind) -> Self {
+        Self { name, kind }
+    }
+}
+
+/// A single cell. Missing optional fields are [Value::Null]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Timestamp(DateTime<Utc>),
+    Decimal(Decimal),
+    Integer(i64),
+    Text(String),
+}
+
+impl From<DateTime<Utc>> for Value {
+    fn from(time: DateTime<Utc>) -> Self {
+        Self::Timestamp(time)
+    }
+}
+
+impl From<Decimal> for Value {
+    fn from(decimal: Decimal) -> Self {
+        Self::Decimal(decimal)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(integer: i64) -> Self {
+        Self::Integer(integer)
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+/// A type that can be written as a row of a columnar file
+pub trait ExportRecord {
+    fn columns() -> Vec<Column>;
+    /// One value per column in the same order as [ExportRecord::columns]
+    fn values(&self) -> Vec<Value>;
+}
+
+/// Kraken returns trades, ledger entries and orders keyed by their id. The id is exported as the
+/// first column
+impl<T: ExportRecord> ExportRecord for (String, T) {
+    fn columns() -> Vec<Column> {
+        let mut columns = vec![Column::new("id", ColumnKind::Text)];
+        columns.extend(T::columns());
+        columns
+    }
+
+    fn values(&self) -> Vec<Value> {
+        let mut values = vec![Value::Text(self.0.clone())];
+        values.extend(self.1.values());
+        values
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("io error while exporting -> {0}")]
+    Io(#[from] std::io::Error),
+    #[error("csv error while exporting -> {0}")]
+    Csv(#[from] csv::Error),
+    #[error("arrow error while exporting -> {0}")]
+    Arrow(#[from] arrow_schema::ArrowError),
+    #[error("parquet error while exporting -> {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error("{value} in column {column} can't be written without losing precision")]
+    Precision {
+        column: &'static str,
+        value: Decimal,
+    },
+    #[error("column {column} is a {kind:?} column but got {value:?}")]
+    Type {
+        column: &'static str,
+        kind: ColumnKind,
+        value: Value,
+    },
+    #[error("the exported stream failed -> {0}")]
+    Source(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Writes records of one type to a file
+pub trait Exporter<R: ExportRecord> {
+    fn write(&mut self, record: &R) -> Result<(), ExportError>;
+    /// Write anything that is buffered and the file footer. Must be called for the file to be
+    /// complete
+    fn finish(self) -> Result<(), ExportError>;
+}
+
+/// Export every record of a stream, e.g. while paging through the trade history. Returns the
+/// number of records written.
+///
+/// If the stream yields an error the records written so far are finished into a complete file
+/// and the error is returned
+pub async fn export_stream<R, E, S, X>(stream: S, mut exporter: X) -> Result<usize, ExportError>
+where
+    R: ExportRecord,
+    E: std::error::Error + Send + Sync + 'static,
+    S: Stream<Item = Result<R, E>>,
+    X: Exporter<R>,
+{
+    let mut stream = std::pin::pin!(stream);
+    let mut written = 0;
+    while let Some(record) = stream.next().await {
+        match record {
+            Ok(record) => {
+                exporter.write(&record)?;
+                written += 1;
+            }
+            Err(err) => {
+                exporter.finish()?;
+                return Err(ExportError::Source(Box::new(err)));
+            }
+        }
+    }
+    exporter.finish()?;
+    Ok(written)
+}
@@ -0,0 +1,209 @@
+use super::{Column, ColumnKind, ExportError, ExportRecord, Exporter, Value};
+use arrow_array::builder::{
+    Decimal128Builder, Int64Builder, StringBuilder, TimestampNanosecondBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use derive_setters::Setters;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use rust_decimal::Decimal;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// decimal128 holds at most 38 digits
+const DECIMAL128_PRECISION: u8 = 38;
+
+/// How decimals are stored in parquet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecimalEncoding {
+    /// decimal128 with a fixed number of decimal places. Values with more significant decimal
+    /// places are rejected rather than rounded
+    Decimal128 { scale: i8 },
+    /// Exactly as the decimal was received
+    Text,
+}
+
+#[derive(Debug, Clone, Setters)]
+#[setters(prefix = "with_")]
+pub struct ParquetOptions {
+    /// Rows buffered in memory before they are written out as a row group
+    row_group_size: usize,
+    decimals: DecimalEncoding,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        Self {
+            row_group_size: 8192,
+            // kraken doesn't quote anything with more than 10 decimal places
+            decimals: DecimalEncoding::Decimal128 { scale: 10 },
+        }
+    }
+}
+
+/// Writes records to a parquet file. Times are stored as nanosecond timestamps in UTC
+pub struct ParquetExporter<W: Write + Send, R> {
+    writer: ArrowWriter<W>,
+    schema: SchemaRef,
+    columns: Vec<Column>,
+    options: ParquetOptions,
+    rows: Vec<Vec<Value>>,
+    record: PhantomData<fn(&R)>,
+}
+
+impl<W: Write + Send, R: ExportRecord> ParquetExporter<W, R> {
+    pub fn new(writer: W) -> Result<Self, ExportError> {
+        Self::new_with_options(writer, ParquetOptions::default())
+    }
+
+    pub fn new_with_options(writer: W, options: ParquetOptions) -> Result<Self, ExportError> {
+        let columns = R::columns();
+        let schema = Arc::new(Schema::new(
+            columns
+                .iter()
+                .map(|column| Field::new(column.name, data_type(column.kind, &options), true))
+                .collect::<Vec<_>>(),
+        ));
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(options.row_group_size.max(1))
+            .build();
+        Ok(Self {
+            writer: ArrowWriter::try_new(writer, schema.clone(), Some(properties))?,
+            schema,
+            columns,
+            rows: Vec::with_capacity(options.row_group_size),
+            options,
+            record: PhantomData,
+        })
+    }
+
+    fn flush(&mut self) -> Result<(), ExportError> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let arrays = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| self.column_array(index, column))
+            .collect::<Result<Vec<_>, _>>()?;
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+        self.writer.write(&batch)?;
+        self.writer.flush()?;
+        self.rows.clear();
+        Ok(())
+    }
+
+    fn column_array(&self, index: usize, column: &Column) -> Result<ArrayRef, ExportError> {
+        let values = self.rows.iter().map(|row| &row[index]);
+        let mismatch = |value: &Value| ExportError::Type {
+            column: column.name,
+            kind: column.kind,
+            value: value.clone(),
+        };
+        let array: ArrayRef = match (column.kind, self.options.decimals) {
+            (ColumnKind::Timestamp, _) => {
+                let mut builder = TimestampNanosecondBuilder::new().with_timezone("UTC");
+                for value in values {
+                    match value {
+                        Value::Null => builder.append_null(),
+                        Value::Timestamp(time) => builder.append_value(
+                            time.timestamp_nanos_opt().ok_or_else(|| mismatch(value))?,
+                        ),
+                        _ => return Err(mismatch(value)),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            (ColumnKind::Decimal, DecimalEncoding::Decimal128 { scale }) => {
+                let mut builder = Decimal128Builder::new()
+                    .with_precision_and_scale(DECIMAL128_PRECISION, scale)?;
+                for value in values {
+                    match value {
+                        Value::Null => builder.append_null(),
+                        Value::Decimal(decimal) => builder.append_value(
+                            to_decimal128(*decimal, scale).ok_or(ExportError::Precision {
+                                column: column.name,
+                                value: *decimal,
+                            })?,
+                        ),
+                        _ => return Err(mismatch(value)),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            (ColumnKind::Integer, _) => {
+                let mut builder = Int64Builder::new();
+                for value in values {
+                    match value {
+                        Value::Null => builder.append_null(),
+                        Value::Integer(integer) => builder.append_value(*integer),
+                        _ => return Err(mismatch(value)),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            (ColumnKind::Text, _) | (ColumnKind::Decimal, DecimalEncoding::Text) => {
+                let mut builder = StringBuilder::new();
+                for value in values {
+                    match (column.kind, value) {
+                        (_, Value::Null) => builder.append_null(),
+                        (ColumnKind::Text, Value::Text(text)) => builder.append_value(text),
+                        (ColumnKind::Decimal, Value::Decimal(decimal)) => {
+                            builder.append_value(decimal.to_string())
+                        }
+                        _ => return Err(mismatch(value)),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+        };
+        Ok(array)
+    }
+
+    /// Finish and get the underlying writer back
+    pub fn into_inner(mut self) -> Result<W, ExportError> {
+        self.flush()?;
+        Ok(self.writer.into_inner()?)
+    }
+}
+
+impl<W: Write + Send, R: ExportRecord> Exporter<R> for ParquetExporter<W, R> {
+    fn write(&mut self, record: &R) -> Result<(), ExportError> {
+        self.rows.push(record.values());
+        if self.rows.len() >= self.options.row_group_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), ExportError> {
+        self.into_inner().map(|_| ())
+    }
+}
+
+fn data_type(kind: ColumnKind, options: &ParquetOptions) -> DataType {
+    match (kind, options.decimals) {
+        (ColumnKind::Timestamp, _) => DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+        (ColumnKind::Decimal, DecimalEncoding::Decimal128 { scale }) => {
+            DataType::Decimal128(DECIMAL128_PRECISION, scale)
+        }
+        (ColumnKind::Integer, _) => DataType::Int64,
+        (ColumnKind::Text, _) | (ColumnKind::Decimal, DecimalEncoding::Text) => DataType::Utf8,
+    }
+}
+
+/// The unscaled decimal128 value of `decimal` at `scale`. None if it would lose precision
+fn to_decimal128(decimal: Decimal, scale: i8) -> Option<i128> {
+    let decimal = decimal.normalize();
+    let shift = i32::from(scale) - decimal.scale() as i32;
+    if shift < 0 {
+        return None;
+    }
+    let unscaled = decimal
+        .mantissa()
+        .checked_mul(10i128.checked_pow(shift as u32)?)?;
+    (unscaled.unsigned_abs() < 10u128.pow(u32::from(DECIMAL128_PRECISION))).then_some(unscaled)
+}
@@ -0,0 +1,334 @@
+use super::{Column, ColumnKind, ExportRecord, Value};
+use crate::api::market_data::{SpreadData, TickData, TradeData};
+use crate::api::user_data::{ClosedOrder, Ledger, Trade};
+use chrono::{TimeZone, Utc};
+use std::fmt::Display;
+
+use ColumnKind::{Decimal, Integer, Text, Timestamp};
+
+fn joined<T: Display>(values: &[T]) -> Value {
+    Value::Text(
+        values
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(","),
+    )
+}
+
+impl ExportRecord for TickData {
+    fn columns() -> Vec<Column> {
+        vec![
+            Column::new("time", Timestamp),
+            Column::new("open", Decimal),
+            Column::new("high", Decimal),
+            Column::new("low", Decimal),
+            Column::new("close", Decimal),
+            Column::new("vwap", Decimal),
+            Column::new("volume", Decimal),
+            Column::new("count", Integer),
+        ]
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            (*self.time()).into(),
+            (*self.open()).into(),
+            (*self.high()).into(),
+            (*self.low()).into(),
+            (*self.close()).into(),
+            (*self.volume_weighted_average_price()).into(),
+            (*self.volume()).into(),
+            (*self.count()).into(),
+        ]
+    }
+}
+
+impl ExportRecord for TradeData {
+    fn columns() -> Vec<Column> {
+        vec![
+            Column::new("time", Timestamp),
+            Column::new("price", Decimal),
+            Column::new("volume", Decimal),
+            Column::new("side", Text),
+            Column::new("trade_type", Text),
+            Column::new("miscellaneous", Text),
+        ]
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            (*self.time()).into(),
+            (*self.price()).into(),
+            (*self.volume()).into(),
+            self.side().to_string().into(),
+            self.trade_type().to_string().into(),
+            self.miscellaneous().as_str().into(),
+        ]
+    }
+}
+
+impl ExportRecord for SpreadData {
+    fn columns() -> Vec<Column> {
+        vec![
+            Column::new("time", Timestamp),
+            Column::new("bid", Decimal),
+            Column::new("ask", Decimal),
+        ]
+    }
+
+    fn values(&self) -> Vec<Value> {
+        // kraken sends the time of the spread in whole seconds
+        let time = i64::try_from(*self.id())
+            .ok()
+            .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single());
+        vec![time.into(), (*self.buy()).into(), (*self.sell()).into()]
+    }
+}
+
+impl ExportRecord for Ledger {
+    fn columns() -> Vec<Column> {
+        vec![
+            Column::new("reference_id", Text),
+            Column::new("time", Timestamp),
+            Column::new("type", Text),
+            Column::new("subtype", Text),
+            Column::new("class", Text),
+            Column::new("asset", Text),
+            Column::new("amount", Decimal),
+            Column::new("fee", Decimal),
+            Column::new("balance", Decimal),
+        ]
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.reference_id().as_str().into(),
+            (*self.time()).into(),
+            self.ledger_type().to_string().into(),
+            self.sub_type().as_str().into(),
+            self.class().as_str().into(),
+            self.asset().as_str().into(),
+            (*self.amount()).into(),
+            (*self.fee()).into(),
+            (*self.balance()).into(),
+        ]
+    }
+}
+
+impl ExportRecord for Trade {
+    fn columns() -> Vec<Column> {
+        vec![
+            Column::new("order_id", Text),
+            Column::new("pair", Text),
+            Column::new("time", Timestamp),
+            Column::new("side", Text),
+            Column::new("order_type", Text),
+            Column::new("price", Decimal),
+            Column::new("cost", Decimal),
+            Column::new("fee", Decimal),
+            Column::new("volume", Decimal),
+            Column::new("margin", Decimal),
+            Column::new("misc", Text),
+            Column::new("position_status", Text),
+            Column::new("close_price", Decimal),
+            Column::new("close_cost", Decimal),
+            Column::new("close_fee", Decimal),
+            Column::new("close_volume", Decimal),
+            Column::new("close_margin", Decimal),
+            Column::new("net", Decimal),
+            Column::new("trades", Text),
+        ]
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.order_id().as_str().into(),
+            self.pair().as_str().into(),
+            (*self.time()).into(),
+            self.side().to_string().into(),
+            self.order_type().to_string().into(),
+            (*self.price()).into(),
+            (*self.cost()).into(),
+            (*self.fee()).into(),
+            (*self.volume()).into(),
+            (*self.margin()).into(),
+            joined(self.misc()),
+            self.position_status()
+                .as_ref()
+                .map(ToString::to_string)
+                .into(),
+            (*self.close_price()).into(),
+            (*self.close_cost()).into(),
+            (*self.close_fee()).into(),
+            (*self.close_volume()).into(),
+            (*self.close_margin()).into(),
+            (*self.net()).into(),
+            joined(self.trades()),
+        ]
+    }
+}
+
+impl ExportRecord for ClosedOrder {
+    fn columns() -> Vec<Column> {
+        vec![
+            Column::new("referral_order_transaction_id", Text),
+            Column::new("status", Text),
+            Column::new("open_time", Timestamp),
+            Column::new("start_time", Timestamp),
+            Column::new("expire_time", Timestamp),
+            Column::new("close_time", Timestamp),
+            Column::new("pair", Text),
+            Column::new("side", Text),
+            Column::new("order_type", Text),
+            Column::new("order_price", Decimal),
+            Column::new("order_secondary_price", Decimal),
+            Column::new("leverage", Decimal),
+            Column::new("description", Text),
+            Column::new("volume", Decimal),
+            Column::new("volume_executed", Decimal),
+            Column::new("cost", Decimal),
+            Column::new("fee", Decimal),
+            Column::new("price", Decimal),
+            Column::new("stop_price", Decimal),
+            Column::new("limit_price", Decimal),
+            Column::new("trigger", Text),
+            Column::new("misc", Text),
+            Column::new("order_flags", Text),
+            Column::new("reason", Text),
+            Column::new("trades", Text),
+        ]
+    }
+
+    fn values(&self) -> Vec<Value> {
+        let order = self.order();
+        let description = order.description();
+        vec![
+            order.referral_order_transaction_id().clone().into(),
+            order.status().to_string().into(),
+            (*order.open_time()).into(),
+            (*order.start_time()).into(),
+            (*order.expire_time()).into(),
+            (*self.close_time()).into(),
+            description.pair().as_str().into(),
+            description.side().to_string().into(),
+            description
+                .order_type()
+                .as_ref()
+                .map(ToString::to_string)
+                .into(),
+            (*description.price()).into(),
+            (*description.secondary_price()).into(),
+            (*description.leverage()).into(),
+            description.order_description().as_str().into(),
+            (*order.volume()).into(),
+            (*order.vol_executed()).into(),
+            (*order.cost()).into(),
+            (*order.fee()).into(),
+            (*order.price()).into(),
+            (*order.stop_price()).into(),
+            (*order.limit_price()).into(),
+            order.trigger().to_string().into(),
+            joined(order.misc()),
+            joined(order.order_flags()),
+            self.reason().clone().into(),
+            joined(order.trades()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod export_tests {
+    use super::*;
+    use crate::export::{
+        export_stream, CsvExporter, DecimalEncoding, ExportError, Exporter, ParquetExporter,
+        ParquetOptions,
+    };
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Decimal128Type;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn ledger(amount: &str) -> (String, Ledger) {
+        let ledger = serde_json::from_value(json!({
+            "refid": "TJKLXX-PGMUI-4NTLXU",
+            "time": 1688464484,
+            "type": "trade",
+            "subtype": "",
+            "aclass": "currency",
+            "asset": "XXBT",
+            "amount": amount,
+            "fee": "0.0000000000",
+            "balance": "0.0437477300"
+        }))
+        .expect("couldn't deserialize ledger");
+        ("L4UESK-KG3EQ-UFO4T5".to_string(), ledger)
+    }
+
+    #[test]
+    fn csv_keeps_decimals_and_utc_times() -> anyhow::Result<()> {
+        let mut exporter = CsvExporter::new(Vec::new());
+        exporter.write(&ledger("-0.0000001000"))?;
+        let csv = String::from_utf8(exporter.into_inner()?)?;
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "id,reference_id,time,type,subtype,class,asset,amount,fee,balance"
+        );
+        assert_eq!(
+            lines[1],
+            "L4UESK-KG3EQ-UFO4T5,TJKLXX-PGMUI-4NTLXU,2023-07-04T09:54:44Z,Trade,,currency,XXBT,-0.0000001000,0.0000000000,0.0437477300"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn streams_are_exported_to_parquet() -> anyhow::Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("linnaeus-export-{}.parquet", uuid::Uuid::new_v4()));
+        let exporter = ParquetExporter::new_with_options(
+            std::fs::File::create(&path)?,
+            ParquetOptions::default().with_row_group_size(2),
+        )?;
+        let records = futures::stream::iter(
+            ["1.5", "-0.0000000001", "2"].map(|amount| Ok::<_, ExportError>(ledger(amount))),
+        );
+        assert_eq!(export_stream(records, exporter).await?, 3);
+
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path)?)?.build()?;
+        let mut amounts = Vec::new();
+        for batch in reader {
+            let batch = batch?;
+            let column = batch.column_by_name("amount").expect("no amount column");
+            amounts.extend(
+                column
+                    .as_primitive::<Decimal128Type>()
+                    .values()
+                    .iter()
+                    .copied(),
+            );
+        }
+        assert_eq!(amounts, vec![15_000_000_000, -1, 20_000_000_000]);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn decimals_that_dont_fit_are_rejected() -> anyhow::Result<()> {
+        let mut exporter = ParquetExporter::new_with_options(
+            Vec::new(),
+            ParquetOptions::default().with_decimals(DecimalEncoding::Decimal128 { scale: 2 }),
+        )?;
+        exporter.write(&ledger("0.001"))?;
+        assert!(matches!(
+            exporter.finish(),
+            Err(ExportError::Precision {
+                column: "amount",
+                ..
+            })
+        ));
+        Ok(())
+    }
+}
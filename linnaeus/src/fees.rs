@@ -0,0 +1,284 @@
+use crate::api::market_data::Fee;
+use crate::api::user_data::{trade_volume, OrderFlags, Side, TradeVolume, TradeVolumeParams};
+use crate::registry::AssetRegistry;
+use derive_getters::Getters;
+use derive_setters::Setters;
+use linnaeus_request::error::RequestError;
+use linnaeus_request::RequestHelpers;
+use linnaeus_types::{AssetId, PairId};
+use linnaeus_ws::messages::private_messages::{self as ws, AddOrder};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liquidity {
+    /// The order rests on the book and is filled by another order
+    Maker,
+    /// The order fills against an order already on the book
+    Taker,
+}
+
+/// The parts of an order that decide its fee
+#[derive(Debug, Clone, Setters)]
+#[setters(prefix = "with_")]
+pub struct FeeOrder {
+    #[setters(skip)]
+    pair: String,
+    #[setters(skip)]
+    side: Side,
+    #[setters(skip)]
+    volume: Decimal,
+    #[setters(skip)]
+    price: Decimal,
+    liquidity: Liquidity,
+    flags: Vec<OrderFlags>,
+}
+
+impl FeeOrder {
+    /// A taker order without flags. `pair` can be any name the [AssetRegistry] knows
+    pub fn new(pair: &str, side: Side, volume: Decimal, price: Decimal) -> Self {
+        Self {
+            pair: pair.to_string(),
+            side,
+            volume,
+            price,
+            liquidity: Liquidity::Taker,
+            flags: Vec::new(),
+        }
+    }
+
+    /// The fee parts of a websocket order. Post only orders are makers and anything else is
+    /// assumed to take liquidity. `market_price` is used when the order has no price
+    pub fn from_add_order(order: &AddOrder, market_price: Decimal) -> Self {
+        let side = match order.side() {
+            ws::Side::Buy => Side::Buy,
+            ws::Side::Sell => Side::Sell,
+        };
+        let flags: Vec<OrderFlags> = order
+            .order_flags()
+            .iter()
+            .flat_map(|flags| flags.split(','))
+            .filter_map(|flag| OrderFlags::from_str(flag.trim()).ok())
+            .collect();
+        let liquidity = if flags.iter().any(|flag| matches!(flag, OrderFlags::Post)) {
+            Liquidity::Maker
+        } else {
+            Liquidity::Taker
+        };
+        Self::new(
+            order.pair(),
+            side,
+            *order.volume(),
+            order.price().unwrap_or(market_price),
+        )
+        .with_liquidity(liquidity)
+        .with_flags(flags)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct FeeEstimate {
+    pair: PairId,
+    liquidity: Liquidity,
+    /// Fee in percent
+    rate: Decimal,
+    /// Asset the fee is charged in
+    currency: AssetId,
+    amount: Decimal,
+}
+
+/// Estimates the fee of an order from the account's fee tiers.
+///
+/// Tiers reported by the TradeVolume endpoint are used where available. Other pairs fall back to
+/// their fee schedule at the account's 30 day volume
+#[derive(Debug, Clone)]
+pub struct FeeCalculator {
+    registry: AssetRegistry,
+    volume: Decimal,
+    taker: HashMap<PairId, Decimal>,
+    maker: HashMap<PairId, Decimal>,
+}
+
+impl FeeCalculator {
+    pub fn new(registry: AssetRegistry, trade_volume: TradeVolume) -> Self {
+        let resolve = |fees: &HashMap<String, _>, fee: fn(&_) -> Decimal| {
+            fees.iter()
+                .filter_map(|(name, tier)| Some((registry.pair_id(name)?.clone(), fee(tier))))
+                .collect::<HashMap<_, _>>()
+        };
+        let taker = resolve(trade_volume.fees(), |tier| *tier.fee());
+        let maker = resolve(trade_volume.fees_maker(), |tier| *tier.fee());
+        Self {
+            volume: *trade_volume.volume(),
+            registry,
+            taker,
+            maker,
+        }
+    }
+
+    /// The same maker and taker fee, in percent, for every pair
+    pub fn flat(registry: AssetRegistry, maker: Decimal, taker: Decimal) -> Self {
+        let pairs: Vec<PairId> = registry.pairs().map(|(id, _)| id.clone()).collect();
+        Self {
+            volume: Decimal::ZERO,
+            taker: pairs.iter().map(|id| (id.clone(), taker)).collect(),
+            maker: pairs.into_iter().map(|id| (id, maker)).collect(),
+            registry,
+        }
+    }
+
+    /// Fetch the account's fee tiers for `pairs`
+    pub async fn load(
+        client: &impl RequestHelpers,
+        registry: AssetRegistry,
+        pairs: &[&str],
+    ) -> Result<Self, RequestError> {
+        let mut params = TradeVolumeParams::default();
+        for pair in pairs {
+            params.add_pair(pair);
+        }
+        let trade_volume = trade_volume(client, &params).await?;
+        Ok(Self::new(registry, trade_volume))
+    }
+
+    pub fn registry(&self) -> &AssetRegistry {
+        &self.registry
+    }
+
+    /// The account's 30 day volume
+    pub fn volume(&self) -> Decimal {
+        self.volume
+    }
+
+    /// Fee in percent for a pair
+    pub fn rate(&self, pair: &PairId, liquidity: Liquidity) -> Option<Decimal> {
+        let taker = match self.taker.get(pair) {
+            Some(rate) => *rate,
+            None => tier(self.registry.pair(pair)?.fees(), self.volume)?,
+        };
+        if liquidity == Liquidity::Taker {
+            return Some(taker);
+        }
+        let maker = match self.maker.get(pair) {
+            Some(rate) => Some(*rate),
+            None => tier(self.registry.pair(pair)?.fees_maker(), self.volume),
+        };
+        // pairs without a maker schedule charge the taker fee to both sides
+        Some(maker.unwrap_or(taker))
+    }
+
+    /// None if the pair is unknown
+    pub fn estimate(&self, order: &FeeOrder) -> Option<FeeEstimate> {
+        let pair = self.registry.pair_id(&order.pair)?.clone();
+        let rate = self.rate(&pair, order.liquidity)?;
+        let (base, quote) = self.registry.pair_assets(&pair)?;
+        let has_flag = |wanted: fn(&OrderFlags) -> bool| order.flags.iter().any(wanted);
+        // kraken charges sells in the base currency and buys in the quote currency unless the
+        // order says otherwise
+        let in_base = match order.side {
+            Side::Buy => has_flag(|flag| matches!(flag, OrderFlags::Fcib)),
+            Side::Sell => !has_flag(|flag| matches!(flag, OrderFlags::Fciq)),
+        };
+        let (currency, charged) = if in_base {
+            (base.clone(), order.volume)
+        } else {
+            (quote.clone(), order.volume * order.price)
+        };
+        Some(FeeEstimate {
+            pair,
+            liquidity: order.liquidity,
+            rate,
+            currency,
+            amount: charged * rate / Decimal::ONE_HUNDRED,
+        })
+    }
+}
+
+/// Fee of the highest tier reached by `volume`. Schedules are sorted by volume
+fn tier(schedule: &[Fee], volume: Decimal) -> Option<Decimal> {
+    schedule
+        .iter()
+        .take_while(|fee| *fee.volume() <= volume)
+        .last()
+        .map(|fee| *fee.percent_fee())
+}
+
+#[cfg(test)]
+mod fees_tests {
+    use super::*;
+    use crate::registry::test_registry;
+    use linnaeus_ws::messages::private_messages::OrderType;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    fn trade_volume() -> TradeVolume {
+        let tier = |fee: &str| json!({"fee": fee, "minfee": "0.1000", "maxfee": "0.2600", "nextfee": null, "nextvolume": null, "tiervolume": "50000.0000"});
+        serde_json::from_value(json!({
+            "currency": "ZUSD",
+            "volume": "60000.0000",
+            "fees": {"XXBTZUSD": tier("0.2000")},
+            "fees_maker": {"XXBTZUSD": tier("0.1000")}
+        }))
+        .expect("couldn't deserialize trade volume")
+    }
+
+    #[test]
+    fn reported_tiers_win_over_the_schedule() {
+        let calculator = FeeCalculator::new(test_registry(), trade_volume());
+        let btc = PairId::new("XXBTZUSD");
+        let eth = PairId::new("XETHZUSD");
+        assert_eq!(calculator.rate(&btc, Liquidity::Taker), Some(dec!(0.2)));
+        assert_eq!(calculator.rate(&btc, Liquidity::Maker), Some(dec!(0.1)));
+        assert_eq!(calculator.rate(&eth, Liquidity::Taker), Some(dec!(0.24)));
+        // no maker schedule
+        assert_eq!(calculator.rate(&eth, Liquidity::Maker), Some(dec!(0.24)));
+    }
+
+    #[test]
+    fn fees_are_charged_in_the_preferred_currency() {
+        let calculator = FeeCalculator::new(test_registry(), trade_volume());
+        let buy = FeeOrder::new("XBT/USD", Side::Buy, dec!(2), dec!(30000));
+        let estimate = calculator.estimate(&buy).expect("no estimate");
+        assert_eq!(estimate.currency().as_str(), "ZUSD");
+        assert_eq!(*estimate.amount(), dec!(120));
+
+        let estimate = calculator
+            .estimate(&buy.clone().with_flags(vec![OrderFlags::Fcib]))
+            .expect("no estimate");
+        assert_eq!(estimate.currency().as_str(), "XXBT");
+        assert_eq!(*estimate.amount(), dec!(0.004));
+
+        let sell = FeeOrder::new("XBTUSD", Side::Sell, dec!(2), dec!(30000));
+        let estimate = calculator.estimate(&sell).expect("no estimate");
+        assert_eq!(estimate.currency().as_str(), "XXBT");
+        let estimate = calculator
+            .estimate(&sell.with_flags(vec![OrderFlags::Fciq]))
+            .expect("no estimate");
+        assert_eq!(estimate.currency().as_str(), "ZUSD");
+
+        assert!(calculator
+            .estimate(&FeeOrder::new("SOL/USD", Side::Buy, dec!(1), dec!(1)))
+            .is_none());
+    }
+
+    #[test]
+    fn post_only_websocket_orders_are_makers() {
+        let calculator = FeeCalculator::new(test_registry(), trade_volume());
+        let order = AddOrder::new(
+            OrderType::Limit,
+            ws::Side::Buy,
+            "XBT/USD".to_string(),
+            dec!(1),
+        )
+        .with_price(dec!(30000))
+        .with_order_flags("post,fcib".to_string());
+        let estimate = calculator
+            .estimate(&FeeOrder::from_add_order(&order, dec!(31000)))
+            .expect("no estimate");
+        assert_eq!(*estimate.liquidity(), Liquidity::Maker);
+        assert_eq!(estimate.currency().as_str(), "XXBT");
+        assert_eq!(*estimate.amount(), dec!(0.001));
+    }
+}
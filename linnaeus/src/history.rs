@@ -0,0 +1,249 @@
+use crate::api::market_data::{
+    ohlc, recent_trades, Interval, OHLCDataParams, RecentTradesParams, TickData,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use derive_setters::Setters;
+use linnaeus_request::error::RequestError;
+use linnaeus_request::RequestHelpers;
+use linnaeus_types as types;
+use linnaeus_types::aggregator::{BarRule, CandleAggregator, CandleEvent};
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Builds a continuous run of candles for a date range.
+///
+/// The OHLC endpoint only returns the most recent 720 candles of an interval. Anything older is
+/// rebuilt from the public trade tape, so long ranges of short intervals can take many requests.
+/// Candles rebuilt from trades are aligned to the unix epoch. Rate limit errors while paging the
+/// trade tape are retried after a pause.
+#[derive(Debug, Clone, Setters)]
+#[setters(prefix = "with_")]
+pub struct OhlcHistory {
+    #[setters(skip)]
+    pair: String,
+    #[setters(skip)]
+    interval: Interval,
+    #[setters(skip)]
+    start: DateTime<Utc>,
+    #[setters(skip)]
+    end: DateTime<Utc>,
+    /// Pause after kraken rejected a request for exceeding the rate limit
+    rate_limit_delay: Duration,
+}
+
+impl OhlcHistory {
+    pub fn new(pair: &str, interval: Interval, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        Self {
+            pair: pair.to_string(),
+            interval,
+            start,
+            end,
+            rate_limit_delay: Duration::from_secs(5),
+        }
+    }
+
+    fn step(&self) -> chrono::Duration {
+        linnaeus_types::Interval::from(self.interval.clone()).duration()
+    }
+
+    /// Every candle from the interval containing `start` up to `end`. Intervals without trades
+    /// are filled with flat candles at the previous close, or at the open of the first candle
+    /// for the intervals before it
+    pub async fn fetch(&self, client: &impl RequestHelpers) -> Result<Vec<TickData>, RequestError> {
+        let mut candles = self.fetch_ohlc(client).await?;
+        let earliest = candles.keys().next().copied().unwrap_or(self.end);
+        if self.start < earliest {
+            // the OHLC endpoint is authoritative wherever the two overlap
+            for (time, candle) in self.fetch_trade_candles(client, earliest).await? {
+                candles.entry(time).or_insert(candle);
+            }
+        }
+        Ok(fill_gaps(
+            candles,
+            bucket(self.start, self.step()),
+            self.step(),
+        ))
+    }
+
+    async fn fetch_ohlc(
+        &self,
+        client: &impl RequestHelpers,
+    ) -> Result<BTreeMap<DateTime<Utc>, TickData>, RequestError> {
+        let first = bucket(self.start, self.step());
+        let mut candles = BTreeMap::new();
+        let mut since = Some(first);
+        loop {
+            let params = OHLCDataParams::new(&self.pair, Some(self.interval.clone()), since);
+            let data = ohlc(client, &params).await?;
+            let mut added = 0;
+            for tick in data.tick_data().values().flatten() {
+                if *tick.time() >= first
+                    && *tick.time() < self.end
+                    && candles.insert(*tick.time(), tick.clone()).is_none()
+                {
+                    added += 1;
+                }
+            }
+            let last = Utc.timestamp_opt(*data.last(), 0).single();
+            debug!(
+                "fetched {} new candles for {} up to {:?}",
+                added, self.pair, last
+            );
+            match last {
+                Some(last) if added > 0 && last < self.end && Some(last) > since => {
+                    since = Some(last)
+                }
+                _ => return Ok(candles),
+            }
+        }
+    }
+
+    async fn fetch_trade_candles(
+        &self,
+        client: &impl RequestHelpers,
+        until: DateTime<Utc>,
+    ) -> Result<BTreeMap<DateTime<Utc>, TickData>, RequestError> {
+        let until = until.min(self.end);
+        let step = self.step();
+        let mut aggregator = CandleAggregator::new(BarRule::Time(step));
+        let mut candles = BTreeMap::new();
+        let mut cursor = bucket(self.start, step).timestamp_nanos_opt().unwrap_or(0) as u64;
+        'pages: loop {
+            let params = RecentTradesParams::new_with_cursor(&self.pair, cursor);
+            let trades = match recent_trades(client, &params).await {
+                Ok(trades) => trades,
+                Err(err) if err.is_rate_limit() => {
+                    warn!(
+                        "rate limited while rebuilding candles for {}. Retrying in {:?}",
+                        self.pair, self.rate_limit_delay
+                    );
+                    tokio::time::sleep(self.rate_limit_delay).await;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let mut received = 0;
+            for trade in trades.trade_data().values().flatten() {
+                if *trade.time() >= until {
+                    break 'pages;
+                }
+                received += 1;
+                for event in aggregator.push(types::Trade::from(trade)) {
+                    if let CandleEvent::Closed(candle) = event {
+                        candles.insert(*candle.time(), TickData::from(&candle));
+                    }
+                }
+            }
+            debug!("rebuilt candles for {} from {} trades", self.pair, received);
+            if received == 0 || *trades.last() <= cursor {
+                break;
+            }
+            cursor = *trades.last();
+        }
+        if let Some(candle) = aggregator.flush() {
+            candles.insert(*candle.time(), TickData::from(&candle));
+        }
+        Ok(candles)
+    }
+}
+
+/// Start of the interval containing `time`, aligned to the unix epoch
+fn bucket(time: DateTime<Utc>, step: chrono::Duration) -> DateTime<Utc> {
+    let step = step.num_seconds().max(1);
+    let seconds = time.timestamp();
+    Utc.timestamp_opt(seconds - seconds.rem_euclid(step), 0)
+        .single()
+        .unwrap_or(time)
+}
+
+/// Insert flat candles wherever an interval from `first` on is missing. Gaps are filled at the
+/// previous close, and the intervals before the first candle at its open
+fn fill_gaps(
+    candles: BTreeMap<DateTime<Utc>, TickData>,
+    first: DateTime<Utc>,
+    step: chrono::Duration,
+) -> Vec<TickData> {
+    let mut filled: Vec<TickData> = Vec::with_capacity(candles.len());
+    for (time, candle) in candles {
+        let (mut next, price) = match filled.last() {
+            Some(previous) => (*previous.time() + step, *previous.close()),
+            None => (first, *candle.open()),
+        };
+        while next < time {
+            filled.push(TickData::flat(next, price));
+            next += step;
+        }
+        filled.push(candle);
+    }
+    filled
+}
+
+#[cfg(test)]
+mod history_tests {
+    use super::*;
+    use crate::api::market_data::TradeData;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    fn time(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    fn trade(price: &str, volume: &str, seconds: f64) -> TradeData {
+        serde_json::from_value(json!([price, volume, seconds, "b", "l", ""]))
+            .expect("couldn't deserialize trade")
+    }
+
+    fn candle(seconds: i64, price: &str) -> TickData {
+        let mut aggregator = CandleAggregator::new(BarRule::Time(chrono::Duration::minutes(1)));
+        aggregator.push(&trade(price, "1", seconds as f64));
+        TickData::from(&aggregator.flush().expect("no candle"))
+    }
+
+    #[test]
+    fn trades_are_bucketed_into_candles() {
+        let step = chrono::Duration::minutes(5);
+        assert_eq!(bucket(time(1_000_123), step), time(999_900));
+
+        let mut aggregator = CandleAggregator::new(BarRule::Time(step));
+        for trade in [
+            trade("10", "1", 1_000_000.5),
+            trade("12", "1", 1_000_010.0),
+            trade("9", "2", 1_000_020.0),
+        ] {
+            aggregator.push(&trade);
+        }
+        let candle = TickData::from(&aggregator.flush().expect("no candle"));
+        assert_eq!(*candle.time(), time(999_900));
+        assert_eq!(*candle.open(), dec!(10));
+        assert_eq!(*candle.high(), dec!(12));
+        assert_eq!(*candle.low(), dec!(9));
+        assert_eq!(*candle.close(), dec!(9));
+        assert_eq!(*candle.volume(), dec!(4));
+        assert_eq!(*candle.volume_weighted_average_price(), dec!(10));
+        assert_eq!(*candle.count(), 3);
+    }
+
+    #[test]
+    fn gaps_are_filled_with_the_previous_close() {
+        let step = chrono::Duration::minutes(1);
+        let candles: BTreeMap<_, _> = [(60, "1"), (240, "2"), (300, "3")]
+            .into_iter()
+            .map(|(seconds, price)| (time(seconds), candle(seconds, price)))
+            .collect();
+        let filled = fill_gaps(candles.clone(), time(60), step);
+        let times: Vec<i64> = filled.iter().map(|c| c.time().timestamp()).collect();
+        assert_eq!(times, vec![60, 120, 180, 240, 300]);
+        assert_eq!(*filled[1].close(), dec!(1));
+        assert_eq!(*filled[2].volume(), dec!(0));
+        assert_eq!(*filled[3].close(), dec!(2));
+
+        let filled = fill_gaps(candles, time(0), step);
+        assert_eq!(filled.len(), 6);
+        assert_eq!(*filled[0].time(), time(0));
+        assert_eq!(*filled[0].open(), dec!(1));
+        assert_eq!(*filled[0].count(), 0);
+    }
+}
@@ -1,20 +1,60 @@
 pub mod api;
+pub mod backfill;
+pub mod backtest;
+pub mod execution;
+#[cfg(feature = "export")]
+pub mod export;
+pub mod fees;
+pub mod history;
+pub mod order_tracker;
+pub mod paper;
+pub mod pnl;
+pub mod portfolio;
+pub mod reconciliation;
+pub mod registry;
+pub mod report;
+pub mod strategy;
+pub mod submission;
 #[cfg(test)]
 mod test_helpers;
+pub mod trading;
 
 use std::sync::Arc;
 use display_json::{DebugAsJson, DisplayAsJsonPretty};
+use futures::future::BoxFuture;
 use linnaeus_request::KrakenKeyPair;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+pub use linnaeus_types as types;
 pub use linnaeus_ws as ws;
+use linnaeus_ws::auth::{AuthToken, TokenProvider};
+use linnaeus_ws::config::WebsocketConfig;
 use linnaeus_ws::error::LinnaeusWebsocketError;
+use linnaeus_ws::v2::LinnaeusWebsocketV2;
 use linnaeus_ws::LinnaeusWebsocket;
 
 static KEY_ROTATION_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
+pub const DEFAULT_WS_AUTH_URL: &str = "wss://ws-auth.kraken.com";
+
+fn default_ws_auth_url() -> String {
+    DEFAULT_WS_AUTH_URL.to_string()
+}
+
+fn default_ws_v2_url() -> String {
+    linnaeus_ws::v2::PUBLIC_URL.to_string()
+}
+
+fn default_ws_v2_auth_url() -> String {
+    linnaeus_ws::v2::AUTHENTICATED_URL.to_string()
+}
+
+fn default_ws_v2_level3_url() -> String {
+    linnaeus_ws::v2::LEVEL3_URL.to_string()
+}
+
 #[derive(DebugAsJson, DisplayAsJsonPretty, Serialize, Deserialize)]
 pub struct Linnaeus {
     #[serde(skip)]
@@ -22,8 +62,26 @@ pub struct Linnaeus {
     keys: Vec<KrakenKeyPair>,
     base_url: String,
     ws_url: String,
+    #[serde(default = "default_ws_auth_url")]
+    ws_auth_url: String,
+    #[serde(default = "default_ws_v2_url")]
+    ws_v2_url: String,
+    #[serde(default = "default_ws_v2_auth_url")]
+    ws_v2_auth_url: String,
+    #[serde(default = "default_ws_v2_level3_url")]
+    ws_v2_level3_url: String,
+    #[serde(skip)]
+    ws_config: WebsocketConfig,
+    #[serde(skip)]
+    ws_client: Option<Arc<LinnaeusWebsocket>>,
+    #[serde(skip)]
+    private_ws_client: Option<Arc<LinnaeusWebsocket>>,
     #[serde(skip)]
-    ws_client: Option<Arc<LinnaeusWebsocket>>
+    ws_v2_client: Option<Arc<LinnaeusWebsocketV2>>,
+    #[serde(skip)]
+    private_ws_v2_client: Option<Arc<LinnaeusWebsocketV2>>,
+    #[serde(skip)]
+    level3_ws_v2_client: Option<Arc<LinnaeusWebsocketV2>>,
 }
 
 impl Linnaeus {
@@ -34,14 +92,67 @@ impl Linnaeus {
             keys,
             base_url: String::from(base_url),
             ws_url: String::from(ws_url),
+            ws_auth_url: default_ws_auth_url(),
+            ws_v2_url: default_ws_v2_url(),
+            ws_v2_auth_url: default_ws_v2_auth_url(),
+            ws_v2_level3_url: default_ws_v2_level3_url(),
+            ws_config: Default::default(),
             ws_client: None,
+            private_ws_client: None,
+            ws_v2_client: None,
+            private_ws_v2_client: None,
+            level3_ws_v2_client: None,
+        }
+    }
+
+    pub fn with_ws_auth_url(mut self, ws_auth_url: &str) -> Self {
+        self.ws_auth_url = String::from(ws_auth_url);
+        self
+    }
+
+    /// Endpoints used by the v2 websocket clients
+    pub fn with_ws_v2_urls(mut self, ws_v2_url: &str, ws_v2_auth_url: &str) -> Self {
+        self.ws_v2_url = String::from(ws_v2_url);
+        self.ws_v2_auth_url = String::from(ws_v2_auth_url);
+        self
+    }
+
+    /// Endpoint used by the v2 websocket client for the level3 channel
+    pub fn with_ws_v2_level3_url(mut self, ws_v2_level3_url: &str) -> Self {
+        self.ws_v2_level3_url = String::from(ws_v2_level3_url);
+        self
+    }
+
+    /// Heartbeat, ping and timeout settings used for websocket connections created after this call
+    pub fn with_ws_config(mut self, ws_config: WebsocketConfig) -> Self {
+        self.ws_config = ws_config;
+        self
+    }
+
+    /// A copy of the client that can only be used for REST requests
+    fn rest_client(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            keys: self.keys.clone(),
+            base_url: self.base_url.clone(),
+            ws_url: self.ws_url.clone(),
+            ws_auth_url: self.ws_auth_url.clone(),
+            ws_v2_url: self.ws_v2_url.clone(),
+            ws_v2_auth_url: self.ws_v2_auth_url.clone(),
+            ws_v2_level3_url: self.ws_v2_level3_url.clone(),
+            ws_config: self.ws_config.clone(),
+            ws_client: None,
+            private_ws_client: None,
+            ws_v2_client: None,
+            private_ws_v2_client: None,
+            level3_ws_v2_client: None,
         }
     }
 
     pub async fn get_websocket_client(&mut self) -> Result<Arc<LinnaeusWebsocket>, LinnaeusWebsocketError> {
         match &self.ws_client {
             None => {
-                let client = LinnaeusWebsocket::new(&self.ws_url).await;
+                let client = LinnaeusWebsocket::new_with_config(&self.ws_url, self.ws_config.clone()).await;
                 match &client {
                     Ok(client) => self.ws_client = Some(client.clone()),
                     _ => {}
@@ -51,6 +162,100 @@ impl Linnaeus {
             Some(client) => Ok(client.clone())
         }
     }
+
+    /// Websocket connected to the authenticated endpoint. Tokens for private subscriptions and
+    /// order events are fetched and renewed automatically.
+    pub async fn get_private_websocket_client(
+        &mut self,
+    ) -> Result<Arc<LinnaeusWebsocket>, LinnaeusWebsocketError> {
+        match &self.private_ws_client {
+            None => {
+                let client = LinnaeusWebsocket::new_authenticated_with_config(
+                    &self.ws_auth_url,
+                    Arc::new(self.rest_client()),
+                    self.ws_config.clone(),
+                )
+                .await;
+                if let Ok(client) = &client {
+                    self.private_ws_client = Some(client.clone());
+                }
+                client
+            }
+            Some(client) => Ok(client.clone()),
+        }
+    }
+
+    /// Websocket using version 2 of the kraken websocket API
+    pub async fn get_websocket_v2_client(
+        &mut self,
+    ) -> Result<Arc<LinnaeusWebsocketV2>, LinnaeusWebsocketError> {
+        match &self.ws_v2_client {
+            None => {
+                let client =
+                    LinnaeusWebsocketV2::new_with_config(&self.ws_v2_url, self.ws_config.clone())
+                        .await;
+                if let Ok(client) = &client {
+                    self.ws_v2_client = Some(client.clone());
+                }
+                client
+            }
+            Some(client) => Ok(client.clone()),
+        }
+    }
+
+    /// Version 2 websocket connected to the authenticated endpoint
+    pub async fn get_private_websocket_v2_client(
+        &mut self,
+    ) -> Result<Arc<LinnaeusWebsocketV2>, LinnaeusWebsocketError> {
+        match &self.private_ws_v2_client {
+            None => {
+                let client = LinnaeusWebsocketV2::new_authenticated_with_config(
+                    &self.ws_v2_auth_url,
+                    Arc::new(self.rest_client()),
+                    self.ws_config.clone(),
+                )
+                .await;
+                if let Ok(client) = &client {
+                    self.private_ws_v2_client = Some(client.clone());
+                }
+                client
+            }
+            Some(client) => Ok(client.clone()),
+        }
+    }
+
+    /// Version 2 websocket connected to the level3 endpoint. Kraken only serves the level3
+    /// channel there, see [LinnaeusWebsocketV2::subscribe_level3]
+    pub async fn get_level3_websocket_v2_client(
+        &mut self,
+    ) -> Result<Arc<LinnaeusWebsocketV2>, LinnaeusWebsocketError> {
+        match &self.level3_ws_v2_client {
+            None => {
+                let client = LinnaeusWebsocketV2::new_authenticated_with_config(
+                    &self.ws_v2_level3_url,
+                    Arc::new(self.rest_client()),
+                    self.ws_config.clone(),
+                )
+                .await;
+                if let Ok(client) = &client {
+                    self.level3_ws_v2_client = Some(client.clone());
+                }
+                client
+            }
+            Some(client) => Ok(client.clone()),
+        }
+    }
+}
+
+impl TokenProvider for Linnaeus {
+    fn fetch_token(&self) -> BoxFuture<'_, Result<AuthToken, LinnaeusWebsocketError>> {
+        Box::pin(async move {
+            let token = api::authenticate_websocket(self)
+                .await
+                .map_err(|e| LinnaeusWebsocketError::Authentication(Box::new(e)))?;
+            Ok(token.into())
+        })
+    }
 }
 
 impl linnaeus_request::RequestClient for Linnaeus {
@@ -0,0 +1,690 @@
+use crate::api::user_data::{Order, OrderBase, OrderStatus, Side};
+use chrono::{DateTime, Utc};
+use derive_getters::Getters;
+use linnaeus_ws::messages::private_messages::{
+    self as ws, OpenOrderOrStatusChange, OpenOrders, OrderStatusChange, OwnTrades,
+};
+use log::{debug, warn};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+/// One trade that filled part of an order
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct Fill {
+    trade_id: String,
+    time: DateTime<Utc>,
+    volume: Decimal,
+    price: Decimal,
+    cost: Decimal,
+    fee: Decimal,
+}
+
+/// Everything known about an order, merged from REST and websocket updates. Orders without a user
+/// reference have kraken's default of 0 stored as None
+#[derive(Debug, Clone, Getters)]
+pub struct TrackedOrder {
+    id: String,
+    user_reference_id: Option<i64>,
+    pair: String,
+    side: Side,
+    status: OrderStatus,
+    opened_at: DateTime<Utc>,
+    volume: Decimal,
+    executed: Decimal,
+    cost: Decimal,
+    fee: Decimal,
+    /// Why the order was canceled or expired
+    reason: Option<String>,
+    fills: Vec<Fill>,
+    /// The entry order that spawned this conditional close order
+    parent_id: Option<String>,
+    /// Description of the conditional close kraken places once this order is filled
+    close_description: Option<String>,
+}
+
+impl TrackedOrder {
+    pub fn remaining(&self) -> Decimal {
+        (self.volume - self.executed).max(Decimal::ZERO)
+    }
+
+    /// None until part of the order has been filled
+    pub fn average_price(&self) -> Option<Decimal> {
+        if self.executed.is_zero() {
+            None
+        } else {
+            Some(self.cost / self.executed)
+        }
+    }
+
+    /// Take the executed volume reported by kraken if it's ahead of what is known. Returns true
+    /// if it was
+    fn update_executed(&mut self, executed: Decimal, cost: Decimal, fee: Decimal) -> bool {
+        if executed <= self.executed {
+            return false;
+        }
+        self.executed = executed;
+        self.cost = cost;
+        self.fee = fee;
+        true
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderEvent {
+    /// `from` is None the first time an order is seen
+    Transition {
+        id: String,
+        user_reference_id: Option<i64>,
+        from: Option<OrderStatus>,
+        to: OrderStatus,
+    },
+    /// More of the order was executed
+    Fill {
+        id: String,
+        user_reference_id: Option<i64>,
+        executed: Decimal,
+        average_price: Option<Decimal>,
+    },
+    /// Kraken placed the conditional close attached to the parent order
+    CloseLinked { parent_id: String, close_id: String },
+}
+
+/// A full description of an order from either api
+struct OrderSnapshot {
+    user_reference_id: Option<i64>,
+    pair: String,
+    side: Side,
+    status: OrderStatus,
+    opened_at: DateTime<Utc>,
+    volume: Decimal,
+    executed: Decimal,
+    cost: Decimal,
+    fee: Decimal,
+    reason: Option<String>,
+    parent_id: Option<String>,
+    close_description: Option<String>,
+}
+
+impl From<&OrderBase> for OrderSnapshot {
+    fn from(order: &OrderBase) -> Self {
+        Self {
+            user_reference_id: order.user_reference_id().filter(|id| *id != 0),
+            pair: order.description().pair().clone(),
+            side: order.description().side().clone(),
+            status: *order.status(),
+            opened_at: *order.open_time(),
+            volume: *order.volume(),
+            executed: *order.vol_executed(),
+            cost: *order.cost(),
+            fee: *order.fee(),
+            reason: None,
+            parent_id: order.referral_order_transaction_id().clone(),
+            close_description: order
+                .description()
+                .conditional_close_description()
+                .clone()
+                .filter(|close| !close.is_empty()),
+        }
+    }
+}
+
+impl From<&ws::OpenOrder> for OrderSnapshot {
+    fn from(order: &ws::OpenOrder) -> Self {
+        Self {
+            user_reference_id: Some(*order.user_reference_id()).filter(|id| *id != 0),
+            pair: order.description().pair().clone(),
+            side: side_from_ws(order.description().side()),
+            status: status_from_ws(order.status()),
+            opened_at: *order.open_time(),
+            volume: *order.volume(),
+            executed: *order.volume_executed(),
+            cost: *order.cost(),
+            fee: *order.fee(),
+            reason: order.cancel_reason().clone(),
+            parent_id: order.reference_id().clone(),
+            close_description: Some(order.description().close_description().clone())
+                .filter(|close| !close.is_empty()),
+        }
+    }
+}
+
+fn side_from_ws(side: &ws::Side) -> Side {
+    match side {
+        ws::Side::Buy => Side::Buy,
+        ws::Side::Sell => Side::Sell,
+    }
+}
+
+fn status_from_ws(status: &ws::OrderStatus) -> OrderStatus {
+    match status {
+        ws::OrderStatus::Pending => OrderStatus::Pending,
+        ws::OrderStatus::Open => OrderStatus::Open,
+        ws::OrderStatus::Closed => OrderStatus::Closed,
+        ws::OrderStatus::Canceled => OrderStatus::Canceled,
+        ws::OrderStatus::Expired => OrderStatus::Expired,
+    }
+}
+
+/// Merged view of every order, keyed by txid and user reference.
+///
+/// REST responses, websocket open order updates and own trades can be applied in any mix.
+/// Statuses only move forward (see [OrderStatus::can_transition_to]) so an outdated REST response
+/// can't reopen an order, and the executed volume never decreases. Own trades that arrive before
+/// their order are kept until the order is seen. Close orders that kraken places when an entry
+/// with a conditional close fills are linked to the entry through their referral txid. Every
+/// transition, fill and link is sent to subscribers
+#[derive(Debug)]
+pub struct OrderTracker {
+    orders: HashMap<String, TrackedOrder>,
+    by_user_reference: HashMap<i64, Vec<String>>,
+    /// Parent txid to the txid of the close order it spawned
+    closes: HashMap<String, String>,
+    pending_fills: HashMap<String, Vec<Fill>>,
+    events: broadcast::Sender<OrderEvent>,
+}
+
+impl Default for OrderTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderTracker {
+    pub fn new() -> Self {
+        Self {
+            orders: HashMap::new(),
+            by_user_reference: HashMap::new(),
+            closes: HashMap::new(),
+            pending_fills: HashMap::new(),
+            events: broadcast::channel(100).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<OrderEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&TrackedOrder> {
+        self.orders.get(id)
+    }
+
+    /// Orders tagged with a user reference, oldest first
+    pub fn by_user_reference(&self, user_reference_id: i64) -> Vec<&TrackedOrder> {
+        let mut orders: Vec<&TrackedOrder> = self
+            .by_user_reference
+            .get(&user_reference_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.orders.get(id))
+            .collect();
+        orders.sort_by_key(|order| order.opened_at);
+        orders
+    }
+
+    pub fn orders(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.orders.values()
+    }
+
+    /// Orders that are pending or open
+    pub fn open_orders(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.orders().filter(|order| !order.status.is_terminal())
+    }
+
+    /// The close order spawned by an entry order, once kraken has placed it
+    pub fn close_order(&self, parent_id: &str) -> Option<&TrackedOrder> {
+        self.closes.get(parent_id).and_then(|id| self.orders.get(id))
+    }
+
+    /// Orders with a conditional close that kraken hasn't placed yet
+    pub fn awaiting_close(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.orders().filter(|order| {
+            order.close_description.is_some() && !self.closes.contains_key(&order.id)
+        })
+    }
+
+    /// Orders from the open orders or closed orders endpoints
+    pub fn apply_rest_orders(&mut self, orders: &HashMap<String, OrderBase>) -> Vec<OrderEvent> {
+        let mut events = Vec::new();
+        for (id, order) in orders {
+            self.merge(id, order.into(), &mut events);
+        }
+        self.publish(events)
+    }
+
+    /// Orders from the query orders endpoint
+    pub fn apply_queried_orders(&mut self, orders: &HashMap<String, Order>) -> Vec<OrderEvent> {
+        let mut events = Vec::new();
+        for (id, order) in orders {
+            let snapshot = match order {
+                Order::Open(order) => order.into(),
+                Order::Closed(closed) => OrderSnapshot {
+                    reason: closed.reason().clone(),
+                    ..closed.order().into()
+                },
+            };
+            self.merge(id, snapshot, &mut events);
+        }
+        self.publish(events)
+    }
+
+    /// A message from the websocket open orders feed. Messages must be applied in the order they
+    /// were received
+    pub fn apply_open_orders(&mut self, orders: &OpenOrders) -> Vec<OrderEvent> {
+        let mut events = Vec::new();
+        for order in orders {
+            match order {
+                OpenOrderOrStatusChange::OpenOrder((id, order)) => {
+                    self.merge(id, order.into(), &mut events)
+                }
+                OpenOrderOrStatusChange::StatusChange((id, change)) => {
+                    self.apply_change(id, change, &mut events)
+                }
+            }
+        }
+        self.publish(events)
+    }
+
+    pub fn apply_own_trades(&mut self, trades: &OwnTrades) -> Vec<OrderEvent> {
+        let mut events = Vec::new();
+        for (trade_id, trade) in trades {
+            let fill = Fill {
+                trade_id: trade_id.clone(),
+                time: *trade.time(),
+                volume: *trade.volume(),
+                price: *trade.price(),
+                cost: *trade.cost(),
+                fee: *trade.fee(),
+            };
+            let order_id = trade.order_transaction_id();
+            if self.orders.contains_key(order_id) {
+                self.add_fills(order_id, vec![fill], &mut events);
+            } else {
+                debug!(
+                    "holding trade {} until order {} is seen",
+                    trade_id, order_id
+                );
+                let pending = self.pending_fills.entry(order_id.clone()).or_default();
+                if !pending.iter().any(|seen| seen.trade_id == fill.trade_id) {
+                    pending.push(fill);
+                }
+            }
+        }
+        self.publish(events)
+    }
+
+    fn merge(&mut self, id: &str, snapshot: OrderSnapshot, events: &mut Vec<OrderEvent>) {
+        let Some(order) = self.orders.get_mut(id) else {
+            self.insert(id, snapshot, events);
+            return;
+        };
+        order.volume = snapshot.volume;
+        if let (None, Some(user_reference_id)) =
+            (order.user_reference_id, snapshot.user_reference_id)
+        {
+            order.user_reference_id = Some(user_reference_id);
+            self.by_user_reference
+                .entry(user_reference_id)
+                .or_default()
+                .push(id.to_string());
+        }
+        if snapshot.reason.is_some() {
+            order.reason = snapshot.reason;
+        }
+        if order.close_description.is_none() {
+            order.close_description = snapshot.close_description;
+        }
+        let link = order.parent_id.is_none() && snapshot.parent_id.is_some();
+        if link {
+            order.parent_id = snapshot.parent_id;
+        }
+        if order.update_executed(snapshot.executed, snapshot.cost, snapshot.fee) {
+            events.push(fill_event(order));
+        }
+        transition(order, snapshot.status, events);
+        if link {
+            self.link_close(id, events);
+        }
+    }
+
+    fn insert(&mut self, id: &str, snapshot: OrderSnapshot, events: &mut Vec<OrderEvent>) {
+        let order = TrackedOrder {
+            id: id.to_string(),
+            user_reference_id: snapshot.user_reference_id,
+            pair: snapshot.pair,
+            side: snapshot.side,
+            status: snapshot.status,
+            opened_at: snapshot.opened_at,
+            volume: snapshot.volume,
+            executed: snapshot.executed,
+            cost: snapshot.cost,
+            fee: snapshot.fee,
+            reason: snapshot.reason,
+            fills: Vec::new(),
+            parent_id: snapshot.parent_id,
+            close_description: snapshot.close_description,
+        };
+        events.push(OrderEvent::Transition {
+            id: order.id.clone(),
+            user_reference_id: order.user_reference_id,
+            from: None,
+            to: order.status,
+        });
+        if let Some(user_reference_id) = order.user_reference_id {
+            self.by_user_reference
+                .entry(user_reference_id)
+                .or_default()
+                .push(id.to_string());
+        }
+        let link = order.parent_id.is_some();
+        self.orders.insert(id.to_string(), order);
+        if link {
+            self.link_close(id, events);
+        }
+        if let Some(fills) = self.pending_fills.remove(id) {
+            self.add_fills(id, fills, events);
+        }
+    }
+
+    fn link_close(&mut self, close_id: &str, events: &mut Vec<OrderEvent>) {
+        let Some(parent_id) = self.orders.get(close_id).and_then(|order| order.parent_id.clone())
+        else {
+            return;
+        };
+        if self.closes.contains_key(&parent_id) {
+            return;
+        }
+        debug!("order {} is the close of order {}", close_id, parent_id);
+        self.closes.insert(parent_id.clone(), close_id.to_string());
+        events.push(OrderEvent::CloseLinked {
+            parent_id,
+            close_id: close_id.to_string(),
+        });
+    }
+
+    fn apply_change(&mut self, id: &str, change: &OrderStatusChange, events: &mut Vec<OrderEvent>) {
+        let Some(order) = self.orders.get_mut(id) else {
+            debug!("ignoring update of unknown order {}", id);
+            return;
+        };
+        if change.reason().is_some() {
+            order.reason = change.reason().clone();
+        }
+        if let (Some(executed), Some(cost), Some(fee)) =
+            (change.volume_executed(), change.cost(), change.fee())
+        {
+            if order.update_executed(*executed, *cost, *fee) {
+                events.push(fill_event(order));
+            }
+        }
+        if let Some(status) = change.status() {
+            transition(order, status_from_ws(status), events);
+        }
+    }
+
+    /// Record fills that haven't been seen yet. Own trades can arrive before the open orders
+    /// update that reports them, in which case the executed volume is taken from the fills
+    fn add_fills(&mut self, id: &str, fills: Vec<Fill>, events: &mut Vec<OrderEvent>) {
+        let Some(order) = self.orders.get_mut(id) else {
+            return;
+        };
+        for fill in fills {
+            if !order
+                .fills
+                .iter()
+                .any(|seen| seen.trade_id == fill.trade_id)
+            {
+                order.fills.push(fill);
+            }
+        }
+        order.fills.sort_by_key(|fill| fill.time);
+        let sum = |field: fn(&Fill) -> Decimal| order.fills.iter().map(field).sum::<Decimal>();
+        let (executed, cost, fee) = (sum(|f| f.volume), sum(|f| f.cost), sum(|f| f.fee));
+        if order.update_executed(executed, cost, fee) {
+            events.push(fill_event(order));
+        }
+    }
+
+    fn publish(&self, events: Vec<OrderEvent>) -> Vec<OrderEvent> {
+        for event in &events {
+            // nobody may be listening
+            let _ = self.events.send(event.clone());
+        }
+        events
+    }
+}
+
+fn transition(order: &mut TrackedOrder, to: OrderStatus, events: &mut Vec<OrderEvent>) {
+    if order.status == to {
+        return;
+    }
+    if !order.status.can_transition_to(&to) {
+        warn!(
+            "ignoring transition of order {} from {} to {}",
+            order.id, order.status, to
+        );
+        return;
+    }
+    events.push(OrderEvent::Transition {
+        id: order.id.clone(),
+        user_reference_id: order.user_reference_id,
+        from: Some(order.status),
+        to,
+    });
+    order.status = to;
+}
+
+fn fill_event(order: &TrackedOrder) -> OrderEvent {
+    OrderEvent::Fill {
+        id: order.id.clone(),
+        user_reference_id: order.user_reference_id,
+        executed: order.executed,
+        average_price: order.average_price(),
+    }
+}
+
+/// A limit buy of `volume` XBT/USD at 20000 as the OpenOrders and ClosedOrders endpoints report it
+#[cfg(test)]
+pub(crate) fn test_rest_order(status: OrderStatus, volume: Decimal) -> OrderBase {
+    use crate::api::user_data::{OrderDescription, OrderFlags, OrderType};
+    use chrono::TimeZone;
+
+    let description = OrderDescription::new(
+        "XBTUSD".to_string(),
+        Side::Buy,
+        OrderType::Limit,
+        format!("buy {} XBTUSD @ limit 20000.0", volume),
+    )
+    .with_price(Decimal::from(20000));
+    OrderBase::new(
+        status,
+        Utc.timestamp_opt(1000, 0).unwrap(),
+        description,
+        volume,
+    )
+    .with_order_flags(vec![OrderFlags::Fciq])
+}
+
+/// The same order as the websocket open orders feed first reports it
+#[cfg(test)]
+pub(crate) fn test_websocket_order(status: ws::OrderStatus, volume: Decimal) -> ws::OpenOrder {
+    use chrono::TimeZone;
+
+    let description = ws::OrderDescription::new(
+        "XBT/USD".to_string(),
+        ws::Side::Buy,
+        ws::OrderType::Limit,
+        String::new(),
+    )
+    .with_price(Decimal::from(20000));
+    ws::OpenOrder::new(
+        status,
+        Utc.timestamp_opt(1000, 0).unwrap(),
+        description,
+        volume,
+    )
+    .with_oflags(Some("fciq".to_string()))
+}
+
+#[cfg(test)]
+mod order_tracker_tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use serde_json::{json, Value};
+
+    fn ws_order(id: &str, status: ws::OrderStatus) -> OpenOrderOrStatusChange {
+        let order = test_websocket_order(status, dec!(1)).with_user_reference_id(42);
+        OpenOrderOrStatusChange::OpenOrder((id.to_string(), order))
+    }
+
+    fn rest_order(status: OrderStatus) -> HashMap<String, OrderBase> {
+        let order = test_rest_order(status, dec!(1)).with_user_reference_id(Some(42));
+        HashMap::from([("O1".to_string(), order)])
+    }
+
+    fn own_trade(id: &str, volume: &str, cost: &str) -> OwnTrades {
+        serde_json::from_value(json!([[id, {
+            "ordertxid": "O1",
+            "postxid": "TKH2SE-M7IF5-CFI7LT",
+            "pair": "XBT/USD",
+            "time": "1001.000000",
+            "type": "buy",
+            "ordertype": "limit",
+            "price": "20000.00000",
+            "cost": cost,
+            "fee": "1.00000",
+            "vol": volume,
+            "margin": "0.00000"
+        }]]))
+        .expect("couldn't deserialize own trade")
+    }
+
+    fn open_orders(updates: Value) -> OpenOrders {
+        serde_json::from_value(updates).expect("couldn't deserialize open orders")
+    }
+
+    #[test]
+    fn lifecycle_is_merged_from_both_apis() {
+        let mut tracker = OrderTracker::new();
+        let mut events = tracker.subscribe();
+        tracker.apply_open_orders(&vec![ws_order("O1", ws::OrderStatus::Pending)]);
+        tracker.apply_open_orders(&open_orders(json!([["O1", {"status": "open"}]])));
+        // the trade arrives before the open orders update that reports it
+        tracker.apply_own_trades(&own_trade("T1", "0.4", "8000"));
+        tracker.apply_open_orders(&open_orders(json!([["O1", {
+            "vol_exec": "0.4", "cost": "8000", "fee": "1", "avg_price": "20000"
+        }]])));
+        let fill = tracker.apply_open_orders(&open_orders(json!([["O1", {
+            "vol_exec": "1.0", "cost": "20600", "fee": "2", "avg_price": "20600"
+        }]])));
+        assert_eq!(fill.len(), 1);
+        tracker.apply_open_orders(&open_orders(json!([["O1", {"status": "closed"}]])));
+
+        let order = tracker.get("O1").expect("order isn't tracked");
+        assert_eq!(*order.status(), OrderStatus::Closed);
+        assert_eq!(order.remaining(), dec!(0));
+        assert_eq!(order.average_price(), Some(dec!(20600)));
+        assert_eq!(order.fills().len(), 1);
+        assert_eq!(tracker.by_user_reference(42).len(), 1);
+        assert_eq!(tracker.open_orders().count(), 0);
+
+        let mut transitions = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let OrderEvent::Transition { from, to, .. } = event {
+                transitions.push((from, to));
+            }
+        }
+        assert_eq!(
+            transitions,
+            vec![
+                (None, OrderStatus::Pending),
+                (Some(OrderStatus::Pending), OrderStatus::Open),
+                (Some(OrderStatus::Open), OrderStatus::Closed),
+            ]
+        );
+    }
+
+    #[test]
+    fn user_reference_learned_later_is_indexed() {
+        let mut tracker = OrderTracker::new();
+        let order = test_websocket_order(ws::OrderStatus::Open, dec!(1));
+        tracker.apply_open_orders(&vec![OpenOrderOrStatusChange::OpenOrder((
+            "O1".to_string(),
+            order,
+        ))]);
+        assert!(tracker.by_user_reference(42).is_empty());
+
+        tracker.apply_rest_orders(&rest_order(OrderStatus::Open));
+        let orders = tracker.by_user_reference(42);
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].id(), "O1");
+    }
+
+    #[test]
+    fn outdated_responses_dont_move_orders_back() {
+        let mut tracker = OrderTracker::new();
+        let order = test_websocket_order(ws::OrderStatus::Open, dec!(1))
+            .with_user_reference_id(42)
+            .with_volume_executed(dec!(0.5));
+        tracker.apply_open_orders(&vec![OpenOrderOrStatusChange::OpenOrder((
+            "O1".to_string(),
+            order,
+        ))]);
+        tracker.apply_open_orders(&open_orders(
+            json!([["O1", {"status": "canceled", "reason": "User requested"}]]),
+        ));
+        let events = tracker.apply_rest_orders(&rest_order(OrderStatus::Open));
+        assert!(events.is_empty());
+
+        let order = tracker.get("O1").expect("order isn't tracked");
+        assert_eq!(*order.status(), OrderStatus::Canceled);
+        assert_eq!(*order.executed(), dec!(0.5));
+        assert_eq!(order.reason().as_deref(), Some("User requested"));
+    }
+
+    #[test]
+    fn close_orders_are_linked_to_their_entry() {
+        let mut tracker = OrderTracker::new();
+        let entry = test_websocket_order(ws::OrderStatus::Open, dec!(1));
+        let description = entry
+            .description()
+            .clone()
+            .with_close_description("close position @ take profit 22000.0".to_string());
+        tracker.apply_open_orders(&vec![OpenOrderOrStatusChange::OpenOrder((
+            "O1".to_string(),
+            entry.with_description(description),
+        ))]);
+        assert_eq!(tracker.awaiting_close().count(), 1);
+
+        tracker.apply_open_orders(&open_orders(json!([["O1", {
+            "status": "closed", "vol_exec": "1.0", "cost": "20000", "fee": "2"
+        }]])));
+        let close = test_websocket_order(ws::OrderStatus::Pending, dec!(1))
+            .with_reference_id(Some("O1".to_string()));
+        let events = tracker.apply_open_orders(&vec![OpenOrderOrStatusChange::OpenOrder((
+            "O2".to_string(),
+            close,
+        ))]);
+        assert!(events.contains(&OrderEvent::CloseLinked {
+            parent_id: "O1".to_string(),
+            close_id: "O2".to_string(),
+        }));
+
+        let close = tracker.close_order("O1").expect("close isn't linked");
+        assert_eq!(close.id(), "O2");
+        assert_eq!(close.parent_id().as_deref(), Some("O1"));
+        assert_eq!(tracker.awaiting_close().count(), 0);
+    }
+
+    #[test]
+    fn transitions_only_move_forward() {
+        use OrderStatus::*;
+        assert!(Pending.can_transition_to(&Open));
+        assert!(Pending.can_transition_to(&Canceled));
+        assert!(Open.can_transition_to(&Closed));
+        assert!(!Open.can_transition_to(&Pending));
+        assert!(!Closed.can_transition_to(&Open));
+        assert!(!Expired.can_transition_to(&Canceled));
+    }
+}
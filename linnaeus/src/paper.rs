@@ -0,0 +1,1262 @@
+use crate::api::user_data::{
+    AccountBalances, ClosedOrders, ClosedOrdersParams, Ledger, LedgerInfo, LedgerInfoParams,
+    LedgerType, OpenOrdersParams, OrderBase, OrderDescription, OrderFlags, OrderStatus, OrderType,
+    Side, Trade, TradeHistory, TradeHistoryParams,
+};
+use crate::api::user_trading::{
+    AddOrderDescription, AddOrderParams, AddOrderResponse, CancelOrderParams, CancelOrderResponse,
+    ConditionalClose, TimeInForce,
+};
+use crate::fees::{FeeCalculator, FeeOrder, Liquidity};
+use crate::trading::TradingClient;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use futures::future::BoxFuture;
+use linnaeus_request::error::{KrakenError, KrakenErrors, RequestError};
+use linnaeus_types::{AssetId, PairId};
+use linnaeus_ws::book::LocalBook;
+use linnaeus_ws::messages::private_messages::{
+    self as ws, OpenOrderOrStatusChange, OpenOrders, OrderStatusChange,
+};
+use linnaeus_ws::messages::public_messages::{PriceLevel, Trades};
+use log::warn;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use tokio::sync::broadcast;
+
+/// Kraken returns at most this many closed orders, trades or ledger entries per request
+const PAGE_SIZE: usize = 50;
+
+/// A simulated kraken account that fills orders against live market data.
+///
+/// Orders are validated, matched and reported the way kraken does it and the account is read
+/// back through the same [TradingClient] methods, so strategy code can't tell it apart from the
+/// real exchange. Feed it the local book and the public trades of every pair it trades:
+///
+/// - market orders walk the book. Whatever the visible book can't fill is canceled
+/// - limit orders take the book up to their price and the remainder rests. Resting orders are
+///   filled as makers, at their own price, by public trades that trade through it
+/// - stop loss and take profit orders trigger on the last trade price
+/// - pairs without a book take liquidity at the last trade price, in full
+///
+/// Fees are charged at the account's tier as estimated by the [FeeCalculator] and takers pay the
+/// configured [Slippage]. Margin trading and scheduled start times aren't simulated
+pub struct PaperExchange {
+    state: Mutex<PaperState>,
+    open_orders: broadcast::Sender<OpenOrders>,
+}
+
+impl PaperExchange {
+    /// An empty account. Fund it with [PaperExchange::deposit]
+    pub fn new(fees: FeeCalculator) -> Self {
+        Self {
+            state: Mutex::new(PaperState {
+                fees,
+                clock: None,
+                sequence: 0,
+                balances: HashMap::new(),
+                books: HashMap::new(),
+                last_prices: HashMap::new(),
+                orders: HashMap::new(),
+                trades: HashMap::new(),
+                ledgers: HashMap::new(),
+                slippage: Slippage::None,
+                updates: Vec::new(),
+            }),
+            open_orders: broadcast::channel(100).0,
+        }
+    }
+
+    pub fn with_slippage(mut self, slippage: Slippage) -> Self {
+        self.state
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .slippage = slippage;
+        self
+    }
+
+    /// Open order feed messages in the websocket format, to drive an
+    /// [crate::order_tracker::OrderTracker] or [crate::execution::ExecutionEngine] from the
+    /// simulated account
+    pub fn subscribe(&self) -> broadcast::Receiver<OpenOrders> {
+        self.open_orders.subscribe()
+    }
+
+    /// Credit the account with a deposit ledger entry. `asset` can be any name the registry knows
+    pub fn deposit(&self, asset: &str, amount: Decimal) -> Result<(), RequestError> {
+        self.update(|state| {
+            let asset = state
+                .fees
+                .registry()
+                .asset_id(asset)
+                .cloned()
+                .ok_or_else(|| rejected("EQuery:Unknown asset"))?;
+            let reference_id = state.next_id('Q');
+            state.book_ledger(
+                &reference_id,
+                LedgerType::Deposit,
+                &asset,
+                amount,
+                Decimal::ZERO,
+            );
+            Ok(())
+        })
+    }
+
+    /// Replace the book of its pair. Orders placed or triggered later take liquidity from it
+    pub fn apply_book(&self, book: &LocalBook) {
+        self.feed(|state| {
+            if let Some(pair) = state.fees.registry().pair_id(book.pair()).cloned() {
+                state.books.insert(pair, book.clone());
+            }
+            Ok(())
+        })
+    }
+
+    /// Public trades of `pair`, any name the registry knows. They move the last price, which
+    /// triggers stop loss and take profit orders, and fill the resting orders they trade through
+    pub fn apply_trades(&self, pair: &str, trades: &Trades) {
+        self.feed(|state| {
+            if let Some(pair) = state.fees.registry().pair_id(pair).cloned() {
+                for trade in trades {
+                    state.apply_trade(&pair, *trade.price(), *trade.volume())?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// A single public trade, see [PaperExchange::apply_trades]
+    pub fn apply_trade(&self, pair: &str, price: Decimal, volume: Decimal) {
+        self.feed(|state| {
+            if let Some(pair) = state.fees.registry().pair_id(pair).cloned() {
+                state.apply_trade(&pair, price, volume)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Run on a simulated clock from now on, to replay recorded market data. Orders that expire
+    /// by `time` are expired. Without it the system clock is used
+    pub fn set_time(&self, time: DateTime<Utc>) {
+        let mut state = self.state();
+        state.clock = Some(time);
+        let expired = state.expire();
+        self.publish(&mut state);
+        if let Err(err) = expired {
+            warn!("paper exchange couldn't expire orders -> {}", err);
+        }
+    }
+
+    pub(crate) fn balances(&self) -> HashMap<AssetId, Decimal> {
+        self.state().balances.clone()
+    }
+
+    /// Every trade, oldest first
+    pub(crate) fn fills(&self) -> Vec<(String, Trade)> {
+        let state = self.state();
+        let mut fills: Vec<_> = state
+            .trades
+            .iter()
+            .map(|(id, trade)| (id.clone(), trade.clone()))
+            .collect();
+        fills.sort_by(|a, b| a.1.time().cmp(b.1.time()).then_with(|| a.0.cmp(&b.0)));
+        fills
+    }
+
+    fn state(&self) -> MutexGuard<'_, PaperState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Expire orders, run `update` and publish the order updates it caused, also when it failed
+    fn update<T>(
+        &self,
+        update: impl FnOnce(&mut PaperState) -> Result<T, RequestError>,
+    ) -> Result<T, RequestError> {
+        let mut state = self.state();
+        let result = state.expire().and_then(|()| update(&mut state));
+        self.publish(&mut state);
+        result
+    }
+
+    /// An update driven by market data. Nobody waits for it so failures are only logged
+    fn feed(&self, update: impl FnOnce(&mut PaperState) -> Result<(), RequestError>) {
+        if let Err(err) = self.update(update) {
+            warn!("paper exchange couldn't apply market data -> {}", err);
+        }
+    }
+
+    fn publish(&self, state: &mut PaperState) {
+        if !state.updates.is_empty() {
+            // nobody listening is fine
+            let _ = self.open_orders.send(std::mem::take(&mut state.updates));
+        }
+    }
+}
+
+impl TradingClient for PaperExchange {
+    fn add_order<'a>(
+        &'a self,
+        params: &'a AddOrderParams,
+    ) -> BoxFuture<'a, Result<AddOrderResponse, RequestError>> {
+        Box::pin(async move { self.update(|state| state.add_order(params)) })
+    }
+
+    fn cancel_order<'a>(
+        &'a self,
+        params: &'a CancelOrderParams,
+    ) -> BoxFuture<'a, Result<CancelOrderResponse, RequestError>> {
+        Box::pin(async move { self.update(|state| state.cancel_order(params)) })
+    }
+
+    fn account_balances(&self) -> BoxFuture<'_, Result<AccountBalances, RequestError>> {
+        Box::pin(async move {
+            let state = self.state();
+            Ok(state
+                .balances
+                .iter()
+                .map(|(asset, balance)| (asset.to_string(), *balance))
+                .collect())
+        })
+    }
+
+    fn open_orders<'a>(
+        &'a self,
+        params: &'a OpenOrdersParams,
+    ) -> BoxFuture<'a, Result<HashMap<String, OrderBase>, RequestError>> {
+        Box::pin(async move {
+            let records = self.update(|state| Ok(state.records()))?;
+            Ok(records
+                .into_iter()
+                .filter(|(_, order, closed_at)| closed_at.is_none() && params.matches(order))
+                .map(|(id, order, _)| (id, order))
+                .collect())
+        })
+    }
+
+    fn closed_orders<'a>(
+        &'a self,
+        params: &'a ClosedOrdersParams,
+    ) -> BoxFuture<'a, Result<ClosedOrders, RequestError>> {
+        Box::pin(async move {
+            let closed = self
+                .update(|state| Ok(state.records()))?
+                .into_iter()
+                .filter_map(|(id, order, closed_at)| {
+                    let closed_at = closed_at?;
+                    params
+                        .matches(&order, closed_at)
+                        .then_some((id, closed_at, order))
+                })
+                .collect();
+            let (closed, count) = page(closed, params.page_offset());
+            Ok(ClosedOrders::new(closed, count))
+        })
+    }
+
+    fn trade_history<'a>(
+        &'a self,
+        params: &'a TradeHistoryParams,
+    ) -> BoxFuture<'a, Result<TradeHistory, RequestError>> {
+        Box::pin(async move {
+            let state = self.state();
+            let trades = state
+                .trades
+                .iter()
+                .filter(|(_, trade)| params.matches(trade))
+                .map(|(id, trade)| (id.clone(), *trade.time(), trade.clone()))
+                .collect();
+            let (trades, count) = page(trades, params.page_offset());
+            Ok(TradeHistory::new(trades, count))
+        })
+    }
+
+    fn ledgers<'a>(
+        &'a self,
+        params: &'a LedgerInfoParams,
+    ) -> BoxFuture<'a, Result<LedgerInfo, RequestError>> {
+        Box::pin(async move {
+            let state = self.state();
+            let ledgers = state
+                .ledgers
+                .iter()
+                .filter(|(_, ledger)| params.matches(ledger))
+                .map(|(id, ledger)| (id.clone(), *ledger.time(), ledger.clone()))
+                .collect();
+            let (ledgers, count) = page(ledgers, params.page_offset());
+            Ok(LedgerInfo::new(ledgers, count))
+        })
+    }
+}
+
+/// Newest first, skipping `offset` entries like kraken's paginated endpoints. Returns the page
+/// and the number of entries on every page
+fn page<T>(
+    mut entries: Vec<(String, DateTime<Utc>, T)>,
+    offset: usize,
+) -> (HashMap<String, T>, usize) {
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+    let count = entries.len();
+    let page = entries
+        .into_iter()
+        .skip(offset)
+        .take(PAGE_SIZE)
+        .map(|(id, _, entry)| (id, entry))
+        .collect();
+    (page, count)
+}
+
+/// Price takers pay on top of the price they fill at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Slippage {
+    #[default]
+    None,
+    /// A fraction of the price in basis points
+    BasisPoints(Decimal),
+    /// A fixed amount of the quote currency
+    Fixed(Decimal),
+}
+
+impl Slippage {
+    /// Buys pay more and sells receive less
+    fn apply(&self, side: &Side, price: Decimal) -> Decimal {
+        let slippage = match self {
+            Self::None => Decimal::ZERO,
+            Self::BasisPoints(points) => price * points / Decimal::from(10_000),
+            Self::Fixed(amount) => *amount,
+        };
+        match side {
+            Side::Buy => price + slippage,
+            Side::Sell => price - slippage,
+        }
+    }
+}
+
+/// The error kraken would have answered with
+fn rejected(error: &str) -> RequestError {
+    match KrakenError::try_from(error) {
+        Ok(error) => KrakenErrors {
+            errors: vec![error],
+        }
+        .into(),
+        Err(err) => err,
+    }
+}
+
+/// Either a unix timestamp or `+<n>` seconds from `now`
+fn parse_time(time: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    match time.strip_prefix('+') {
+        Some(seconds) => Some(now + Duration::seconds(seconds.parse().ok()?)),
+        None => Utc.timestamp_opt(time.parse().ok()?, 0).single(),
+    }
+}
+
+/// The price part of kraken's order descriptions, like `limit 20000.0`
+fn describe_price(
+    order_type: &OrderType,
+    price: Option<Decimal>,
+    price2: Option<Decimal>,
+) -> String {
+    let price = price.unwrap_or_default();
+    let price2 = price2.unwrap_or_default();
+    match order_type {
+        OrderType::Market => "market".to_string(),
+        OrderType::Limit => format!("limit {}", price),
+        OrderType::StopLoss => format!("stop loss {}", price),
+        OrderType::TakeProfit => format!("take profit {}", price),
+        OrderType::StopLossLimit => format!("stop loss {} -> limit {}", price, price2),
+        OrderType::TakeProfitLimit => format!("take profit {} -> limit {}", price, price2),
+        OrderType::SettlePosition => "settle position".to_string(),
+    }
+}
+
+fn describe_close(close: &ConditionalClose) -> String {
+    format!(
+        "close position @ {}",
+        describe_price(close.order_type(), Some(*close.price()), *close.price2())
+    )
+}
+
+#[derive(Debug, Clone)]
+struct PaperOrder {
+    params: AddOrderParams,
+    pair: PairId,
+    /// The entry order of a conditional close
+    parent_id: Option<String>,
+    status: OrderStatus,
+    open_time: DateTime<Utc>,
+    expire_time: Option<DateTime<Utc>>,
+    close_time: Option<DateTime<Utc>>,
+    reason: Option<String>,
+    /// Stop loss and take profit orders don't match until their trigger price is reached
+    triggered: bool,
+    executed: Decimal,
+    cost: Decimal,
+    fee: Decimal,
+    trades: Vec<String>,
+}
+
+impl PaperOrder {
+    fn remaining(&self) -> Decimal {
+        self.params.volume() - self.executed
+    }
+
+    fn is_open(&self) -> bool {
+        !self.status.is_terminal()
+    }
+
+    fn has_flag(&self, wanted: fn(&OrderFlags) -> bool) -> bool {
+        self.params
+            .order_flags()
+            .as_ref()
+            .is_some_and(|flags| flags.iter().any(wanted))
+    }
+
+    /// The worst price the order trades at, none for market orders
+    fn limit(&self) -> Option<Decimal> {
+        match self.params.order_type() {
+            OrderType::Limit => *self.params.price(),
+            OrderType::StopLossLimit | OrderType::TakeProfitLimit => *self.params.price2(),
+            _ => None,
+        }
+    }
+
+    fn trigger(&self) -> Option<Decimal> {
+        match self.params.order_type() {
+            OrderType::StopLoss
+            | OrderType::TakeProfit
+            | OrderType::StopLossLimit
+            | OrderType::TakeProfitLimit => *self.params.price(),
+            _ => None,
+        }
+    }
+
+    fn accepts(&self, price: Decimal) -> bool {
+        match (self.limit(), self.params.side()) {
+            (None, _) => true,
+            (Some(limit), Side::Buy) => price <= limit,
+            (Some(limit), Side::Sell) => price >= limit,
+        }
+    }
+
+    /// Stops trigger when the price moves against the position and take profits when it moves
+    /// in its favour
+    fn is_triggered_by(&self, price: Decimal) -> bool {
+        let Some(trigger) = self.trigger() else {
+            return true;
+        };
+        let stop = matches!(
+            self.params.order_type(),
+            OrderType::StopLoss | OrderType::StopLossLimit
+        );
+        match (stop, self.params.side()) {
+            (true, Side::Buy) | (false, Side::Sell) => price >= trigger,
+            (true, Side::Sell) | (false, Side::Buy) => price <= trigger,
+        }
+    }
+
+    fn average_price(&self) -> Decimal {
+        if self.executed.is_zero() {
+            Decimal::ZERO
+        } else {
+            self.cost / self.executed
+        }
+    }
+
+    fn description(&self, pair_name: &str) -> String {
+        format!(
+            "{} {} {} @ {}",
+            self.params.side().to_string().to_lowercase(),
+            self.params.volume(),
+            pair_name,
+            describe_price(
+                self.params.order_type(),
+                *self.params.price(),
+                *self.params.price2()
+            )
+        )
+    }
+
+    fn flags(&self) -> String {
+        self.params
+            .order_flags()
+            .iter()
+            .flatten()
+            .map(|flag| flag.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// The order as the OpenOrders and ClosedOrders endpoints return it
+    fn record(&self, pair_name: &str) -> OrderBase {
+        let description = OrderDescription::new(
+            pair_name.to_string(),
+            self.params.side().clone(),
+            self.params.order_type().clone(),
+            self.description(pair_name),
+        )
+        .with_price(self.params.price().unwrap_or_default())
+        .with_secondary_price(self.params.price2().unwrap_or_default())
+        .with_conditional_close_description(self.params.close().as_ref().map(describe_close));
+        OrderBase::new(
+            self.status,
+            self.open_time,
+            description,
+            *self.params.volume(),
+        )
+        .with_referral_order_transaction_id(self.parent_id.clone())
+        .with_user_reference_id(self.params.user_reference_id().map(i64::from))
+        .with_client_order_id(self.params.client_order_id().clone())
+        .with_expire_time(self.expire_time.unwrap_or(DateTime::UNIX_EPOCH))
+        .with_vol_executed(self.executed)
+        .with_cost(self.cost)
+        .with_fee(self.fee)
+        .with_price(self.average_price())
+        .with_order_flags(self.params.order_flags().clone().unwrap_or_default())
+        .with_trades(self.trades.clone())
+    }
+
+    /// The first message the websocket open orders feed sends for the order
+    fn websocket_order(&self, pair_name: &str) -> ws::OpenOrder {
+        let description = ws::OrderDescription::new(
+            pair_name.to_string(),
+            self.params.side().into(),
+            self.params.order_type().into(),
+            self.description(pair_name),
+        )
+        .with_price(self.params.price().unwrap_or_default())
+        .with_secondary_price(self.params.price2().unwrap_or_default())
+        .with_close_description(
+            self.params
+                .close()
+                .as_ref()
+                .map(describe_close)
+                .unwrap_or_default(),
+        );
+        ws::OpenOrder::new(
+            ws::OrderStatus::Pending,
+            self.open_time,
+            description,
+            *self.params.volume(),
+        )
+        .with_reference_id(self.parent_id.clone())
+        .with_user_reference_id(self.params.user_reference_id().map_or(0, i64::from))
+        .with_expire_time(self.expire_time.unwrap_or(DateTime::UNIX_EPOCH))
+        .with_oflags(Some(self.flags()))
+    }
+
+    fn websocket_change(&self) -> OrderStatusChange {
+        OrderStatusChange::new((&self.status).into())
+            .with_volume_executed(Some(self.executed))
+            .with_cost(Some(self.cost))
+            .with_fee(Some(self.fee))
+            .with_average_price(Some(self.average_price()))
+            .with_user_reference_id(self.params.user_reference_id().map(i64::from))
+            .with_reason(self.reason.clone())
+    }
+}
+
+struct PaperState {
+    fees: FeeCalculator,
+    clock: Option<DateTime<Utc>>,
+    /// Numbers the ids handed out so replays produce the same ids
+    sequence: u64,
+    balances: HashMap<AssetId, Decimal>,
+    books: HashMap<PairId, LocalBook>,
+    last_prices: HashMap<PairId, Decimal>,
+    orders: HashMap<String, PaperOrder>,
+    trades: HashMap<String, Trade>,
+    ledgers: HashMap<String, Ledger>,
+    slippage: Slippage,
+    /// Open order feed messages that haven't been published yet
+    updates: OpenOrders,
+}
+
+impl PaperState {
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.unwrap_or_else(Utc::now)
+    }
+
+    /// Kraken style ids, `O` for orders, `T` for trades, `L` for ledger entries
+    fn next_id(&mut self, prefix: char) -> String {
+        self.sequence += 1;
+        format!(
+            "{}{:05}-PAPER-{:06}",
+            prefix,
+            self.sequence / 1_000_000,
+            self.sequence % 1_000_000
+        )
+    }
+
+    fn pair_name(&self, pair: &PairId) -> String {
+        self.fees
+            .registry()
+            .pair(pair)
+            .map_or_else(|| pair.to_string(), |info| info.alt_name().clone())
+    }
+
+    fn websocket_name(&self, pair: &PairId) -> String {
+        self.fees
+            .registry()
+            .websocket_name(pair)
+            .map_or_else(|| self.pair_name(pair), str::to_string)
+    }
+
+    /// Open orders in the order they were placed, so replays fill them in the same order
+    fn open_ids(&self, pair: &PairId) -> Vec<String> {
+        let mut open: Vec<_> = self
+            .orders
+            .iter()
+            .filter(|(_, order)| order.is_open() && order.pair == *pair)
+            .map(|(id, order)| (order.open_time, id.clone()))
+            .collect();
+        open.sort();
+        open.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// Every order with the time it was closed at
+    fn records(&self) -> Vec<(String, OrderBase, Option<DateTime<Utc>>)> {
+        self.orders
+            .iter()
+            .map(|(id, order)| {
+                let record = order.record(&self.pair_name(&order.pair));
+                (id.clone(), record, order.close_time)
+            })
+            .collect()
+    }
+
+    /// What a buy reserves per unit: its limit or trigger price, else the best ask
+    fn reference_price(&self, order: &PaperOrder) -> Decimal {
+        order
+            .limit()
+            .or_else(|| order.trigger())
+            .or_else(|| Some(*self.books.get(&order.pair)?.best_ask()?.price()))
+            .or_else(|| self.last_prices.get(&order.pair).copied())
+            .unwrap_or_default()
+    }
+
+    /// The assets an order still has reserved: what it spends plus its fee, estimated as a taker
+    /// at the reference price. The fee can be charged in a different asset than the one spent
+    fn reservations(&self, order: &PaperOrder) -> Vec<(AssetId, Decimal)> {
+        let Some((base, quote)) = self.fees.registry().pair_assets(&order.pair) else {
+            return Vec::new();
+        };
+        let side = order.params.side().clone();
+        let price = self.reference_price(order);
+        let spent = match side {
+            Side::Buy => (quote.clone(), order.remaining() * price),
+            Side::Sell => (base.clone(), order.remaining()),
+        };
+        let fee_order = FeeOrder::new(order.pair.as_str(), side, order.remaining(), price)
+            .with_flags(order.params.order_flags().clone().unwrap_or_default());
+        match self.fees.estimate(&fee_order) {
+            Some(estimate) => vec![spent, (estimate.currency().clone(), *estimate.amount())],
+            None => vec![spent],
+        }
+    }
+
+    /// Balance that isn't reserved by open orders
+    fn available(&self, asset: &AssetId) -> Decimal {
+        let reserved: Decimal = self
+            .orders
+            .values()
+            .filter(|order| order.is_open())
+            .flat_map(|order| self.reservations(order))
+            .filter(|(reserved, _)| reserved == asset)
+            .map(|(_, amount)| amount)
+            .sum();
+        self.balances.get(asset).copied().unwrap_or_default() - reserved
+    }
+
+    /// True if the balances not reserved by open orders cover the order and its fee
+    fn is_funded(&self, order: &PaperOrder) -> bool {
+        let mut needed: HashMap<AssetId, Decimal> = HashMap::new();
+        for (asset, amount) in self.reservations(order) {
+            *needed.entry(asset).or_default() += amount;
+        }
+        needed
+            .iter()
+            .all(|(asset, amount)| *amount <= self.available(asset))
+    }
+
+    fn add_order(&mut self, params: &AddOrderParams) -> Result<AddOrderResponse, RequestError> {
+        let registry = self.fees.registry();
+        let pair = registry
+            .pair_id(params.pair())
+            .cloned()
+            .ok_or_else(|| rejected("EQuery:Unknown asset pair"))?;
+        let order_min = registry
+            .pair(&pair)
+            .map(|info| *info.order_min())
+            .unwrap_or_default();
+        if params.leverage().is_some() || params.reduce_only().unwrap_or_default() {
+            return Err(rejected("EOrder:Cannot open position"));
+        }
+        let needs_price = !matches!(params.order_type(), OrderType::Market);
+        let needs_price2 = matches!(
+            params.order_type(),
+            OrderType::StopLossLimit | OrderType::TakeProfitLimit
+        );
+        if matches!(params.order_type(), OrderType::SettlePosition)
+            || (needs_price && params.price().is_none())
+            || (needs_price2 && params.price2().is_none())
+            || (params.user_reference_id().is_some() && params.client_order_id().is_some())
+            || params
+                .order_flags()
+                .iter()
+                .flatten()
+                .any(|flag| matches!(flag, OrderFlags::Viqc))
+        {
+            return Err(rejected("EGeneral:Invalid arguments"));
+        }
+        if *params.volume() < order_min {
+            return Err(rejected("EOrder:Order minimum not met"));
+        }
+        let now = self.now();
+        let expire_time = match params.expire_time() {
+            Some(time) => {
+                Some(parse_time(time, now).ok_or_else(|| rejected("EGeneral:Invalid arguments"))?)
+            }
+            None => None,
+        };
+        let order = PaperOrder {
+            params: params.clone(),
+            pair,
+            parent_id: None,
+            status: OrderStatus::Pending,
+            open_time: now,
+            expire_time,
+            close_time: None,
+            reason: None,
+            triggered: false,
+            executed: Decimal::ZERO,
+            cost: Decimal::ZERO,
+            fee: Decimal::ZERO,
+            trades: Vec::new(),
+        };
+        let description = AddOrderDescription::new(
+            order.description(&self.pair_name(&order.pair)),
+            params.close().as_ref().map(describe_close),
+        );
+        if params.validate().unwrap_or_default() {
+            return Ok(AddOrderResponse::new(description, Vec::new()));
+        }
+        if !self.is_funded(&order) {
+            return Err(rejected("EOrder:Insufficient funds"));
+        }
+        let id = self.open(order)?;
+        Ok(AddOrderResponse::new(description, vec![id]))
+    }
+
+    /// A txid, a user reference or a client order id
+    fn cancel_order(
+        &mut self,
+        params: &CancelOrderParams,
+    ) -> Result<CancelOrderResponse, RequestError> {
+        let user_reference_id = params
+            .transaction_id()
+            .as_ref()
+            .and_then(|id| id.parse::<i32>().ok());
+        let ids: Vec<String> = self
+            .orders
+            .iter()
+            .filter(|(id, order)| {
+                order.is_open()
+                    && (params.transaction_id().as_ref() == Some(*id)
+                        || (user_reference_id.is_some()
+                            && *order.params.user_reference_id() == user_reference_id)
+                        || (params.client_order_id().is_some()
+                            && order.params.client_order_id() == params.client_order_id()))
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        if ids.is_empty() {
+            return Err(rejected("EOrder:Unknown order"));
+        }
+        for id in &ids {
+            self.close(id, OrderStatus::Canceled, Some("User requested"))?;
+        }
+        Ok(CancelOrderResponse::new(ids.len(), false))
+    }
+
+    /// Place an order, announce it on the open order feed and match it
+    fn open(&mut self, mut order: PaperOrder) -> Result<String, RequestError> {
+        let id = self.next_id('O');
+        let message = order.websocket_order(&self.websocket_name(&order.pair));
+        self.updates
+            .push(OpenOrderOrStatusChange::OpenOrder((id.clone(), message)));
+        order.status = OrderStatus::Open;
+        order.triggered = order.trigger().is_none();
+        self.updates.push(OpenOrderOrStatusChange::StatusChange((
+            id.clone(),
+            OrderStatusChange::new(ws::OrderStatus::Open),
+        )));
+        self.orders.insert(id.clone(), order);
+        self.process(&id)?;
+        Ok(id)
+    }
+
+    /// Match an order that just opened or was triggered against the book
+    fn process(&mut self, id: &str) -> Result<(), RequestError> {
+        let Some(order) = self.orders.get_mut(id) else {
+            return Ok(());
+        };
+        if !order.is_open() {
+            return Ok(());
+        }
+        if !order.triggered {
+            match self.last_prices.get(&order.pair) {
+                Some(price) if order.is_triggered_by(*price) => order.triggered = true,
+                _ => return Ok(()),
+            }
+        }
+        let post_only = order.has_flag(|flag| matches!(flag, OrderFlags::Post));
+        let immediate = *order.params.time_in_force() == Some(TimeInForce::ImmediateOrCancel);
+        let market = order.limit().is_none();
+        if post_only && self.take(id, false)? {
+            return self.close(id, OrderStatus::Canceled, Some("Post only order"));
+        }
+        self.take(id, true)?;
+        if market {
+            self.close(id, OrderStatus::Canceled, Some("Insufficient liquidity"))
+        } else if immediate {
+            self.close(id, OrderStatus::Canceled, Some("Immediate or cancel"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Fill the order against the book levels it accepts at their prices, or against the last
+    /// trade price if the pair has no book. Returns whether any liquidity was accepted. The book
+    /// isn't depleted, it's replaced by the next snapshot
+    fn take(&mut self, id: &str, fill: bool) -> Result<bool, RequestError> {
+        let Some(order) = self.orders.get(id) else {
+            return Ok(false);
+        };
+        let levels: Vec<(Decimal, Decimal)> =
+            match self.books.get(&order.pair).filter(|book| book.is_valid()) {
+                Some(book) => {
+                    let level = |level: &PriceLevel| (*level.price(), *level.volume());
+                    match order.params.side() {
+                        Side::Buy => book.asks().map(level).collect(),
+                        Side::Sell => book.bids().map(level).collect(),
+                    }
+                }
+                None => self
+                    .last_prices
+                    .get(&order.pair)
+                    .map(|price| (*price, order.remaining()))
+                    .into_iter()
+                    .collect(),
+            };
+        let mut remaining = order.remaining();
+        let mut fills = Vec::new();
+        for (price, volume) in levels {
+            if remaining.is_zero() || !order.accepts(price) {
+                break;
+            }
+            let volume = remaining.min(volume);
+            remaining -= volume;
+            // slippage never takes a limit order past its limit
+            let slipped = self.slippage.apply(order.params.side(), price);
+            let price = match (order.limit(), order.params.side()) {
+                (Some(limit), Side::Buy) => slipped.min(limit),
+                (Some(limit), Side::Sell) => slipped.max(limit),
+                (None, _) => slipped,
+            };
+            fills.push((volume, price));
+        }
+        let accepted = !fills.is_empty();
+        if fill {
+            for (volume, price) in fills {
+                self.fill(id, volume, price, Liquidity::Taker)?;
+            }
+        }
+        Ok(accepted)
+    }
+
+    fn apply_trade(
+        &mut self,
+        pair: &PairId,
+        price: Decimal,
+        volume: Decimal,
+    ) -> Result<(), RequestError> {
+        self.last_prices.insert(pair.clone(), price);
+        let mut left = volume;
+        for id in self.open_ids(pair) {
+            let Some(order) = self.orders.get(&id) else {
+                continue;
+            };
+            if !order.triggered {
+                self.process(&id)?;
+                continue;
+            }
+            let Some(limit) = order.limit() else {
+                continue;
+            };
+            // orders queued ahead at the same price are assumed to take trades at the limit
+            let through = match order.params.side() {
+                Side::Buy => price < limit,
+                Side::Sell => price > limit,
+            };
+            if through && !left.is_zero() {
+                let volume = left.min(order.remaining());
+                left -= volume;
+                self.fill(&id, volume, limit, Liquidity::Maker)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Book a fill: a trade, a ledger entry for both assets and the order's progress
+    fn fill(
+        &mut self,
+        id: &str,
+        volume: Decimal,
+        price: Decimal,
+        liquidity: Liquidity,
+    ) -> Result<(), RequestError> {
+        let order = self
+            .orders
+            .get(id)
+            .ok_or_else(|| rejected("EOrder:Unknown order"))?;
+        let Some((base, quote)) = self.fees.registry().pair_assets(&order.pair) else {
+            return Ok(());
+        };
+        let (base, quote) = (base.clone(), quote.clone());
+        let pair = order.pair.clone();
+        let side = order.params.side().clone();
+        let order_type = order.params.order_type().clone();
+        let cost = volume * price;
+        let fee_order = FeeOrder::new(order.pair.as_str(), side.clone(), volume, price)
+            .with_liquidity(liquidity)
+            .with_flags(order.params.order_flags().clone().unwrap_or_default());
+        let (fee_asset, fee) = self
+            .fees
+            .estimate(&fee_order)
+            .map_or((quote.clone(), Decimal::ZERO), |estimate| {
+                (estimate.currency().clone(), *estimate.amount())
+            });
+        let (base_amount, quote_amount) = match side {
+            Side::Buy => (volume, -cost),
+            Side::Sell => (-volume, cost),
+        };
+        let trade_id = self.next_id('T');
+        for (asset, amount) in [(&base, base_amount), (&quote, quote_amount)] {
+            let fee = if *asset == fee_asset {
+                fee
+            } else {
+                Decimal::ZERO
+            };
+            self.book_ledger(&trade_id, LedgerType::Trade, asset, amount, fee);
+        }
+        // kraken reports trade fees in the quote currency
+        let trade_fee = if fee_asset == quote { fee } else { fee * price };
+        let trade = Trade::new(
+            id.to_string(),
+            pair.to_string(),
+            self.now(),
+            side,
+            order_type,
+            price,
+            volume,
+        )
+        .with_fee(trade_fee);
+        self.trades.insert(trade_id.clone(), trade);
+
+        let order = self
+            .orders
+            .get_mut(id)
+            .ok_or_else(|| rejected("EOrder:Unknown order"))?;
+        order.executed += volume;
+        order.cost += cost;
+        order.fee += trade_fee;
+        order.trades.push(trade_id);
+        if order.remaining() > Decimal::ZERO {
+            let change = order.websocket_change();
+            self.updates.push(OpenOrderOrStatusChange::StatusChange((
+                id.to_string(),
+                change,
+            )));
+            Ok(())
+        } else {
+            self.close(id, OrderStatus::Closed, None)
+        }
+    }
+
+    fn book_ledger(
+        &mut self,
+        reference_id: &str,
+        ledger_type: LedgerType,
+        asset: &AssetId,
+        amount: Decimal,
+        fee: Decimal,
+    ) {
+        let time = self.now();
+        let balance = self.balances.entry(asset.clone()).or_default();
+        *balance += amount - fee;
+        let ledger = Ledger::new(
+            reference_id.to_string(),
+            time,
+            ledger_type,
+            asset.to_string(),
+            amount,
+            *balance,
+        )
+        .with_fee(fee);
+        let id = self.next_id('L');
+        self.ledgers.insert(id, ledger);
+    }
+
+    /// End an open order. Filled orders with a conditional close place it for the filled volume
+    /// and the entry's fee currency flags, unless the balances can't cover it and its fee
+    fn close(
+        &mut self,
+        id: &str,
+        status: OrderStatus,
+        reason: Option<&str>,
+    ) -> Result<(), RequestError> {
+        let now = self.now();
+        let Some(order) = self.orders.get_mut(id).filter(|order| order.is_open()) else {
+            return Ok(());
+        };
+        order.status = status;
+        order.close_time = Some(now);
+        order.reason = reason.map(str::to_string);
+        let change = order.websocket_change();
+        let close = order.params.close().filter(|_| !order.executed.is_zero());
+        let entry = order.clone();
+        self.updates.push(OpenOrderOrStatusChange::StatusChange((
+            id.to_string(),
+            change,
+        )));
+
+        if let Some(close) = close {
+            let side = match entry.params.side() {
+                Side::Buy => Side::Sell,
+                Side::Sell => Side::Buy,
+            };
+            let mut params = AddOrderParams::new(
+                close.order_type().clone(),
+                side,
+                entry.params.pair().clone(),
+                entry.executed,
+            )
+            .with_price(*close.price());
+            if let Some(price2) = close.price2() {
+                params = params.with_price2(*price2);
+            }
+            let fee_flags: Vec<OrderFlags> = entry
+                .params
+                .order_flags()
+                .iter()
+                .flatten()
+                .filter(|flag| matches!(flag, OrderFlags::Fcib | OrderFlags::Fciq))
+                .cloned()
+                .collect();
+            if !fee_flags.is_empty() {
+                params = params.with_order_flags(fee_flags);
+            }
+            let order = PaperOrder {
+                params,
+                parent_id: Some(id.to_string()),
+                status: OrderStatus::Pending,
+                open_time: now,
+                expire_time: None,
+                close_time: None,
+                reason: None,
+                triggered: false,
+                executed: Decimal::ZERO,
+                cost: Decimal::ZERO,
+                fee: Decimal::ZERO,
+                trades: Vec::new(),
+                ..entry
+            };
+            if self.is_funded(&order) {
+                self.open(order)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn expire(&mut self) -> Result<(), RequestError> {
+        let now = self.now();
+        let expired: Vec<String> = self
+            .orders
+            .iter()
+            .filter(|(_, order)| {
+                order.is_open() && order.expire_time.is_some_and(|expire| expire <= now)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            self.close(&id, OrderStatus::Expired, None)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod paper_tests {
+    use super::*;
+    use crate::api::user_data::TradeVolume;
+    use crate::registry::test_registry;
+    use linnaeus_ws::messages::general_messages::Depth;
+    use linnaeus_ws::messages::public_messages::Book;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    fn exchange() -> PaperExchange {
+        let volume: TradeVolume = serde_json::from_value(json!({
+            "currency": "ZUSD",
+            "volume": "0.0000",
+            "fees": {},
+            "fees_maker": {}
+        }))
+        .expect("couldn't deserialize trade volume");
+        let exchange = PaperExchange::new(FeeCalculator::new(test_registry(), volume));
+        exchange.set_time(Utc.timestamp_opt(1_700_000_000, 0).unwrap());
+        exchange.deposit("USD", dec!(100000)).expect("known asset");
+
+        let mut book = LocalBook::new("XBT/USD".to_string(), Depth::Ten);
+        let snapshot: Book = serde_json::from_value(json!({
+            "as": [["30000.0", "0.5", "1700000000.0"], ["30100.0", "1.0", "1700000000.0"]],
+            "bs": [["29900.0", "1.0", "1700000000.0"]]
+        }))
+        .expect("couldn't deserialize book");
+        book.apply(&snapshot);
+        exchange.apply_book(&book);
+        exchange
+    }
+
+    fn trade(price: &str, volume: &str) -> Trades {
+        serde_json::from_value(json!([[price, volume, "1700000001.000000", "s", "l", ""]]))
+            .expect("couldn't deserialize trades")
+    }
+
+    async fn balance(exchange: &PaperExchange, asset: &str) -> Decimal {
+        let balances = exchange.account_balances().await.expect("balances");
+        balances.get(asset).copied().unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn market_orders_walk_the_book() -> Result<(), RequestError> {
+        let exchange = exchange();
+        let params =
+            AddOrderParams::new(OrderType::Market, Side::Buy, "XBTUSD".to_string(), dec!(1));
+        let response = exchange.add_order(&params).await?;
+        assert_eq!(response.description().order(), "buy 1 XBTUSD @ market");
+        let id = &response.transaction_ids()[0];
+
+        let closed = exchange
+            .closed_orders(&ClosedOrdersParams::default())
+            .await?;
+        let order = &closed.closed()[id];
+        assert_eq!(*order.status(), OrderStatus::Closed);
+        assert_eq!(*order.price(), dec!(30050));
+        assert_eq!(order.trades().len(), 2);
+        assert_eq!(*order.fee(), dec!(78.13));
+        assert_eq!(balance(&exchange, "XXBT").await, dec!(1));
+        assert_eq!(balance(&exchange, "ZUSD").await, dec!(69871.87));
+
+        let ledgers = exchange.ledgers(&LedgerInfoParams::default()).await?;
+        assert_eq!(*ledgers.count(), 5);
+        let history = exchange
+            .trade_history(&TradeHistoryParams::default())
+            .await?;
+        assert_eq!(*history.count(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resting_orders_fill_as_makers_on_trades_through_their_price(
+    ) -> Result<(), RequestError> {
+        let exchange = exchange();
+        let mut feed = exchange.subscribe();
+        let params =
+            AddOrderParams::new(OrderType::Limit, Side::Buy, "XBT/USD".to_string(), dec!(1))
+                .with_price(dec!(29000))
+                .with_client_order_id("grid-1".to_string());
+        let id = exchange.add_order(&params).await?.transaction_ids()[0].clone();
+        let open = exchange.open_orders(&OpenOrdersParams::default()).await?;
+        assert_eq!(*open[&id].status(), OrderStatus::Open);
+
+        // reserved by the open order
+        let too_big =
+            AddOrderParams::new(OrderType::Limit, Side::Buy, "XBTUSD".to_string(), dec!(3))
+                .with_price(dec!(29000));
+        let err = exchange
+            .add_order(&too_big)
+            .await
+            .expect_err("not enough funds");
+        assert!(matches!(err, RequestError::Kraken(_)));
+
+        exchange.apply_trades("XBT/USD", &trade("29000.0", "5"));
+        exchange.apply_trades("XBT/USD", &trade("28990.0", "0.4"));
+        let open = exchange.open_orders(&OpenOrdersParams::default()).await?;
+        assert_eq!(*open[&id].vol_executed(), dec!(0.4));
+        exchange.apply_trades("XBT/USD", &trade("28950.0", "2"));
+
+        let closed = exchange
+            .closed_orders(&ClosedOrdersParams::default())
+            .await?;
+        let order = &closed.closed()[&id];
+        assert_eq!(*order.status(), OrderStatus::Closed);
+        assert_eq!(*order.price(), dec!(29000));
+        assert_eq!(*order.fee(), dec!(46.4));
+
+        let mut tracker = crate::order_tracker::OrderTracker::new();
+        while let Ok(message) = feed.try_recv() {
+            tracker.apply_open_orders(&message);
+        }
+        let tracked = tracker.get(&id).expect("tracked from the feed");
+        assert_eq!(*tracked.executed(), dec!(1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stops_trigger_and_close_orders_follow_their_entry() -> Result<(), RequestError> {
+        let unfunded = exchange();
+        let params = AddOrderParams::new(
+            OrderType::Market,
+            Side::Buy,
+            "XBTUSD".to_string(),
+            dec!(0.5),
+        )
+        .with_close(ConditionalClose::stop_loss(dec!(29500)));
+        // the close would sell the whole position and couldn't also pay its fee in XBT
+        unfunded.add_order(&params).await?;
+        assert!(unfunded
+            .open_orders(&OpenOrdersParams::default())
+            .await?
+            .is_empty());
+
+        let exchange = exchange();
+        let params = params.with_order_flags(vec![OrderFlags::Fciq]);
+        let id = exchange.add_order(&params).await?.transaction_ids()[0].clone();
+        let open = exchange.open_orders(&OpenOrdersParams::default()).await?;
+        let (close_id, close) = open.into_iter().next().expect("close order placed");
+        assert_eq!(
+            close.referral_order_transaction_id().as_deref(),
+            Some(id.as_str())
+        );
+        assert_eq!(*close.volume(), dec!(0.5));
+
+        exchange.apply_trades("XBT/USD", &trade("29600.0", "1"));
+        assert!(!exchange
+            .open_orders(&OpenOrdersParams::default())
+            .await?
+            .is_empty());
+        exchange.apply_trades("XBT/USD", &trade("29500.0", "1"));
+        let closed = exchange
+            .closed_orders(&ClosedOrdersParams::default())
+            .await?;
+        assert_eq!(*closed.closed()[&close_id].price(), dec!(29900));
+        assert_eq!(balance(&exchange, "ZUSD").await, dec!(99872.13));
+        assert_eq!(balance(&exchange, "XXBT").await, Decimal::ZERO);
+        Ok(())
+    }
+}
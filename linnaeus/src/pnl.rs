@@ -0,0 +1,694 @@
+use crate::api::market_data::{ticker_information, MultiTickerInformation, TickerInfoParams};
+use crate::api::user_data::{Ledger, LedgerType, Side, Trade};
+use crate::registry::AssetRegistry;
+use chrono::{DateTime, Utc};
+use derive_getters::Getters;
+use linnaeus_request::error::RequestError;
+use linnaeus_request::RequestHelpers;
+use linnaeus_types::{AssetId, PairId};
+use log::debug;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::OnceLock;
+use thiserror::Error;
+
+/// How sells are matched against the lots that were bought
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LotMethod {
+    /// Oldest lots are sold first
+    #[default]
+    Fifo,
+    /// Newest lots are sold first
+    Lifo,
+    /// Lots with the highest unit cost are sold first
+    Hifo,
+    /// Every buy is pooled into a single lot at the average cost
+    AverageCost,
+}
+
+#[derive(Debug, Error)]
+pub enum PnlError {
+    #[error("pair {pair} of trade {trade} isn't in the registry")]
+    UnknownPair { trade: String, pair: String },
+}
+
+/// A quantity of an asset bought in one trade. Lots pooled with [LotMethod::AverageCost] keep the
+/// id and time of the first trade
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct Lot {
+    trade_id: String,
+    acquired_at: DateTime<Utc>,
+    quantity: Decimal,
+    /// Cost of the remaining quantity including fees, in the quote currency
+    cost: Decimal,
+}
+
+impl Lot {
+    pub fn unit_cost(&self) -> Decimal {
+        if self.quantity.is_zero() {
+            Decimal::ZERO
+        } else {
+            self.cost / self.quantity
+        }
+    }
+}
+
+/// The part of a sell that was matched against one lot
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct Disposal {
+    trade_id: String,
+    asset: AssetId,
+    /// Currency of the proceeds and cost basis
+    currency: AssetId,
+    /// None for the part of a sell that isn't covered by the trade history, e.g. deposited funds.
+    /// Its cost basis is zero
+    acquired_at: Option<DateTime<Utc>>,
+    disposed_at: DateTime<Utc>,
+    quantity: Decimal,
+    /// Proceeds after fees
+    proceeds: Decimal,
+    cost_basis: Decimal,
+}
+
+impl Disposal {
+    pub fn gain(&self) -> Decimal {
+        self.proceeds - self.cost_basis
+    }
+}
+
+/// Holdings of an asset bought with one quote currency
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct Position {
+    asset: AssetId,
+    currency: AssetId,
+    lots: Vec<Lot>,
+    realized: Decimal,
+}
+
+impl Position {
+    fn new(asset: AssetId, currency: AssetId) -> Self {
+        Self {
+            asset,
+            currency,
+            lots: Vec::new(),
+            realized: Decimal::ZERO,
+        }
+    }
+
+    pub fn quantity(&self) -> Decimal {
+        self.lots.iter().map(|lot| lot.quantity).sum()
+    }
+
+    pub fn cost_basis(&self) -> Decimal {
+        self.lots.iter().map(|lot| lot.cost).sum()
+    }
+
+    fn acquire(&mut self, lot: Lot, method: LotMethod) {
+        match (method, self.lots.first_mut()) {
+            (LotMethod::AverageCost, Some(pool)) => {
+                pool.quantity += lot.quantity;
+                pool.cost += lot.cost;
+            }
+            _ => self.lots.push(lot),
+        }
+    }
+
+    /// Index of the next lot to sell from
+    fn next_lot(&self, method: LotMethod) -> Option<usize> {
+        let lots = self.lots.iter().enumerate();
+        let (index, _) = match method {
+            LotMethod::Fifo | LotMethod::AverageCost => lots.min_by_key(|(_, lot)| lot.acquired_at),
+            LotMethod::Lifo => lots.max_by_key(|(_, lot)| lot.acquired_at),
+            LotMethod::Hifo => lots.max_by_key(|(_, lot)| lot.unit_cost()),
+        }?;
+        Some(index)
+    }
+
+    fn dispose(
+        &mut self,
+        trade_id: &str,
+        disposed_at: DateTime<Utc>,
+        quantity: Decimal,
+        proceeds: Decimal,
+        method: LotMethod,
+    ) -> Vec<Disposal> {
+        // the lots each part was sold from and their cost
+        let mut parts = Vec::new();
+        let mut remaining = quantity;
+        while remaining > Decimal::ZERO {
+            let Some(index) = self.next_lot(method) else {
+                break;
+            };
+            let lot = &mut self.lots[index];
+            let sold = remaining.min(lot.quantity);
+            let cost = if sold == lot.quantity {
+                lot.cost
+            } else {
+                lot.cost * sold / lot.quantity
+            };
+            lot.quantity -= sold;
+            lot.cost -= cost;
+            remaining -= sold;
+            parts.push((Some(lot.acquired_at), sold, cost));
+            if lot.quantity.is_zero() {
+                self.lots.remove(index);
+            }
+        }
+        if remaining > Decimal::ZERO {
+            parts.push((None, remaining, Decimal::ZERO));
+        }
+
+        let mut proceeds_left = proceeds;
+        let last = parts.len().saturating_sub(1);
+        let disposals: Vec<Disposal> = parts
+            .into_iter()
+            .enumerate()
+            .map(|(index, (acquired_at, sold, cost_basis))| {
+                // the last part gets whatever is left so that rounding doesn't lose any proceeds
+                let part = if index == last {
+                    proceeds_left
+                } else {
+                    proceeds * sold / quantity
+                };
+                proceeds_left -= part;
+                Disposal {
+                    trade_id: trade_id.to_string(),
+                    asset: self.asset.clone(),
+                    currency: self.currency.clone(),
+                    acquired_at,
+                    disposed_at,
+                    quantity: sold,
+                    proceeds: part,
+                    cost_basis,
+                }
+            })
+            .collect();
+        self.realized += disposals.iter().map(Disposal::gain).sum::<Decimal>();
+        disposals
+    }
+}
+
+/// A position valued at the last traded price
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct Valuation {
+    asset: AssetId,
+    currency: AssetId,
+    quantity: Decimal,
+    cost_basis: Decimal,
+    realized: Decimal,
+    /// None if no ticker was given for the pair
+    price: Option<Decimal>,
+    market_value: Option<Decimal>,
+    unrealized: Option<Decimal>,
+}
+
+/// Base and quote asset of a pair
+type PairAssets = (AssetId, AssetId);
+
+/// A trade's place in the history
+type TradeKey = (DateTime<Utc>, String);
+
+/// Positions and disposals built from the trades up to `applied`
+#[derive(Debug, Clone, Default)]
+struct Replay {
+    positions: HashMap<PairAssets, Position>,
+    disposals: Vec<Disposal>,
+    applied: Option<TradeKey>,
+}
+
+impl Replay {
+    /// Apply the trades that come after the last one applied
+    fn extend(
+        &mut self,
+        trades: &BTreeMap<TradeKey, (Trade, PairAssets)>,
+        ledger_fees: &HashMap<String, (AssetId, Decimal)>,
+        method: LotMethod,
+    ) {
+        let start = match self.applied.clone() {
+            Some(applied) => Bound::Excluded(applied),
+            None => Bound::Unbounded,
+        };
+        for (key, (trade, assets)) in trades.range((start, Bound::Unbounded)) {
+            self.apply(&key.1, trade, assets.clone(), ledger_fees, method);
+            self.applied = Some(key.clone());
+        }
+    }
+
+    fn apply(
+        &mut self,
+        id: &str,
+        trade: &Trade,
+        (base, quote): PairAssets,
+        ledger_fees: &HashMap<String, (AssetId, Decimal)>,
+        method: LotMethod,
+    ) {
+        let base_fee = match ledger_fees.get(id) {
+            Some((asset, fee)) if *asset == base => Some(*fee),
+            _ => None,
+        };
+        let (volume, cost, fee) = (*trade.volume(), *trade.cost(), *trade.fee());
+        let position = self
+            .positions
+            .entry((base.clone(), quote.clone()))
+            .or_insert_with(|| Position::new(base, quote));
+        match trade.side() {
+            Side::Buy => {
+                let (quantity, cost) = match base_fee {
+                    Some(base_fee) => (volume - base_fee, cost),
+                    None => (volume, cost + fee),
+                };
+                let lot = Lot {
+                    trade_id: id.to_string(),
+                    acquired_at: *trade.time(),
+                    quantity,
+                    cost,
+                };
+                position.acquire(lot, method);
+            }
+            Side::Sell => {
+                let (quantity, proceeds) = match base_fee {
+                    Some(base_fee) => (volume + base_fee, cost),
+                    None => (volume, cost - fee),
+                };
+                let disposals = position.dispose(id, *trade.time(), quantity, proceeds, method);
+                self.disposals.extend(disposals);
+            }
+        }
+    }
+}
+
+/// Computes cost basis and realized and unrealized PnL from the trade history.
+///
+/// Positions are kept per asset and quote currency, so bitcoin bought with dollars and bitcoin
+/// bought with euros are separate positions. Fees are added to the cost of buys and taken from the
+/// proceeds of sells. The fee of a trade is always given in the quote currency, but kraken may have
+/// charged it in the base asset instead. That is only known from the ledger entries of the trade,
+/// without them fees are assumed to be charged in the quote currency. Margin trades are ignored.
+///
+/// Trades and ledgers can be added in any order and over several calls, e.g. one page at a time.
+/// Trades made after every trade already applied are applied in place. A trade that sorts before
+/// them, or the ledger fee of a trade that was applied, means the positions are rebuilt from the
+/// whole history the next time they're read, so pages added newest first are only replayed once.
+#[derive(Debug, Clone)]
+pub struct PnlEngine {
+    registry: AssetRegistry,
+    method: LotMethod,
+    /// Asset and amount of the fee of a trade charged in an asset other than the quote currency,
+    /// keyed by trade id
+    ledger_fees: HashMap<String, (AssetId, Decimal)>,
+    /// Every trade added so far with its base and quote asset
+    trades: BTreeMap<TradeKey, (Trade, PairAssets)>,
+    /// Unset until the positions are read after a change that needs the history replayed
+    replay: OnceLock<Replay>,
+}
+
+impl PnlEngine {
+    pub fn new(registry: AssetRegistry, method: LotMethod) -> Self {
+        Self {
+            registry,
+            method,
+            ledger_fees: HashMap::new(),
+            trades: BTreeMap::new(),
+            replay: OnceLock::new(),
+        }
+    }
+
+    pub fn method(&self) -> LotMethod {
+        self.method
+    }
+
+    /// Learn which asset and amount fees were charged in from the ledger entries of trades
+    pub fn add_ledgers<'a>(&mut self, ledgers: impl IntoIterator<Item = &'a Ledger>) {
+        let mut changed = HashSet::new();
+        for ledger in ledgers {
+            if !matches!(ledger.ledger_type(), LedgerType::Trade) || ledger.fee().is_zero() {
+                continue;
+            }
+            if let Some(asset) = self.registry.asset_id(ledger.asset()) {
+                let fee = (asset.clone(), *ledger.fee());
+                let id = ledger.reference_id();
+                if self.ledger_fees.get(id) != Some(&fee) {
+                    self.ledger_fees.insert(id.clone(), fee);
+                    changed.insert(id.as_str());
+                }
+            }
+        }
+        // every added trade has been applied if the positions are built
+        if self.replay.get().is_some()
+            && self
+                .trades
+                .keys()
+                .any(|(_, id)| changed.contains(id.as_str()))
+        {
+            self.replay = OnceLock::new();
+        }
+    }
+
+    /// Add trades keyed by their id, e.g. the trades of a
+    /// [TradeHistory](crate::api::user_data::TradeHistory) page. Trades that were already added
+    /// are replaced. Nothing is added if the pair of a trade is unknown
+    pub fn add_trades<'a>(
+        &mut self,
+        trades: impl IntoIterator<Item = (&'a String, &'a Trade)>,
+    ) -> Result<(), PnlError> {
+        let mut resolved = Vec::new();
+        for (id, trade) in trades {
+            if !trade.margin().is_zero() || trade.position_status().is_some() {
+                debug!("ignoring margin trade {}", id);
+                continue;
+            }
+            let assets = self
+                .registry
+                .pair_id(trade.pair())
+                .and_then(|pair| self.registry.pair_assets(pair))
+                .map(|(base, quote)| (base.clone(), quote.clone()))
+                .ok_or_else(|| PnlError::UnknownPair {
+                    trade: id.clone(),
+                    pair: trade.pair().clone(),
+                })?;
+            resolved.push(((*trade.time(), id.clone()), (trade.clone(), assets)));
+        }
+        let Some(earliest) = resolved.iter().map(|(key, _)| key.clone()).min() else {
+            return Ok(());
+        };
+        self.trades.extend(resolved);
+        let in_order = self
+            .replay
+            .get()
+            .and_then(|replay| replay.applied.as_ref())
+            .is_none_or(|applied| *applied < earliest);
+        match self.replay.get_mut() {
+            Some(replay) if in_order => replay.extend(&self.trades, &self.ledger_fees, self.method),
+            Some(_) => self.replay = OnceLock::new(),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// The positions built from every trade, replaying the history if needed
+    fn replayed(&self) -> &Replay {
+        self.replay.get_or_init(|| {
+            let mut replay = Replay::default();
+            replay.extend(&self.trades, &self.ledger_fees, self.method);
+            replay
+        })
+    }
+
+    pub fn position(&self, asset: &AssetId, currency: &AssetId) -> Option<&Position> {
+        self.replayed()
+            .positions
+            .get(&(asset.clone(), currency.clone()))
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = &Position> {
+        self.replayed().positions.values()
+    }
+
+    /// Every sell matched against the lots it sold, in the order they were made
+    pub fn disposals(&self) -> &[Disposal] {
+        &self.replayed().disposals
+    }
+
+    /// Realized PnL of every position in a currency
+    pub fn realized(&self, currency: &AssetId) -> Decimal {
+        self.positions()
+            .filter(|position| &position.currency == currency)
+            .map(|position| position.realized)
+            .sum()
+    }
+
+    /// Value every position with the last traded price of its pair
+    pub fn valuations(&self, tickers: &MultiTickerInformation) -> Vec<Valuation> {
+        let prices: HashMap<(&AssetId, &AssetId), Decimal> = tickers
+            .iter()
+            .filter_map(|(name, ticker)| {
+                let assets = self.registry.pair_assets(self.registry.pair_id(name)?)?;
+                Some((assets, *ticker.last_trade_closed().price()))
+            })
+            .collect();
+        self.positions()
+            .map(|position| {
+                let quantity = position.quantity();
+                let cost_basis = position.cost_basis();
+                let price = prices.get(&(&position.asset, &position.currency)).copied();
+                let market_value = price.map(|price| price * quantity);
+                Valuation {
+                    asset: position.asset.clone(),
+                    currency: position.currency.clone(),
+                    quantity,
+                    cost_basis,
+                    realized: position.realized,
+                    price,
+                    market_value,
+                    unrealized: market_value.map(|value| value - cost_basis),
+                }
+            })
+            .collect()
+    }
+
+    /// Fetch the tickers of every open position and value them
+    pub async fn fetch_valuations(
+        &self,
+        client: &impl RequestHelpers,
+    ) -> Result<Vec<Valuation>, RequestError> {
+        let pairs: Vec<&str> = self
+            .positions()
+            .filter(|position| !position.lots.is_empty())
+            .filter_map(|position| {
+                self.registry
+                    .pair_by_assets(&position.asset, &position.currency)
+            })
+            .map(PairId::as_str)
+            .collect();
+        let tickers = if pairs.is_empty() {
+            MultiTickerInformation::new()
+        } else {
+            ticker_information(client, &TickerInfoParams::new(pairs.join(","))).await?
+        };
+        Ok(self.valuations(&tickers))
+    }
+}
+
+#[cfg(test)]
+mod pnl_tests {
+    use super::*;
+    use crate::registry::test_registry;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    fn trade(time: i64, side: &str, price: Decimal, volume: Decimal, fee: Decimal) -> Trade {
+        trade_on("XXBTZUSD", time, side, price, volume, fee)
+    }
+
+    fn trade_on(
+        pair: &str,
+        time: i64,
+        side: &str,
+        price: Decimal,
+        volume: Decimal,
+        fee: Decimal,
+    ) -> Trade {
+        serde_json::from_value(json!({
+            "ordertxid": "OQCLML-BW3P3-BUCMWZ",
+            "pair": pair,
+            "time": time,
+            "type": side,
+            "ordertype": "limit",
+            "price": price.to_string(),
+            "cost": (price * volume).to_string(),
+            "fee": fee.to_string(),
+            "vol": volume.to_string(),
+            "margin": "0.00000"
+        }))
+        .expect("couldn't deserialize trade")
+    }
+
+    fn history() -> HashMap<String, Trade> {
+        HashMap::from([
+            (
+                "T1".to_string(),
+                trade(100, "buy", dec!(100), dec!(1), dec!(0)),
+            ),
+            (
+                "T2".to_string(),
+                trade(200, "buy", dec!(300), dec!(1), dec!(0)),
+            ),
+            (
+                "T3".to_string(),
+                trade(300, "buy", dec!(200), dec!(1), dec!(0)),
+            ),
+            (
+                "T4".to_string(),
+                trade(400, "sell", dec!(250), dec!(2), dec!(0)),
+            ),
+        ])
+    }
+
+    #[test]
+    fn sells_are_matched_by_the_lot_method() -> anyhow::Result<()> {
+        let btc = AssetId::new("XXBT");
+        let usd = AssetId::new("ZUSD");
+        for (method, realized, remaining_cost) in [
+            (LotMethod::Fifo, dec!(100), dec!(200)),
+            (LotMethod::Lifo, dec!(0), dec!(100)),
+            (LotMethod::Hifo, dec!(0), dec!(100)),
+            (LotMethod::AverageCost, dec!(100), dec!(200)),
+        ] {
+            let mut engine = PnlEngine::new(test_registry(), method);
+            engine.add_trades(&history())?;
+            assert_eq!(engine.realized(&usd), realized, "{:?}", method);
+            let position = engine.position(&btc, &usd).expect("no position");
+            assert_eq!(position.quantity(), dec!(1), "{:?}", method);
+            assert_eq!(position.cost_basis(), remaining_cost, "{:?}", method);
+        }
+
+        let mut engine = PnlEngine::new(test_registry(), LotMethod::Fifo);
+        engine.add_trades(&history())?;
+        let disposals = engine.disposals();
+        assert_eq!(disposals.len(), 2);
+        assert_eq!(
+            disposals[0].acquired_at().map(|time| time.timestamp()),
+            Some(100)
+        );
+        assert_eq!(*disposals[0].proceeds(), dec!(250));
+        assert_eq!(disposals[0].gain(), dec!(150));
+        assert_eq!(disposals[1].gain(), dec!(-50));
+        Ok(())
+    }
+
+    #[test]
+    fn fees_are_taken_from_the_currency_they_were_charged_in() -> anyhow::Result<()> {
+        let ledger: Ledger = serde_json::from_value(json!({
+            "refid": "T1",
+            "time": 100,
+            "type": "trade",
+            "subtype": "",
+            "aclass": "currency",
+            "asset": "XXBT",
+            "amount": "1.0000000000",
+            "fee": "0.0100000000",
+            "balance": "0.9900000000"
+        }))?;
+        let trades = HashMap::from([
+            (
+                "T1".to_string(),
+                trade(100, "buy", dec!(100), dec!(1), dec!(1)),
+            ),
+            (
+                "T2".to_string(),
+                trade(200, "sell", dec!(200), dec!(0.5), dec!(1)),
+            ),
+        ]);
+        let mut engine = PnlEngine::new(test_registry(), LotMethod::Fifo);
+        engine.add_trades(&trades)?;
+        engine.add_ledgers([&ledger]);
+
+        let disposal = &engine.disposals()[0];
+        assert_eq!(*disposal.proceeds(), dec!(99));
+        let position = engine
+            .position(&AssetId::new("XXBT"), &AssetId::new("ZUSD"))
+            .expect("no position");
+        assert_eq!(position.quantity(), dec!(0.49));
+        assert_eq!(*disposal.cost_basis() + position.cost_basis(), dec!(100));
+        Ok(())
+    }
+
+    #[test]
+    fn pages_added_newest_first_are_applied_in_order() -> anyhow::Result<()> {
+        let mut pages: Vec<HashMap<String, Trade>> = history()
+            .into_iter()
+            .map(|(id, trade)| HashMap::from([(id, trade)]))
+            .collect();
+        pages.sort_by_key(|page| page.values().map(|trade| *trade.time()).max());
+        let mut engine = PnlEngine::new(test_registry(), LotMethod::Fifo);
+        for page in pages.iter().rev() {
+            engine.add_trades(page)?;
+        }
+        engine.add_trades(&pages[0])?;
+        assert_eq!(engine.realized(&AssetId::new("ZUSD")), dec!(100));
+        assert_eq!(engine.disposals().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn later_trades_are_applied_in_place() -> anyhow::Result<()> {
+        let usd = AssetId::new("ZUSD");
+        let history = history();
+        let page = |ids: &[&str]| -> HashMap<String, Trade> {
+            ids.iter()
+                .map(|id| (id.to_string(), history[*id].clone()))
+                .collect()
+        };
+        let mut engine = PnlEngine::new(test_registry(), LotMethod::Fifo);
+        engine.add_trades(&page(&["T1", "T2"]))?;
+        assert!(engine.disposals().is_empty());
+        engine.add_trades(&page(&["T3", "T4"]))?;
+        assert!(engine.replay.get().is_some());
+        assert_eq!(engine.realized(&usd), dec!(100));
+
+        // an older trade is sold first, so the history is replayed when it's next read
+        let older = HashMap::from([(
+            "T0".to_string(),
+            trade(50, "buy", dec!(50), dec!(1), dec!(0)),
+        )]);
+        engine.add_trades(&older)?;
+        assert!(engine.replay.get().is_none());
+        assert_eq!(engine.realized(&usd), dec!(350));
+        Ok(())
+    }
+
+    #[test]
+    fn uncovered_sells_and_valuations() -> anyhow::Result<()> {
+        let trades = HashMap::from([
+            (
+                "T1".to_string(),
+                trade(100, "buy", dec!(100), dec!(1), dec!(0)),
+            ),
+            (
+                "T2".to_string(),
+                trade(200, "sell", dec!(150), dec!(1.5), dec!(0)),
+            ),
+            (
+                "T3".to_string(),
+                trade(300, "buy", dec!(120), dec!(2), dec!(0)),
+            ),
+        ]);
+        let mut engine = PnlEngine::new(test_registry(), LotMethod::Fifo);
+        engine.add_trades(&trades)?;
+        let uncovered = &engine.disposals()[1];
+        assert_eq!(*uncovered.acquired_at(), None);
+        assert_eq!(*uncovered.quantity(), dec!(0.5));
+        assert_eq!(uncovered.gain(), dec!(75));
+
+        let tickers: MultiTickerInformation = serde_json::from_value(json!({
+            "XXBTZUSD": {
+                "a": ["131.0", "1", "1.000"],
+                "b": ["129.0", "1", "1.000"],
+                "c": ["130.0", "0.1"],
+                "v": ["10", "20"],
+                "p": ["125", "126"],
+                "t": [5, 10],
+                "l": ["110", "110"],
+                "h": ["140", "140"],
+                "o": "120"
+            }
+        }))?;
+        let valuations = engine.valuations(&tickers);
+        assert_eq!(valuations.len(), 1);
+        assert_eq!(*valuations[0].market_value(), Some(dec!(260)));
+        assert_eq!(*valuations[0].unrealized(), Some(dec!(20)));
+        assert_eq!(*valuations[0].realized(), dec!(125));
+
+        let unknown = HashMap::from([(
+            "T4".to_string(),
+            trade_on("SOLUSD", 400, "buy", dec!(1), dec!(1), dec!(0)),
+        )]);
+        assert!(matches!(
+            engine.add_trades(&unknown),
+            Err(PnlError::UnknownPair { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,777 @@
+use crate::api::market_data::{ticker_information, TickerInfoParams};
+use crate::api::user_data::{
+    account_balances, open_orders, trade_balances, AccountBalances, OpenOrdersParams, OrderBase,
+    Side, TradeBalances, TradeBalancesParams,
+};
+use crate::registry::AssetRegistry;
+use crate::Linnaeus;
+use chrono::{DateTime, Utc};
+use derive_getters::Getters;
+use futures::channel::mpsc;
+use futures::stream::{select_all, BoxStream};
+use futures::{Stream, StreamExt};
+use linnaeus_request::error::RequestError;
+use linnaeus_request::RequestHelpers;
+use linnaeus_types::{AssetId, PairId};
+use linnaeus_ws::error::LinnaeusWebsocketError;
+use linnaeus_ws::messages::private_messages::{
+    self as ws, OpenOrderOrStatusChange, OpenOrders, OwnTrades,
+};
+use linnaeus_ws::messages::public_messages::Ticker;
+use linnaeus_ws::messages::Pair;
+use linnaeus_ws::subscription::SubscriptionOptions;
+use log::{debug, warn};
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Error)]
+pub enum PortfolioError {
+    #[error("couldn't load the portfolio -> {0}")]
+    Request(#[from] RequestError),
+    #[error("couldn't subscribe to portfolio updates -> {0}")]
+    Websocket(#[from] LinnaeusWebsocketError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortfolioChange {
+    /// The total or available balance of an asset changed
+    Balance {
+        asset: AssetId,
+        total: Decimal,
+        available: Decimal,
+    },
+    /// Price of an asset in the quote currency
+    Price { asset: AssetId, price: Decimal },
+    /// None while an asset that is held has no price
+    Equity(Option<Decimal>),
+}
+
+/// Funds locked by an open order. Buys lock the quote currency at the order price and sells lock
+/// the base currency
+#[derive(Debug, Clone, Getters)]
+pub struct Reservation {
+    pair: PairId,
+    side: Side,
+    price: Decimal,
+    volume: Decimal,
+    executed: Decimal,
+}
+
+impl Reservation {
+    fn remaining(&self) -> Decimal {
+        (self.volume - self.executed).max(Decimal::ZERO)
+    }
+}
+
+/// Balances, funds reserved by open orders and prices of an account.
+///
+/// Balances are updated from own trades, reservations from open order updates and prices from
+/// tickers of the pairs that trade each asset for the quote currency. Fees of own trades are
+/// assumed to be charged in the quote currency. Margin trades don't change balances
+#[derive(Debug, Clone)]
+pub struct Portfolio {
+    registry: AssetRegistry,
+    quote: AssetId,
+    balances: HashMap<AssetId, Decimal>,
+    orders: HashMap<String, Reservation>,
+    prices: HashMap<AssetId, Decimal>,
+    trade_balances: Option<TradeBalances>,
+    /// Own trades made before this were included in the loaded balances
+    since: DateTime<Utc>,
+    applied_trades: HashSet<String>,
+}
+
+impl Portfolio {
+    /// Own trades made at or before `since` are assumed to be included in `balances`
+    pub fn new(
+        registry: AssetRegistry,
+        quote: AssetId,
+        balances: AccountBalances,
+        open_orders: HashMap<String, OrderBase>,
+        since: DateTime<Utc>,
+    ) -> Self {
+        let mut portfolio = Self {
+            registry,
+            quote,
+            balances: HashMap::new(),
+            orders: HashMap::new(),
+            prices: HashMap::new(),
+            trade_balances: None,
+            since,
+            applied_trades: HashSet::new(),
+        };
+        for (name, balance) in balances {
+            let asset = portfolio.asset_id(&name);
+            portfolio.balances.insert(asset, balance);
+        }
+        for (id, order) in open_orders {
+            let description = order.description();
+            portfolio.reserve(
+                id,
+                description.pair(),
+                description.side().clone(),
+                *description.price(),
+                *order.volume(),
+                *order.vol_executed(),
+            );
+        }
+        portfolio
+    }
+
+    /// Fetch balances, open orders, trade balances and the prices of every asset that is held
+    pub async fn load(
+        client: &impl RequestHelpers,
+        registry: AssetRegistry,
+        quote: AssetId,
+    ) -> Result<Self, RequestError> {
+        let balances = account_balances(client).await?;
+        let since = Utc::now();
+        let orders = open_orders(client, &OpenOrdersParams::default()).await?;
+        let trade_balances = trade_balances(
+            client,
+            &TradeBalancesParams::new(quote.as_str().to_string()),
+        )
+        .await?;
+        let mut portfolio = Self::new(registry, quote, balances, orders, since);
+        portfolio.trade_balances = Some(trade_balances);
+
+        let pairs: Vec<&str> = portfolio
+            .priced_pairs()
+            .map(|(_, pair)| pair.as_str())
+            .collect();
+        if !pairs.is_empty() {
+            let tickers =
+                ticker_information(client, &TickerInfoParams::new(pairs.join(","))).await?;
+            for (name, ticker) in tickers {
+                portfolio.set_price(&name, *ticker.last_trade_closed().price());
+            }
+        }
+        Ok(portfolio)
+    }
+
+    pub fn quote(&self) -> &AssetId {
+        &self.quote
+    }
+
+    /// Margin balances as of [Portfolio::load]. They aren't kept up to date
+    pub fn trade_balances(&self) -> Option<&TradeBalances> {
+        self.trade_balances.as_ref()
+    }
+
+    pub fn balances(&self) -> impl Iterator<Item = (&AssetId, Decimal)> {
+        self.balances
+            .iter()
+            .map(|(asset, balance)| (asset, *balance))
+    }
+
+    pub fn balance(&self, asset: &AssetId) -> Decimal {
+        self.balances.get(asset).copied().unwrap_or_default()
+    }
+
+    /// Funds locked in open orders
+    pub fn reserved(&self, asset: &AssetId) -> Decimal {
+        self.orders
+            .values()
+            .filter_map(|order| self.reservation(order))
+            .filter(|(reserved, _)| reserved == asset)
+            .map(|(_, amount)| amount)
+            .sum()
+    }
+
+    /// Balance that isn't locked in open orders
+    pub fn available(&self, asset: &AssetId) -> Decimal {
+        self.balance(asset) - self.reserved(asset)
+    }
+
+    /// Open orders keyed by their id
+    pub fn orders(&self) -> &HashMap<String, Reservation> {
+        &self.orders
+    }
+
+    /// Price in the quote currency. The quote currency is always worth one
+    pub fn price(&self, asset: &AssetId) -> Option<Decimal> {
+        if *asset == self.quote {
+            return Some(Decimal::ONE);
+        }
+        self.prices.get(asset).copied()
+    }
+
+    /// Value of every balance in the quote currency. None if an asset that is held has no price
+    pub fn equity(&self) -> Option<Decimal> {
+        self.balances
+            .iter()
+            .filter(|(_, balance)| !balance.is_zero())
+            .map(|(asset, balance)| Some(self.price(asset)? * balance))
+            .sum()
+    }
+
+    /// Websocket names of the pairs that price every held asset
+    pub fn ticker_pairs(&self) -> Vec<Pair> {
+        self.priced_pairs()
+            .filter_map(|(_, pair)| self.registry.websocket_name(pair))
+            .map(String::from)
+            .collect()
+    }
+
+    pub fn apply_own_trades(&mut self, trades: &OwnTrades) -> Vec<PortfolioChange> {
+        let before = self.state();
+        for (id, trade) in trades {
+            if *trade.time() <= self.since || !self.applied_trades.insert(id.clone()) {
+                continue;
+            }
+            if let Some(order) = self.orders.get_mut(trade.order_transaction_id()) {
+                order.executed += *trade.volume();
+            }
+            if !trade.margin().is_zero() {
+                debug!("margin trade {} doesn't change balances", id);
+                continue;
+            }
+            let Some((base, quote)) = self.pair_assets(trade.pair()) else {
+                debug!("trade {} is on unknown pair {}", id, trade.pair());
+                continue;
+            };
+            let (volume, cost, fee) = (*trade.volume(), *trade.cost(), *trade.fee());
+            let (base_change, quote_change) = match trade.side() {
+                ws::Side::Buy => (volume, -cost - fee),
+                ws::Side::Sell => (-volume, cost - fee),
+            };
+            *self.balances.entry(base).or_default() += base_change;
+            *self.balances.entry(quote).or_default() += quote_change;
+        }
+        self.changes(before)
+    }
+
+    pub fn apply_open_orders(&mut self, orders: &OpenOrders) -> Vec<PortfolioChange> {
+        let before = self.state();
+        for order in orders {
+            match order {
+                OpenOrderOrStatusChange::OpenOrder((id, order)) if is_open(order.status()) => {
+                    let description = order.description();
+                    let side = match description.side() {
+                        ws::Side::Buy => Side::Buy,
+                        ws::Side::Sell => Side::Sell,
+                    };
+                    self.reserve(
+                        id.clone(),
+                        description.pair(),
+                        side,
+                        *description.price(),
+                        *order.volume(),
+                        *order.volume_executed(),
+                    );
+                }
+                OpenOrderOrStatusChange::OpenOrder((id, _)) => {
+                    self.orders.remove(id);
+                }
+                OpenOrderOrStatusChange::StatusChange((id, change)) => {
+                    if change
+                        .status()
+                        .as_ref()
+                        .is_some_and(|status| !is_open(status))
+                    {
+                        self.orders.remove(id);
+                    } else if let (Some(order), Some(executed)) =
+                        (self.orders.get_mut(id), change.volume_executed())
+                    {
+                        order.executed = order.executed.max(*executed);
+                    }
+                }
+            }
+        }
+        self.changes(before)
+    }
+
+    pub fn apply_ticker(&mut self, pair: &Pair, ticker: &Ticker) -> Vec<PortfolioChange> {
+        let before = self.state();
+        self.set_price(pair, *ticker.close().price());
+        self.changes(before)
+    }
+
+    fn asset_id(&self, name: &str) -> AssetId {
+        self.registry
+            .asset_id(name)
+            .cloned()
+            .unwrap_or_else(|| AssetId::new(name))
+    }
+
+    fn pair_assets(&self, pair: &str) -> Option<(AssetId, AssetId)> {
+        let (base, quote) = self.registry.pair_assets(self.registry.pair_id(pair)?)?;
+        Some((base.clone(), quote.clone()))
+    }
+
+    fn reserve(
+        &mut self,
+        id: String,
+        pair: &str,
+        side: Side,
+        price: Decimal,
+        volume: Decimal,
+        executed: Decimal,
+    ) {
+        let Some(pair) = self.registry.pair_id(pair).cloned() else {
+            debug!("order {} is on unknown pair {}", id, pair);
+            return;
+        };
+        self.orders.insert(
+            id,
+            Reservation {
+                pair,
+                side,
+                price,
+                volume,
+                executed,
+            },
+        );
+    }
+
+    /// The asset and amount an order locks
+    fn reservation(&self, order: &Reservation) -> Option<(AssetId, Decimal)> {
+        let (base, quote) = self.registry.pair_assets(&order.pair)?;
+        Some(match order.side {
+            Side::Buy => (quote.clone(), order.remaining() * order.price),
+            Side::Sell => (base.clone(), order.remaining()),
+        })
+    }
+
+    fn set_price(&mut self, pair: &str, price: Decimal) {
+        match self.pair_assets(pair) {
+            Some((base, quote)) if quote == self.quote => {
+                self.prices.insert(base, price);
+            }
+            _ => debug!("{} doesn't price an asset in {}", pair, self.quote),
+        }
+    }
+
+    /// Assets other than the quote currency that are held and the pair that prices them
+    fn priced_pairs(&self) -> impl Iterator<Item = (&AssetId, &PairId)> {
+        self.balances
+            .iter()
+            .filter(|(asset, balance)| !balance.is_zero() && **asset != self.quote)
+            .filter_map(|(asset, _)| {
+                let pair = self.registry.pair_by_assets(asset, &self.quote)?;
+                Some((asset, pair))
+            })
+    }
+
+    fn state(&self) -> PortfolioState {
+        let assets: HashSet<&AssetId> = self.balances.keys().chain(self.prices.keys()).collect();
+        PortfolioState {
+            assets: assets
+                .into_iter()
+                .map(|asset| {
+                    let state = (
+                        self.balance(asset),
+                        self.available(asset),
+                        self.price(asset),
+                    );
+                    (asset.clone(), state)
+                })
+                .collect(),
+            equity: self.equity(),
+        }
+    }
+
+    fn changes(&self, before: PortfolioState) -> Vec<PortfolioChange> {
+        let after = self.state();
+        let mut changes = Vec::new();
+        let mut assets: Vec<&AssetId> = after.assets.keys().collect();
+        assets.sort();
+        for asset in assets {
+            let (total, available, price) = after.assets[asset];
+            let (old_total, old_available, old_price) = before
+                .assets
+                .get(asset)
+                .copied()
+                .unwrap_or((Decimal::ZERO, Decimal::ZERO, None));
+            if (total, available) != (old_total, old_available) {
+                changes.push(PortfolioChange::Balance {
+                    asset: asset.clone(),
+                    total,
+                    available,
+                });
+            }
+            if let Some(price) = price.filter(|price| Some(*price) != old_price) {
+                changes.push(PortfolioChange::Price {
+                    asset: asset.clone(),
+                    price,
+                });
+            }
+        }
+        if after.equity != before.equity {
+            changes.push(PortfolioChange::Equity(after.equity));
+        }
+        changes
+    }
+
+    /// Keep the portfolio up to date from websocket subscriptions. The streams are consumed until
+    /// they end or the returned [LivePortfolio] is dropped
+    pub fn run<T, O, P>(self, own_trades: T, open_orders: O, tickers: P) -> LivePortfolio
+    where
+        T: Stream<Item = OwnTrades> + Send + 'static,
+        O: Stream<Item = OpenOrders> + Send + 'static,
+        P: Stream<Item = (Pair, Ticker)> + Send + 'static,
+    {
+        self.run_with_new_pairs(own_trades, open_orders, tickers, None)
+    }
+
+    /// Same as [Portfolio::run] but the pairs that have to be subscribed to price an asset that
+    /// wasn't held before are sent to `new_pairs`
+    fn run_with_new_pairs<T, O, P>(
+        self,
+        own_trades: T,
+        open_orders: O,
+        tickers: P,
+        new_pairs: Option<mpsc::UnboundedSender<Vec<Pair>>>,
+    ) -> LivePortfolio
+    where
+        T: Stream<Item = OwnTrades> + Send + 'static,
+        O: Stream<Item = OpenOrders> + Send + 'static,
+        P: Stream<Item = (Pair, Ticker)> + Send + 'static,
+    {
+        let mut subscribed: HashSet<Pair> = self.ticker_pairs().into_iter().collect();
+        let updates: Vec<BoxStream<'static, Update>> = vec![
+            own_trades.map(Update::OwnTrades).boxed(),
+            open_orders.map(Update::OpenOrders).boxed(),
+            tickers
+                .map(|(pair, ticker)| Update::Ticker(pair, Box::new(ticker)))
+                .boxed(),
+        ];
+        let (state, receiver) = watch::channel(self);
+        let (changes, _) = broadcast::channel(100);
+        let sender = changes.clone();
+        let task = tokio::spawn(async move {
+            let mut updates = select_all(updates);
+            while let Some(update) = updates.next().await {
+                let mut applied = Vec::new();
+                state.send_if_modified(|portfolio| {
+                    applied = match &update {
+                        Update::OwnTrades(trades) => portfolio.apply_own_trades(trades),
+                        Update::OpenOrders(orders) => portfolio.apply_open_orders(orders),
+                        Update::Ticker(pair, ticker) => portfolio.apply_ticker(pair, ticker),
+                    };
+                    !applied.is_empty()
+                });
+                let balance_changed = applied
+                    .iter()
+                    .any(|change| matches!(change, PortfolioChange::Balance { .. }));
+                for change in applied {
+                    // nobody may be listening
+                    let _ = sender.send(change);
+                }
+                if let (true, Some(new_pairs)) = (balance_changed, &new_pairs) {
+                    let pairs: Vec<Pair> = state
+                        .borrow()
+                        .ticker_pairs()
+                        .into_iter()
+                        .filter(|pair| subscribed.insert(pair.clone()))
+                        .collect();
+                    if !pairs.is_empty() {
+                        let _ = new_pairs.unbounded_send(pairs);
+                    }
+                }
+            }
+        });
+        LivePortfolio {
+            state: receiver,
+            changes,
+            task,
+        }
+    }
+}
+
+fn is_open(status: &ws::OrderStatus) -> bool {
+    matches!(status, ws::OrderStatus::Pending | ws::OrderStatus::Open)
+}
+
+/// Balance, available balance and price of each asset
+struct PortfolioState {
+    assets: HashMap<AssetId, (Decimal, Decimal, Option<Decimal>)>,
+    equity: Option<Decimal>,
+}
+
+enum Update {
+    OwnTrades(OwnTrades),
+    OpenOrders(OpenOrders),
+    Ticker(Pair, Box<Ticker>),
+}
+
+/// A [Portfolio] kept up to date by a background task
+#[derive(Debug)]
+pub struct LivePortfolio {
+    state: watch::Receiver<Portfolio>,
+    changes: broadcast::Sender<PortfolioChange>,
+    task: JoinHandle<()>,
+}
+
+impl LivePortfolio {
+    /// A copy of the current state
+    pub fn snapshot(&self) -> Portfolio {
+        self.state.borrow().clone()
+    }
+
+    /// Notified after every update that changed the portfolio
+    pub fn watch(&self) -> watch::Receiver<Portfolio> {
+        self.state.clone()
+    }
+
+    /// Every change to a balance, price or the equity
+    pub fn subscribe(&self) -> broadcast::Receiver<PortfolioChange> {
+        self.changes.subscribe()
+    }
+
+    /// False once every subscription has ended
+    pub fn is_running(&self) -> bool {
+        !self.task.is_finished()
+    }
+}
+
+impl Drop for LivePortfolio {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Linnaeus {
+    /// Load the portfolio and keep it up to date over the private and public websockets. Tickers
+    /// are subscribed for the assets held when the portfolio is loaded and for every asset bought
+    /// later on
+    pub async fn track_portfolio(
+        &mut self,
+        registry: AssetRegistry,
+        quote: AssetId,
+        options: SubscriptionOptions,
+    ) -> Result<LivePortfolio, PortfolioError> {
+        let portfolio = Portfolio::load(&self.rest_client(), registry, quote).await?;
+        let private = self.get_private_websocket_client().await?;
+        let own_trades = private.subscribe_own_trades(options.clone()).await?;
+        let open_orders = private.subscribe_open_orders(options.clone()).await?;
+        let public = self.get_websocket_client().await?;
+        let pairs = portfolio.ticker_pairs();
+        let tickers = if pairs.is_empty() {
+            futures::stream::empty().boxed()
+        } else {
+            public
+                .subscribe_ticker(&pairs, options.clone())
+                .await?
+                .boxed()
+        };
+
+        // tickers of pairs subscribed later on are forwarded into the same stream
+        let (pair_sender, mut pair_receiver) = mpsc::unbounded::<Vec<Pair>>();
+        let (ticker_sender, later_tickers) = mpsc::unbounded();
+        tokio::spawn(async move {
+            while let Some(pairs) = pair_receiver.next().await {
+                match public.subscribe_ticker(&pairs, options.clone()).await {
+                    Ok(stream) => {
+                        let sender = ticker_sender.clone();
+                        tokio::spawn(stream.map(Ok).forward(sender));
+                    }
+                    Err(err) => warn!(
+                        "couldn't subscribe to the tickers of {:?} -> {}",
+                        pairs, err
+                    ),
+                }
+            }
+        });
+        let tickers = futures::stream::select(tickers, later_tickers);
+        Ok(portfolio.run_with_new_pairs(own_trades, open_orders, tickers, Some(pair_sender)))
+    }
+}
+
+#[cfg(test)]
+mod portfolio_tests {
+    use super::*;
+    use crate::registry::test_registry;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    fn portfolio() -> Portfolio {
+        let balances = AccountBalances::from([
+            ("XXBT".to_string(), dec!(1)),
+            ("ZUSD".to_string(), dec!(10000)),
+        ]);
+        let since = DateTime::from_timestamp(1000, 0).expect("invalid time");
+        Portfolio::new(
+            test_registry(),
+            AssetId::new("ZUSD"),
+            balances,
+            HashMap::new(),
+            since,
+        )
+    }
+
+    fn open_order(id: &str, side: &str, price: &str, volume: &str) -> OpenOrders {
+        serde_json::from_value(json!([[id, {
+            "avg_price": "0.00000",
+            "cost": "0.00000",
+            "descr": {
+                "close": "",
+                "leverage": "0:1",
+                "order": "",
+                "ordertype": "limit",
+                "pair": "XBT/USD",
+                "price": price,
+                "price2": "0.00000",
+                "type": side
+            },
+            "expiretm": "0.000000",
+            "fee": "0.00000",
+            "limitprice": "0.00000",
+            "misc": "",
+            "oflags": "fciq",
+            "opentm": "1000.000000",
+            "refid": null,
+            "starttm": "0.000000",
+            "status": "open",
+            "stopprice": "0.000000",
+            "userref": 0,
+            "vol": volume,
+            "vol_exec": "0.00000000"
+        }]]))
+        .expect("couldn't deserialize open order")
+    }
+
+    fn own_trade(id: &str, order_id: &str, time: &str, volume: &str) -> OwnTrades {
+        own_trade_on("XBT/USD", id, order_id, time, volume)
+    }
+
+    fn own_trade_on(pair: &str, id: &str, order_id: &str, time: &str, volume: &str) -> OwnTrades {
+        serde_json::from_value(json!([[id, {
+            "ordertxid": order_id,
+            "postxid": "TKH2SE-M7IF5-CFI7LT",
+            "pair": pair,
+            "time": time,
+            "type": "buy",
+            "ordertype": "limit",
+            "price": "20000.00000",
+            "cost": "10000.00000",
+            "fee": "20.00000",
+            "vol": volume,
+            "margin": "0.00000"
+        }]]))
+        .expect("couldn't deserialize own trade")
+    }
+
+    fn ticker(price: &str) -> Ticker {
+        serde_json::from_value(json!({
+            "a": [price, 1, "1.000"],
+            "b": [price, 1, "1.000"],
+            "c": [price, "0.1"],
+            "v": ["10", "20"],
+            "p": [price, price],
+            "l": [price, price],
+            "h": [price, price],
+            "o": [price, price]
+        }))
+        .expect("couldn't deserialize ticker")
+    }
+
+    #[test]
+    fn open_orders_reserve_funds_until_they_close() {
+        let mut portfolio = portfolio();
+        let usd = AssetId::new("ZUSD");
+        let btc = AssetId::new("XXBT");
+        let changes = portfolio.apply_open_orders(&open_order("O1", "buy", "20000", "0.5"));
+        assert_eq!(
+            changes,
+            vec![PortfolioChange::Balance {
+                asset: usd.clone(),
+                total: dec!(10000),
+                available: dec!(0),
+            }]
+        );
+        portfolio.apply_open_orders(&open_order("O2", "sell", "30000", "0.25"));
+        assert_eq!(portfolio.reserved(&btc), dec!(0.25));
+
+        // trades from before the portfolio was loaded are already in the balances
+        assert!(portfolio
+            .apply_own_trades(&own_trade("T0", "O0", "999.000000", "0.5"))
+            .is_empty());
+        portfolio.apply_own_trades(&own_trade("T1", "O1", "1001.000000", "0.5"));
+        assert_eq!(portfolio.balance(&btc), dec!(1.5));
+        assert_eq!(portfolio.balance(&usd), dec!(-20));
+        assert_eq!(portfolio.reserved(&usd), dec!(0));
+        // the snapshot sent after a reconnect doesn't apply trades twice
+        assert!(portfolio
+            .apply_own_trades(&own_trade("T1", "O1", "1001.000000", "0.5"))
+            .is_empty());
+
+        let closed: OpenOrders = serde_json::from_value(json!([
+            ["O1", {"status": "closed"}],
+            ["O2", {"status": "canceled"}]
+        ]))
+        .expect("couldn't deserialize status change");
+        portfolio.apply_open_orders(&closed);
+        assert!(portfolio.orders().is_empty());
+        assert_eq!(portfolio.available(&btc), dec!(1.5));
+    }
+
+    #[test]
+    fn equity_is_valued_in_the_quote_currency() {
+        let mut portfolio = portfolio();
+        assert_eq!(portfolio.ticker_pairs(), vec!["XBT/USD".to_string()]);
+        assert_eq!(portfolio.equity(), None);
+        let changes = portfolio.apply_ticker(&"XBT/USD".to_string(), &ticker("20000"));
+        assert_eq!(
+            changes,
+            vec![
+                PortfolioChange::Price {
+                    asset: AssetId::new("XXBT"),
+                    price: dec!(20000),
+                },
+                PortfolioChange::Equity(Some(dec!(30000))),
+            ]
+        );
+        assert!(portfolio
+            .apply_ticker(&"XBT/USD".to_string(), &ticker("20000"))
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn live_portfolio_follows_the_streams() -> anyhow::Result<()> {
+        let (ticker_sender, tickers) = futures::channel::mpsc::unbounded();
+        let live = portfolio().run(
+            futures::stream::pending(),
+            futures::stream::pending(),
+            tickers,
+        );
+        let mut changes = live.subscribe();
+        ticker_sender.unbounded_send(("XBT/USD".to_string(), ticker("25000")))?;
+        assert_eq!(
+            changes.recv().await?,
+            PortfolioChange::Price {
+                asset: AssetId::new("XXBT"),
+                price: dec!(25000),
+            }
+        );
+        assert_eq!(
+            changes.recv().await?,
+            PortfolioChange::Equity(Some(dec!(35000)))
+        );
+        assert_eq!(live.snapshot().equity(), Some(dec!(35000)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn assets_bought_later_are_subscribed() -> anyhow::Result<()> {
+        let (trade_sender, own_trades) = futures::channel::mpsc::unbounded();
+        let (pair_sender, mut new_pairs) = mpsc::unbounded();
+        let live = portfolio().run_with_new_pairs(
+            own_trades,
+            futures::stream::pending(),
+            futures::stream::pending(),
+            Some(pair_sender),
+        );
+        trade_sender.unbounded_send(own_trade("T1", "O1", "1001.000000", "0.5"))?;
+        trade_sender.unbounded_send(own_trade_on("ETH/USD", "T2", "O2", "1002.000000", "2"))?;
+        assert_eq!(new_pairs.next().await, Some(vec!["ETH/USD".to_string()]));
+        drop(trade_sender);
+        drop(live);
+        assert_eq!(new_pairs.next().await, None);
+        Ok(())
+    }
+}
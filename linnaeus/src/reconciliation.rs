@@ -0,0 +1,226 @@
+use crate::api::user_data::{OpenOrdersParams, OrderBase, TradeHistory, TradeHistoryParams};
+use crate::trading::TradingClient;
+use crate::Linnaeus;
+use chrono::{DateTime, Utc};
+use linnaeus_request::error::RequestError;
+use linnaeus_ws::messages::Channel;
+use linnaeus_ws::sequence::{SequenceEvent, SequenceEventKind};
+use linnaeus_ws::LinnaeusWebsocket;
+use log::{info, warn};
+use std::collections::HashMap;
+use tokio::sync::{broadcast, mpsc};
+
+/// Margin applied to the start of the trade history request so that trades made just before the
+/// last message are included
+const TRADE_HISTORY_MARGIN: chrono::Duration = chrono::Duration::seconds(5);
+
+/// State fetched over REST to fill a hole in a private websocket feed
+#[derive(Debug, Clone)]
+pub enum Reconciliation {
+    /// Every order that is currently open
+    OpenOrders(HashMap<String, OrderBase>),
+    /// Trades made since the last ownTrades message that was received in sequence
+    Trades(TradeHistory),
+}
+
+/// Fetch whatever may have been missed by the sequence event. Duplicates don't lose anything so
+/// they return None.
+pub async fn reconcile(
+    client: &impl TradingClient,
+    event: &SequenceEvent,
+) -> Result<Option<Reconciliation>, RequestError> {
+    if let SequenceEventKind::Duplicate { .. } = event.kind() {
+        return Ok(None);
+    }
+    match event.channel() {
+        Channel::OpenOrders => {
+            let orders = client.open_orders(&OpenOrdersParams::default()).await?;
+            Ok(Some(Reconciliation::OpenOrders(orders)))
+        }
+        Channel::OwnTrades => {
+            let since = event
+                .last_received_at()
+                .map(|received_at| received_at - TRADE_HISTORY_MARGIN);
+            let trades = trades_since(client, since).await?;
+            Ok(Some(Reconciliation::Trades(trades)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Every trade made since `since`. Kraken returns the history a page at a time, newest first, so
+/// pages are fetched until all of the trades it counted have been seen
+async fn trades_since(
+    client: &impl TradingClient,
+    since: Option<DateTime<Utc>>,
+) -> Result<TradeHistory, RequestError> {
+    let mut trades = HashMap::new();
+    loop {
+        let params = TradeHistoryParams::default()
+            .start(since)
+            .offset(trades.len());
+        let page = client.trade_history(&params).await?;
+        let count = *page.count();
+        let before = trades.len();
+        trades.extend(page.trades().clone());
+        // a page of trades already seen means the history shifted under us and nothing is left
+        if trades.len() == before || trades.len() >= count {
+            return Ok(TradeHistory::new(trades, count));
+        }
+    }
+}
+
+impl Linnaeus {
+    /// Reconcile over REST every time a gap is detected on the private channels of the websocket.
+    /// The task stops when the returned receiver or the websocket is dropped.
+    pub fn reconcile_sequence_gaps(
+        &self,
+        websocket: &LinnaeusWebsocket,
+    ) -> mpsc::Receiver<(SequenceEvent, Result<Reconciliation, RequestError>)> {
+        let client = self.rest_client();
+        let mut events = websocket.sequence_events();
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("missed {} sequence events while reconciling", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                let result = match reconcile(&client, &event).await {
+                    Ok(Some(reconciliation)) => Ok(reconciliation),
+                    Ok(None) => continue,
+                    Err(err) => Err(err),
+                };
+                info!("reconciled {} after sequence gap", event.channel());
+                if sender.send((event, result)).await.is_err() {
+                    return;
+                }
+            }
+        });
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod reconciliation_tests {
+    use super::*;
+    use crate::api::user_data::{
+        AccountBalances, ClosedOrders, ClosedOrdersParams, LedgerInfo, LedgerInfoParams, Trade,
+    };
+    use crate::api::user_trading::{
+        AddOrderParams, AddOrderResponse, CancelOrderParams, CancelOrderResponse,
+    };
+    use futures::future::BoxFuture;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    const PAGE_SIZE: usize = 50;
+
+    /// Serves a trade history a page at a time, like kraken's TradesHistory endpoint
+    struct PagedHistory {
+        trades: Vec<(String, Trade)>,
+        pages_served: Mutex<usize>,
+    }
+
+    fn unsupported<'a, T: Send + 'a>() -> BoxFuture<'a, Result<T, RequestError>> {
+        Box::pin(async { Err(RequestError::Other("not supported".to_string())) })
+    }
+
+    impl TradingClient for PagedHistory {
+        fn add_order<'a>(
+            &'a self,
+            _params: &'a AddOrderParams,
+        ) -> BoxFuture<'a, Result<AddOrderResponse, RequestError>> {
+            unsupported()
+        }
+
+        fn cancel_order<'a>(
+            &'a self,
+            _params: &'a CancelOrderParams,
+        ) -> BoxFuture<'a, Result<CancelOrderResponse, RequestError>> {
+            unsupported()
+        }
+
+        fn account_balances(&self) -> BoxFuture<'_, Result<AccountBalances, RequestError>> {
+            unsupported()
+        }
+
+        fn open_orders<'a>(
+            &'a self,
+            _params: &'a OpenOrdersParams,
+        ) -> BoxFuture<'a, Result<HashMap<String, OrderBase>, RequestError>> {
+            unsupported()
+        }
+
+        fn closed_orders<'a>(
+            &'a self,
+            _params: &'a ClosedOrdersParams,
+        ) -> BoxFuture<'a, Result<ClosedOrders, RequestError>> {
+            unsupported()
+        }
+
+        /// The next page of the history, whatever offset is asked for
+        fn trade_history<'a>(
+            &'a self,
+            _params: &'a TradeHistoryParams,
+        ) -> BoxFuture<'a, Result<TradeHistory, RequestError>> {
+            Box::pin(async move {
+                let mut pages_served = self.pages_served.lock().expect("poisoned");
+                let page = self
+                    .trades
+                    .iter()
+                    .skip(*pages_served * PAGE_SIZE)
+                    .take(PAGE_SIZE)
+                    .cloned()
+                    .collect();
+                *pages_served += 1;
+                Ok(TradeHistory::new(page, self.trades.len()))
+            })
+        }
+
+        fn ledgers<'a>(
+            &'a self,
+            _params: &'a LedgerInfoParams,
+        ) -> BoxFuture<'a, Result<LedgerInfo, RequestError>> {
+            unsupported()
+        }
+    }
+
+    fn trade(order_id: &str) -> Trade {
+        serde_json::from_value(json!({
+            "ordertxid": order_id,
+            "pair": "XXBTZUSD",
+            "time": 1_700_000_000.0,
+            "type": "buy",
+            "ordertype": "market",
+            "price": "30000.0",
+            "cost": "300.0",
+            "fee": "0.78",
+            "vol": "0.01",
+            "margin": "0.0",
+            "misc": ""
+        }))
+        .expect("couldn't deserialize trade")
+    }
+
+    #[tokio::test]
+    async fn every_missed_fill_is_reported() -> Result<(), RequestError> {
+        // more fills than fit in one page of trade history
+        let client = PagedHistory {
+            trades: (0..70)
+                .map(|index| (format!("T{}", index), trade(&format!("O{}", index))))
+                .collect(),
+            pages_served: Mutex::new(0),
+        };
+        let trades = trades_since(&client, None).await?;
+        assert_eq!(*client.pages_served.lock().expect("poisoned"), 2);
+        assert_eq!(*trades.count(), 70);
+        assert_eq!(trades.trades().len(), 70);
+        Ok(())
+    }
+}
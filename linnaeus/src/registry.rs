@@ -0,0 +1,242 @@
+use crate::api::market_data::{
+    all_asset_info, all_tradable_asset_pairs, Asset, AssetInfo, TradingAssetPair, TradingAssetPairs,
+};
+use linnaeus_request::error::RequestError;
+use linnaeus_request::RequestHelpers;
+use linnaeus_types::{AssetId, Pair, PairId};
+use std::collections::HashMap;
+
+/// Names the v2 websocket api uses for assets whose REST altname is a legacy code
+const ASSET_ALIASES: [(&str, &str); 2] = [("BTC", "XBT"), ("DOGE", "XDG")];
+
+/// Darkpool pairs (suffixed `.d`) share their websocket name and assets with the regular pair.
+/// The regular pair wins whatever order kraken lists them in
+fn insert_pair<K: Eq + std::hash::Hash>(names: &mut HashMap<K, PairId>, name: K, id: &PairId) {
+    names
+        .entry(name)
+        .and_modify(|existing| {
+            if existing.as_str().ends_with(".d") {
+                *existing = id.clone();
+            }
+        })
+        .or_insert_with(|| id.clone());
+}
+
+/// Every asset and pair kraken lists, indexed by all of the names kraken uses for them.
+///
+/// Assets can be looked up by their REST key (`XXBT`) or altname (`XBT`) and pairs by their REST
+/// key (`XXBTZUSD`), altname (`XBTUSD`) or websocket name (`XBT/USD`). Pairs sent by the v2
+/// websocket api (`BTC/USD`) are resolved through their assets.
+#[derive(Debug, Clone)]
+pub struct AssetRegistry {
+    assets: HashMap<AssetId, Asset>,
+    pairs: HashMap<PairId, TradingAssetPair>,
+    asset_names: HashMap<String, AssetId>,
+    pair_names: HashMap<String, PairId>,
+    pairs_by_assets: HashMap<(AssetId, AssetId), PairId>,
+}
+
+impl AssetRegistry {
+    pub fn new(assets: AssetInfo, pairs: TradingAssetPairs) -> Self {
+        let mut asset_names = HashMap::new();
+        let assets: HashMap<AssetId, Asset> = assets
+            .into_iter()
+            .map(|(key, asset)| {
+                let id = AssetId::new(&key);
+                asset_names.insert(key.to_ascii_uppercase(), id.clone());
+                asset_names
+                    .entry(asset.alt_name().to_ascii_uppercase())
+                    .or_insert_with(|| id.clone());
+                (id, asset)
+            })
+            .collect();
+        for (alias, alt_name) in ASSET_ALIASES {
+            if let Some(id) = asset_names.get(alt_name).cloned() {
+                asset_names.entry(alias.to_string()).or_insert(id);
+            }
+        }
+
+        let mut pair_names = HashMap::new();
+        let mut pairs_by_assets = HashMap::new();
+        let pairs: HashMap<PairId, TradingAssetPair> = pairs
+            .into_iter()
+            .map(|(key, pair)| {
+                let id = PairId::new(&key);
+                pair_names.insert(key.to_ascii_uppercase(), id.clone());
+                insert_pair(&mut pair_names, pair.alt_name().to_ascii_uppercase(), &id);
+                if let Some(websocket_name) = pair.websocket_name() {
+                    insert_pair(&mut pair_names, websocket_name.to_ascii_uppercase(), &id);
+                }
+                let base = asset_names.get(&pair.base_asset_id().to_ascii_uppercase());
+                let quote = asset_names.get(&pair.quote_asset_id().to_ascii_uppercase());
+                if let (Some(base), Some(quote)) = (base, quote) {
+                    insert_pair(&mut pairs_by_assets, (base.clone(), quote.clone()), &id);
+                }
+                (id, pair)
+            })
+            .collect();
+
+        Self {
+            assets,
+            pairs,
+            asset_names,
+            pair_names,
+            pairs_by_assets,
+        }
+    }
+
+    /// Build the registry from the asset and asset pair endpoints
+    pub async fn load(client: &impl RequestHelpers) -> Result<Self, RequestError> {
+        let assets = all_asset_info(client).await?;
+        let pairs = all_tradable_asset_pairs(client).await?;
+        Ok(Self::new(assets, pairs))
+    }
+
+    /// Resolve any name of an asset to its id
+    pub fn asset_id(&self, name: &str) -> Option<&AssetId> {
+        self.asset_names.get(&name.to_ascii_uppercase())
+    }
+
+    pub fn asset(&self, id: &AssetId) -> Option<&Asset> {
+        self.assets.get(id)
+    }
+
+    pub fn assets(&self) -> impl Iterator<Item = (&AssetId, &Asset)> {
+        self.assets.iter()
+    }
+
+    /// Resolve any name of a pair to its id. Names in the `BASE/QUOTE` form that kraken doesn't
+    /// list are matched by their assets
+    pub fn pair_id(&self, name: &str) -> Option<&PairId> {
+        if let Some(id) = self.pair_names.get(&name.to_ascii_uppercase()) {
+            return Some(id);
+        }
+        self.resolve_pair(&name.parse().ok()?)
+    }
+
+    /// Find the pair that trades the assets of a websocket pair
+    pub fn resolve_pair(&self, pair: &Pair) -> Option<&PairId> {
+        let base = self.asset_id(pair.base())?.clone();
+        let quote = self.asset_id(pair.quote())?.clone();
+        self.pairs_by_assets.get(&(base, quote))
+    }
+
+    /// The pair that trades `base` for `quote`
+    pub fn pair_by_assets(&self, base: &AssetId, quote: &AssetId) -> Option<&PairId> {
+        self.pairs_by_assets.get(&(base.clone(), quote.clone()))
+    }
+
+    pub fn pair(&self, id: &PairId) -> Option<&TradingAssetPair> {
+        self.pairs.get(id)
+    }
+
+    pub fn pairs(&self) -> impl Iterator<Item = (&PairId, &TradingAssetPair)> {
+        self.pairs.iter()
+    }
+
+    /// The base and quote asset of a pair
+    pub fn pair_assets(&self, id: &PairId) -> Option<(&AssetId, &AssetId)> {
+        let pair = self.pairs.get(id)?;
+        Some((
+            self.asset_id(pair.base_asset_id())?,
+            self.asset_id(pair.quote_asset_id())?,
+        ))
+    }
+
+    /// The name to subscribe to the pair with on the v1 websocket api. Not every pair is
+    /// available over websockets
+    pub fn websocket_name(&self, id: &PairId) -> Option<&str> {
+        self.pairs.get(id)?.websocket_name().as_deref()
+    }
+
+    /// Same as [AssetRegistry::websocket_name] but parsed into a [Pair]
+    pub fn websocket_pair(&self, id: &PairId) -> Option<Pair> {
+        self.websocket_name(id)?.parse().ok()
+    }
+}
+
+/// Bitcoin, ether, dogecoin and polkadot against the dollar plus the bitcoin darkpool pair.
+/// Bitcoin has tiered fees and ether has no maker schedule
+#[cfg(test)]
+pub(crate) fn test_registry() -> AssetRegistry {
+    use serde_json::json;
+
+    let asset = |alt_name: &str| json!({"aclass": "currency", "altname": alt_name, "decimals": 10, "display_decimals": 5});
+    let assets: AssetInfo = serde_json::from_value(json!({
+        "XXBT": asset("XBT"),
+        "ZUSD": asset("USD"),
+        "XETH": asset("ETH"),
+        "XXDG": asset("XDG"),
+        "DOT": asset("DOT"),
+    }))
+    .expect("couldn't deserialize assets");
+    let pair = |alt_name: &str, websocket_name: &str, base: &str, fees_maker: serde_json::Value| {
+        json!({
+            "altname": alt_name,
+            "wsname": websocket_name,
+            "aclass_base": "currency",
+            "base": base,
+            "aclass_quote": "currency",
+            "quote": "ZUSD",
+            "lot": "unit",
+            "pair_decimals": 1,
+            "cost_decimals": 5,
+            "lot_decimals": 8,
+            "lot_multiplier": 1,
+            "leverage_buy": [2, 3],
+            "leverage_sell": [2, 3],
+            "fees": [[0, 0.26], [50000, 0.24], [100000, 0.22]],
+            "fees_maker": fees_maker,
+            "fee_volume_currency": "ZUSD",
+            "margin_call": 80,
+            "margin_stop": 40,
+            "ordermin": "0.0001"
+        })
+    };
+    let maker = || json!([[0, 0.16], [50000, 0.14]]);
+    let pairs: TradingAssetPairs = serde_json::from_value(json!({
+        "XXBTZUSD": pair("XBTUSD", "XBT/USD", "XXBT", maker()),
+        "XBTUSD.d": pair("XBTUSD.d", "XBT/USD", "XXBT", maker()),
+        "XETHZUSD": pair("ETHUSD", "ETH/USD", "XETH", json!([])),
+        "XDGUSD": pair("XDGUSD", "XDG/USD", "XXDG", maker()),
+        "DOTUSD": pair("DOTUSD", "DOT/USD", "DOT", maker()),
+    }))
+    .expect("couldn't deserialize pairs");
+    AssetRegistry::new(assets, pairs)
+}
+
+#[cfg(test)]
+mod registry_tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn resolve_asset_names() {
+        let registry = test_registry();
+        let bitcoin = AssetId::new("XXBT");
+        assert_eq!(registry.asset_id("XXBT"), Some(&bitcoin));
+        assert_eq!(registry.asset_id("XBT"), Some(&bitcoin));
+        assert_eq!(registry.asset_id("btc"), Some(&bitcoin));
+        assert_eq!(registry.asset_id("ZUSD"), Some(&AssetId::new("ZUSD")));
+        assert_eq!(registry.asset_id("DOGE"), Some(&AssetId::new("XXDG")));
+        assert!(registry.asset_id("SOL").is_none());
+    }
+
+    #[test]
+    fn resolve_pair_names() {
+        let registry = test_registry();
+        let bitcoin = PairId::new("XXBTZUSD");
+        for name in ["XXBTZUSD", "XBTUSD", "XBT/USD", "BTC/USD", "xbt/usd"] {
+            assert_eq!(registry.pair_id(name), Some(&bitcoin), "{}", name);
+        }
+        assert_eq!(registry.pair_id("DOGE/USD"), Some(&PairId::new("XDGUSD")));
+        assert_eq!(registry.pair_id("XBTUSD.d"), Some(&PairId::new("XBTUSD.d")));
+        assert!(registry.pair_id("SOL/USD").is_none());
+
+        assert_eq!(registry.websocket_name(&bitcoin), Some("XBT/USD"));
+        assert_eq!(
+            registry.pair_assets(&bitcoin),
+            Some((&AssetId::new("XXBT"), &AssetId::new("ZUSD")))
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::api::user_data::{
+    add_export, export_status, remove_export, retrieve_export, AddExportParams, ExportFormat,
+    ExportReportStatus, ExportStatusParams, Ledger, RemoveExportParams, RemoveExportType,
+    RetrieveExportParams, Trade,
+};
+use chrono::NaiveDateTime;
+use derive_setters::Setters;
+use linnaeus_request::error::RequestError;
+use linnaeus_request::RequestHelpers;
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::io::{Cursor, Read};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Format of the times in report files. Times are in UTC
+const REPORT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+
+#[derive(Debug, Error)]
+pub enum ReportError {
+    #[error("export request failed -> {0}")]
+    Request(#[from] RequestError),
+    #[error("report {0} wasn't processed in time")]
+    Timeout(String),
+    #[error("report {0} is no longer listed by kraken")]
+    Missing(String),
+    #[error("couldn't open the report archive -> {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("couldn't read the report archive -> {0}")]
+    Io(#[from] std::io::Error),
+    #[error("couldn't read the report -> {0}")]
+    Csv(#[from] csv::Error),
+    #[error("couldn't parse row {row} of the report -> {reason}")]
+    Row { row: usize, reason: String },
+}
+
+/// Requests a report, waits for kraken to process it and downloads it.
+///
+/// Reports cover the whole date range in one file, unlike the ledger and trade history endpoints
+/// which return 50 rows at a time. The report is removed from kraken once it has been downloaded
+/// unless `remove` is turned off, and cancelled if it isn't processed before the timeout.
+#[derive(Debug, Clone, Setters)]
+#[setters(prefix = "with_")]
+pub struct ReportExport {
+    #[setters(skip)]
+    params: AddExportParams,
+    /// Time between status checks
+    poll_interval: Duration,
+    timeout: Duration,
+    remove: bool,
+}
+
+impl ReportExport {
+    pub fn new(params: AddExportParams) -> Self {
+        Self {
+            params,
+            poll_interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(30 * 60),
+            remove: true,
+        }
+    }
+
+    /// The zip archive of the report
+    pub async fn download(&self, client: &impl RequestHelpers) -> Result<Vec<u8>, ReportError> {
+        let id = add_export(client, &self.params).await?.id().clone();
+        debug!("requested {} report {}", self.params.report_type(), id);
+        if let Err(err) = self.wait_until_processed(client, &id).await {
+            self.remove(client, &id, RemoveExportType::Cancel).await;
+            return Err(err);
+        }
+        let archive = retrieve_export(client, &RetrieveExportParams::new(id.clone())).await?;
+        if self.remove {
+            self.remove(client, &id, RemoveExportType::Delete).await;
+        }
+        Ok(archive)
+    }
+
+    /// Download a trades report and parse it. The report must include the default columns
+    pub async fn fetch_trades(
+        &self,
+        client: &impl RequestHelpers,
+    ) -> Result<Vec<(String, Trade)>, ReportError> {
+        parse_trades(&self.download(client).await?, self.params.export_format())
+    }
+
+    /// Download a ledgers report and parse it. The report must include the default columns
+    pub async fn fetch_ledgers(
+        &self,
+        client: &impl RequestHelpers,
+    ) -> Result<Vec<(String, Ledger)>, ReportError> {
+        parse_ledgers(&self.download(client).await?, self.params.export_format())
+    }
+
+    async fn wait_until_processed(
+        &self,
+        client: &impl RequestHelpers,
+        id: &str,
+    ) -> Result<(), ReportError> {
+        let params = ExportStatusParams::new(self.params.report_type());
+        let poll = async {
+            loop {
+                let reports = export_status(client, &params).await?;
+                let Some(report) = reports.iter().find(|report| report.id() == id) else {
+                    return Err(ReportError::Missing(id.to_string()));
+                };
+                if *report.status() == ExportReportStatus::Processed {
+                    return Ok(());
+                }
+                debug!("report {} is {}", id, report.status());
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        };
+        tokio::time::timeout(self.timeout, poll)
+            .await
+            .unwrap_or_else(|_| Err(ReportError::Timeout(id.to_string())))
+    }
+
+    async fn remove(&self, client: &impl RequestHelpers, id: &str, remove_type: RemoveExportType) {
+        let params = RemoveExportParams::new(id.to_string(), remove_type);
+        if let Err(err) = remove_export(client, &params).await {
+            warn!("couldn't {} report {} -> {}", remove_type, id, err);
+        }
+    }
+}
+
+/// Parse the trades in a report archive keyed by their transaction id
+pub fn parse_trades(
+    archive: &[u8],
+    format: ExportFormat,
+) -> Result<Vec<(String, Trade)>, ReportError> {
+    parse_report(archive, format)
+}
+
+/// Parse the ledger entries in a report archive keyed by their ledger id
+pub fn parse_ledgers(
+    archive: &[u8],
+    format: ExportFormat,
+) -> Result<Vec<(String, Ledger)>, ReportError> {
+    parse_report(archive, format)
+}
+
+/// Rows are rebuilt into the json the REST api returns so that they deserialize into the same
+/// structs. Empty cells are left out
+fn parse_report<T: DeserializeOwned>(
+    archive: &[u8],
+    format: ExportFormat,
+) -> Result<Vec<(String, T)>, ReportError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive))?;
+    let mut rows = Vec::new();
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        if file.is_dir() {
+            continue;
+        }
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(match format {
+                ExportFormat::Csv => b',',
+                ExportFormat::Tsv => b'\t',
+            })
+            .from_reader(contents.as_slice());
+        let headers = reader.headers()?.clone();
+        for record in reader.records() {
+            let record = record?;
+            let row = rows.len() + 1;
+            let error = |reason: String| ReportError::Row { row, reason };
+            let mut fields: Map<String, Value> = headers
+                .iter()
+                .zip(record.iter())
+                .filter(|(_, cell)| !cell.is_empty())
+                .map(|(header, cell)| (header.to_string(), Value::String(cell.to_string())))
+                .collect();
+            let Some(Value::String(id)) = fields.remove("txid") else {
+                return Err(error("missing txid".to_string()));
+            };
+            if let Some(Value::String(time)) = fields.get("time") {
+                let time = NaiveDateTime::parse_from_str(time, REPORT_TIME_FORMAT)
+                    .map_err(|err| error(format!("invalid time {} -> {}", time, err)))?
+                    .and_utc();
+                let seconds =
+                    time.timestamp() as f64 + f64::from(time.timestamp_subsec_nanos()) / 1e9;
+                fields.insert("time".to_string(), seconds.into());
+            }
+            let parsed = serde_json::from_value(Value::Object(fields))
+                .map_err(|err| error(err.to_string()))?;
+            rows.push((id, parsed));
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod report_tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use std::io::Write;
+
+    fn archive(name: &str, contents: &str) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file(name, zip::write::SimpleFileOptions::default())
+            .expect("couldn't start file");
+        writer
+            .write_all(contents.as_bytes())
+            .expect("couldn't write file");
+        writer
+            .finish()
+            .expect("couldn't finish archive")
+            .into_inner()
+    }
+
+    #[test]
+    fn trades_are_parsed_from_the_archive() -> anyhow::Result<()> {
+        let archive = archive(
+            "trades.csv",
+            "\"txid\",\"ordertxid\",\"pair\",\"time\",\"type\",\"ordertype\",\"price\",\"cost\",\"fee\",\"vol\",\"margin\",\"misc\",\"ledgers\"\n\
+             \"TZX2WP-XSEOP-FP7WYR\",\"OIGSEZ-HSKVD-6SG4UD\",\"XXBTZUSD\",\"2023-07-06 17:31:37\",\"buy\",\"limit\",\"30000.00000\",\"300.00000\",\"0.48000\",\"0.01000000\",\"0.00000\",\"\",\"L6RHG5-FMZCE-NCJBTY,LSRFQB-JCDXT-VSJ5KX\"\n",
+        );
+        let trades = parse_trades(&archive, ExportFormat::Csv)?;
+        assert_eq!(trades.len(), 1);
+        let (id, trade) = &trades[0];
+        assert_eq!(id, "TZX2WP-XSEOP-FP7WYR");
+        assert_eq!(trade.order_id(), "OIGSEZ-HSKVD-6SG4UD");
+        assert_eq!(*trade.volume(), dec!(0.01));
+        assert_eq!(trade.time().timestamp(), 1688664697);
+        assert!(trade.misc().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn ledgers_are_parsed_from_tsv() -> anyhow::Result<()> {
+        let archive = archive(
+            "ledgers.tsv",
+            "txid\trefid\ttime\ttype\tsubtype\taclass\tasset\twallet\tamount\tfee\tbalance\n\
+             L6RHG5-FMZCE-NCJBTY\tTZX2WP-XSEOP-FP7WYR\t2023-07-06 17:31:37\ttrade\t\tcurrency\tXXBT\tspot / main\t0.0100000000\t0.0000000000\t0.0100000000\n",
+        );
+        let ledgers = parse_ledgers(&archive, ExportFormat::Tsv)?;
+        assert_eq!(ledgers[0].0, "L6RHG5-FMZCE-NCJBTY");
+        assert_eq!(*ledgers[0].1.amount(), dec!(0.01));
+        assert_eq!(ledgers[0].1.sub_type(), "");
+        Ok(())
+    }
+
+    #[test]
+    fn bad_rows_are_reported() {
+        let archive = archive("ledgers.csv", "txid,time\nL1,yesterday\n");
+        assert!(matches!(
+            parse_ledgers(&archive, ExportFormat::Csv),
+            Err(ReportError::Row { row: 1, .. })
+        ));
+    }
+}
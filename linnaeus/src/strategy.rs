@@ -0,0 +1,239 @@
+use crate::api::market_data::{TickData, TradeData};
+use crate::api::user_trading::{AddOrderParams, CancelOrderParams};
+use crate::order_tracker::{OrderEvent, OrderTracker};
+use crate::trading::TradingClient;
+use crate::Linnaeus;
+use chrono::{DateTime, Duration, Utc};
+use futures::{Stream, StreamExt};
+use linnaeus_request::error::RequestError;
+use linnaeus_ws::book::LocalBook;
+use linnaeus_ws::error::LinnaeusWebsocketError;
+use linnaeus_ws::messages::private_messages::OpenOrders;
+use linnaeus_ws::messages::public_messages::Trades;
+use linnaeus_ws::messages::Pair;
+use linnaeus_ws::subscription::SubscriptionOptions;
+use log::warn;
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+/// Market data a [Strategy] reacts to, from live feeds or a replay
+#[derive(Debug, Clone)]
+pub enum MarketEvent {
+    /// A finished candle. The candle's time is the start of its interval and the event happens
+    /// when the interval ends
+    Candle {
+        pair: String,
+        candle: TickData,
+        interval: Duration,
+    },
+    Trade {
+        pair: String,
+        time: DateTime<Utc>,
+        price: Decimal,
+        volume: Decimal,
+    },
+    /// The whole book after an update
+    Book {
+        pair: String,
+        time: DateTime<Utc>,
+        book: LocalBook,
+    },
+}
+
+impl MarketEvent {
+    pub fn candle(pair: &str, candle: TickData, interval: Duration) -> Self {
+        Self::Candle {
+            pair: pair.to_string(),
+            candle,
+            interval,
+        }
+    }
+
+    /// A trade from the REST api, see [crate::backfill::TradeBackfill]
+    pub fn trade(pair: &str, trade: &TradeData) -> Self {
+        Self::Trade {
+            pair: pair.to_string(),
+            time: *trade.time(),
+            price: *trade.price(),
+            volume: *trade.volume(),
+        }
+    }
+
+    /// Trades from the websocket trade feed
+    pub fn trades(pair: &str, trades: &Trades) -> Vec<Self> {
+        trades
+            .iter()
+            .map(|trade| Self::Trade {
+                pair: pair.to_string(),
+                time: *trade.time(),
+                price: *trade.price(),
+                volume: *trade.volume(),
+            })
+            .collect()
+    }
+
+    pub fn pair(&self) -> &str {
+        match self {
+            Self::Candle { pair, .. } | Self::Trade { pair, .. } | Self::Book { pair, .. } => pair,
+        }
+    }
+
+    pub fn time(&self) -> DateTime<Utc> {
+        match self {
+            Self::Candle {
+                candle, interval, ..
+            } => *candle.time() + *interval,
+            Self::Trade { time, .. } | Self::Book { time, .. } => *time,
+        }
+    }
+
+    /// The trade price, the candle's close or the middle of the book
+    pub fn price(&self) -> Option<Decimal> {
+        match self {
+            Self::Candle { candle, .. } => Some(*candle.close()),
+            Self::Trade { price, .. } => Some(*price),
+            Self::Book { book, .. } => {
+                Some((book.best_ask()?.price() + book.best_bid()?.price()) / Decimal::TWO)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum OrderRequest {
+    Add(Box<AddOrderParams>),
+    Cancel(CancelOrderParams),
+}
+
+/// Collects the orders a strategy places and cancels. They're sent once the callback returns
+#[derive(Debug)]
+pub struct StrategyContext {
+    now: DateTime<Utc>,
+    requests: Vec<OrderRequest>,
+}
+
+impl StrategyContext {
+    pub(crate) fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now,
+            requests: Vec::new(),
+        }
+    }
+
+    /// The simulated time in a backtest, the system time when live
+    pub fn now(&self) -> DateTime<Utc> {
+        self.now
+    }
+
+    pub fn add_order(&mut self, params: AddOrderParams) {
+        self.requests.push(OrderRequest::Add(Box::new(params)));
+    }
+
+    pub fn cancel_order(&mut self, params: CancelOrderParams) {
+        self.requests.push(OrderRequest::Cancel(params));
+    }
+
+    pub(crate) fn into_requests(self) -> Vec<OrderRequest> {
+        self.requests
+    }
+}
+
+/// Trading logic that runs unchanged on live websocket data, with [run_strategy], and in a
+/// [crate::backtest::Backtester].
+///
+/// Order events come from an [OrderTracker] fed with the account's open orders, so they include
+/// orders the strategy didn't place. Tag orders with a user reference or client order id to tell
+/// them apart
+pub trait Strategy {
+    fn on_market(&mut self, event: &MarketEvent, context: &mut StrategyContext);
+
+    fn on_order(&mut self, _event: &OrderEvent, _context: &mut StrategyContext) {}
+
+    /// The exchange refused to place an order
+    fn on_rejected(
+        &mut self,
+        _params: &AddOrderParams,
+        _error: &RequestError,
+        _context: &mut StrategyContext,
+    ) {
+    }
+}
+
+enum Update {
+    Market(MarketEvent),
+    OpenOrders(OpenOrders),
+}
+
+/// Drive a strategy from live feeds and send its orders through `client`, which can be a
+/// [crate::paper::PaperExchange]. Runs until both feeds end
+pub async fn run_strategy<S, C, M, O>(strategy: &mut S, client: &C, market: M, open_orders: O)
+where
+    S: Strategy,
+    C: TradingClient,
+    M: Stream<Item = MarketEvent>,
+    O: Stream<Item = OpenOrders>,
+{
+    let mut tracker = OrderTracker::new();
+    let updates = futures::stream::select(
+        market.map(Update::Market),
+        open_orders.map(Update::OpenOrders),
+    );
+    let mut updates = std::pin::pin!(updates);
+    while let Some(update) = updates.next().await {
+        let mut context = StrategyContext::new(Utc::now());
+        match update {
+            Update::Market(event) => strategy.on_market(&event, &mut context),
+            Update::OpenOrders(orders) => {
+                for event in tracker.apply_open_orders(&orders) {
+                    strategy.on_order(&event, &mut context);
+                }
+            }
+        }
+        submit(strategy, client, context).await;
+    }
+}
+
+async fn submit(
+    strategy: &mut impl Strategy,
+    client: &impl TradingClient,
+    context: StrategyContext,
+) {
+    let mut requests: VecDeque<OrderRequest> = context.into_requests().into();
+    while let Some(request) = requests.pop_front() {
+        match request {
+            OrderRequest::Add(params) => {
+                if let Err(err) = client.add_order(&params).await {
+                    let mut context = StrategyContext::new(Utc::now());
+                    strategy.on_rejected(&params, &err, &mut context);
+                    requests.extend(context.into_requests());
+                }
+            }
+            OrderRequest::Cancel(params) => {
+                if let Err(err) = client.cancel_order(&params).await {
+                    warn!("couldn't cancel strategy order -> {}", err);
+                }
+            }
+        }
+    }
+}
+
+impl Linnaeus {
+    /// Run a strategy on the public trades of `pairs` and the account's open orders. Orders are
+    /// placed on the account
+    pub async fn run_strategy(
+        &mut self,
+        strategy: &mut impl Strategy,
+        pairs: &[Pair],
+        options: SubscriptionOptions,
+    ) -> Result<(), LinnaeusWebsocketError> {
+        let private = self.get_private_websocket_client().await?;
+        let open_orders = private.subscribe_open_orders(options.clone()).await?;
+        let public = self.get_websocket_client().await?;
+        let market = public
+            .subscribe_trades(pairs, options)
+            .await?
+            .flat_map(|(pair, trades)| futures::stream::iter(MarketEvent::trades(&pair, &trades)));
+        run_strategy(strategy, &self.rest_client(), market, open_orders).await;
+        Ok(())
+    }
+}
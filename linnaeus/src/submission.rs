@@ -0,0 +1,410 @@
+use crate::api::user_data::{ClosedOrdersParams, OpenOrdersParams, OrderBase};
+use crate::api::user_trading::AddOrderParams;
+use crate::trading::TradingClient;
+use chrono::{DateTime, Utc};
+use derive_getters::Getters;
+use derive_setters::Setters;
+use linnaeus_request::error::RequestError;
+use log::warn;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum SubmitError {
+    #[error("orders submitted with a client order id can't also have a user reference id")]
+    UserReference,
+    #[error("order {client_order_id} was rejected -> {source}")]
+    Rejected {
+        client_order_id: String,
+        #[source]
+        source: RequestError,
+    },
+    #[error("couldn't determine whether order {client_order_id} was placed -> {source}")]
+    Unknown {
+        client_order_id: String,
+        #[source]
+        source: RequestError,
+    },
+    #[error("order {client_order_id} wasn't placed after {attempts} attempts")]
+    NotPlaced {
+        client_order_id: String,
+        attempts: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct SubmittedOrder {
+    client_order_id: String,
+    transaction_ids: Vec<String>,
+    /// True if the transaction ids were found by looking the order up after an indeterminate
+    /// response instead of coming from the AddOrder response
+    recovered: bool,
+}
+
+/// Places orders tagged with a client order id so that a submission can be safely repeated.
+///
+/// Orders without a client order id get a random one. If a request times out or fails in a way
+/// that leaves it unclear whether kraken accepted it, the open and closed orders are searched for
+/// the id before the order is sent again, so an order is never placed twice. Clones share the
+/// record of submitted ids and submitting an id again within `ttl` returns the original
+/// transaction ids, waiting for the first submission if it's still in flight. Older records are
+/// dropped so that the record doesn't grow forever.
+#[derive(Debug, Clone, Setters)]
+#[setters(prefix = "with_")]
+pub struct OrderSubmitter {
+    /// Time to wait for an AddOrder response before treating it as indeterminate
+    timeout: Duration,
+    /// Number of times an order that couldn't be found is sent again
+    retries: usize,
+    /// Pause before looking up an order, giving kraken time to list it
+    lookup_delay: Duration,
+    /// How long a submission is remembered
+    ttl: Duration,
+    #[setters(skip)]
+    submitted: Arc<Mutex<HashMap<String, Slot>>>,
+}
+
+/// The submission of a client order id. Locked while it's in flight and empty if it failed
+type Slot = Arc<tokio::sync::Mutex<Option<(SubmittedOrder, Instant)>>>;
+
+impl Default for OrderSubmitter {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            retries: 2,
+            lookup_delay: Duration::from_secs(1),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            submitted: Default::default(),
+        }
+    }
+}
+
+impl OrderSubmitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The result of an earlier submission with this client order id
+    pub fn get(&self, client_order_id: &str) -> Option<SubmittedOrder> {
+        let slot = self.submitted().get(client_order_id)?.clone();
+        let slot = slot.try_lock().ok()?;
+        slot.as_ref().map(|(order, _)| order.clone())
+    }
+
+    pub async fn submit(
+        &self,
+        client: &impl TradingClient,
+        params: AddOrderParams,
+    ) -> Result<SubmittedOrder, SubmitError> {
+        if params.user_reference_id().is_some() {
+            return Err(SubmitError::UserReference);
+        }
+        let client_order_id = params
+            .client_order_id()
+            .clone()
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        // the slot is locked while the record is held so a new slot can't be dropped as failed
+        // before the submission starts
+        let (slot, locked) = {
+            let mut submitted = self.submitted();
+            let slot = submitted
+                .entry(client_order_id.clone())
+                .or_default()
+                .clone();
+            let locked = slot.clone().try_lock_owned().ok();
+            (slot, locked)
+        };
+        // a concurrent submission of the same id waits here for the first one's result
+        let mut slot = match locked {
+            Some(slot) => slot,
+            None => slot.lock_owned().await,
+        };
+        if let Some((order, _)) = slot.as_ref() {
+            return Ok(order.clone());
+        }
+        let order = self.place(client, params, client_order_id).await?;
+        *slot = Some((order.clone(), Instant::now()));
+        Ok(order)
+    }
+
+    /// Send the order until it's placed or found, without looking at earlier submissions
+    async fn place(
+        &self,
+        client: &impl TradingClient,
+        params: AddOrderParams,
+        client_order_id: String,
+    ) -> Result<SubmittedOrder, SubmitError> {
+        let params = params.with_client_order_id(client_order_id.clone());
+        // leave room for clock differences when searching closed orders
+        let submitted_after = Utc::now() - chrono::Duration::minutes(1);
+
+        for attempt in 0..=self.retries {
+            let error = match tokio::time::timeout(self.timeout, client.add_order(&params)).await {
+                Ok(Ok(response)) => {
+                    return Ok(SubmittedOrder {
+                        client_order_id,
+                        transaction_ids: response.transaction_ids().clone(),
+                        recovered: false,
+                    })
+                }
+                Ok(Err(error)) if !error.is_indeterminate() => {
+                    return Err(SubmitError::Rejected {
+                        client_order_id,
+                        source: error,
+                    })
+                }
+                Ok(Err(error)) => error.to_string(),
+                Err(_) => format!("no response after {:?}", self.timeout),
+            };
+            warn!(
+                "attempt {} to place order {} is indeterminate -> {}",
+                attempt + 1,
+                client_order_id,
+                error
+            );
+            tokio::time::sleep(self.lookup_delay).await;
+            let transaction_ids = find_order(client, &client_order_id, submitted_after)
+                .await
+                .map_err(|source| SubmitError::Unknown {
+                    client_order_id: client_order_id.clone(),
+                    source,
+                })?;
+            if !transaction_ids.is_empty() {
+                return Ok(SubmittedOrder {
+                    client_order_id,
+                    transaction_ids,
+                    recovered: true,
+                });
+            }
+        }
+        Err(SubmitError::NotPlaced {
+            client_order_id,
+            attempts: self.retries + 1,
+        })
+    }
+
+    /// The record of submissions without the failed ones and the ones older than `ttl`
+    fn submitted(&self) -> std::sync::MutexGuard<'_, HashMap<String, Slot>> {
+        let mut submitted = self
+            .submitted
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        submitted.retain(|_, slot| match slot.try_lock() {
+            Ok(slot) => slot
+                .as_ref()
+                .is_some_and(|(_, recorded_at)| recorded_at.elapsed() < self.ttl),
+            // in flight
+            Err(_) => true,
+        });
+        submitted
+    }
+}
+
+/// Transaction ids of the open or closed orders tagged with the client order id
+pub async fn find_order(
+    client: &impl TradingClient,
+    client_order_id: &str,
+    submitted_after: DateTime<Utc>,
+) -> Result<Vec<String>, RequestError> {
+    let params = OpenOrdersParams::default().cl_ord_id(Some(client_order_id.to_string()));
+    let open = client.open_orders(&params).await?;
+    let transaction_ids = matching_orders(&open, client_order_id);
+    if !transaction_ids.is_empty() {
+        return Ok(transaction_ids);
+    }
+    let params = ClosedOrdersParams::default()
+        .cl_ord_id(Some(client_order_id.to_string()))
+        .start(Some(submitted_after));
+    let closed = client.closed_orders(&params).await?;
+    Ok(matching_orders(closed.closed(), client_order_id))
+}
+
+fn matching_orders(orders: &HashMap<String, OrderBase>, client_order_id: &str) -> Vec<String> {
+    let mut transaction_ids: Vec<String> = orders
+        .iter()
+        .filter(|(_, order)| order.client_order_id().as_deref() == Some(client_order_id))
+        .map(|(id, _)| id.clone())
+        .collect();
+    transaction_ids.sort();
+    transaction_ids
+}
+
+#[cfg(test)]
+mod submission_tests {
+    use super::*;
+    use crate::api::user_data::{
+        AccountBalances, ClosedOrders, LedgerInfo, LedgerInfoParams, OrderStatus, OrderType, Side,
+        TradeHistory, TradeHistoryParams,
+    };
+    use crate::api::user_trading::{AddOrderResponse, CancelOrderParams, CancelOrderResponse};
+    use crate::order_tracker::test_rest_order;
+    use crate::Linnaeus;
+    use futures::future::BoxFuture;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    fn record(submitter: &OrderSubmitter, order: SubmittedOrder) -> SubmittedOrder {
+        let slot = Arc::new(tokio::sync::Mutex::new(Some((
+            order.clone(),
+            Instant::now(),
+        ))));
+        submitter
+            .submitted()
+            .insert(order.client_order_id.clone(), slot);
+        order
+    }
+
+    /// Takes a while to accept each order and counts the AddOrder requests
+    #[derive(Default)]
+    struct SlowClient {
+        add_orders: Mutex<usize>,
+    }
+
+    fn unsupported<'a, T: Send + 'a>() -> BoxFuture<'a, Result<T, RequestError>> {
+        Box::pin(async { Err(RequestError::Other("not supported".to_string())) })
+    }
+
+    impl TradingClient for SlowClient {
+        fn add_order<'a>(
+            &'a self,
+            _params: &'a AddOrderParams,
+        ) -> BoxFuture<'a, Result<AddOrderResponse, RequestError>> {
+            Box::pin(async move {
+                let count = {
+                    let mut add_orders = self.add_orders.lock().expect("poisoned");
+                    *add_orders += 1;
+                    *add_orders
+                };
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Ok(serde_json::from_value(json!({
+                    "descr": {"order": ""},
+                    "txid": [format!("O{}", count)]
+                }))
+                .expect("valid response"))
+            })
+        }
+
+        fn cancel_order<'a>(
+            &'a self,
+            _params: &'a CancelOrderParams,
+        ) -> BoxFuture<'a, Result<CancelOrderResponse, RequestError>> {
+            unsupported()
+        }
+
+        fn account_balances(&self) -> BoxFuture<'_, Result<AccountBalances, RequestError>> {
+            unsupported()
+        }
+
+        fn open_orders<'a>(
+            &'a self,
+            _params: &'a OpenOrdersParams,
+        ) -> BoxFuture<'a, Result<HashMap<String, OrderBase>, RequestError>> {
+            unsupported()
+        }
+
+        fn closed_orders<'a>(
+            &'a self,
+            _params: &'a ClosedOrdersParams,
+        ) -> BoxFuture<'a, Result<ClosedOrders, RequestError>> {
+            unsupported()
+        }
+
+        fn trade_history<'a>(
+            &'a self,
+            _params: &'a TradeHistoryParams,
+        ) -> BoxFuture<'a, Result<TradeHistory, RequestError>> {
+            unsupported()
+        }
+
+        fn ledgers<'a>(
+            &'a self,
+            _params: &'a LedgerInfoParams,
+        ) -> BoxFuture<'a, Result<LedgerInfo, RequestError>> {
+            unsupported()
+        }
+    }
+
+    fn order(client_order_id: Option<&str>) -> OrderBase {
+        test_rest_order(OrderStatus::Open, dec!(1))
+            .with_client_order_id(client_order_id.map(str::to_string))
+    }
+
+    #[test]
+    fn matches_orders_by_client_order_id() {
+        let orders = HashMap::from([
+            ("OB-2".to_string(), order(Some("abc"))),
+            ("OA-1".to_string(), order(Some("abc"))),
+            ("OC-3".to_string(), order(Some("def"))),
+            ("OD-4".to_string(), order(None)),
+        ]);
+        assert_eq!(matching_orders(&orders, "abc"), vec!["OA-1", "OB-2"]);
+        assert!(matching_orders(&orders, "xyz").is_empty());
+    }
+
+    #[tokio::test]
+    async fn repeated_submission_returns_existing_order() {
+        let client = Linnaeus::new(vec![], "http://localhost", "ws://localhost");
+        let submitter = OrderSubmitter::new();
+        let existing = record(
+            &submitter,
+            SubmittedOrder {
+                client_order_id: "abc".to_string(),
+                transaction_ids: vec!["OA-1".to_string()],
+                recovered: true,
+            },
+        );
+        let params = AddOrderParams::new(OrderType::Limit, Side::Buy, "XBTUSD".into(), dec!(1))
+            .with_client_order_id("abc".into());
+        let submitted = submitter
+            .clone()
+            .submit(&client, params)
+            .await
+            .expect("recorded order");
+        assert_eq!(submitted, existing);
+
+        let params = AddOrderParams::new(OrderType::Limit, Side::Buy, "XBTUSD".into(), dec!(1))
+            .with_user_reference_id(7);
+        assert!(matches!(
+            submitter.submit(&client, params).await,
+            Err(SubmitError::UserReference)
+        ));
+    }
+
+    #[test]
+    fn submissions_are_forgotten_after_the_ttl() {
+        let submitter = OrderSubmitter::new();
+        record(
+            &submitter,
+            SubmittedOrder {
+                client_order_id: "abc".to_string(),
+                transaction_ids: vec!["OA-1".to_string()],
+                recovered: false,
+            },
+        );
+        assert!(submitter.get("abc").is_some());
+        let submitter = submitter.with_ttl(Duration::ZERO);
+        assert!(submitter.get("abc").is_none());
+        assert!(submitter.submitted().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_submissions_of_an_id_place_one_order() {
+        let client = SlowClient::default();
+        let submitter = OrderSubmitter::new();
+        let params = AddOrderParams::new(OrderType::Limit, Side::Buy, "XBTUSD".into(), dec!(1))
+            .with_client_order_id("abc".into());
+        let clone = submitter.clone();
+        let (first, second) = tokio::join!(
+            submitter.submit(&client, params.clone()),
+            clone.submit(&client, params)
+        );
+        let (first, second) = (first.expect("placed"), second.expect("placed"));
+        assert_eq!(*client.add_orders.lock().expect("poisoned"), 1);
+        assert_eq!(first, second);
+        assert_eq!(first.transaction_ids(), &vec!["O1".to_string()]);
+    }
+}
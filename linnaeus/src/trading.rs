@@ -0,0 +1,98 @@
+use crate::api::user_data::{
+    account_balances, closed_orders, get_ledger_info, open_orders, trade_history, AccountBalances,
+    ClosedOrders, ClosedOrdersParams, LedgerInfo, LedgerInfoParams, OpenOrdersParams, OrderBase,
+    TradeHistory, TradeHistoryParams,
+};
+use crate::api::user_trading::{
+    add_order, cancel_order, AddOrderParams, AddOrderResponse, CancelOrderParams,
+    CancelOrderResponse,
+};
+use futures::future::BoxFuture;
+use linnaeus_request::error::RequestError;
+use linnaeus_request::RequestHelpers;
+use std::collections::HashMap;
+
+/// Places and cancels orders and reads back the account. Implemented by every REST client through
+/// the [crate::api::user_trading] and [crate::api::user_data] endpoints, and by
+/// [crate::paper::PaperExchange]
+pub trait TradingClient: Send + Sync {
+    fn add_order<'a>(
+        &'a self,
+        params: &'a AddOrderParams,
+    ) -> BoxFuture<'a, Result<AddOrderResponse, RequestError>>;
+
+    fn cancel_order<'a>(
+        &'a self,
+        params: &'a CancelOrderParams,
+    ) -> BoxFuture<'a, Result<CancelOrderResponse, RequestError>>;
+
+    fn account_balances(&self) -> BoxFuture<'_, Result<AccountBalances, RequestError>>;
+
+    fn open_orders<'a>(
+        &'a self,
+        params: &'a OpenOrdersParams,
+    ) -> BoxFuture<'a, Result<HashMap<String, OrderBase>, RequestError>>;
+
+    fn closed_orders<'a>(
+        &'a self,
+        params: &'a ClosedOrdersParams,
+    ) -> BoxFuture<'a, Result<ClosedOrders, RequestError>>;
+
+    fn trade_history<'a>(
+        &'a self,
+        params: &'a TradeHistoryParams,
+    ) -> BoxFuture<'a, Result<TradeHistory, RequestError>>;
+
+    fn ledgers<'a>(
+        &'a self,
+        params: &'a LedgerInfoParams,
+    ) -> BoxFuture<'a, Result<LedgerInfo, RequestError>>;
+}
+
+impl<C: RequestHelpers + Send + Sync> TradingClient for C {
+    fn add_order<'a>(
+        &'a self,
+        params: &'a AddOrderParams,
+    ) -> BoxFuture<'a, Result<AddOrderResponse, RequestError>> {
+        Box::pin(add_order(self, params))
+    }
+
+    fn cancel_order<'a>(
+        &'a self,
+        params: &'a CancelOrderParams,
+    ) -> BoxFuture<'a, Result<CancelOrderResponse, RequestError>> {
+        Box::pin(cancel_order(self, params))
+    }
+
+    fn account_balances(&self) -> BoxFuture<'_, Result<AccountBalances, RequestError>> {
+        Box::pin(account_balances(self))
+    }
+
+    fn open_orders<'a>(
+        &'a self,
+        params: &'a OpenOrdersParams,
+    ) -> BoxFuture<'a, Result<HashMap<String, OrderBase>, RequestError>> {
+        Box::pin(open_orders(self, params))
+    }
+
+    fn closed_orders<'a>(
+        &'a self,
+        params: &'a ClosedOrdersParams,
+    ) -> BoxFuture<'a, Result<ClosedOrders, RequestError>> {
+        Box::pin(closed_orders(self, params))
+    }
+
+    fn trade_history<'a>(
+        &'a self,
+        params: &'a TradeHistoryParams,
+    ) -> BoxFuture<'a, Result<TradeHistory, RequestError>> {
+        Box::pin(trade_history(self, params))
+    }
+
+    fn ledgers<'a>(
+        &'a self,
+        params: &'a LedgerInfoParams,
+    ) -> BoxFuture<'a, Result<LedgerInfo, RequestError>> {
+        Box::pin(get_ledger_info(self, params))
+    }
+}
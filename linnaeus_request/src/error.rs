@@ -98,6 +98,9 @@ pub enum KrakenErrorMessage {
     OrdersLimitExceeded,
     #[strum(serialize="Rate limit exceeded")]
     RateLimitExceeded,
+    ///Public endpoints were called too often
+    #[strum(serialize="Too many requests")]
+    TooManyRequests,
     #[strum(serialize="Positions limit exceeded")]
     PositionsLimitExceeded,
     #[strum(serialize="Unknown position")]
@@ -184,3 +187,35 @@ pub enum RequestError {
     #[error("An error occurred with message -> {0}")]
     Other(String),
 }
+
+impl RequestError {
+    /// True if kraken rejected the request because too many requests were made
+    pub fn is_rate_limit(&self) -> bool {
+        match self {
+            Self::Kraken(errors) => errors.errors.iter().any(|error| {
+                matches!(
+                    error.message,
+                    KrakenErrorMessage::RateLimitExceeded | KrakenErrorMessage::TooManyRequests
+                )
+            }),
+            _ => false,
+        }
+    }
+
+    /// True if the request may or may not have been processed by kraken, e.g. the connection
+    /// dropped after the request was sent or kraken reported a service problem
+    pub fn is_indeterminate(&self) -> bool {
+        match self {
+            Self::Http(error) => !error.is_builder(),
+            Self::DeserializationError(_, _) | Self::ParsingError(_) => true,
+            Self::Kraken(errors) => errors.errors.iter().any(|error| match error.category {
+                Category::Service => true,
+                Category::General => {
+                    matches!(&error.message, KrakenErrorMessage::Other(message) if message == "Internal error")
+                }
+                _ => false,
+            }),
+            _ => false,
+        }
+    }
+}
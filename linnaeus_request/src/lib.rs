@@ -230,30 +230,11 @@ impl<O> TryFrom<Response<O>> for KrakenErrors {
     }
 }
 
-async fn deserialize_response<O>(resp: reqwest::Response) -> Result<O, RequestError>
-where
-    O: DeserializeOwned,
-{
+/// The body of a successful response. Any other status is turned into an error
+async fn success_bytes(resp: reqwest::Response) -> Result<Vec<u8>, RequestError> {
     if resp.status().is_success() {
         trace!("Got success response for request to {}", resp.url());
-        let resp_bytes = resp.bytes().await?;
-        let resp: Response<O> = match serde_json::from_slice(&resp_bytes) {
-            Ok(resp) => resp,
-            Err(e) => {
-                return Err(RequestError::DeserializationError(
-                    e,
-                    String::from_utf8(resp_bytes.into())
-                        .unwrap_or_else(|_| "Non UTF-8 Json".into()),
-                ))
-            }
-        };
-        match resp.result {
-            Some(result) => Ok(result),
-            None => {
-                let errors: KrakenErrors = resp.try_into()?;
-                Err(errors.into())
-            }
-        }
+        Ok(resp.bytes().await?.to_vec())
     } else {
         let status_code = resp.status().as_u16();
         let resp_body = resp.text().await?;
@@ -264,9 +245,44 @@ where
     }
 }
 
+async fn deserialize_response<O>(resp: reqwest::Response) -> Result<O, RequestError>
+where
+    O: DeserializeOwned,
+{
+    let resp_bytes = success_bytes(resp).await?;
+    let resp: Response<O> = match serde_json::from_slice(&resp_bytes) {
+        Ok(resp) => resp,
+        Err(e) => {
+            return Err(RequestError::DeserializationError(
+                e,
+                String::from_utf8(resp_bytes).unwrap_or_else(|_| "Non UTF-8 Json".into()),
+            ))
+        }
+    };
+    match resp.result {
+        Some(result) => Ok(result),
+        None => {
+            let errors: KrakenErrors = resp.try_into()?;
+            Err(errors.into())
+        }
+    }
+}
+
+/// For endpoints that respond with a file rather than json. Kraken still reports errors as json
+async fn bytes_response(resp: reqwest::Response) -> Result<Vec<u8>, RequestError> {
+    let resp_bytes = success_bytes(resp).await?;
+    if let Ok(resp) = serde_json::from_slice::<Response<serde_json::Value>>(&resp_bytes) {
+        if !resp.error.is_empty() {
+            let errors: KrakenErrors = resp.try_into()?;
+            return Err(errors.into());
+        }
+    }
+    Ok(resp_bytes)
+}
+
 #[inline]
 async fn execute_request<O>(
-    linnaeus_client: &(impl RequestClient + RequestHelpers),
+    linnaeus_client: &impl RequestHelpers,
     req: RequestBuilder,
 ) -> Result<O, RequestError>
 where
@@ -278,7 +294,7 @@ where
 }
 
 pub async fn do_request_with_body<I, O>(
-    linnaeus_client: &(impl RequestClient + RequestHelpers),
+    linnaeus_client: &impl RequestHelpers,
     url: &str,
     method: http::Method,
     security_type: EndpointSecurityType,
@@ -293,7 +309,7 @@ where
 }
 
 pub async fn do_request_with_query<Q, O>(
-    linnaeus_client: &(impl RequestClient + RequestHelpers),
+    linnaeus_client: &impl RequestHelpers,
     url: &str,
     method: http::Method,
     security_type: EndpointSecurityType,
@@ -308,7 +324,7 @@ where
 }
 
 pub async fn do_request<I, Q, O>(
-    linnaeus_client: &(impl RequestClient + RequestHelpers),
+    linnaeus_client: &impl RequestHelpers,
     url: &str,
     method: http::Method,
     security_type: EndpointSecurityType,
@@ -325,7 +341,7 @@ where
 }
 
 pub async fn do_request_no_params<O>(
-    linnaeus_client: &(impl RequestClient + RequestHelpers),
+    linnaeus_client: &impl RequestHelpers,
     url: &str,
     method: http::Method,
     security_type: EndpointSecurityType,
@@ -337,6 +353,25 @@ where
     execute_request(linnaeus_client, req).await
 }
 
+/// Same as [do_request_with_body] for endpoints that respond with raw bytes, such as the zip file
+/// of an export report
+pub async fn do_request_with_body_bytes<I>(
+    linnaeus_client: &impl RequestHelpers,
+    url: &str,
+    method: http::Method,
+    security_type: EndpointSecurityType,
+    body: &I,
+) -> Result<Vec<u8>, RequestError>
+where
+    I: Serialize,
+{
+    let req = linnaeus_client
+        .generate_req_with_body(url, method, security_type, body)?
+        .build()?;
+    let resp = linnaeus_client.get_client().execute(req).await?;
+    bytes_response(resp).await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
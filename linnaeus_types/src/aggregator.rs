@@ -0,0 +1,309 @@
+use crate::market_data::{Candle, Interval, Trade};
+use chrono::{DateTime, TimeZone, Utc};
+use rust_decimal::Decimal;
+
+/// When a bar closes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarRule {
+    /// Fixed length bars aligned to the unix epoch. Durations are rounded down to whole seconds
+    Time(chrono::Duration),
+    /// Bars of a fixed traded volume in the base asset
+    Volume(Decimal),
+    /// Bars of a fixed traded value in the quote asset, a.k.a dollar bars
+    Notional(Decimal),
+}
+
+impl From<Interval> for BarRule {
+    fn from(interval: Interval) -> Self {
+        Self::Time(interval.duration())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CandleEvent {
+    /// The bar in progress after a trade. Sent for every trade that doesn't close the bar
+    Update(Candle),
+    /// A finished bar. Sent exactly once per bar
+    Closed(Candle),
+}
+
+#[derive(Debug, Clone)]
+struct Bar {
+    time: DateTime<Utc>,
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    close: Decimal,
+    volume: Decimal,
+    cost: Decimal,
+    trades: u64,
+}
+
+impl Bar {
+    fn new(time: DateTime<Utc>, price: Decimal) -> Self {
+        Self {
+            time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: Decimal::ZERO,
+            cost: Decimal::ZERO,
+            trades: 0,
+        }
+    }
+
+    fn add(&mut self, price: Decimal, volume: Decimal) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume += volume;
+        self.cost += price * volume;
+        self.trades += 1;
+    }
+
+    fn candle(&self) -> Candle {
+        let vwap = if self.volume.is_zero() {
+            self.close
+        } else {
+            self.cost / self.volume
+        };
+        Candle::new(
+            self.time,
+            self.open,
+            self.high,
+            self.low,
+            self.close,
+            vwap,
+            self.volume,
+            self.trades,
+        )
+    }
+}
+
+/// Builds candles from a stream of trades.
+///
+/// Trades from the REST api and both websocket apis can be pushed after converting them into
+/// [Trade]. Time bars are only started by trades so intervals without any trades have no bar.
+/// A trade that would overfill a volume or notional bar is split between the bars, in which case
+/// it counts as a trade in both.
+#[derive(Debug, Clone)]
+pub struct CandleAggregator {
+    rule: BarRule,
+    current: Option<Bar>,
+}
+
+impl CandleAggregator {
+    pub fn new(rule: impl Into<BarRule>) -> Self {
+        Self {
+            rule: rule.into(),
+            current: None,
+        }
+    }
+
+    pub fn rule(&self) -> &BarRule {
+        &self.rule
+    }
+
+    /// The bar in progress
+    pub fn current(&self) -> Option<Candle> {
+        self.current.as_ref().map(Bar::candle)
+    }
+
+    /// Add a trade. Trades must be pushed in the order they happened
+    pub fn push(&mut self, trade: impl Into<Trade>) -> Vec<CandleEvent> {
+        let trade = trade.into();
+        let mut events = Vec::new();
+        match self.rule {
+            BarRule::Time(duration) => {
+                let start = bucket(*trade.time(), duration);
+                if let Some(bar) = self.current.take_if(|bar| bar.time != start) {
+                    events.push(CandleEvent::Closed(bar.candle()));
+                }
+                self.current
+                    .get_or_insert_with(|| Bar::new(start, *trade.price()))
+                    .add(*trade.price(), *trade.volume());
+            }
+            BarRule::Volume(size) if size > Decimal::ZERO => {
+                self.fill(&trade, size, |bar| bar.volume, Decimal::ONE, &mut events);
+            }
+            BarRule::Notional(size) if size > Decimal::ZERO && *trade.price() > Decimal::ZERO => {
+                self.fill(&trade, size, |bar| bar.cost, *trade.price(), &mut events);
+            }
+            // a bar that can never fill
+            BarRule::Volume(_) | BarRule::Notional(_) => {}
+        }
+        if let Some(bar) = &self.current {
+            events.push(CandleEvent::Update(bar.candle()));
+        }
+        events
+    }
+
+    /// Close every time bar that ended before `now`. Lets bars close on a timer when trading is
+    /// quiet instead of waiting for the next trade
+    pub fn close_until(&mut self, now: DateTime<Utc>) -> Option<Candle> {
+        let BarRule::Time(duration) = self.rule else {
+            return None;
+        };
+        self.current
+            .take_if(|bar| bar.time + duration <= now)
+            .map(|bar| bar.candle())
+    }
+
+    /// Close the bar in progress, e.g. at the end of a backfill
+    pub fn flush(&mut self) -> Option<Candle> {
+        self.current.take().map(|bar| bar.candle())
+    }
+
+    /// Fill bars until `measure` reaches `size`, splitting the trade if needed. `unit` is how much
+    /// each unit of the trade's volume adds to the measure
+    fn fill(
+        &mut self,
+        trade: &Trade,
+        size: Decimal,
+        measure: fn(&Bar) -> Decimal,
+        unit: Decimal,
+        events: &mut Vec<CandleEvent>,
+    ) {
+        let price = *trade.price();
+        let mut remaining = *trade.volume();
+        while !remaining.is_zero() {
+            let bar = self
+                .current
+                .get_or_insert_with(|| Bar::new(*trade.time(), price));
+            let room = (size - measure(bar)) / unit;
+            let filled = remaining.min(room);
+            bar.add(price, filled);
+            remaining -= filled;
+            // filling the room closes the bar even if rounding leaves it a hair short of `size`
+            if filled == room || measure(bar) >= size {
+                events.push(CandleEvent::Closed(bar.candle()));
+                self.current = None;
+            }
+        }
+    }
+}
+
+/// Start of the bar containing `time`, aligned to the unix epoch
+fn bucket(time: DateTime<Utc>, duration: chrono::Duration) -> DateTime<Utc> {
+    let step = duration.num_seconds().max(1);
+    let seconds = time.timestamp();
+    Utc.timestamp_opt(seconds - seconds.rem_euclid(step), 0)
+        .single()
+        .unwrap_or(time)
+}
+
+#[cfg(test)]
+mod aggregator_tests {
+    use super::*;
+    use crate::market_data::{Side, TradeType};
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+
+    fn trade(price: Decimal, volume: Decimal, seconds: i64) -> Trade {
+        Trade::new(
+            price,
+            volume,
+            Utc.timestamp_opt(seconds, 0).unwrap(),
+            Side::Buy,
+            TradeType::Market,
+            None,
+        )
+    }
+
+    fn closed(events: &[CandleEvent]) -> Vec<&Candle> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                CandleEvent::Closed(candle) => Some(candle),
+                CandleEvent::Update(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn time_bars_close_once_on_the_next_bar() {
+        let mut aggregator = CandleAggregator::new(BarRule::Time(chrono::Duration::minutes(2)));
+        let events = aggregator.push(trade(dec!(10), dec!(1), 0));
+        assert!(matches!(events[..], [CandleEvent::Update(_)]));
+        aggregator.push(trade(dec!(12), dec!(1), 60));
+        aggregator.push(trade(dec!(8), dec!(2), 119));
+
+        let events = aggregator.push(trade(dec!(11), dec!(1), 300));
+        let bars = closed(&events);
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].time().timestamp(), 0);
+        assert_eq!(*bars[0].high(), dec!(12));
+        assert_eq!(*bars[0].low(), dec!(8));
+        assert_eq!(*bars[0].close(), dec!(8));
+        assert_eq!(*bars[0].vwap(), dec!(9.5));
+        assert_eq!(*bars[0].trades(), 3);
+        assert_eq!(
+            aggregator.current().map(|bar| bar.time().timestamp()),
+            Some(240)
+        );
+
+        assert!(aggregator
+            .close_until(Utc.timestamp_opt(300, 0).unwrap())
+            .is_none());
+        let bar = aggregator.close_until(Utc.timestamp_opt(360, 0).unwrap());
+        assert_eq!(bar.map(|bar| *bar.open()), Some(dec!(11)));
+        assert!(aggregator.current().is_none());
+    }
+
+    #[test]
+    fn volume_bars_split_trades() {
+        let mut aggregator = CandleAggregator::new(BarRule::Volume(dec!(2)));
+        assert!(closed(&aggregator.push(trade(dec!(10), dec!(1.5), 0))).is_empty());
+        let events = aggregator.push(trade(dec!(20), dec!(3), 1));
+        let bars = closed(&events);
+        assert_eq!(bars.len(), 2);
+        assert_eq!(*bars[0].volume(), dec!(2));
+        assert_eq!(*bars[0].close(), dec!(20));
+        assert_eq!(*bars[1].volume(), dec!(2));
+        assert_eq!(*bars[1].trades(), 1);
+        assert_eq!(
+            aggregator.current().map(|bar| *bar.volume()),
+            Some(dec!(0.5))
+        );
+        assert!(matches!(events.last(), Some(CandleEvent::Update(_))));
+    }
+
+    #[test]
+    fn notional_bars() {
+        let mut aggregator = CandleAggregator::new(BarRule::Notional(dec!(100)));
+        let bars = aggregator.push(trade(dec!(50), dec!(3), 0));
+        let bars = closed(&bars);
+        assert_eq!(bars.len(), 1);
+        assert_eq!(*bars[0].volume(), dec!(2));
+        assert_eq!(aggregator.flush().map(|bar| *bar.volume()), Some(dec!(1)));
+    }
+
+    #[test]
+    fn notional_bars_hold_trades_at_different_prices() {
+        let mut aggregator = CandleAggregator::new(BarRule::Notional(dec!(100)));
+        let mut bars = Vec::new();
+        for (index, (price, volume)) in [
+            (dec!(3), dec!(50)),
+            (dec!(7), dec!(100)),
+            (dec!(26492.3), dec!(0.0123)),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let events = aggregator.push(trade(price, volume, index as i64));
+            bars.extend(closed(&events).into_iter().cloned());
+        }
+        // 150 + 700 + 325.855 traded
+        assert_eq!(bars.len(), 11);
+        for bar in &bars {
+            let vwap = bar.vwap().round_dp(8);
+            assert!(vwap >= *bar.low() && vwap <= *bar.high());
+            assert_eq!((bar.vwap() * bar.volume()).round_dp(8), dec!(100));
+        }
+        assert_eq!(*bars[1].low(), dec!(3));
+        assert_eq!(*bars[1].high(), dec!(7));
+        let rest = aggregator.flush().expect("bar in progress");
+        assert_eq!(*rest.volume(), dec!(0.0123) - dec!(250) / dec!(26492.3));
+    }
+}
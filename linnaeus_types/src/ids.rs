@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// Canonical identifier of an asset. This is the key kraken uses for the asset in REST
+/// responses, e.g. `XXBT` or `ZUSD`.
+///
+/// Use an asset registry to resolve the other names of an asset to its id
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct AssetId(String);
+
+/// Canonical identifier of a trading pair. This is the key kraken uses for the pair in REST
+/// responses, e.g. `XXBTZUSD`.
+///
+/// Use an asset registry to resolve the altname and websocket name of a pair to its id
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct PairId(String);
+
+macro_rules! impl_id {
+    ($id:ident) => {
+        impl $id {
+            pub fn new(id: &str) -> Self {
+                Self(id.to_string())
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Display for $id {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl AsRef<str> for $id {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+    };
+}
+
+impl_id!(AssetId);
+impl_id!(PairId);
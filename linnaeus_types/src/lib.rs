@@ -0,0 +1,15 @@
+//! Transport independent market data types and identifiers.
+//!
+//! The REST api, the v1 websocket and the v2 websocket all describe the same market data in
+//! slightly different shapes. Each transport crate provides `From` conversions into the types
+//! here so that snapshots and live updates can be fed into the same code.
+
+pub mod aggregator;
+pub mod ids;
+pub mod market_data;
+pub mod pair;
+
+pub use aggregator::{BarRule, CandleAggregator, CandleEvent};
+pub use ids::{AssetId, PairId};
+pub use market_data::{Candle, Interval, PriceLevel, Side, Ticker, Trade, TradeType};
+pub use pair::{Pair, ParsePairError};
@@ -0,0 +1,259 @@
+// the derived constructors take every field
+#![allow(clippy::too_many_arguments)]
+
+use chrono::{DateTime, Utc};
+use derive_getters::Getters;
+use derive_new::new;
+use display_json::{DebugAsJson, DisplayAsJsonPretty};
+use rust_decimal::Decimal;
+use serde::de::{Error as DeError, Unexpected};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use strum::Display as EnumDisplay;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, EnumDisplay)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn opposite(&self) -> Self {
+        match self {
+            Self::Buy => Self::Sell,
+            Self::Sell => Self::Buy,
+        }
+    }
+}
+
+/// Whether the taker of a trade sent a market or a limit order
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, EnumDisplay)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeType {
+    Market,
+    Limit,
+}
+
+/// Candle length. Serialized as a number of minutes and compared and hashed by its minutes so
+/// `Other(5)` is `FiveMin`
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Interval {
+    #[default]
+    OneMin,
+    FiveMin,
+    FifteenMin,
+    ThirtyMin,
+    OneHour,
+    FourHour,
+    OneDay,
+    OneWeek,
+    FifteenDay,
+    /// An interval kraken doesn't currently offer
+    Other(u32),
+}
+
+impl Interval {
+    pub fn minutes(&self) -> u32 {
+        u32::from(*self)
+    }
+
+    pub fn duration(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.minutes() as i64)
+    }
+}
+
+impl From<u32> for Interval {
+    fn from(minutes: u32) -> Self {
+        match minutes {
+            1 => Self::OneMin,
+            5 => Self::FiveMin,
+            15 => Self::FifteenMin,
+            30 => Self::ThirtyMin,
+            60 => Self::OneHour,
+            240 => Self::FourHour,
+            1440 => Self::OneDay,
+            10080 => Self::OneWeek,
+            21600 => Self::FifteenDay,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<Interval> for u32 {
+    fn from(interval: Interval) -> Self {
+        match interval {
+            Interval::OneMin => 1,
+            Interval::FiveMin => 5,
+            Interval::FifteenMin => 15,
+            Interval::ThirtyMin => 30,
+            Interval::OneHour => 60,
+            Interval::FourHour => 240,
+            Interval::OneDay => 1440,
+            Interval::OneWeek => 10080,
+            Interval::FifteenDay => 21600,
+            Interval::Other(minutes) => minutes,
+        }
+    }
+}
+
+impl PartialEq for Interval {
+    fn eq(&self, other: &Self) -> bool {
+        self.minutes() == other.minutes()
+    }
+}
+
+impl Eq for Interval {}
+
+impl PartialOrd for Interval {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Interval {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.minutes().cmp(&other.minutes())
+    }
+}
+
+impl Hash for Interval {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.minutes().hash(state)
+    }
+}
+
+impl Serialize for Interval {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.minutes())
+    }
+}
+
+/// The websocket sends the number of minutes as a number or as a string containing the number
+impl<'de> Deserialize<'de> for Interval {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Minutes {
+            Number(u32),
+            Text(String),
+        }
+        match Minutes::deserialize(deserializer)? {
+            Minutes::Number(minutes) => Ok(minutes.into()),
+            Minutes::Text(text) => text.parse::<u32>().map(Into::into).map_err(|_| {
+                D::Error::invalid_value(Unexpected::Str(&text), &"a number of minutes")
+            }),
+        }
+    }
+}
+
+/// One OHLC bar
+#[derive(
+    Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, new, Clone, PartialEq,
+)]
+pub struct Candle {
+    /// Start of the interval the candle covers
+    time: DateTime<Utc>,
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    close: Decimal,
+    vwap: Decimal,
+    volume: Decimal,
+    trades: u64,
+}
+
+/// A public trade
+#[derive(
+    Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, new, Clone, PartialEq,
+)]
+pub struct Trade {
+    price: Decimal,
+    volume: Decimal,
+    time: DateTime<Utc>,
+    side: Side,
+    trade_type: TradeType,
+    /// Only the v2 websocket api sends trade ids
+    trade_id: Option<u64>,
+}
+
+/// A level of the aggregated order book
+#[derive(
+    Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, new, Clone, PartialEq,
+)]
+pub struct PriceLevel {
+    price: Decimal,
+    volume: Decimal,
+    /// Time of the last change to the level. Not sent by the v2 websocket api
+    timestamp: Option<DateTime<Utc>>,
+}
+
+/// Top of book and rolling 24 hour statistics
+#[derive(
+    Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, new, Clone, PartialEq,
+)]
+pub struct Ticker {
+    bid: Decimal,
+    bid_volume: Decimal,
+    ask: Decimal,
+    ask_volume: Decimal,
+    last: Decimal,
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    volume: Decimal,
+    vwap: Decimal,
+}
+
+impl Ticker {
+    pub fn spread(&self) -> Decimal {
+        self.ask - self.bid
+    }
+
+    pub fn mid(&self) -> Decimal {
+        (self.ask + self.bid) / Decimal::TWO
+    }
+}
+
+#[cfg(test)]
+mod market_data_tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn intervals_round_trip_as_minutes() {
+        assert_eq!(Interval::from(240), Interval::FourHour);
+        assert_eq!(Interval::from(3), Interval::Other(3));
+        assert_eq!(Interval::OneDay.duration(), chrono::Duration::days(1));
+        assert_eq!(
+            serde_json::to_string(&Interval::FifteenMin).expect("couldn't serialize interval"),
+            "15"
+        );
+        let interval: Interval = serde_json::from_str("10080").expect("couldn't parse interval");
+        assert_eq!(interval, Interval::OneWeek);
+        let interval: Interval = serde_json::from_str(r#""60""#).expect("couldn't parse interval");
+        assert_eq!(interval, Interval::OneHour);
+        assert_eq!(Interval::Other(5), Interval::FiveMin);
+    }
+
+    #[test]
+    fn ticker_spread() {
+        let ticker = Ticker::new(
+            dec!(99),
+            dec!(1),
+            dec!(101),
+            dec!(2),
+            dec!(100),
+            dec!(90),
+            dec!(110),
+            dec!(85),
+            dec!(1000),
+            dec!(98),
+        );
+        assert_eq!(ticker.spread(), dec!(2));
+        assert_eq!(ticker.mid(), dec!(100));
+        assert_eq!(Side::Buy.opposite(), Side::Sell);
+    }
+}
@@ -0,0 +1,82 @@
+use derive_getters::Getters;
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("expected a pair in the form BASE/QUOTE. Got {0}")]
+pub struct ParsePairError(String);
+
+/// A trading pair in the `BASE/QUOTE` form used by the websocket apis.
+///
+/// The asset names are kept exactly as kraken sent them. REST names such as `XXBTZUSD` have no
+/// separator and can't be parsed without knowing the assets
+#[derive(
+    SerializeDisplay,
+    DeserializeFromStr,
+    Debug,
+    Getters,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+)]
+pub struct Pair {
+    base: String,
+    quote: String,
+}
+
+impl Pair {
+    pub fn new(base: &str, quote: &str) -> Self {
+        Self {
+            base: base.to_string(),
+            quote: quote.to_string(),
+        }
+    }
+}
+
+impl Display for Pair {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.base, self.quote)
+    }
+}
+
+impl FromStr for Pair {
+    type Err = ParsePairError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some((base, quote))
+                if !base.is_empty() && !quote.is_empty() && !quote.contains('/') =>
+            {
+                Ok(Self::new(base, quote))
+            }
+            _ => Err(ParsePairError(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod pair_tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_and_display() {
+        let pair: Pair = "XBT/USD".parse().expect("couldn't parse pair");
+        assert_eq!(pair.base(), "XBT");
+        assert_eq!(pair.quote(), "USD");
+        assert_eq!(pair.to_string(), "XBT/USD");
+        assert_eq!(
+            serde_json::to_string(&pair).expect("couldn't serialize pair"),
+            "\"XBT/USD\""
+        );
+
+        assert!("XXBTZUSD".parse::<Pair>().is_err());
+        assert!("XBT/".parse::<Pair>().is_err());
+        assert!("A/B/C".parse::<Pair>().is_err());
+    }
+}
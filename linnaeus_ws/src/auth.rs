@@ -0,0 +1,117 @@
+use crate::error::LinnaeusWebsocketError;
+use chrono::Utc;
+use derive_getters::Getters;
+use futures::future::BoxFuture;
+use log::{info, trace};
+use std::fmt::Formatter;
+use std::sync::Arc;
+
+/// A token used to authenticate private subscriptions and order events on ws-auth.kraken.com
+#[derive(Debug, Clone, Getters)]
+pub struct AuthToken {
+    token: String,
+    expiry_time: chrono::DateTime<Utc>,
+}
+
+impl AuthToken {
+    pub fn new(token: String, expiry_time: chrono::DateTime<Utc>) -> Self {
+        Self { token, expiry_time }
+    }
+
+    /// true if the token will still be valid after margin has passed
+    pub fn valid_for(&self, margin: chrono::Duration) -> bool {
+        Utc::now() + margin < self.expiry_time
+    }
+}
+
+/// Source of fresh websocket tokens. Usually implemented by something that can call the
+/// GetWebSocketsToken REST endpoint.
+pub trait TokenProvider: Send + Sync {
+    fn fetch_token(&self) -> BoxFuture<'_, Result<AuthToken, LinnaeusWebsocketError>>;
+}
+
+/// Caches the current token and renews it when it's close to expiring.
+///
+/// Kraken only requires the token to be valid when a subscription or order event is sent so it only
+/// needs to be renewed when reconnecting or making new requests.
+pub(crate) struct TokenManager {
+    provider: Arc<dyn TokenProvider>,
+    current: tokio::sync::Mutex<Option<AuthToken>>,
+    renewal_margin: chrono::Duration,
+}
+
+impl std::fmt::Debug for TokenManager {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenManager")
+            .field("renewal_margin", &self.renewal_margin)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TokenManager {
+    pub(crate) fn new(provider: Arc<dyn TokenProvider>) -> Self {
+        Self {
+            provider,
+            current: Default::default(),
+            renewal_margin: chrono::Duration::seconds(60),
+        }
+    }
+
+    pub(crate) async fn token(&self) -> Result<String, LinnaeusWebsocketError> {
+        let mut current = self.current.lock().await;
+        if let Some(token) = current.as_ref() {
+            if token.valid_for(self.renewal_margin) {
+                trace!("re-using websocket token that expires at {}", token.expiry_time);
+                return Ok(token.token.clone());
+            }
+        }
+        let token = self.provider.fetch_token().await?;
+        info!("fetched new websocket token that expires at {}", token.expiry_time);
+        let token_string = token.token.clone();
+        *current = Some(token);
+        Ok(token_string)
+    }
+}
+
+#[cfg(test)]
+mod auth_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingProvider {
+        lifetime: chrono::Duration,
+        count: AtomicUsize,
+    }
+
+    impl TokenProvider for CountingProvider {
+        fn fetch_token(&self) -> BoxFuture<'_, Result<AuthToken, LinnaeusWebsocketError>> {
+            Box::pin(async move {
+                let n = self.count.fetch_add(1, Ordering::SeqCst);
+                Ok(AuthToken::new(format!("token-{}", n), Utc::now() + self.lifetime))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn token_is_reused_until_close_to_expiry() {
+        let provider = Arc::new(CountingProvider {
+            lifetime: chrono::Duration::minutes(15),
+            count: AtomicUsize::new(0),
+        });
+        let manager = TokenManager::new(provider.clone());
+        assert_eq!(manager.token().await.unwrap(), "token-0");
+        assert_eq!(manager.token().await.unwrap(), "token-0");
+        assert_eq!(provider.count.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn token_is_renewed_before_expiry() {
+        let provider = Arc::new(CountingProvider {
+            lifetime: chrono::Duration::seconds(30),
+            count: AtomicUsize::new(0),
+        });
+        let manager = TokenManager::new(provider.clone());
+        assert_eq!(manager.token().await.unwrap(), "token-0");
+        assert_eq!(manager.token().await.unwrap(), "token-1");
+    }
+}
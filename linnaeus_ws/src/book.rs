@@ -0,0 +1,254 @@
+use crate::error::LinnaeusWebsocketError;
+use crate::liveness::ConnectionHealth;
+use crate::messages::general_messages::Depth;
+use crate::messages::public_messages::{Book, PriceLevel};
+use crate::messages::{ChannelMessageWrapper, Pair};
+use crate::stream;
+use crate::subscription::{recv_counted, Received, SubscriptionMetrics, SubscriptionReceiver};
+use crate::LinnaeusWebsocket;
+use log::warn;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Local copy of a level 2 order book built from a book subscription.
+///
+/// The book starts out invalid and becomes valid when a snapshot is applied. If an update is
+/// lost the book must be invalidated and a new snapshot requested. Updates applied to an invalid
+/// book are ignored.
+#[derive(Debug, Clone)]
+pub struct LocalBook {
+    pair: Pair,
+    depth: Depth,
+    asks: BTreeMap<Decimal, PriceLevel>,
+    bids: BTreeMap<Decimal, PriceLevel>,
+    valid: bool,
+}
+
+impl LocalBook {
+    pub fn new(pair: Pair, depth: Depth) -> Self {
+        Self {
+            pair,
+            depth,
+            asks: Default::default(),
+            bids: Default::default(),
+            valid: false,
+        }
+    }
+
+    pub fn pair(&self) -> &Pair {
+        &self.pair
+    }
+
+    pub fn depth(&self) -> Depth {
+        self.depth
+    }
+
+    /// false until a snapshot has been applied and after the book has been invalidated
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    /// Mark the book as out of date and clear it
+    pub fn invalidate(&mut self) {
+        self.valid = false;
+        self.asks.clear();
+        self.bids.clear();
+    }
+
+    /// Apply a snapshot or an update. Returns false if the update was ignored because the book
+    /// is waiting for a snapshot
+    pub fn apply(&mut self, book: &Book) -> bool {
+        if *book.snapshot() {
+            self.asks.clear();
+            self.bids.clear();
+            self.valid = true;
+        } else if !self.valid {
+            return false;
+        }
+        let depth = u16::from(self.depth) as usize;
+        if let Some(asks) = book.ask_levels() {
+            apply_levels(&mut self.asks, asks);
+            while self.asks.len() > depth {
+                self.asks.pop_last();
+            }
+        }
+        if let Some(bids) = book.bid_levels() {
+            apply_levels(&mut self.bids, bids);
+            while self.bids.len() > depth {
+                self.bids.pop_first();
+            }
+        }
+        true
+    }
+
+    /// Lowest ask first
+    pub fn asks(&self) -> impl Iterator<Item = &PriceLevel> {
+        self.asks.values()
+    }
+
+    /// Highest bid first
+    pub fn bids(&self) -> impl Iterator<Item = &PriceLevel> {
+        self.bids.values().rev()
+    }
+
+    pub fn best_ask(&self) -> Option<&PriceLevel> {
+        self.asks().next()
+    }
+
+    pub fn best_bid(&self) -> Option<&PriceLevel> {
+        self.bids().next()
+    }
+
+    pub fn spread(&self) -> Option<Decimal> {
+        Some(self.best_ask()?.price() - self.best_bid()?.price())
+    }
+}
+
+/// A book subscription that keeps a local book up to date.
+///
+/// Lost updates never go unnoticed. If the subscriber falls behind, or the connection drops, the
+/// book is invalidated until a new snapshot arrives.
+#[derive(Debug)]
+pub struct LocalBookSubscription {
+    websocket: Arc<LinnaeusWebsocket>,
+    book: LocalBook,
+    receiver: SubscriptionReceiver<ChannelMessageWrapper>,
+    metrics: SubscriptionMetrics,
+    health: watch::Receiver<ConnectionHealth>,
+}
+
+impl LocalBookSubscription {
+    pub(crate) fn new(
+        websocket: Arc<LinnaeusWebsocket>,
+        pair: Pair,
+        depth: Depth,
+        receiver: SubscriptionReceiver<ChannelMessageWrapper>,
+        health: watch::Receiver<ConnectionHealth>,
+    ) -> Self {
+        Self {
+            websocket,
+            book: LocalBook::new(pair, depth),
+            receiver,
+            metrics: Default::default(),
+            health,
+        }
+    }
+
+    /// Wait for the next update to be applied. Returns None once the subscription is closed
+    pub async fn next(&mut self) -> Result<Option<&LocalBook>, LinnaeusWebsocketError> {
+        loop {
+            if self.health.has_changed().unwrap_or(false)
+                && *self.health.borrow_and_update() != ConnectionHealth::Healthy
+            {
+                // kraken sends a new snapshot once the subscription is restored
+                self.book.invalidate();
+            }
+            match recv_counted(&mut self.receiver, &self.metrics).await {
+                Received::Message(message) => {
+                    let Some((_, update)) = stream::book(*message) else {
+                        continue;
+                    };
+                    if self.book.apply(&update) {
+                        return Ok(Some(&self.book));
+                    }
+                }
+                Received::Lagged(skipped) => {
+                    warn!(
+                        "lost {} book updates for {}. Requesting a new snapshot",
+                        skipped,
+                        self.book.pair()
+                    );
+                    // the lost messages may have included a requested snapshot so always ask
+                    self.book.invalidate();
+                    self.websocket
+                        .request_book_snapshot(self.book.pair(), self.book.depth())
+                        .await?;
+                }
+                Received::Closed => return Ok(None),
+            }
+        }
+    }
+
+    /// The current state of the book. Check is_valid before using it
+    pub fn book(&self) -> &LocalBook {
+        &self.book
+    }
+
+    pub fn metrics(&self) -> &SubscriptionMetrics {
+        &self.metrics
+    }
+}
+
+fn apply_levels(side: &mut BTreeMap<Decimal, PriceLevel>, levels: &[PriceLevel]) {
+    for level in levels {
+        if level.volume().is_zero() {
+            side.remove(level.price());
+        } else {
+            side.insert(*level.price(), level.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod book_tests {
+    use super::*;
+    use crate::messages::ChannelMessage;
+    use crate::test_utils;
+    use rust_decimal_macros::dec;
+
+    fn load_book(file: &str) -> Book {
+        let j = test_utils::load_test_json(file).expect("couldn't load test json from file");
+        let message: ChannelMessageWrapper =
+            serde_json::from_str(&j).expect("couldn't deserialize message");
+        match message.message() {
+            ChannelMessage::Book(book) => book.clone(),
+            _ => panic!("expected a book message"),
+        }
+    }
+
+    #[test]
+    fn updates_are_ignored_until_snapshot() {
+        let mut book = LocalBook::new("XBT/USD".to_string(), Depth::Ten);
+        assert!(!book.apply(&load_book("public/book/book_update_a")));
+        assert!(!book.is_valid());
+        assert!(book.best_ask().is_none());
+
+        assert!(book.apply(&load_book("public/book/book_snapshot")));
+        assert!(book.is_valid());
+        assert_eq!(*book.best_ask().unwrap().price(), dec!(5541.30000));
+        assert_eq!(*book.best_bid().unwrap().price(), dec!(5541.20000));
+        assert_eq!(book.spread(), Some(dec!(0.1)));
+    }
+
+    #[test]
+    fn updates_modify_levels() {
+        let mut book = LocalBook::new("XBT/USD".to_string(), Depth::Ten);
+        book.apply(&load_book("public/book/book_snapshot"));
+        assert!(book.apply(&load_book("public/book/book_update_a")));
+        assert_eq!(book.asks().count(), 4);
+        assert_eq!(*book.best_ask().unwrap().volume(), dec!(2.50700000));
+
+        let bids: Vec<Decimal> = book.bids().map(|level| *level.price()).collect();
+        assert_eq!(bids, vec![dec!(5541.2), dec!(5539.9), dec!(5539.5)]);
+
+        // zero volume removes the level
+        let update: Book =
+            serde_json::from_str(r#"{"a":[["5541.30000","0.00000000","1534614335.345903"]]}"#)
+                .expect("couldn't deserialize book");
+        book.apply(&update);
+        assert_eq!(book.asks().count(), 3);
+        assert_eq!(*book.best_ask().unwrap().price(), dec!(5541.8));
+    }
+
+    #[test]
+    fn invalidate_clears_book() {
+        let mut book = LocalBook::new("XBT/USD".to_string(), Depth::Ten);
+        book.apply(&load_book("public/book/book_snapshot"));
+        book.invalidate();
+        assert!(!book.is_valid());
+        assert!(book.best_bid().is_none());
+        assert!(!book.apply(&load_book("public/book/book_update_b")));
+    }
+}
@@ -0,0 +1,74 @@
+use crate::liveness::StaleAction;
+use crate::recorder::Recorder;
+use derive_setters::Setters;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Setters)]
+#[setters(prefix = "with_")]
+pub struct WebsocketConfig {
+    /// Maximum time since the last message or heartbeat before the connection is stale. Kraken
+    /// sends a heartbeat every second when there is no other traffic.
+    max_silence: Duration,
+    /// How often to ping kraken. None disables pings
+    #[setters(strip_option)]
+    ping_interval: Option<Duration>,
+    /// How long a ping can go unanswered before the connection is stale
+    pong_timeout: Duration,
+    stale_action: StaleAction,
+    /// How long to wait for kraken to respond to a request such as addOrder
+    request_timeout: Duration,
+    /// How long to wait for the system status message after connecting
+    system_status_timeout: Duration,
+    /// Record every frame received from kraken
+    #[setters(strip_option)]
+    recorder: Option<Recorder>,
+}
+
+impl Default for WebsocketConfig {
+    fn default() -> Self {
+        Self {
+            max_silence: Duration::from_secs(10),
+            ping_interval: Some(Duration::from_secs(30)),
+            pong_timeout: Duration::from_secs(5),
+            stale_action: StaleAction::Reconnect,
+            request_timeout: Duration::from_secs(10),
+            system_status_timeout: Duration::from_secs(10),
+            recorder: None,
+        }
+    }
+}
+
+impl WebsocketConfig {
+    /// Only rely on heartbeats and other traffic to detect a dead connection
+    pub fn without_pings(mut self) -> Self {
+        self.ping_interval = None;
+        self
+    }
+
+    pub fn max_silence(&self) -> Duration {
+        self.max_silence
+    }
+    pub fn ping_interval(&self) -> Option<Duration> {
+        self.ping_interval
+    }
+    pub fn pong_timeout(&self) -> Duration {
+        self.pong_timeout
+    }
+    pub fn stale_action(&self) -> StaleAction {
+        self.stale_action
+    }
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+    pub fn system_status_timeout(&self) -> Duration {
+        self.system_status_timeout
+    }
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    /// how often the liveness monitor wakes up to check the connection
+    pub(crate) fn check_interval(&self) -> Duration {
+        (self.max_silence / 4).clamp(Duration::from_millis(100), Duration::from_secs(1))
+    }
+}
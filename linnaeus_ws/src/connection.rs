@@ -0,0 +1,581 @@
+use crate::auth::TokenManager;
+use crate::config::WebsocketConfig;
+use crate::error::LinnaeusWebsocketError;
+use crate::liveness::{ConnectionHealth, Liveness, StaleAction, StaleReason};
+use crate::recorder::{self, RecordedFrame, ReplaySpeed};
+use dashmap::DashMap;
+use futures::stream::{SplitSink, SplitStream};
+use futures::{Future, SinkExt, StreamExt};
+use log::{error, info, trace, warn};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::{oneshot, watch, Notify};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message as TungstenMessage};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+type ReadStream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+type WriteSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, TungstenMessage>;
+
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+fn websocket_error_is_fatal(error: &tokio_tungstenite::tungstenite::Error) -> bool {
+    use tokio_tungstenite::tungstenite::Error::*;
+    matches!(
+        error,
+        ConnectionClosed | AlreadyClosed | Io(_) | Tls(_) | Url(_) | Http(_) | HttpFormat(_)
+    )
+}
+
+/// What a protocol made of a message received while waiting for kraken to report its status
+pub(crate) enum Handshake<S> {
+    /// Not the status message. Keep waiting
+    Waiting,
+    Online(S),
+    /// Kraken isn't accepting connections or sent something unexpected
+    Offline,
+}
+
+/// The parts of a websocket client that depend on the version of the API. Everything else
+/// (connecting, reconnecting, liveness and shutdown) is handled by [Connection].
+pub(crate) trait Protocol: Send + Sync + Sized + 'static {
+    /// The status message kraken sends once the connection is open
+    type Status: Send + 'static;
+
+    fn handshake(message: &str) -> Handshake<Self::Status>;
+
+    fn connection(&self) -> &Connection;
+
+    /// Handle a text message received after the handshake
+    fn handle_message(&self, message: String) -> impl Future<Output = ()> + Send;
+
+    /// Bring a new connection up to date, usually by replaying subscriptions. The connection
+    /// isn't considered healthy until this succeeds
+    fn restore(
+        &self,
+        status: Self::Status,
+    ) -> impl Future<Output = Result<(), LinnaeusWebsocketError>> + Send;
+
+    /// The connection went stale and won't be re-established
+    fn disconnected(&self);
+
+    /// Ping kraken. Resolves to false if there was no answer within the timeout
+    fn ping_with_timeout(&self, timeout: Duration) -> impl Future<Output = bool> + Send;
+}
+
+/// The read half of a new connection. Handed to [start] once the client has been created
+pub(crate) struct Reader {
+    read: ReadStream,
+    close_receiver: oneshot::Receiver<()>,
+}
+
+/// Requests waiting for kraken to respond, keyed by request id
+#[derive(Debug)]
+pub(crate) struct PendingRequests<T> {
+    pending: DashMap<u64, oneshot::Sender<T>, ahash::RandomState>,
+}
+
+impl<T> Default for PendingRequests<T> {
+    fn default() -> Self {
+        Self {
+            pending: Default::default(),
+        }
+    }
+}
+
+impl<T> PendingRequests<T> {
+    pub(crate) fn register(&self, id: u64) -> oneshot::Receiver<T> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending.insert(id, sender);
+        receiver
+    }
+
+    /// Hand the response to whoever is waiting for it
+    pub(crate) fn complete(&self, id: u64, response: T) {
+        if let Some((_, pending)) = self.pending.remove(&id) {
+            if pending.send(response).is_err() {
+                trace!("nobody was waiting for the response to request {}", id);
+            }
+        }
+    }
+
+    pub(crate) fn remove(&self, id: u64) {
+        self.pending.remove(&id);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_pending(&self, id: u64) -> bool {
+        self.pending.contains_key(&id)
+    }
+
+    /// Responses to requests sent on an old connection will never arrive
+    pub(crate) fn clear(&self) {
+        self.pending.clear();
+    }
+}
+
+/// A connection to kraken that is re-established when it drops or goes stale
+#[derive(Debug)]
+pub(crate) struct Connection {
+    url: url::Url,
+    config: WebsocketConfig,
+    /// None when replaying a recording
+    writer: tokio::sync::Mutex<Option<WriteSink>>,
+    reader: tokio::sync::Mutex<Option<tokio::task::JoinHandle<ReadStream>>>,
+    closer: oneshot::Sender<()>,
+    request_id: AtomicU64,
+    token_manager: Option<TokenManager>,
+    liveness: Liveness,
+    health: watch::Sender<ConnectionHealth>,
+    /// notified by the liveness monitor when the connection has gone stale
+    stale: Notify,
+}
+
+impl Connection {
+    /// Connect and wait for kraken to report that it's online
+    pub(crate) async fn open<P: Protocol>(
+        url: &str,
+        config: WebsocketConfig,
+        token_manager: Option<TokenManager>,
+    ) -> Result<(Self, Reader, P::Status), LinnaeusWebsocketError> {
+        if !url.starts_with("wss://") {
+            return Err(LinnaeusWebsocketError::Url {
+                reason: "websocket url must start with \"wss://\"",
+            });
+        }
+        let url = url::Url::parse(url)?;
+
+        let (write, read, status) = open_socket::<P>(&url, &config).await?;
+        let (close_sender, close_receiver) = oneshot::channel();
+
+        let connection = Self {
+            url,
+            config,
+            writer: tokio::sync::Mutex::new(Some(write)),
+            reader: Default::default(),
+            closer: close_sender,
+            request_id: Default::default(),
+            token_manager,
+            liveness: Liveness::new(),
+            health: watch::channel(ConnectionHealth::Healthy).0,
+            stale: Default::default(),
+        };
+        Ok((
+            connection,
+            Reader {
+                read,
+                close_receiver,
+            },
+            status,
+        ))
+    }
+
+    /// A connection that is fed from a recording instead of kraken. Nothing is sent anywhere
+    pub(crate) fn replay(config: WebsocketConfig) -> Self {
+        Self {
+            url: url::Url::parse("replay:recording").expect("the replay url is valid"),
+            config,
+            writer: Default::default(),
+            reader: Default::default(),
+            closer: oneshot::channel().0,
+            request_id: Default::default(),
+            token_manager: None,
+            liveness: Liveness::new(),
+            health: watch::channel(ConnectionHealth::Healthy).0,
+            stale: Default::default(),
+        }
+    }
+
+    pub(crate) fn config(&self) -> &WebsocketConfig {
+        &self.config
+    }
+
+    pub(crate) fn health(&self) -> watch::Receiver<ConnectionHealth> {
+        self.health.subscribe()
+    }
+
+    pub(crate) fn next_id(&self) -> u64 {
+        self.request_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub(crate) fn is_authenticated(&self) -> bool {
+        self.token_manager.is_some()
+    }
+
+    pub(crate) async fn token(
+        &self,
+        request: &'static str,
+    ) -> Result<String, LinnaeusWebsocketError> {
+        match &self.token_manager {
+            Some(token_manager) => token_manager.token().await,
+            None => Err(LinnaeusWebsocketError::NotAuthenticated(request)),
+        }
+    }
+
+    pub(crate) async fn send_json<T: Serialize>(
+        &self,
+        message: &T,
+    ) -> Result<(), LinnaeusWebsocketError> {
+        let json = serde_json::to_string(message)?;
+        trace!("sending json over websocket {}", json);
+        let mut writer = self.writer.lock().await;
+        match writer.as_mut() {
+            Some(writer) => writer.send(TungstenMessage::Text(json)).await?,
+            None => trace!("replaying a recording. Message wasn't sent"),
+        }
+        Ok(())
+    }
+
+    /// Send a message and wait for the response kraken sends back with the same request id
+    pub(crate) async fn request<T, M: Serialize>(
+        &self,
+        pending: &PendingRequests<T>,
+        id: u64,
+        message: &M,
+    ) -> Result<T, LinnaeusWebsocketError> {
+        let receiver = pending.register(id);
+
+        if let Err(err) = self.send_json(message).await {
+            pending.remove(id);
+            return Err(err);
+        }
+
+        match tokio::time::timeout(self.config.request_timeout(), receiver).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(LinnaeusWebsocketError::ResponseDropped(id)),
+            Err(_) => {
+                pending.remove(id);
+                Err(LinnaeusWebsocketError::RequestTimeout(id))
+            }
+        }
+    }
+
+    pub(crate) async fn shutdown(self) {
+        self.health.send_replace(ConnectionHealth::Closed);
+        let Some(writer) = self.writer.into_inner() else {
+            // replaying, there is no websocket to close
+            return;
+        };
+        if self.closer.send(()).is_err() {
+            warn!("the reader had already stopped before shutdown");
+        }
+        let reader_join_handle;
+        {
+            let mut reader = self.reader.lock().await;
+            let Some(handle) = reader.take() else {
+                warn!("couldn't get the join handle during shutdown");
+                return;
+            };
+            reader_join_handle = handle;
+        }
+
+        let Ok(read_sink) = reader_join_handle.await else {
+            warn!("couldn't get read stream from jh during shutdown");
+            return;
+        };
+        let Ok(mut websocket) = writer.reunite(read_sink) else {
+            warn!("couldn't reunite sender and receiver for websocket shutdown");
+            return;
+        };
+
+        if let Err(err) = websocket
+            .close(Some(CloseFrame {
+                code: CloseCode::Normal,
+                reason: Default::default(),
+            }))
+            .await
+        {
+            error!(
+                "error while sending close message over websocket -> {}",
+                err
+            );
+        }
+    }
+}
+
+/// Start reading from the connection and monitoring its liveness
+pub(crate) async fn start<P: Protocol>(client: &Arc<P>, reader: Reader) {
+    let reader_handle = tokio::spawn(read_loop(
+        client.clone(),
+        reader.read,
+        reader.close_receiver,
+    ));
+    {
+        let mut reader = client.connection().reader.lock().await;
+        *reader = Some(reader_handle);
+    }
+    tokio::spawn(monitor(Arc::downgrade(client)));
+}
+
+/// Open a new connection and wait for kraken to report that it's online
+async fn open_socket<P: Protocol>(
+    url: &url::Url,
+    config: &WebsocketConfig,
+) -> Result<(WriteSink, ReadStream, P::Status), LinnaeusWebsocketError> {
+    let (ws_stream, _) = connect_async(url.clone()).await?;
+    info!("WebSocket handshake has been successfully completed");
+    let (write, mut read) = ws_stream.split();
+
+    match wait_for_status::<P>(&mut read, config).await {
+        Some(status) => Ok((write, read, status)),
+        None => Err(LinnaeusWebsocketError::KrakenOffline),
+    }
+}
+
+async fn wait_for_status<P: Protocol>(
+    read: &mut ReadStream,
+    config: &WebsocketConfig,
+) -> Option<P::Status> {
+    let timer = tokio::time::timeout(config.system_status_timeout(), async {
+        while let Some(msg) = read.next().await {
+            let Ok(TungstenMessage::Text(msg)) = msg else {
+                return None;
+            };
+            if let Some(recorder) = config.recorder() {
+                recorder.record(&msg);
+            }
+            match P::handshake(&msg) {
+                Handshake::Waiting => continue,
+                Handshake::Online(status) => return Some(status),
+                Handshake::Offline => return None,
+            }
+        }
+        None
+    })
+    .await;
+
+    timer.unwrap_or(None)
+}
+
+/// Re-establish the connection and restore the client's state. Returns None if the websocket
+/// was shutdown while reconnecting.
+async fn reconnect<P: Protocol>(
+    client: &P,
+    close_receiver: &mut oneshot::Receiver<()>,
+) -> Option<ReadStream> {
+    let connection = client.connection();
+    connection
+        .health
+        .send_replace(ConnectionHealth::Reconnecting);
+    let mut delay = Duration::from_secs(1);
+    loop {
+        let attempt = tokio::select! {
+            attempt = open_socket::<P>(&connection.url, &connection.config) => attempt,
+            _ = &mut *close_receiver => return None,
+        };
+        match attempt {
+            Ok((write, read, status)) => {
+                {
+                    let mut writer = connection.writer.lock().await;
+                    *writer = Some(write);
+                }
+                match client.restore(status).await {
+                    Ok(_) => {
+                        info!("reconnected to {}", connection.url);
+                        connection.liveness.reset();
+                        connection.health.send_replace(ConnectionHealth::Healthy);
+                        return Some(read);
+                    }
+                    Err(err) => error!("failed to resubscribe after reconnecting -> {}", err),
+                }
+            }
+            Err(err) => warn!("failed to reconnect to {} -> {}", connection.url, err),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {},
+            _ = &mut *close_receiver => return None,
+        }
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+async fn read_loop<P: Protocol>(
+    client: Arc<P>,
+    mut read: ReadStream,
+    mut close_receiver: oneshot::Receiver<()>,
+) -> ReadStream {
+    let client = client.as_ref();
+    let connection = client.connection();
+
+    loop {
+        let msg: Option<Result<TungstenMessage, _>> = tokio::select! {
+            msg = read.next() => msg,
+            _ = connection.stale.notified() => {
+                if connection.config.stale_action() == StaleAction::Disconnect {
+                    warn!("disconnecting all subscribers from {}", connection.url);
+                    client.disconnected();
+                    connection.health.send_replace(ConnectionHealth::Closed);
+                    return read;
+                }
+                match reconnect(client, &mut close_receiver).await {
+                    Some(new_read) => {
+                        read = new_read;
+                        continue;
+                    }
+                    None => return read,
+                }
+            }
+            _ = &mut close_receiver => {
+                return read;
+            }
+        };
+
+        let msg = match msg {
+            Some(Ok(msg)) => msg,
+            Some(Err(err)) if !websocket_error_is_fatal(&err) => {
+                error!("error while reading from websocket -> {}", err);
+                continue;
+            }
+            Some(Err(err)) => {
+                error!("fatal error while reading from websocket -> {}", err);
+                match reconnect(client, &mut close_receiver).await {
+                    Some(new_read) => {
+                        read = new_read;
+                        continue;
+                    }
+                    None => return read,
+                }
+            }
+            None => {
+                warn!("websocket stream ended");
+                match reconnect(client, &mut close_receiver).await {
+                    Some(new_read) => {
+                        read = new_read;
+                        continue;
+                    }
+                    None => return read,
+                }
+            }
+        };
+        connection.liveness.record_activity();
+        let msg = match msg {
+            TungstenMessage::Text(msg) => msg,
+            _ => {
+                warn!("got non text based message on the websocket");
+                continue;
+            }
+        };
+
+        trace!("got new json message {}", msg);
+        if let Some(recorder) = connection.config.recorder() {
+            recorder.record(&msg);
+        }
+        client.handle_message(msg).await;
+    }
+}
+
+/// Feed recorded frames through the client as if they had just been read from the websocket
+pub(crate) async fn replay<P: Protocol>(
+    client: &P,
+    recording: PathBuf,
+    speed: ReplaySpeed,
+) -> std::io::Result<()> {
+    // decompressing blocks so the frames are read on another thread
+    let (sender, mut frames) = tokio::sync::mpsc::channel::<RecordedFrame>(1024);
+    let reading = tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+        for frame in recorder::read_recording(recording)? {
+            match frame {
+                Ok(frame) => {
+                    if sender.blocking_send(frame).is_err() {
+                        return Ok(());
+                    }
+                }
+                Err(err) => recorder::warn_unreadable(&err),
+            }
+        }
+        Ok(())
+    });
+
+    let started = tokio::time::Instant::now();
+    let mut first_received = None;
+    while let Some(frame) = frames.recv().await {
+        let first = *first_received.get_or_insert(*frame.received_at());
+        match speed.offset(*frame.received_at() - first) {
+            Some(offset) => tokio::time::sleep_until(started + offset).await,
+            // let subscribers keep up
+            None => tokio::task::yield_now().await,
+        }
+        client.connection().liveness.record_activity();
+        client.handle_message(frame.frame().clone()).await;
+    }
+    reading.await.map_err(std::io::Error::other)?
+}
+
+/// Periodically checks that kraken is still talking to us. Stops once the websocket is closed
+/// or dropped.
+async fn monitor<P: Protocol>(client: Weak<P>) {
+    let check_interval = match client.upgrade() {
+        Some(client) => client.connection().config.check_interval(),
+        None => return,
+    };
+    loop {
+        tokio::time::sleep(check_interval).await;
+        let Some(client) = client.upgrade() else {
+            return;
+        };
+        let connection = client.connection();
+        let health = *connection.health.borrow();
+        match health {
+            ConnectionHealth::Closed => return,
+            // the reader is busy dealing with it
+            ConnectionHealth::Stale(_) | ConnectionHealth::Reconnecting => continue,
+            ConnectionHealth::Healthy => {}
+        }
+        if let Some(reason) = check_liveness(client.as_ref()).await {
+            warn!(
+                "websocket connection to {} is stale -> {:?}",
+                connection.url, reason
+            );
+            connection
+                .health
+                .send_replace(ConnectionHealth::Stale(reason));
+            connection.stale.notify_one();
+        }
+    }
+}
+
+async fn check_liveness<P: Protocol>(client: &P) -> Option<StaleReason> {
+    let connection = client.connection();
+    let config = &connection.config;
+    if let Some(silence) = connection.liveness.silence(config.max_silence()) {
+        return Some(StaleReason::Silence(silence));
+    }
+    if !connection.liveness.ping_due(config.ping_interval()) {
+        return None;
+    }
+    if client.ping_with_timeout(config.pong_timeout()).await {
+        None
+    } else {
+        Some(StaleReason::PongTimeout)
+    }
+}
+
+#[cfg(test)]
+mod connection_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pending_requests_are_completed_by_id() {
+        let pending = PendingRequests::default();
+        let first = pending.register(1);
+        let second = pending.register(2);
+        pending.complete(2, "two");
+        pending.complete(1, "one");
+        // responses to unknown requests are ignored
+        pending.complete(3, "three");
+        assert_eq!(first.await.unwrap(), "one");
+        assert_eq!(second.await.unwrap(), "two");
+    }
+
+    #[tokio::test]
+    async fn cleared_requests_are_dropped() {
+        let pending: PendingRequests<()> = PendingRequests::default();
+        let receiver = pending.register(1);
+        pending.clear();
+        assert!(receiver.await.is_err());
+    }
+}
@@ -11,5 +11,17 @@ pub enum LinnaeusWebsocketError {
     #[error("Invalid websocket url -> {reason}")]
     Url{reason: &'static str},
     #[error("Kraken is not online")]
-    KrakenOffline
+    KrakenOffline,
+    #[error("couldn't get a websocket token -> {0}")]
+    Authentication(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("{0} requires an authenticated websocket connection")]
+    NotAuthenticated(&'static str),
+    #[error("timed out waiting for a response to request {0}")]
+    RequestTimeout(u64),
+    #[error("the connection was lost before a response to request {0} was received")]
+    ResponseDropped(u64),
+    #[error("unexpected response to request -> {0}")]
+    UnexpectedResponse(String),
+    #[error("kraken rejected the request -> {0}")]
+    Kraken(String),
 }
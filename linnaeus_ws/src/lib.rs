@@ -1,218 +1,297 @@
 extern crate core;
 
+pub mod auth;
+pub mod book;
+pub mod config;
+mod connection;
 pub mod error;
+pub mod liveness;
 pub mod messages;
+pub mod recorder;
+pub mod sequence;
+pub mod stream;
+pub mod subscription;
 #[cfg(test)]
 mod test_utils;
+pub mod v2;
+
+pub use linnaeus_types as types;
 
-use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast;
 
-use crate::messages::{Channel, ChannelMessageWrapper, Event, EventType};
-use futures::stream::{SplitSink, SplitStream};
-use futures::{SinkExt, StreamExt};
+use crate::auth::{TokenManager, TokenProvider};
+use crate::book::LocalBookSubscription;
+use crate::config::WebsocketConfig;
+use crate::connection::{Connection, Handshake, PendingRequests, Protocol};
+use crate::liveness::ConnectionHealth;
+use crate::messages::general_messages::{Depth, Interval, Subscribe};
+use crate::messages::private_messages::{
+    AddOrder, AddOrderStatus, AuthenticatedRequest, CancelAll, CancelAllOrdersAfter,
+    CancelAllOrdersAfterStatus, CancelAllStatus, CancelOrder, CancelOrderStatus, EditOrder,
+    EditOrderStatus, RequestResponse, RequestStatus,
+};
+use crate::messages::private_messages::{OpenOrders, OwnTrades};
+use crate::messages::public_messages::{Book, Spread, Ticker, Trades, OHLC};
+use crate::messages::{Channel, ChannelMessageWrapper, Event, EventType, Pair};
+use crate::sequence::{SequenceEvent, SequenceEventKind, SequenceTracker};
+use crate::stream::{ChannelStream, Extractor};
+use crate::subscription::{Subscribers, SubscriptionOptions, SubscriptionReceiver};
 use log::{error, info, trace, warn};
-use tokio::net::TcpStream;
-use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
-use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message as TungstenMessage};
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
-
-type ReadStream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
-
-fn websocket_error_is_fatal(error: &tokio_tungstenite::tungstenite::Error) -> bool {
-    use tokio_tungstenite::tungstenite::Error::*;
-    matches!(
-        error,
-        ConnectionClosed | AlreadyClosed | Io(_) | Tls(_) | Url(_) | Http(_) | HttpFormat(_)
-    )
-}
 
+/// Client for version 1 of the kraken websocket API. See [v2::LinnaeusWebsocketV2] for version 2
 #[derive(Debug)]
 pub struct LinnaeusWebsocket {
-    subscriptions: DashMap<u64, broadcast::Sender<ChannelMessageWrapper>, ahash::RandomState>,
-    request_id: AtomicU64,
-    pending_requests: DashMap<u64, tokio::sync::oneshot::Sender<Event>, ahash::RandomState>,
+    connection: Connection,
+    subscriptions: Subscribers<ChannelMessageWrapper>,
+    /// subscribe requests that are replayed when the connection is re-established, one per
+    /// channel and pair keyed like the subscribers
+    active_subscriptions: DashMap<u64, messages::general_messages::Subscribe, ahash::RandomState>,
+    pending_requests: PendingRequests<Event>,
     recent_events: DashMap<EventType, Event, ahash::RandomState>,
-    writer:
-        tokio::sync::Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, TungstenMessage>>,
-    reader: tokio::sync::Mutex<Option<tokio::task::JoinHandle<ReadStream>>>,
-    closer: tokio::sync::oneshot::Sender<()>,
+    sequences: SequenceTracker,
+    sequence_events: broadcast::Sender<SequenceEvent>,
 }
 
 impl LinnaeusWebsocket {
     pub async fn new(url: &str) -> Result<Arc<Self>, error::LinnaeusWebsocketError> {
-        if !url.starts_with("wss://") {
-            return Err(error::LinnaeusWebsocketError::Url {
-                reason: "websocket url must start with \"wss://\"",
-            });
-        }
-        let url = url::Url::parse(url)?;
+        Self::connect(url, WebsocketConfig::default(), None).await
+    }
+
+    pub async fn new_with_config(
+        url: &str,
+        config: WebsocketConfig,
+    ) -> Result<Arc<Self>, error::LinnaeusWebsocketError> {
+        Self::connect(url, config, None).await
+    }
+
+    /// Connect to an authenticated endpoint (wss://ws-auth.kraken.com). Tokens are fetched from the
+    /// provider when they are needed and renewed before they expire.
+    pub async fn new_authenticated(
+        url: &str,
+        token_provider: Arc<dyn TokenProvider>,
+    ) -> Result<Arc<Self>, error::LinnaeusWebsocketError> {
+        Self::new_authenticated_with_config(url, token_provider, WebsocketConfig::default()).await
+    }
 
-        let (ws_stream, _) = connect_async(url.clone()).await?;
-        info!("WebSocket handshake has been successfully completed");
-        let (write, mut read) = ws_stream.split();
+    pub async fn new_authenticated_with_config(
+        url: &str,
+        token_provider: Arc<dyn TokenProvider>,
+        config: WebsocketConfig,
+    ) -> Result<Arc<Self>, error::LinnaeusWebsocketError> {
+        Self::connect(url, config, Some(TokenManager::new(token_provider))).await
+    }
 
-        let (close_sender, close_receiver) = tokio::sync::oneshot::channel();
+    async fn connect(
+        url: &str,
+        config: WebsocketConfig,
+        token_manager: Option<TokenManager>,
+    ) -> Result<Arc<Self>, error::LinnaeusWebsocketError> {
+        let (connection, reader, system_status) =
+            Connection::open::<Self>(url, config, token_manager).await?;
 
         let linnaeus_websocket = Arc::new(Self {
+            connection,
             subscriptions: Default::default(),
-            request_id: Default::default(),
+            active_subscriptions: Default::default(),
             pending_requests: Default::default(),
             recent_events: Default::default(),
-            writer: tokio::sync::Mutex::new(write),
-            reader: Default::default(),
-            closer: close_sender,
+            sequences: Default::default(),
+            sequence_events: broadcast::channel(100).0,
         });
+        linnaeus_websocket
+            .recent_events
+            .insert(EventType::SystemStatus, system_status);
 
-        let online = linnaeus_websocket.wait_for_system_status(&mut read).await;
+        connection::start(&linnaeus_websocket, reader).await;
 
-        if !online {
-            return Err(error::LinnaeusWebsocketError::KrakenOffline);
-        }
+        Ok(linnaeus_websocket)
+    }
 
-        let reader_handle = tokio::spawn(Self::reader(
-            linnaeus_websocket.clone(),
-            read,
-            close_receiver,
-        ));
+    /// A client that is fed from a recording made with [recorder::Recorder] instead of kraken.
+    /// Subscribe as usual then call [LinnaeusWebsocket::replay]. Nothing is sent to kraken so
+    /// private requests fail and pings are never answered
+    pub fn new_replay(config: WebsocketConfig) -> Arc<Self> {
+        Arc::new(Self {
+            connection: Connection::replay(config),
+            subscriptions: Default::default(),
+            active_subscriptions: Default::default(),
+            pending_requests: Default::default(),
+            recent_events: Default::default(),
+            sequences: Default::default(),
+            sequence_events: broadcast::channel(100).0,
+        })
+    }
 
-        {
-            let mut reader = linnaeus_websocket.reader.lock().await;
-            *reader = Some(reader_handle);
+    /// Replay a recording file, or every recording in a directory, through the subscriptions.
+    /// Returns once every frame has been delivered
+    pub async fn replay(
+        &self,
+        recording: impl AsRef<std::path::Path>,
+        speed: recorder::ReplaySpeed,
+    ) -> std::io::Result<()> {
+        connection::replay(self, recording.as_ref().to_path_buf(), speed).await
+    }
+
+    async fn resubscribe(&self) -> Result<(), error::LinnaeusWebsocketError> {
+        let subscriptions: Vec<messages::general_messages::Subscribe> = self
+            .active_subscriptions
+            .iter()
+            .map(|sub| sub.value().clone())
+            .collect();
+        for sub_event in subscriptions {
+            let sub_event = self.authenticate_subscription(sub_event).await?;
+            self.send_event(Event::Subscribe(sub_event)).await?;
         }
+        Ok(())
+    }
 
-        Ok(linnaeus_websocket)
+    /// Returns false if the message is a duplicate and shouldn't be forwarded
+    fn check_sequence(&self, message: &ChannelMessageWrapper) -> bool {
+        let Some(sequence) = message.sequence() else {
+            return true;
+        };
+        let Some(event) = self
+            .sequences
+            .observe(message.channel(), *sequence.sequence())
+        else {
+            return true;
+        };
+        warn!("sequence error on {} -> {:?}", message.channel(), event.kind());
+        let duplicate = matches!(event.kind(), SequenceEventKind::Duplicate { .. });
+        // nobody listening is fine
+        let _ = self.sequence_events.send(event);
+        !duplicate
     }
 
-    async fn wait_for_system_status(self: &Arc<Self>, read: &mut ReadStream) -> bool {
-        //TODO configurable timeout
-        let timer = tokio::time::timeout(Duration::from_secs_f64(10.0), async {
-            while let Some(msg) = read.next().await {
-                let Ok(msg) = msg else {
-                    return false;
-                };
-
-                let TungstenMessage::Text(msg) = msg else {
-                    return false;
-                };
-
-                let event: Event = match serde_json::from_str(&msg) {
-                    Ok(event) => event,
-                    Err(_) => return false,
-                };
-
-                match &event {
-                    Event::Heartbeat => continue,
-                    Event::SystemStatus(ss) => {
-                        info!("got system status {:?}", ss);
-                        let online = matches!(
-                            ss.status(),
-                            messages::general_messages::SystemStatusCode::Online
-                        );
-                        self.recent_events.insert(EventType::SystemStatus, event);
-                        return online;
-                    }
-                    _ => return false,
-                }
-            }
-            false
-        })
-        .await;
+    /// Gaps and duplicates detected on the private channels
+    pub fn sequence_events(&self) -> broadcast::Receiver<SequenceEvent> {
+        self.sequence_events.subscribe()
+    }
 
-        timer.unwrap_or(false)
+    /// Watch the health of the connection
+    pub fn health(&self) -> tokio::sync::watch::Receiver<ConnectionHealth> {
+        self.connection.health()
     }
 
-    async fn reader(
-        client: Arc<Self>,
-        mut read: ReadStream,
-        mut close_receiver: tokio::sync::oneshot::Receiver<()>,
-    ) -> ReadStream {
-        let client = client.as_ref();
+    pub fn config(&self) -> &WebsocketConfig {
+        self.connection.config()
+    }
 
-        loop {
-            let msg: Option<Result<TungstenMessage, _>> = tokio::select! {
-                msg = read.next() => msg,
-                _ = &mut close_receiver => {
-                    return read;
-                }
-            };
+    async fn send_event(&self, event: Event) -> Result<(), error::LinnaeusWebsocketError> {
+        self.connection.send_json(&event).await
+    }
 
-            let Some(msg) = msg else {
-                continue;
-            };
+    fn next_id(&self) -> u64 {
+        self.connection.next_id()
+    }
 
-            let msg = match msg {
-                Ok(msg) => msg,
-                Err(err) => {
-                    error!("error while reading from websocket -> {}", err);
-                    if websocket_error_is_fatal(&err) {
-                        return read;
-                    }
-                    continue;
-                }
-            };
-            let msg = match msg {
-                TungstenMessage::Text(msg) => msg,
-                _ => {
-                    warn!("got non text based message on the websocket");
-                    continue;
-                }
-            };
+    async fn token(&self, request: &'static str) -> Result<String, error::LinnaeusWebsocketError> {
+        self.connection.token(request).await
+    }
 
-            trace!("got new json message {}", msg);
+    /// true if this connection was created with a token provider
+    pub fn is_authenticated(&self) -> bool {
+        self.connection.is_authenticated()
+    }
 
-            let msg: messages::Message = match serde_json::from_str(&msg) {
-                Ok(msg) => msg,
-                Err(e) => {
-                    error!("error while deserializing websocket message {}", e);
-                    continue;
-                }
-            };
+    /// Send an event and wait for the event kraken sends back with the same request id
+    async fn request(&self, id: u64, event: Event) -> Result<Event, error::LinnaeusWebsocketError> {
+        self.connection
+            .request(&self.pending_requests, id, &event)
+            .await
+    }
 
-            match msg {
-                messages::Message::ChannelMessage(channel_message) => {
-                    let key = channel_message.get_channel_identifier();
-                    if let Some(sub) = client.subscriptions.get(&key) {
-                        if let Err(e) = sub.send(channel_message) {
-                            error!("failed to send channel message over broadcast -> {}", e);
-                        }
-                    }
-                }
-                messages::Message::Event(event) => {
-                    trace!(
-                        "got new event message with type {}",
-                        messages::EventType::from(&event)
-                    );
-                    client.recent_events.insert((&event).into(), event.clone());
-                    if let Some(request_id) = event.get_request_id() {
-                        if let Some((_, pending)) =
-                            client.pending_requests.remove(&(request_id as u64))
-                        {
-                            if let Err(e) = pending.send(event) {
-                                error!("failed to send event to pending request channel -> {}", e);
-                            }
-                        }
-                    }
-                }
-            }
+    async fn private_request<R: AuthenticatedRequest, S: RequestResponse>(
+        &self,
+        name: &'static str,
+        mut request: R,
+        to_event: fn(R) -> Event,
+        from_event: fn(Event) -> Option<S>,
+    ) -> Result<S, error::LinnaeusWebsocketError> {
+        let token = self.token(name).await?;
+        let id = self.next_id();
+        request.set_token(token);
+        request.set_request_id(id as i64);
+        let event = self.request(id, to_event(request)).await?;
+        let event_type = EventType::from(&event);
+        let response = from_event(event).ok_or_else(|| {
+            error::LinnaeusWebsocketError::UnexpectedResponse(event_type.to_string())
+        })?;
+        match response.request_status() {
+            RequestStatus::Ok => Ok(response),
+            RequestStatus::Error => Err(error::LinnaeusWebsocketError::Kraken(
+                response
+                    .request_error_message()
+                    .unwrap_or("no error message")
+                    .to_string(),
+            )),
         }
     }
 
-    async fn send_event(&self, event: Event) -> Result<(), error::LinnaeusWebsocketError> {
-        let event_json = serde_json::to_string(&event)?;
-        trace!("sending json over websocket {}", event_json);
-        let message = TungstenMessage::Text(event_json);
-        let mut writer = self.writer.lock().await;
-        writer.send(message).await?;
-        Ok(())
+    pub async fn add_order(
+        &self,
+        order: AddOrder,
+    ) -> Result<AddOrderStatus, error::LinnaeusWebsocketError> {
+        self.private_request("addOrder", order, Event::AddOrder, |event| match event {
+            Event::AddOrderStatus(status) => Some(status),
+            _ => None,
+        })
+        .await
     }
 
-    fn next_id(&self) -> u64 {
-        self.request_id.fetch_add(1, Ordering::SeqCst)
+    pub async fn edit_order(
+        &self,
+        order: EditOrder,
+    ) -> Result<EditOrderStatus, error::LinnaeusWebsocketError> {
+        self.private_request("editOrder", order, Event::EditOrder, |event| match event {
+            Event::EditOrderStatus(status) => Some(status),
+            _ => None,
+        })
+        .await
+    }
+
+    pub async fn cancel_order(
+        &self,
+        cancel: CancelOrder,
+    ) -> Result<CancelOrderStatus, error::LinnaeusWebsocketError> {
+        self.private_request("cancelOrder", cancel, Event::CancelOrder, |event| match event {
+            Event::CancelOrderStatus(status) => Some(status),
+            _ => None,
+        })
+        .await
+    }
+
+    pub async fn cancel_all(&self) -> Result<CancelAllStatus, error::LinnaeusWebsocketError> {
+        self.private_request(
+            "cancelAll",
+            CancelAll::default(),
+            Event::CancelAll,
+            |event| match event {
+                Event::CancelAllStatus(status) => Some(status),
+                _ => None,
+            },
+        )
+        .await
+    }
+
+    /// Dead man's switch. Cancels all orders after the timeout unless it's called again
+    pub async fn cancel_all_orders_after(
+        &self,
+        request: CancelAllOrdersAfter,
+    ) -> Result<CancelAllOrdersAfterStatus, error::LinnaeusWebsocketError> {
+        self.private_request(
+            "cancelAllOrdersAfter",
+            request,
+            Event::CancelAllOrdersAfter,
+            |event| match event {
+                Event::CancelAllOrdersAfterStatus(status) => Some(status),
+                _ => None,
+            },
+        )
+        .await
     }
 
     pub async fn ping(
@@ -222,8 +301,7 @@ impl LinnaeusWebsocket {
 
         let ping = Event::Ping(messages::general_messages::Ping::new(id as i64));
 
-        let (one_shot_sender, one_shot_receiver) = tokio::sync::oneshot::channel();
-        self.pending_requests.insert(id, one_shot_sender);
+        let one_shot_receiver = self.pending_requests.register(id);
 
         self.send_event(ping).await?;
 
@@ -234,80 +312,319 @@ impl LinnaeusWebsocket {
         self.recent_events.get(&event_type).map(|e| e.clone())
     }
 
-    pub async fn subscribe(
+    /// Private subscriptions need a valid token
+    async fn authenticate_subscription(
         &self,
         sub_event: messages::general_messages::Subscribe,
-    ) -> Result<Vec<broadcast::Receiver<ChannelMessageWrapper>>, error::LinnaeusWebsocketError>
-    {
+    ) -> Result<messages::general_messages::Subscribe, error::LinnaeusWebsocketError> {
+        if !Channel::from(sub_event.subscription()).is_private() {
+            return Ok(sub_event);
+        }
+        let token = self.token("private subscriptions").await?;
+        Ok(sub_event.with_token(token))
+    }
+
+    /// The key of every channel/pair a subscription covers with the subscription for just that
+    /// channel/pair
+    fn subscription_keys(sub_event: &Subscribe) -> Vec<(u64, Subscribe)> {
         let channel = Channel::from(sub_event.subscription());
+        match sub_event.pair() {
+            None => vec![(
+                channel.generate_identifier_no_pair(),
+                sub_event.for_pair(None),
+            )],
+            Some(pairs) => pairs
+                .iter()
+                .map(|pair| {
+                    (
+                        channel.generate_identifier(pair),
+                        sub_event.for_pair(Some(pair)),
+                    )
+                })
+                .collect(),
+        }
+    }
 
-        let keys = sub_event.pair().as_ref().map_or(
-            vec![channel.generate_identifier_no_pair()],
-            |pairs| {
-                pairs
-                    .iter()
-                    .map(|pair| channel.generate_identifier(pair))
-                    .collect()
-            },
-        );
+    /// Send the subscription and return the keys of every channel/pair it covers
+    async fn send_subscription(
+        &self,
+        sub_event: Subscribe,
+    ) -> Result<Vec<u64>, error::LinnaeusWebsocketError> {
+        let channel = Channel::from(sub_event.subscription());
+        let subscriptions = Self::subscription_keys(&sub_event);
 
+        let sub_event = self.authenticate_subscription(sub_event).await?;
+        if channel.is_private() {
+            self.sequences.reset_channel(&channel);
+        }
         self.send_event(Event::Subscribe(sub_event)).await?;
+        Ok(subscriptions
+            .into_iter()
+            .map(|(key, sub_event)| {
+                // subscribing again to the same channel/pair replaces the earlier request
+                self.active_subscriptions.insert(key, sub_event);
+                key
+            })
+            .collect())
+    }
 
-        let mut receivers = Vec::with_capacity(keys.len());
+    /// Unsubscribe from every channel/pair the subscription covers. Their receivers are closed
+    /// and they're no longer resubscribed after a reconnect
+    pub async fn unsubscribe(
+        &self,
+        sub_event: Subscribe,
+    ) -> Result<(), error::LinnaeusWebsocketError> {
+        let subscriptions = Self::subscription_keys(&sub_event);
+        let sub_event = self.authenticate_subscription(sub_event).await?;
+        self.send_event(Event::Unsubscribe((&sub_event).into()))
+            .await?;
+        for (key, _) in subscriptions {
+            self.active_subscriptions.remove(&key);
+            self.subscriptions.remove(key);
+        }
+        Ok(())
+    }
 
-        for key in keys {
-            match self.subscriptions.entry(key) {
-                Entry::Occupied(entry) => {
-                    receivers.push(entry.get().subscribe());
-                }
-                Entry::Vacant(vacant) => {
-                    let (sender, receiver) = broadcast::channel(100);
-                    vacant.insert(sender);
-                    receivers.push(receiver)
+    pub async fn subscribe(
+        &self,
+        sub_event: Subscribe,
+    ) -> Result<Vec<broadcast::Receiver<ChannelMessageWrapper>>, error::LinnaeusWebsocketError>
+    {
+        let keys = self.send_subscription(sub_event).await?;
+        let options = SubscriptionOptions::default();
+        Ok(keys
+            .into_iter()
+            .map(|key| {
+                let (subscriber, receiver) =
+                    subscription::broadcast_channel(options.buffer_size());
+                self.subscriptions.add(key, subscriber);
+                receiver
+            })
+            .collect())
+    }
+
+    async fn subscribe_with_options(
+        &self,
+        sub_event: Subscribe,
+        options: &SubscriptionOptions,
+    ) -> Result<Vec<SubscriptionReceiver<ChannelMessageWrapper>>, error::LinnaeusWebsocketError>
+    {
+        let keys = self.send_subscription(sub_event).await?;
+        Ok(keys
+            .into_iter()
+            .map(|key| {
+                let (subscriber, receiver) = subscription::channel(options);
+                self.subscriptions.add(key, subscriber);
+                receiver
+            })
+            .collect())
+    }
+
+    async fn subscribe_stream<I: Send + 'static>(
+        &self,
+        channel: Channel,
+        pairs: &[Pair],
+        options: SubscriptionOptions,
+        extract: Extractor<ChannelMessageWrapper, I>,
+    ) -> Result<ChannelStream<I>, error::LinnaeusWebsocketError> {
+        let sub_event = pairs
+            .iter()
+            .fold(Subscribe::new(channel), |sub_event, pair| {
+                sub_event.with_pair(pair.clone())
+            });
+        let receivers = self.subscribe_with_options(sub_event, &options).await?;
+        Ok(ChannelStream::from_receivers(
+            receivers,
+            Default::default(),
+            extract,
+        ))
+    }
+
+    /// Maintain a local copy of the book for a pair. If an update is lost because the
+    /// subscriber fell behind the book is invalidated and a fresh snapshot is requested
+    pub async fn subscribe_local_book(
+        self: &Arc<Self>,
+        pair: Pair,
+        depth: Depth,
+        options: SubscriptionOptions,
+    ) -> Result<LocalBookSubscription, error::LinnaeusWebsocketError> {
+        let health = self.health();
+        let sub_event = Subscribe::new(Channel::Book(depth)).with_pair(pair.clone());
+        let mut receivers = self.subscribe_with_options(sub_event, &options).await?;
+        let receiver = receivers
+            .pop()
+            .expect("a subscription to a single pair has a single receiver");
+        Ok(LocalBookSubscription::new(
+            self.clone(),
+            pair,
+            depth,
+            receiver,
+            health,
+        ))
+    }
+
+    /// Unsubscribe and subscribe again so that kraken sends a new snapshot. Subscribers are kept
+    pub(crate) async fn request_book_snapshot(
+        &self,
+        pair: &Pair,
+        depth: Depth,
+    ) -> Result<(), error::LinnaeusWebsocketError> {
+        let sub_event = Subscribe::new(Channel::Book(depth)).with_pair(pair.clone());
+        self.send_event(Event::Unsubscribe((&sub_event).into()))
+            .await?;
+        self.send_event(Event::Subscribe(sub_event)).await
+    }
+
+    pub async fn subscribe_ticker(
+        &self,
+        pairs: &[Pair],
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<(Pair, Ticker)>, error::LinnaeusWebsocketError> {
+        self.subscribe_stream(Channel::Ticker, pairs, options, stream::ticker)
+            .await
+    }
+
+    pub async fn subscribe_ohlc(
+        &self,
+        pairs: &[Pair],
+        interval: Interval,
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<(Pair, OHLC)>, error::LinnaeusWebsocketError> {
+        self.subscribe_stream(Channel::OHLC(interval), pairs, options, stream::ohlc)
+            .await
+    }
+
+    pub async fn subscribe_trades(
+        &self,
+        pairs: &[Pair],
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<(Pair, Trades)>, error::LinnaeusWebsocketError> {
+        self.subscribe_stream(Channel::Trade, pairs, options, stream::trades)
+            .await
+    }
+
+    pub async fn subscribe_spread(
+        &self,
+        pairs: &[Pair],
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<(Pair, Spread)>, error::LinnaeusWebsocketError> {
+        self.subscribe_stream(Channel::Spread, pairs, options, stream::spread)
+            .await
+    }
+
+    pub async fn subscribe_book(
+        &self,
+        pairs: &[Pair],
+        depth: Depth,
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<(Pair, Book)>, error::LinnaeusWebsocketError> {
+        self.subscribe_stream(Channel::Book(depth), pairs, options, stream::book)
+            .await
+    }
+
+    /// Requires an authenticated connection
+    pub async fn subscribe_own_trades(
+        &self,
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<OwnTrades>, error::LinnaeusWebsocketError> {
+        self.subscribe_stream(Channel::OwnTrades, &[], options, stream::own_trades)
+            .await
+    }
+
+    /// Requires an authenticated connection
+    pub async fn subscribe_open_orders(
+        &self,
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<OpenOrders>, error::LinnaeusWebsocketError> {
+        self.subscribe_stream(Channel::OpenOrders, &[], options, stream::open_orders)
+            .await
+    }
+
+    pub async fn shutdown(self) {
+        self.connection.shutdown().await
+    }
+}
+
+impl Protocol for LinnaeusWebsocket {
+    type Status = Event;
+
+    fn handshake(message: &str) -> Handshake<Event> {
+        let event: Event = match serde_json::from_str(message) {
+            Ok(event) => event,
+            Err(_) => return Handshake::Offline,
+        };
+
+        match &event {
+            Event::Heartbeat => Handshake::Waiting,
+            Event::SystemStatus(ss) => {
+                info!("got system status {:?}", ss);
+                match ss.status() {
+                    messages::general_messages::SystemStatusCode::Online => {
+                        Handshake::Online(event)
+                    }
+                    _ => Handshake::Offline,
                 }
             }
+            _ => Handshake::Offline,
         }
+    }
 
-        Ok(receivers)
+    fn connection(&self) -> &Connection {
+        &self.connection
     }
 
-    pub async fn shutdown(self) {
-        self.closer
-            .send(())
-            .expect("couldn't send shutdown command");
-        let reader_join_handle;
-        {
-            let mut reader = self.reader.lock().await;
-            let handle = std::mem::replace(&mut (*reader), None);
-            let Some(handle) = handle else {
-                warn!("couldn't get the join handle during shutdown");
+    async fn handle_message(&self, message: String) {
+        let msg: messages::Message = match serde_json::from_str(&message) {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("error while deserializing websocket message {}", e);
                 return;
-            };
-            reader_join_handle = handle;
-        }
-
-        let Ok(read_sink) = reader_join_handle.await else {
-            warn!("couldn't get read stream from jh during shutdown");
-            return;
-        };
-        let Ok(mut websocket) = self.writer.into_inner().reunite(read_sink) else {
-            warn!("couldn't reunite sender and receiver for websocket shutdown");
-            return;
+            }
         };
 
-        if let Err(err) = websocket
-            .close(Some(CloseFrame {
-                code: CloseCode::Normal,
-                reason: Default::default(),
-            }))
-            .await
-        {
-            error!(
-                "error while sending close message over websocket -> {}",
-                err
-            );
+        match msg {
+            messages::Message::ChannelMessage(channel_message) => {
+                if !self.check_sequence(&channel_message) {
+                    return;
+                }
+                let key = channel_message.get_channel_identifier();
+                self.subscriptions.deliver(key, &channel_message).await;
+            }
+            messages::Message::Event(event) => {
+                trace!(
+                    "got new event message with type {}",
+                    messages::EventType::from(&event)
+                );
+                self.recent_events.insert((&event).into(), event.clone());
+                if let Some(request_id) = event.get_request_id() {
+                    self.pending_requests.complete(request_id as u64, event);
+                }
+            }
         }
     }
+
+    async fn restore(&self, system_status: Event) -> Result<(), error::LinnaeusWebsocketError> {
+        self.recent_events
+            .insert(EventType::SystemStatus, system_status);
+        self.pending_requests.clear();
+        // private channels start counting from 1 again once resubscribed
+        self.sequences.reset();
+        self.resubscribe().await
+    }
+
+    fn disconnected(&self) {
+        self.subscriptions.clear();
+        self.active_subscriptions.clear();
+        self.pending_requests.clear();
+    }
+
+    async fn ping_with_timeout(&self, timeout: Duration) -> bool {
+        let Ok(pong) = self.ping().await else {
+            return false;
+        };
+        // a dropped response means we're reconnecting for some other reason
+        tokio::time::timeout(timeout, pong).await.is_ok()
+    }
 }
 
 #[cfg(test)]
@@ -340,6 +657,33 @@ mod websocket_tests {
         shared.as_ref().unwrap().clone()
     }
 
+    #[tokio::test]
+    async fn resubscribing_replaces_and_unsubscribing_removes() -> anyhow::Result<()> {
+        let lws = LinnaeusWebsocket::new_replay(Default::default());
+        let sub_req = || {
+            messages::general_messages::Subscribe::new(Channel::Ticker)
+                .with_pair("XBT/USD".into())
+                .with_pair("XBT/EUR".into())
+        };
+        lws.subscribe(sub_req()).await?;
+        let mut receivers = lws.subscribe(sub_req()).await?;
+        assert_eq!(lws.active_subscriptions.len(), 2);
+
+        let unsub_req =
+            messages::general_messages::Subscribe::new(Channel::Ticker).with_pair("XBT/USD".into());
+        lws.unsubscribe(unsub_req).await?;
+        assert_eq!(lws.active_subscriptions.len(), 1);
+        assert!(matches!(
+            receivers[0].try_recv(),
+            Err(broadcast::error::TryRecvError::Closed)
+        ));
+        assert!(matches!(
+            receivers[1].try_recv(),
+            Err(broadcast::error::TryRecvError::Empty)
+        ));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_ping() -> anyhow::Result<()> {
         setup();
@@ -0,0 +1,119 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// What to do once the connection has been detected as stale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleAction {
+    /// Drop the connection, reconnect and replay subscriptions
+    Reconnect,
+    /// Close the connection. Subscribers will see their channels close
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleReason {
+    /// Nothing (including heartbeats) was received for this long
+    Silence(Duration),
+    /// A ping wasn't answered within the pong timeout
+    PongTimeout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionHealth {
+    Healthy,
+    Stale(StaleReason),
+    Reconnecting,
+    Closed,
+}
+
+/// Tracks activity on the current connection
+#[derive(Debug)]
+pub(crate) struct Liveness {
+    last_activity: std::sync::Mutex<Instant>,
+    last_ping: std::sync::Mutex<Instant>,
+}
+
+impl Liveness {
+    pub(crate) fn new() -> Self {
+        Self {
+            last_activity: std::sync::Mutex::new(Instant::now()),
+            last_ping: std::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    pub(crate) fn record_activity(&self) {
+        *self.last_activity.lock().expect("liveness lock poisoned") = Instant::now();
+    }
+
+    /// Restart all timers. Used when a new connection is established
+    pub(crate) fn reset(&self) {
+        self.record_activity();
+        *self.last_ping.lock().expect("liveness lock poisoned") = Instant::now();
+    }
+
+    /// Some if nothing has been received for longer than max silence
+    pub(crate) fn silence(&self, max_silence: Duration) -> Option<Duration> {
+        let elapsed = self
+            .last_activity
+            .lock()
+            .expect("liveness lock poisoned")
+            .elapsed();
+        (elapsed > max_silence).then_some(elapsed)
+    }
+
+    /// true if a ping should be sent now. Records the ping time when it returns true
+    pub(crate) fn ping_due(&self, ping_interval: Option<Duration>) -> bool {
+        let Some(ping_interval) = ping_interval else {
+            return false;
+        };
+        let mut last_ping = self.last_ping.lock().expect("liveness lock poisoned");
+        if last_ping.elapsed() < ping_interval {
+            return false;
+        }
+        *last_ping = Instant::now();
+        true
+    }
+}
+
+#[cfg(test)]
+mod liveness_tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn silence_is_detected() {
+        let liveness = Liveness::new();
+        let max_silence = Duration::from_secs(10);
+        assert!(liveness.silence(max_silence).is_none());
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        liveness.record_activity();
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert!(liveness.silence(max_silence).is_none());
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(
+            liveness.silence(max_silence),
+            Some(Duration::from_secs(11))
+        );
+
+        liveness.reset();
+        assert!(liveness.silence(max_silence).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pings_are_spaced_by_interval() {
+        let liveness = Liveness::new();
+        let interval = Some(Duration::from_secs(30));
+        assert!(!liveness.ping_due(None));
+        assert!(!liveness.ping_due(interval));
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert!(liveness.ping_due(interval));
+        assert!(!liveness.ping_due(interval));
+
+        tokio::time::advance(Duration::from_secs(29)).await;
+        assert!(!liveness.ping_due(interval));
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(liveness.ping_due(interval));
+    }
+}
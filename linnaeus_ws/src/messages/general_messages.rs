@@ -1,9 +1,15 @@
 use crate::messages::{Channel, Pair};
 use derive_getters::Getters;
 use display_json::{DebugAsJson, DisplayAsJsonPretty};
-use serde::{Deserialize, Serialize};
-use serde_repr::{Deserialize_repr, Serialize_repr};
+pub use linnaeus_types::Interval;
+use serde::de::{Error as DeError, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_with::skip_serializing_none;
+use std::cmp::Ordering;
+use std::fmt::Formatter;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::str::FromStr;
 
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
 pub struct Ping {
@@ -41,43 +47,123 @@ pub struct SystemStatus {
     version: String,
 }
 
-#[derive(
-    Debug, Serialize_repr, Deserialize_repr, Clone, Copy, PartialOrd, PartialEq, Ord, Eq, Hash,
-)]
-#[repr(u32)]
-pub enum Interval {
-    OneMin = 1,
-    FiveMin = 5,
-    FifteenMin = 15,
-    ThirtyMin = 30,
-    OneHour = 60,
-    FourHour = 240,
-    OneDay = 1440,
-    OneWeek = 10080,
-    FifteenDay = 21600,
+/// Number of price levels in a book subscription. Kraken supports other depths for some pairs.
+/// Compared and hashed by the number of levels so `Other(10)` is `Ten`
+#[derive(Debug, Clone, Copy)]
+pub enum Depth {
+    Ten,
+    TwentyFive,
+    OneHundred,
+    FiveHundred,
+    OneThousand,
+    /// A depth this version of linnaeus doesn't know about
+    Other(u16),
 }
 
-impl Default for Interval {
+impl Default for Depth {
     fn default() -> Self {
-        Self::OneMin
+        Self::Ten
     }
 }
 
-#[derive(
-    Debug, Serialize_repr, Deserialize_repr, Clone, Copy, PartialOrd, PartialEq, Ord, Eq, Hash,
-)]
-#[repr(u16)]
-pub enum Depth {
-    Ten = 10,
-    TwentyFive = 25,
-    OneHundred = 100,
-    FiveHundred = 500,
-    OneThousand = 1000,
+impl From<u16> for Depth {
+    fn from(depth: u16) -> Self {
+        match depth {
+            10 => Self::Ten,
+            25 => Self::TwentyFive,
+            100 => Self::OneHundred,
+            500 => Self::FiveHundred,
+            1000 => Self::OneThousand,
+            other => Self::Other(other),
+        }
+    }
 }
 
-impl Default for Depth {
+impl From<Depth> for u16 {
+    fn from(depth: Depth) -> Self {
+        match depth {
+            Depth::Ten => 10,
+            Depth::TwentyFive => 25,
+            Depth::OneHundred => 100,
+            Depth::FiveHundred => 500,
+            Depth::OneThousand => 1000,
+            Depth::Other(depth) => depth,
+        }
+    }
+}
+
+impl PartialEq for Depth {
+    fn eq(&self, other: &Self) -> bool {
+        u16::from(*self) == u16::from(*other)
+    }
+}
+
+impl Eq for Depth {}
+
+impl PartialOrd for Depth {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Depth {
+    fn cmp(&self, other: &Self) -> Ordering {
+        u16::from(*self).cmp(&u16::from(*other))
+    }
+}
+
+impl Hash for Depth {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        u16::from(*self).hash(state)
+    }
+}
+
+impl Serialize for Depth {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16((*self).into())
+    }
+}
+
+impl<'de> Deserialize<'de> for Depth {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(NumberVisitor::<u16, Self>::default())
+    }
+}
+
+/// Accepts a number or a string containing a number. Known values become named variants and
+/// anything else ends up in Other
+struct NumberVisitor<N, T> {
+    number: PhantomData<(N, T)>,
+}
+
+impl<N, T> Default for NumberVisitor<N, T> {
     fn default() -> Self {
-        Self::Ten
+        Self {
+            number: PhantomData,
+        }
+    }
+}
+
+impl<'de, N, T> Visitor<'de> for NumberVisitor<N, T>
+where
+    N: TryFrom<u64> + FromStr + Into<T>,
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        write!(formatter, "a {}", std::any::type_name::<N>())
+    }
+
+    fn visit_u64<E: DeError>(self, v: u64) -> Result<Self::Value, E> {
+        N::try_from(v)
+            .map(Into::into)
+            .map_err(|_| E::invalid_value(Unexpected::Unsigned(v), &self))
+    }
+
+    fn visit_str<E: DeError>(self, v: &str) -> Result<Self::Value, E> {
+        v.parse::<N>()
+            .map(Into::into)
+            .map_err(|_| E::invalid_value(Unexpected::Str(v), &self))
     }
 }
 
@@ -99,7 +185,7 @@ impl From<&crate::messages::Channel> for SubscribableChannel {
     fn from(c: &Channel) -> Self {
         match c {
             Channel::Ticker => Self::Ticker,
-            Channel::OHLC(_) => Self::Ticker,
+            Channel::OHLC(_) => Self::OHLC,
             Channel::Trade => Self::Trade,
             Channel::Spread => Self::Spread,
             Channel::Book(_) => Self::Book,
@@ -121,6 +207,24 @@ pub struct SubscribeInfo {
     token: Option<String>,
 }
 
+impl From<&Channel> for SubscribeInfo {
+    fn from(channel: &Channel) -> Self {
+        let (depth, interval) = match channel {
+            Channel::Book(depth) => (Some(*depth), None),
+            Channel::OHLC(interval) => (None, Some(*interval)),
+            _ => (None, None),
+        };
+        Self {
+            depth,
+            interval,
+            name: channel.into(),
+            rate_counter: None,
+            snapshot: None,
+            token: None,
+        }
+    }
+}
+
 #[skip_serializing_none]
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
 pub struct Subscribe {
@@ -132,46 +236,10 @@ pub struct Subscribe {
 
 impl Subscribe {
     pub fn new(channel: Channel) -> Self {
-        let name;
-        let mut depth = None;
-        let mut interval = None;
-        match channel {
-            Channel::Ticker => {
-                name = SubscribableChannel::Ticker;
-            }
-            Channel::OHLC(i) => {
-                name = SubscribableChannel::OHLC;
-                interval = Some(i);
-            }
-            Channel::Trade => {
-                name = SubscribableChannel::Trade;
-            }
-            Channel::Spread => {
-                name = SubscribableChannel::Spread;
-            }
-            Channel::Book(d) => {
-                name = SubscribableChannel::Book;
-                depth = Some(d)
-            }
-            Channel::OwnTrades => {
-                name = SubscribableChannel::OwnTrades;
-            }
-            Channel::OpenOrders => {
-                name = SubscribableChannel::OpenOrders;
-            }
-        };
-
         Subscribe {
             request_id: None,
             pair: None,
-            subscription: SubscribeInfo {
-                depth,
-                interval,
-                name,
-                rate_counter: None,
-                snapshot: None,
-                token: None,
-            },
+            subscription: (&channel).into(),
         }
     }
 
@@ -192,13 +260,22 @@ impl Subscribe {
         self.request_id = Some(request_id);
         self
     }
+
+    /// The same subscription for a single pair, or for no pair at all
+    pub(crate) fn for_pair(&self, pair: Option<&Pair>) -> Self {
+        Subscribe {
+            request_id: None,
+            pair: pair.map(|pair| vec![pair.clone()]),
+            subscription: self.subscription.clone(),
+        }
+    }
 }
 
 #[skip_serializing_none]
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct UnSubscribeInfo {
-    depth: Option<u16>,
+    depth: Option<Depth>,
     interval: Option<Interval>,
     name: SubscribableChannel,
     token: Option<String>,
@@ -214,6 +291,22 @@ pub struct UnSubscribe {
     subscription: UnSubscribeInfo,
 }
 
+impl From<&Subscribe> for UnSubscribe {
+    fn from(subscribe: &Subscribe) -> Self {
+        let info = &subscribe.subscription;
+        UnSubscribe {
+            request_id: subscribe.request_id,
+            pair: subscribe.pair.clone(),
+            subscription: UnSubscribeInfo {
+                depth: info.depth,
+                interval: info.interval,
+                name: info.name.clone(),
+                token: info.token.clone(),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum Status {
@@ -229,12 +322,27 @@ pub struct Subscription {
     depth: Option<Depth>,
     interval: Option<Interval>,
     max_rate_count: Option<i64>,
-    name: String, //TODO is there an enum that could be used here
+    name: SubscribableChannel,
     token: Option<String>,
 }
 
+impl From<&Subscription> for Channel {
+    fn from(subscription: &Subscription) -> Self {
+        match subscription.name() {
+            SubscribableChannel::Book => Self::Book(subscription.depth().unwrap_or_default()),
+            SubscribableChannel::OHLC => Self::OHLC(subscription.interval().unwrap_or_default()),
+            SubscribableChannel::OpenOrders => Self::OpenOrders,
+            SubscribableChannel::OwnTrades => Self::OwnTrades,
+            SubscribableChannel::Spread => Self::Spread,
+            SubscribableChannel::Ticker => Self::Ticker,
+            SubscribableChannel::Trade => Self::Trade,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
 #[serde(rename_all = "camelCase")]
+#[serde(from = "RawSubscriptionStatus")]
 pub struct SubscriptionStatus {
     #[serde(rename = "channelID")]
     channel_id: Option<i64>,
@@ -247,6 +355,37 @@ pub struct SubscriptionStatus {
     subscription: Subscription,
 }
 
+/// Kraken leaves the channel name out of error responses
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawSubscriptionStatus {
+    #[serde(rename = "channelID")]
+    channel_id: Option<i64>,
+    error_message: Option<String>,
+    channel_name: Option<crate::messages::Channel>,
+    #[serde(rename = "reqid")]
+    request_id: Option<i64>,
+    pair: Option<String>,
+    status: Status,
+    subscription: Subscription,
+}
+
+impl From<RawSubscriptionStatus> for SubscriptionStatus {
+    fn from(raw: RawSubscriptionStatus) -> Self {
+        Self {
+            channel_name: raw
+                .channel_name
+                .unwrap_or_else(|| (&raw.subscription).into()),
+            channel_id: raw.channel_id,
+            error_message: raw.error_message,
+            request_id: raw.request_id,
+            pair: raw.pair,
+            status: raw.status,
+            subscription: raw.subscription,
+        }
+    }
+}
+
 #[cfg(test)]
 mod general_message_tests {
     use crate::messages::*;
@@ -491,7 +630,6 @@ mod general_message_tests {
         assert!(matches!(sub_status.status, Status::Subscribed))
     }
 
-    #[ignore = "variable depths not supported"]
     #[test]
     fn subscription_status_error() {
         let j = test_utils::load_test_json("general/subscription_status/subscription_status_error")
@@ -504,16 +642,83 @@ mod general_message_tests {
         let Event::SubscriptionStatus(sub_status) = event else {
             panic!("expected subscription status event")
         };
-        assert!(matches!(sub_status.channel_name, Channel::Ticker));
-        assert_str_eq!(sub_status.pair.expect("expected a pair"), "XBT/EUR");
+        // error responses don't include a channel name so it comes from the subscription
+        assert_eq!(sub_status.channel_name, Channel::Book(Depth::Other(42)));
+        assert_str_eq!(sub_status.pair.clone().expect("expected a pair"), "XBT/USD");
         assert_str_eq!(
-            sub_status.error_message.expect("expected an error message"),
+            sub_status.error_message.clone().expect("expected an error message"),
             "Subscription depth not supported"
         );
         assert!(matches!(sub_status.status, Status::Error));
-        assert!(matches!(
-            sub_status.subscription.depth.expect("expected a depth") as u16,
+        assert_eq!(
+            u16::from(sub_status.subscription.depth.expect("expected a depth")),
             42
-        ));
+        );
+
+        let round_trip: SubscriptionStatus =
+            serde_json::from_str(&serde_json::to_string(&sub_status).unwrap())
+                .expect("couldn't deserialize serialized subscription status");
+        assert_eq!(round_trip.channel_name, sub_status.channel_name);
+    }
+
+    #[test]
+    fn unknown_depths_and_intervals() {
+        assert_eq!(Channel::from_str("book-42"), Ok(Channel::Book(Depth::Other(42))));
+        assert_eq!(Channel::from_str("book-25"), Ok(Channel::Book(Depth::TwentyFive)));
+        assert_eq!(
+            Channel::from_str("ohlc-120"),
+            Ok(Channel::OHLC(Interval::Other(120)))
+        );
+        assert_eq!(
+            serde_json::to_string(&Channel::Book(Depth::Other(42))).unwrap(),
+            r#""book-42""#
+        );
+        let depth: Depth = serde_json::from_str(r#""100""#).unwrap();
+        assert_eq!(depth, Depth::OneHundred);
+        assert!(serde_json::from_str::<Depth>("70000").is_err());
+    }
+
+    #[test]
+    fn known_values_built_as_other_are_the_named_variant() {
+        assert_eq!(Depth::Other(10), Depth::Ten);
+        assert_eq!(Interval::Other(5), Interval::FiveMin);
+        let pair = "XBT/USD".to_string();
+        assert_eq!(
+            Channel::Book(Depth::Other(10)).generate_identifier(&pair),
+            Channel::Book(Depth::Ten).generate_identifier(&pair)
+        );
+        assert_eq!(
+            Channel::OHLC(Interval::Other(60)).generate_identifier(&pair),
+            Channel::OHLC(Interval::OneHour).generate_identifier(&pair)
+        );
+        assert!(Depth::Other(50) < Depth::OneHundred);
+    }
+
+    #[test]
+    fn channels_map_to_subscriptions() {
+        let channels = [
+            Channel::Ticker,
+            Channel::OHLC(Interval::FifteenMin),
+            Channel::Trade,
+            Channel::Spread,
+            Channel::Book(Depth::Other(42)),
+            Channel::OwnTrades,
+            Channel::OpenOrders,
+        ];
+        for channel in channels {
+            let info = SubscribeInfo::from(&channel);
+            assert_eq!(Channel::from(&info), channel);
+            let name = serde_json::to_value(SubscribableChannel::from(&channel)).unwrap();
+            assert_eq!(name, serde_json::to_value(info.name()).unwrap());
+
+            let unsubscribe = UnSubscribe::from(&Subscribe::new(channel.clone()));
+            let unsubscribe_info = unsubscribe.subscription();
+            assert_eq!(
+                serde_json::to_value(unsubscribe_info.name()).unwrap(),
+                name
+            );
+            assert_eq!(*unsubscribe_info.depth(), *info.depth());
+            assert_eq!(*unsubscribe_info.interval(), *info.interval());
+        }
     }
 }
@@ -13,7 +13,11 @@ use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 use strum::Display as DisplayEnum;
 
-use crate::messages::private_messages::{OpenOrders, OwnTrades};
+use crate::messages::private_messages::{
+    AddOrder, AddOrderStatus, CancelAll, CancelAllOrdersAfter, CancelAllOrdersAfterStatus,
+    CancelAllStatus, CancelOrder, CancelOrderStatus, EditOrder, EditOrderStatus, OpenOrders,
+    OwnTrades,
+};
 use general_messages::*;
 use public_messages::*;
 
@@ -38,6 +42,16 @@ pub enum Event {
     Subscribe(Subscribe),
     Unsubscribe(UnSubscribe),
     SubscriptionStatus(SubscriptionStatus),
+    AddOrder(AddOrder),
+    AddOrderStatus(AddOrderStatus),
+    EditOrder(EditOrder),
+    EditOrderStatus(EditOrderStatus),
+    CancelOrder(CancelOrder),
+    CancelOrderStatus(CancelOrderStatus),
+    CancelAll(CancelAll),
+    CancelAllStatus(CancelAllStatus),
+    CancelAllOrdersAfter(CancelAllOrdersAfter),
+    CancelAllOrdersAfterStatus(CancelAllOrdersAfterStatus),
 }
 
 #[derive(
@@ -60,6 +74,16 @@ pub enum EventType {
     Subscribe,
     Unsubscribe,
     SubscriptionStatus,
+    AddOrder,
+    AddOrderStatus,
+    EditOrder,
+    EditOrderStatus,
+    CancelOrder,
+    CancelOrderStatus,
+    CancelAll,
+    CancelAllStatus,
+    CancelAllOrdersAfter,
+    CancelAllOrdersAfterStatus,
 }
 
 impl From<&Event> for EventType {
@@ -72,6 +96,16 @@ impl From<&Event> for EventType {
             Event::Subscribe(_) => Self::Subscribe,
             Event::Unsubscribe(_) => Self::Unsubscribe,
             Event::SubscriptionStatus(_) => Self::SubscriptionStatus,
+            Event::AddOrder(_) => Self::AddOrder,
+            Event::AddOrderStatus(_) => Self::AddOrderStatus,
+            Event::EditOrder(_) => Self::EditOrder,
+            Event::EditOrderStatus(_) => Self::EditOrderStatus,
+            Event::CancelOrder(_) => Self::CancelOrder,
+            Event::CancelOrderStatus(_) => Self::CancelOrderStatus,
+            Event::CancelAll(_) => Self::CancelAll,
+            Event::CancelAllStatus(_) => Self::CancelAllStatus,
+            Event::CancelAllOrdersAfter(_) => Self::CancelAllOrdersAfter,
+            Event::CancelAllOrdersAfterStatus(_) => Self::CancelAllOrdersAfterStatus,
         }
     }
 }
@@ -84,6 +118,16 @@ impl Event {
             Event::Subscribe(s) => s.request_id().clone(),
             Event::Unsubscribe(u) => u.request_id().clone(),
             Event::SubscriptionStatus(s) => s.request_id().clone(),
+            Event::AddOrder(a) => *a.request_id(),
+            Event::AddOrderStatus(a) => *a.request_id(),
+            Event::EditOrder(e) => *e.request_id(),
+            Event::EditOrderStatus(e) => *e.request_id(),
+            Event::CancelOrder(c) => *c.request_id(),
+            Event::CancelOrderStatus(c) => *c.request_id(),
+            Event::CancelAll(c) => *c.request_id(),
+            Event::CancelAllStatus(c) => *c.request_id(),
+            Event::CancelAllOrdersAfter(c) => *c.request_id(),
+            Event::CancelAllOrdersAfterStatus(c) => *c.request_id(),
             _ => None,
         }
     }
@@ -115,6 +159,11 @@ impl From<&SubscribeInfo> for Channel {
 }
 
 impl Channel {
+    /// private channels can only be subscribed to with a token on an authenticated connection
+    pub fn is_private(&self) -> bool {
+        matches!(self, Channel::OwnTrades | Channel::OpenOrders)
+    }
+
     pub(crate) fn generate_identifier(&self, pair: &Pair) -> u64 {
         let mut hasher = ahash::AHasher::default();
         self.hash(&mut hasher);
@@ -139,12 +188,12 @@ impl Serialize for Channel {
         match self {
             Channel::Ticker => serializer.serialize_str("ticker"),
             Channel::OHLC(interval) => {
-                serializer.serialize_str(format!("ohlc-{}", *interval as u32).as_str())
+                serializer.serialize_str(format!("ohlc-{}", u32::from(*interval)).as_str())
             }
             Channel::Trade => serializer.serialize_str("trade"),
             Channel::Spread => serializer.serialize_str("spread"),
             Channel::Book(depth) => {
-                serializer.serialize_str(format!("book-{}", *depth as u16).as_str())
+                serializer.serialize_str(format!("book-{}", u16::from(*depth)).as_str())
             }
             Channel::OwnTrades => serializer.serialize_str("ownTrades"),
             Channel::OpenOrders => serializer.serialize_str("openOrders"),
@@ -242,14 +291,34 @@ impl ChannelMessage {
             Channel::OHLC(_) => ChannelMessage::OHLC(serde_json::from_value(data)?),
             Channel::Trade => ChannelMessage::Trade(serde_json::from_value(data)?),
             Channel::Spread => ChannelMessage::Spread(serde_json::from_value(data)?),
-            Channel::Book(_) => ChannelMessage::Book(serde_json::from_value(data)?),
-            Channel::OwnTrades => ChannelMessage::OwnTrades(serde_json::from_value(data)?),
-            Channel::OpenOrders => ChannelMessage::OpenOrders(serde_json::from_value(data)?),
+            Channel::Book(_) => ChannelMessage::Book(serde_json::from_value(merge_objects(data))?),
+            Channel::OwnTrades => {
+                ChannelMessage::OwnTrades(private_messages::own_trades_from_value(data)?)
+            }
+            Channel::OpenOrders => {
+                ChannelMessage::OpenOrders(private_messages::open_orders_from_value(data)?)
+            }
         };
         Ok(channel_message)
     }
 }
 
+/// Book updates with changes on both sides are split over two objects
+fn merge_objects(data: Value) -> Value {
+    match data {
+        Value::Array(values) if values.iter().all(Value::is_object) => {
+            let mut merged = serde_json::Map::new();
+            for value in values {
+                if let Value::Object(object) = value {
+                    merged.extend(object);
+                }
+            }
+            Value::Object(merged)
+        }
+        data => data,
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Getters)]
 pub struct Sequence {
     sequence: i64,
@@ -257,7 +326,8 @@ pub struct Sequence {
 
 #[derive(Serialize, DebugAsJson, Clone, Getters, DisplayAsJsonPretty)]
 pub struct ChannelMessageWrapper {
-    id: i64,
+    /// public channels have a numeric channel id, private channels don't
+    id: Option<i64>,
     message: ChannelMessage,
     channel: Channel,
     pair: Option<String>,
@@ -271,6 +341,10 @@ impl ChannelMessageWrapper {
             Some(pair) => self.channel.generate_identifier(pair),
         }
     }
+
+    pub(crate) fn into_parts(self) -> (ChannelMessage, Option<Pair>) {
+        (self.message, self.pair)
+    }
 }
 
 //This is nasty. Kraken why you like this
@@ -295,18 +369,35 @@ impl<'de> Deserialize<'de> for ChannelMessageWrapper {
                 let min_num_objects = match seq.size_hint() {
                     None => 1,
                     Some(n) => {
-                        if n <= 3 {
+                        if n < 3 {
                             return Err(DeError::invalid_length(n, &self));
                         }
-                        n - 3
+                        n - 2
                     }
                 };
 
-                let id: i64 = seq
+                let mut messages = Vec::with_capacity(min_num_objects);
+
+                // private channels don't have a channel id
+                let first: Value = seq
                     .next_element()?
                     .ok_or_else(|| DeError::invalid_length(0, &self))?;
+                let id = match first {
+                    Value::Number(id) => Some(id.as_i64().ok_or_else(|| {
+                        DeError::custom(format!("channel id {} is not an i64", id))
+                    })?),
+                    Value::Object(_) | Value::Array(_) => {
+                        messages.push(first);
+                        None
+                    }
+                    _ => {
+                        return Err(DeError::custom(
+                            "unexpected value. Expected channel id or json object",
+                        ))
+                    }
+                };
+
                 let channel: Channel;
-                let mut messages = Vec::with_capacity(min_num_objects);
                 loop {
                     let val: Value = seq
                         .next_element()?
@@ -369,17 +460,29 @@ impl<'de> Deserialize<'de> for ChannelMessageWrapper {
                     }
                 };
 
-                //TODO may need to do some magic to determine if there is a pair here...
-                let pair: String = seq
-                    .next_element()?
-                    .ok_or_else(|| DeError::invalid_length(3, &self))?;
+                // public channels are followed by the pair, private channels by the sequence
+                let mut pair = None;
+                let mut sequence = None;
+                while let Some(val) = seq.next_element::<Value>()? {
+                    match val {
+                        Value::String(p) if pair.is_none() => pair = Some(p),
+                        Value::Object(_) if sequence.is_none() => {
+                            sequence = serde_json::from_value(val).ok()
+                        }
+                        _ => {
+                            return Err(DeError::custom(format!(
+                                "unexpected trailing value in {} message -> {}",
+                                channel, val
+                            )))
+                        }
+                    }
+                }
 
-                let sequence = seq.next_element::<Sequence>().ok().unwrap_or(None);
                 Ok(ChannelMessageWrapper {
                     id,
                     message,
                     channel,
-                    pair: Some(pair),
+                    pair,
                     sequence,
                 })
             }
@@ -447,6 +550,43 @@ mod wrapper_message_tests {
         Ok(())
     }
 
+    #[test]
+    fn deserialize_book_messages() -> anyhow::Result<()> {
+        let j = test_utils::load_test_json("public/book/book_snapshot")?;
+        let channel_message: ChannelMessageWrapper = serde_json::from_str(&j)?;
+        let ChannelMessage::Book(book) = channel_message.message() else {
+            bail!("expected book type");
+        };
+        assert!(*book.snapshot());
+        assert_eq!(book.ask_levels().as_ref().map(Vec::len), Some(3));
+        assert_eq!(book.bid_levels().as_ref().map(Vec::len), Some(3));
+
+        let j = test_utils::load_test_json("public/book/book_update_a")?;
+        let channel_message: ChannelMessageWrapper = serde_json::from_str(&j)?;
+        let ChannelMessage::Book(book) = channel_message.message() else {
+            bail!("expected book type");
+        };
+        assert!(!*book.snapshot());
+        assert_eq!(book.ask_levels().as_ref().map(Vec::len), Some(2));
+        assert!(book.bid_levels().is_none());
+        assert_eq!(*book.checksum(), Some(974942666));
+
+        let j = test_utils::load_test_json("public/book/book_update_both")?;
+        let channel_message: ChannelMessageWrapper = serde_json::from_str(&j)?;
+        let ChannelMessage::Book(book) = channel_message.message() else {
+            bail!("expected book type");
+        };
+        assert!(!*book.snapshot());
+        assert_eq!(book.ask_levels().as_ref().map(Vec::len), Some(2));
+        assert_eq!(book.bid_levels().as_ref().map(Vec::len), Some(1));
+        assert_eq!(*book.checksum(), Some(974942666));
+        assert_str_eq!(
+            channel_message.pair().as_ref().expect("didnt' have a pair"),
+            "XBT/USD"
+        );
+        Ok(())
+    }
+
     #[test]
     fn multiple_objects_in_message() -> anyhow::Result<()> {
         let j = test_utils::load_test_json("public/ticker")?;
@@ -464,4 +604,42 @@ mod wrapper_message_tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn convert_to_core_types() -> anyhow::Result<()> {
+        use linnaeus_types as types;
+        use rust_decimal_macros::dec;
+
+        let j = test_utils::load_test_json("public/trade")?;
+        let wrapper: ChannelMessageWrapper = serde_json::from_str(&j)?;
+        let ChannelMessage::Trade(trades) = wrapper.message() else {
+            bail!("expected trade type");
+        };
+        let trade = types::Trade::from(&trades[0]);
+        assert_eq!(*trade.price(), dec!(5541.2));
+        assert_eq!(*trade.side(), types::Side::Sell);
+        assert_eq!(*trade.trade_type(), types::TradeType::Limit);
+
+        let j = test_utils::load_test_json("public/ticker")?;
+        let wrapper: ChannelMessageWrapper = serde_json::from_str(&j)?;
+        let ChannelMessage::Ticker(ticker) = wrapper.message() else {
+            bail!("expected ticker type");
+        };
+        let ticker = types::Ticker::from(ticker);
+        assert_eq!(*ticker.bid(), dec!(5525.1));
+        assert_eq!(*ticker.open(), dec!(5760.7));
+        assert_eq!(*ticker.high(), dec!(5783));
+
+        let j = test_utils::load_test_json("public/ohlc-5")?;
+        let wrapper: ChannelMessageWrapper = serde_json::from_str(&j)?;
+        let (ChannelMessage::OHLC(ohlc), Channel::OHLC(interval)) =
+            (wrapper.message(), wrapper.channel())
+        else {
+            bail!("expected ohlc type");
+        };
+        let candle = ohlc.to_candle(*interval);
+        assert_eq!(candle.time().timestamp(), 1542057060);
+        assert_eq!(*candle.trades(), 2);
+        Ok(())
+    }
 }
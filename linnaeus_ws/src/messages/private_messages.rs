@@ -1,8 +1,13 @@
+use crate::messages::Pair;
 use derive_getters::Getters;
+use derive_setters::Setters;
 use display_json::{DebugAsJson, DisplayAsJsonPretty};
 use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_with::{serde_as, TimestampSecondsWithFrac};
+use serde_json::Value;
+use serde_with::{serde_as, skip_serializing_none, DefaultOnError, TimestampSecondsWithFrac};
+use std::collections::HashMap;
 
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Clone)]
 #[serde(rename_all = "lowercase")]
@@ -12,7 +17,7 @@ pub enum Side {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "kebab-case")]
 pub enum OrderType {
     Market,
     Limit,
@@ -24,31 +29,35 @@ pub enum OrderType {
 }
 
 #[serde_as]
+#[skip_serializing_none]
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
-#[serde(rename_all = "lowercase")]
 pub struct OwnTrade {
+    #[serde(rename = "ordertxid")]
     order_transaction_id: String,
+    #[serde(rename = "postxid")]
     position_trade_id: String,
     pair: String,
     #[serde_as(as = "TimestampSecondsWithFrac<String>")]
     time: chrono::DateTime<chrono::Utc>,
     #[serde(rename = "type")]
     side: Side,
+    #[serde(rename = "ordertype")]
     order_type: OrderType,
     price: Decimal,
     cost: Decimal,
     fee: Decimal,
+    #[serde(rename = "vol")]
     volume: Decimal,
     margin: Decimal,
     #[serde(rename = "userref")]
-    user_reference_id: i64
+    user_reference_id: Option<i64>
 }
 
 pub type OwnTradePair = (String, OwnTrade);
 
 pub type OwnTrades = Vec<OwnTradePair>;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum OrderStatus {
     Pending,
@@ -59,36 +68,63 @@ pub enum OrderStatus {
 }
 
 #[serde_as]
-#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
-#[serde(rename_all = "lowercase")]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Setters, Clone)]
+#[setters(prefix = "with_")]
 pub struct OrderDescription {
+    #[setters(skip)]
     pair: String,
     #[serde(rename = "position")]
     position_id: Option<String>,
     #[serde(rename = "type")]
+    #[setters(skip)]
     side:Side,
+    #[serde(rename = "ordertype")]
+    #[setters(skip)]
     order_type: OrderType,
     price: Decimal,
     #[serde(rename = "price2")]
     secondary_price: Decimal,
-    leverage: Decimal,
+    #[serde_as(deserialize_as = "DefaultOnError")]
+    #[serde(default)]
+    leverage: Option<Decimal>,
     #[serde(rename = "order")]
+    #[setters(skip)]
     order_description: String,
     #[serde(rename = "close")]
+    #[serde(default)]
     close_description: String
 }
 
+impl OrderDescription {
+    /// Unpriced, outside of a position and without a conditional close
+    pub fn new(pair: String, side: Side, order_type: OrderType, order_description: String) -> Self {
+        Self {
+            pair,
+            position_id: None,
+            side,
+            order_type,
+            price: Decimal::ZERO,
+            secondary_price: Decimal::ZERO,
+            leverage: None,
+            order_description,
+            close_description: String::new(),
+        }
+    }
+}
+
 #[serde_as]
-#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
-#[serde(rename_all = "lowercase")]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Setters, Clone)]
+#[setters(prefix = "with_")]
 pub struct OpenOrder {
     #[serde(rename = "refid")]
-    reference_id: String,
+    reference_id: Option<String>,
     #[serde(rename = "userref")]
     user_reference_id: i64,
+    #[setters(skip)]
     status: OrderStatus,
     #[serde_as(as = "TimestampSecondsWithFrac<String>")]
     #[serde(rename = "opentm")]
+    #[setters(skip)]
     open_time: chrono::DateTime<chrono::Utc>,
     #[serde_as(as = "TimestampSecondsWithFrac<String>")]
     #[serde(rename = "starttm")]
@@ -99,6 +135,7 @@ pub struct OpenOrder {
     #[serde(rename = "descr")]
     description: OrderDescription,
     #[serde(rename = "vol")]
+    #[setters(skip)]
     volume: Decimal,
     #[serde(rename = "vol_exec")]
     volume_executed: Decimal,
@@ -106,7 +143,9 @@ pub struct OpenOrder {
     fee: Decimal,
     #[serde(rename = "avg_price")]
     average_price: Decimal,
+    #[serde(rename = "stopprice")]
     stop_price:Decimal,
+    #[serde(rename = "limitprice")]
     limit_price: Decimal,
     misc: String,
     oflags: Option<String>,
@@ -115,12 +154,73 @@ pub struct OpenOrder {
     rate_count: Option<i64>
 }
 
+impl OpenOrder {
+    /// An order that hasn't been filled yet, without a start or expire time
+    pub fn new(
+        status: OrderStatus,
+        open_time: chrono::DateTime<chrono::Utc>,
+        description: OrderDescription,
+        volume: Decimal,
+    ) -> Self {
+        Self {
+            reference_id: None,
+            user_reference_id: 0,
+            status,
+            open_time,
+            start_time: chrono::DateTime::UNIX_EPOCH,
+            expire_time: chrono::DateTime::UNIX_EPOCH,
+            description,
+            volume,
+            volume_executed: Decimal::ZERO,
+            cost: Decimal::ZERO,
+            fee: Decimal::ZERO,
+            average_price: Decimal::ZERO,
+            stop_price: Decimal::ZERO,
+            limit_price: Decimal::ZERO,
+            misc: String::new(),
+            oflags: None,
+            time_in_force: None,
+            cancel_reason: None,
+            rate_count: None
+        }
+    }
+}
+
 pub type OpenOrderPair = (String, OpenOrder);
 
-#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
-#[serde(rename_all = "lowercase")]
+/// Partial update of an order. Status changes only carry the status and fills only carry the
+/// executed volume and its cost
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Setters, Clone)]
+#[setters(prefix = "with_")]
 pub struct OrderStatusChange {
-    status: OrderStatus
+    #[setters(skip)]
+    status: Option<OrderStatus>,
+    #[serde(rename = "vol_exec")]
+    volume_executed: Option<Decimal>,
+    cost: Option<Decimal>,
+    fee: Option<Decimal>,
+    #[serde(rename = "avg_price")]
+    average_price: Option<Decimal>,
+    #[serde(rename = "userref")]
+    user_reference_id: Option<i64>,
+    #[serde(alias = "cancel_reason")]
+    reason: Option<String>,
+}
+
+impl OrderStatusChange {
+    /// A change of the order's status alone
+    pub fn new(status: OrderStatus) -> Self {
+        Self {
+            status: Some(status),
+            volume_executed: None,
+            cost: None,
+            fee: None,
+            average_price: None,
+            user_reference_id: None,
+            reason: None,
+        }
+    }
 }
 
 pub type OrderStatusChangePair = (String, OrderStatusChange);
@@ -130,7 +230,582 @@ pub type OrderStatusChangePair = (String, OrderStatusChange);
 #[serde(untagged)]
 pub enum OpenOrderOrStatusChange {
     OpenOrder(OpenOrderPair),
-    StatusChange(OrderStatusChange)
+    StatusChange(OrderStatusChangePair)
+}
+
+pub type OpenOrders = Vec<OpenOrderOrStatusChange>;
+
+/// Private feeds send a list of single entry objects keyed by the transaction id
+fn keyed_entries(data: Value) -> Result<Vec<(String, Value)>, serde_json::Error> {
+    let entries: Vec<HashMap<String, Value>> = serde_json::from_value(data)?;
+    Ok(entries.into_iter().flatten().collect())
+}
+
+fn keyed_list<T: DeserializeOwned>(data: Value) -> Result<Vec<(String, T)>, serde_json::Error> {
+    keyed_entries(data)?
+        .into_iter()
+        .map(|(id, value)| Ok((id, serde_json::from_value(value)?)))
+        .collect()
+}
+
+pub(crate) fn own_trades_from_value(data: Value) -> Result<OwnTrades, serde_json::Error> {
+    keyed_list(data)
+}
+
+pub(crate) fn open_orders_from_value(data: Value) -> Result<OpenOrders, serde_json::Error> {
+    keyed_entries(data)?
+        .into_iter()
+        .map(|(id, value)| {
+            if value.get("descr").is_some() {
+                Ok(OpenOrderOrStatusChange::OpenOrder((
+                    id,
+                    serde_json::from_value(value)?,
+                )))
+            } else {
+                Ok(OpenOrderOrStatusChange::StatusChange((
+                    id,
+                    serde_json::from_value(value)?,
+                )))
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestStatus {
+    Ok,
+    Error,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum TimeInForce {
+    #[serde(rename = "GTC")]
+    GoodTillCancelled,
+    #[serde(rename = "IOC")]
+    ImmediateOrCancel,
+    #[serde(rename = "GTD")]
+    GoodTillDate,
 }
 
-pub type OpenOrders = Vec<OpenOrderOrStatusChange>;
\ No newline at end of file
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Setters, Clone)]
+#[setters(strip_option, prefix = "with_")]
+pub struct AddOrder {
+    #[setters(skip)]
+    token: Option<String>,
+    #[serde(rename = "reqid")]
+    #[setters(skip)]
+    request_id: Option<i64>,
+    #[serde(rename = "ordertype")]
+    #[setters(skip)]
+    order_type: OrderType,
+    #[serde(rename = "type")]
+    #[setters(skip)]
+    side: Side,
+    #[setters(skip)]
+    pair: Pair,
+    price: Option<Decimal>,
+    price2: Option<Decimal>,
+    #[setters(skip)]
+    volume: Decimal,
+    leverage: Option<Decimal>,
+    reduce_only: Option<bool>,
+    #[serde(rename = "oflags")]
+    order_flags: Option<String>,
+    #[serde(rename = "starttm")]
+    start_time: Option<String>,
+    #[serde(rename = "expiretm")]
+    expire_time: Option<String>,
+    #[serde(rename = "userref")]
+    user_reference_id: Option<String>,
+    validate: Option<String>,
+    #[serde(rename = "close[ordertype]")]
+    close_order_type: Option<OrderType>,
+    #[serde(rename = "close[price]")]
+    close_price: Option<Decimal>,
+    #[serde(rename = "close[price2]")]
+    close_price2: Option<Decimal>,
+    #[serde(rename = "timeinforce")]
+    time_in_force: Option<TimeInForce>,
+}
+
+impl AddOrder {
+    pub fn new(order_type: OrderType, side: Side, pair: Pair, volume: Decimal) -> Self {
+        Self {
+            token: None,
+            request_id: None,
+            order_type,
+            side,
+            pair,
+            price: None,
+            price2: None,
+            volume,
+            leverage: None,
+            reduce_only: None,
+            order_flags: None,
+            start_time: None,
+            expire_time: None,
+            user_reference_id: None,
+            validate: None,
+            close_order_type: None,
+            close_price: None,
+            close_price2: None,
+            time_in_force: None,
+        }
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AddOrderStatus {
+    #[serde(rename = "reqid")]
+    request_id: Option<i64>,
+    status: RequestStatus,
+    #[serde(rename = "txid")]
+    transaction_id: Option<String>,
+    #[serde(rename = "descr")]
+    description: Option<String>,
+    error_message: Option<String>,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Setters, Clone)]
+#[setters(strip_option, prefix = "with_")]
+pub struct EditOrder {
+    #[setters(skip)]
+    token: Option<String>,
+    #[serde(rename = "reqid")]
+    #[setters(skip)]
+    request_id: Option<i64>,
+    #[serde(rename = "orderid")]
+    #[setters(skip)]
+    order_id: String,
+    #[setters(skip)]
+    pair: Pair,
+    price: Option<Decimal>,
+    price2: Option<Decimal>,
+    volume: Option<Decimal>,
+    #[serde(rename = "oflags")]
+    order_flags: Option<String>,
+    #[serde(rename = "newuserref")]
+    new_user_reference_id: Option<String>,
+    validate: Option<String>,
+}
+
+impl EditOrder {
+    pub fn new(order_id: String, pair: Pair) -> Self {
+        Self {
+            token: None,
+            request_id: None,
+            order_id,
+            pair,
+            price: None,
+            price2: None,
+            volume: None,
+            order_flags: None,
+            new_user_reference_id: None,
+            validate: None,
+        }
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EditOrderStatus {
+    #[serde(rename = "reqid")]
+    request_id: Option<i64>,
+    status: RequestStatus,
+    #[serde(rename = "txid")]
+    transaction_id: Option<String>,
+    #[serde(rename = "originaltxid")]
+    original_transaction_id: Option<String>,
+    #[serde(rename = "descr")]
+    description: Option<String>,
+    error_message: Option<String>,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct CancelOrder {
+    token: Option<String>,
+    #[serde(rename = "reqid")]
+    request_id: Option<i64>,
+    #[serde(rename = "txid")]
+    transaction_ids: Vec<String>,
+}
+
+impl CancelOrder {
+    pub fn new(transaction_ids: Vec<String>) -> Self {
+        Self {
+            token: None,
+            request_id: None,
+            transaction_ids,
+        }
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelOrderStatus {
+    #[serde(rename = "reqid")]
+    request_id: Option<i64>,
+    status: RequestStatus,
+    error_message: Option<String>,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone, Default)]
+pub struct CancelAll {
+    token: Option<String>,
+    #[serde(rename = "reqid")]
+    request_id: Option<i64>,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelAllStatus {
+    #[serde(rename = "reqid")]
+    request_id: Option<i64>,
+    status: RequestStatus,
+    count: Option<u64>,
+    error_message: Option<String>,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct CancelAllOrdersAfter {
+    token: Option<String>,
+    #[serde(rename = "reqid")]
+    request_id: Option<i64>,
+    /// seconds until all orders are cancelled. 0 disables the timer
+    timeout: u64,
+}
+
+impl CancelAllOrdersAfter {
+    pub fn new(timeout: u64) -> Self {
+        Self {
+            token: None,
+            request_id: None,
+            timeout,
+        }
+    }
+}
+
+#[serde_as]
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelAllOrdersAfterStatus {
+    #[serde(rename = "reqid")]
+    request_id: Option<i64>,
+    status: RequestStatus,
+    current_time: Option<chrono::DateTime<chrono::Utc>>,
+    /// None when the timer has been disabled
+    #[serde_as(deserialize_as = "DefaultOnError")]
+    #[serde(default)]
+    trigger_time: Option<chrono::DateTime<chrono::Utc>>,
+    error_message: Option<String>,
+}
+
+/// Private requests that need the websocket token and a request id filled in before being sent
+pub(crate) trait AuthenticatedRequest {
+    fn set_token(&mut self, token: String);
+    fn set_request_id(&mut self, request_id: i64);
+}
+
+macro_rules! impl_authenticated_request {
+    ($($request:ty),*) => {
+        $(
+            impl AuthenticatedRequest for $request {
+                fn set_token(&mut self, token: String) {
+                    self.token = Some(token);
+                }
+                fn set_request_id(&mut self, request_id: i64) {
+                    self.request_id = Some(request_id);
+                }
+            }
+        )*
+    };
+}
+
+impl_authenticated_request!(AddOrder, EditOrder, CancelOrder, CancelAll, CancelAllOrdersAfter);
+
+/// Responses to private requests. Kraken reports failures with an error status and message
+pub(crate) trait RequestResponse {
+    fn request_status(&self) -> &RequestStatus;
+    fn request_error_message(&self) -> Option<&str>;
+}
+
+macro_rules! impl_request_response {
+    ($($response:ty),*) => {
+        $(
+            impl RequestResponse for $response {
+                fn request_status(&self) -> &RequestStatus {
+                    &self.status
+                }
+                fn request_error_message(&self) -> Option<&str> {
+                    self.error_message.as_deref()
+                }
+            }
+        )*
+    };
+}
+
+impl_request_response!(
+    AddOrderStatus,
+    EditOrderStatus,
+    CancelOrderStatus,
+    CancelAllStatus,
+    CancelAllOrdersAfterStatus
+);
+
+#[cfg(test)]
+mod private_message_tests {
+    use super::*;
+    use crate::messages::{Channel, ChannelMessage, ChannelMessageWrapper, Event, Message};
+    use crate::test_utils;
+    use pretty_assertions::assert_eq;
+    use pretty_assertions::assert_str_eq;
+    use rust_decimal_macros::dec;
+
+    fn assert_json_eq(event: Event, file: &str) {
+        let expected: serde_json::Value = serde_json::from_str(
+            &test_utils::load_test_json(file).expect("couldn't load test json from file"),
+        )
+        .expect("test json is invalid");
+        let produced = serde_json::to_value(&event).expect("couldn't serialise event");
+        assert_eq!(produced, expected);
+    }
+
+    fn load_event(file: &str) -> Event {
+        let j = test_utils::load_test_json(file).expect("couldn't load test json from file");
+        let message: Message =
+            serde_json::from_str(&j).expect("failed to deserialize test json to message");
+        let Message::Event(event) = message else {
+            panic!("expected event");
+        };
+        event
+    }
+
+    #[test]
+    fn own_trades() {
+        let j = test_utils::load_test_json("private/own_trades")
+            .expect("couldn't load test json from file");
+        let message: ChannelMessageWrapper =
+            serde_json::from_str(&j).expect("failed to deserialize own trades");
+        assert!(message.id().is_none());
+        assert!(message.pair().is_none());
+        assert!(matches!(message.channel(), Channel::OwnTrades));
+        assert_eq!(
+            *message.sequence().as_ref().expect("expected a sequence").sequence(),
+            2948
+        );
+        let ChannelMessage::OwnTrades(trades) = message.message() else {
+            panic!("expected own trades");
+        };
+        assert_eq!(trades.len(), 4);
+        let (id, trade) = &trades[0];
+        assert_str_eq!(id, "TDLH43-DVQXD-2KHVYY");
+        assert_str_eq!(trade.position_trade_id(), "OGTT3Y-C6I3P-XRI6HX");
+        assert_eq!(*trade.fee(), dec!(1600));
+        assert!(matches!(trade.side(), Side::Sell));
+        assert!(matches!(trade.order_type(), OrderType::Limit));
+    }
+
+    #[test]
+    fn open_orders() {
+        let j = test_utils::load_test_json("private/open_orders/open_orders")
+            .expect("couldn't load test json from file");
+        let message: ChannelMessageWrapper =
+            serde_json::from_str(&j).expect("failed to deserialize open orders");
+        assert_eq!(
+            *message.sequence().as_ref().expect("expected a sequence").sequence(),
+            234
+        );
+        let ChannelMessage::OpenOrders(orders) = message.message() else {
+            panic!("expected open orders");
+        };
+        assert_eq!(orders.len(), 4);
+        let OpenOrderOrStatusChange::OpenOrder((id, order)) = &orders[1] else {
+            panic!("expected an open order");
+        };
+        assert_str_eq!(id, "OGTT3Y-C6I3P-XRI6HX");
+        assert_eq!(*order.description().price(), dec!(5334.6));
+        assert!(order.description().leverage().is_none());
+        assert!(matches!(order.status(), OrderStatus::Open));
+    }
+
+    #[test]
+    fn open_order_status_change() {
+        let j = test_utils::load_test_json("private/open_orders/open_order_status_change")
+            .expect("couldn't load test json from file");
+        let message: ChannelMessageWrapper =
+            serde_json::from_str(&j).expect("failed to deserialize open order status change");
+        let ChannelMessage::OpenOrders(orders) = message.message() else {
+            panic!("expected open orders");
+        };
+        assert_eq!(orders.len(), 2);
+        assert!(matches!(
+            &orders[0],
+            OpenOrderOrStatusChange::StatusChange((id, change))
+                if id == "OGTT3Y-C6I3P-XRI6HX" && *change.status() == Some(OrderStatus::Closed)
+        ));
+    }
+
+    #[test]
+    fn open_order_fill_update() {
+        let data = serde_json::json!([{
+            "OGTT3Y-C6I3P-XRI6HX": {
+                "vol_exec": "0.50000000",
+                "cost": "2667.30000",
+                "fee": "4.26768",
+                "avg_price": "5334.60000",
+                "userref": 0
+            }
+        }]);
+        let orders = open_orders_from_value(data).expect("failed to deserialize fill update");
+        let OpenOrderOrStatusChange::StatusChange((_, change)) = &orders[0] else {
+            panic!("expected a status change");
+        };
+        assert!(change.status().is_none());
+        assert_eq!(*change.volume_executed(), Some(dec!(0.5)));
+        assert_eq!(*change.average_price(), Some(dec!(5334.6)));
+    }
+
+    #[test]
+    fn add_order_request() {
+        let mut add_order = AddOrder::new(
+            OrderType::Limit,
+            Side::Buy,
+            "XBT/USD".to_string(),
+            dec!(10.123),
+        )
+        .with_price(dec!(9000));
+        add_order.set_token("0000000000000000000000000000000000000000".to_string());
+        assert_json_eq(
+            Event::AddOrder(add_order),
+            "private/add_order/request/add_order_request_a",
+        );
+
+        let mut add_order =
+            AddOrder::new(OrderType::Limit, Side::Buy, "XBT/USD".to_string(), dec!(10))
+                .with_price(dec!(9000))
+                .with_close_order_type(OrderType::Limit)
+                .with_close_price(dec!(9100));
+        add_order.set_token("0000000000000000000000000000000000000000".to_string());
+        assert_json_eq(
+            Event::AddOrder(add_order),
+            "private/add_order/request/add_order_request_b",
+        );
+    }
+
+    #[test]
+    fn add_order_response() {
+        let Event::AddOrderStatus(status) = load_event("private/add_order/response/add_order_response") else {
+            panic!("expected add order status");
+        };
+        assert_eq!(status.status, RequestStatus::Ok);
+        assert_str_eq!(status.transaction_id.expect("expected a txid"), "ONPNXH-KMKMU-F4MR5V");
+
+        let Event::AddOrderStatus(status) = load_event("private/add_order/response/add_order_response_err") else {
+            panic!("expected add order status");
+        };
+        assert_eq!(status.status, RequestStatus::Error);
+        assert_str_eq!(
+            status.error_message.expect("expected an error message"),
+            "EOrder:Order minimum not met"
+        );
+    }
+
+    #[test]
+    fn edit_order() {
+        let mut edit_order = EditOrder::new("O26VH7-COEPR-YFYXLK".to_string(), "XBT/USD".to_string())
+            .with_price(dec!(9000))
+            .with_order_flags(String::new())
+            .with_new_user_reference_id("666".to_string());
+        edit_order.set_token("0000000000000000000000000000000000000000".to_string());
+        edit_order.set_request_id(3);
+        assert_json_eq(
+            Event::EditOrder(edit_order),
+            "private/edit_order/edit_order_request",
+        );
+
+        let Event::EditOrderStatus(status) = load_event("private/edit_order/edit_order_response") else {
+            panic!("expected edit order status");
+        };
+        assert_eq!(status.request_id, Some(3));
+        assert_str_eq!(status.transaction_id.expect("expected a txid"), "OTI672-HJFAO-XOIPPK");
+        assert_str_eq!(
+            status.original_transaction_id.expect("expected an original txid"),
+            "O65KZW-J4AW3-VFS74A"
+        );
+    }
+
+    #[test]
+    fn cancel_order() {
+        let mut cancel_order = CancelOrder::new(vec![
+            "OGTT3Y-C6I3P-XRI6HX".to_string(),
+            "OGTT3Y-C6I3P-X2I6HX".to_string(),
+        ]);
+        cancel_order.set_token("0000000000000000000000000000000000000000".to_string());
+        assert_json_eq(
+            Event::CancelOrder(cancel_order),
+            "private/cancel_order/cancel_order_request",
+        );
+
+        let Event::CancelOrderStatus(status) = load_event("private/cancel_order/cancel_order_response") else {
+            panic!("expected cancel order status");
+        };
+        assert_eq!(status.status, RequestStatus::Ok);
+
+        let Event::CancelOrderStatus(status) = load_event("private/cancel_order/cancel_order_err") else {
+            panic!("expected cancel order status");
+        };
+        assert_eq!(status.status, RequestStatus::Error);
+    }
+
+    #[test]
+    fn cancel_all() {
+        let mut cancel_all = CancelAll::default();
+        cancel_all.set_token("0000000000000000000000000000000000000000".to_string());
+        assert_json_eq(
+            Event::CancelAll(cancel_all),
+            "private/cancel_all/cancel_all_request",
+        );
+
+        let Event::CancelAllStatus(status) = load_event("private/cancel_all/cancel_all_response") else {
+            panic!("expected cancel all status");
+        };
+        assert_eq!(status.count, Some(2));
+    }
+
+    #[test]
+    fn cancel_all_orders_after() {
+        let mut request = CancelAllOrdersAfter::new(60);
+        request.set_token("0000000000000000000000000000000000000000".to_string());
+        request.set_request_id(1608543428050);
+        assert_json_eq(
+            Event::CancelAllOrdersAfter(request),
+            "private/cancel_all_orders_after/request/cancel_all_orders_after_request_a",
+        );
+
+        let Event::CancelAllOrdersAfterStatus(status) = load_event(
+            "private/cancel_all_orders_after/response/cancel_all_orders_after_response_a",
+        ) else {
+            panic!("expected cancel all orders after status");
+        };
+        assert!(status.trigger_time.is_some());
+
+        let Event::CancelAllOrdersAfterStatus(status) = load_event(
+            "private/cancel_all_orders_after/response/cancel_all_orders_after_response_b",
+        ) else {
+            panic!("expected cancel all orders after status");
+        };
+        assert!(status.trigger_time.is_none());
+    }
+}
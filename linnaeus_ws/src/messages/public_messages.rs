@@ -5,6 +5,8 @@ use display_json::{DebugAsJson, DisplayAsJsonPretty};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, TimestampSecondsWithFrac, DisplayFromStr};
+use linnaeus_types as types;
+use crate::messages::general_messages::Interval;
 
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
 pub struct Price {
@@ -71,9 +73,9 @@ pub enum Side {
 
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Clone)]
 pub enum OrderType {
-    #[serde(rename = "b")]
+    #[serde(rename = "m")]
     Market,
-    #[serde(rename = "s")]
+    #[serde(rename = "l")]
     Limit
 }
 
@@ -117,6 +119,17 @@ pub struct PriceLevel {
     timestamp: chrono::DateTime<chrono::Utc>,
     update_type: Option<BookUpdateType>
 }
+impl PriceLevel {
+    pub(crate) fn new(price: Decimal, volume: Decimal, timestamp: chrono::DateTime<chrono::Utc>) -> Self {
+        Self {
+            price,
+            volume,
+            timestamp,
+            update_type: None,
+        }
+    }
+}
+
 //This is nasty. Kraken why you like this
 impl<'de> Deserialize<'de> for PriceLevel {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -171,16 +184,124 @@ impl<'de> Deserialize<'de> for PriceLevel {
     }
 }
 
+/// Snapshots use "as" and "bs", updates use "a" and "b"
 #[serde_as]
+#[derive(Deserialize)]
+struct RawBook {
+    #[serde(rename = "as")]
+    ask_snapshot: Option<Vec<PriceLevel>>,
+    #[serde(rename = "bs")]
+    bid_snapshot: Option<Vec<PriceLevel>>,
+    #[serde(rename = "a")]
+    ask_updates: Option<Vec<PriceLevel>>,
+    #[serde(rename = "b")]
+    bid_updates: Option<Vec<PriceLevel>>,
+    #[serde(rename = "c")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    checksum: Option<u32>,
+}
+
 #[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+#[serde(from = "RawBook")]
 pub struct Book {
-    #[serde(alias = "as")]
-    #[serde(alias = "a")]
     ask_levels: Option<Vec<PriceLevel>>,
-    #[serde(alias = "bs")]
-    #[serde(alias = "b")]
     bid_levels: Option<Vec<PriceLevel>>,
-    #[serde(rename = "c")]
-    #[serde_as(as = "Option<DisplayFromStr>")]
-    checksum: Option<u32>
-}
\ No newline at end of file
+    checksum: Option<u32>,
+    /// true if this replaces the whole book rather than updating it
+    snapshot: bool,
+}
+
+impl Book {
+    /// A snapshot that replaces the whole book
+    pub(crate) fn new_snapshot(ask_levels: Vec<PriceLevel>, bid_levels: Vec<PriceLevel>) -> Self {
+        Self {
+            ask_levels: Some(ask_levels),
+            bid_levels: Some(bid_levels),
+            checksum: None,
+            snapshot: true,
+        }
+    }
+}
+
+impl From<RawBook> for Book {
+    fn from(raw: RawBook) -> Self {
+        let snapshot = raw.ask_snapshot.is_some() || raw.bid_snapshot.is_some();
+        Self {
+            ask_levels: raw.ask_snapshot.or(raw.ask_updates),
+            bid_levels: raw.bid_snapshot.or(raw.bid_updates),
+            checksum: raw.checksum,
+            snapshot,
+        }
+    }
+}
+
+impl From<Side> for types::Side {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Buy => Self::Buy,
+            Side::Sell => Self::Sell,
+        }
+    }
+}
+
+impl From<OrderType> for types::TradeType {
+    fn from(order_type: OrderType) -> Self {
+        match order_type {
+            OrderType::Market => Self::Market,
+            OrderType::Limit => Self::Limit,
+        }
+    }
+}
+
+impl From<&Trade> for types::Trade {
+    fn from(trade: &Trade) -> Self {
+        Self::new(
+            trade.price,
+            trade.volume,
+            trade.time,
+            trade.side.clone().into(),
+            trade.order_type.clone().into(),
+            None,
+        )
+    }
+}
+
+impl From<&PriceLevel> for types::PriceLevel {
+    fn from(level: &PriceLevel) -> Self {
+        Self::new(level.price, level.volume, Some(level.timestamp))
+    }
+}
+
+impl From<&Ticker> for types::Ticker {
+    fn from(ticker: &Ticker) -> Self {
+        Self::new(
+            ticker.bid.price,
+            ticker.bid.lot_volume,
+            ticker.ask.price,
+            ticker.ask.lot_volume,
+            ticker.close.price,
+            ticker.open_price.today,
+            ticker.high_price.last_24_hours,
+            ticker.low_price.last_24_hours,
+            ticker.volume.last_24_hours,
+            ticker.volume_weighted_average_price.last_24_hours,
+        )
+    }
+}
+
+impl OHLC {
+    /// The message only carries the end of the interval so the channel's interval is needed to
+    /// find where the candle starts
+    pub fn to_candle(&self, interval: Interval) -> types::Candle {
+        types::Candle::new(
+            self.end_time - interval.duration(),
+            self.open,
+            self.high,
+            self.low,
+            self.close,
+            self.volume_weighted_average_price,
+            self.volume,
+            self.count,
+        )
+    }
+}
@@ -0,0 +1,354 @@
+use chrono::{DateTime, Utc};
+use derive_getters::Getters;
+use derive_setters::Setters;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const RECORDING_EXTENSION: &str = "jsonl.gz";
+
+/// A text frame exactly as it was read from the websocket and the local time it arrived
+#[derive(Serialize, Deserialize, Debug, Clone, Getters)]
+pub struct RecordedFrame {
+    received_at: DateTime<Utc>,
+    frame: String,
+}
+
+impl RecordedFrame {
+    pub fn new(received_at: DateTime<Utc>, frame: String) -> Self {
+        Self { received_at, frame }
+    }
+}
+
+#[derive(Debug, Clone, Setters)]
+#[setters(prefix = "with_")]
+pub struct RecorderOptions {
+    /// Start a new file once the current one reaches this many compressed bytes
+    max_file_size: u64,
+    /// Start a new file once the current one has been open this long
+    max_file_age: Duration,
+    /// Frames are compressed and appended in batches. A batch is written once it has this many
+    /// frames or `flush_interval` has passed
+    batch_size: usize,
+    flush_interval: Duration,
+}
+
+impl Default for RecorderOptions {
+    fn default() -> Self {
+        Self {
+            max_file_size: 64 * 1024 * 1024,
+            max_file_age: Duration::from_secs(60 * 60),
+            batch_size: 256,
+            flush_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Records every frame received on a connection to rotating, gzip compressed files of json
+/// lines.
+///
+/// Each batch is appended as its own gzip member so that a file is readable up to the last
+/// complete batch even if the process dies while writing. Files are named
+/// `<prefix>-<creation time>.jsonl.gz` and sort in the order they were written. Writing happens
+/// on a separate thread. Recording stops once every clone of the recorder has been dropped.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    sender: mpsc::Sender<RecordedFrame>,
+}
+
+impl Recorder {
+    pub fn start(
+        directory: impl AsRef<Path>,
+        prefix: &str,
+        options: RecorderOptions,
+    ) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&directory)?;
+        let (sender, receiver) = mpsc::channel();
+        let writer = RecordingWriter {
+            directory,
+            prefix: prefix.to_string(),
+            options,
+            current: None,
+            batch: Vec::new(),
+            batched: 0,
+        };
+        std::thread::Builder::new()
+            .name(format!("{}-recorder", prefix))
+            .spawn(move || writer.run(receiver))?;
+        Ok(Self { sender })
+    }
+
+    pub(crate) fn record(&self, frame: &str) {
+        let frame = RecordedFrame::new(Utc::now(), frame.to_string());
+        if self.sender.send(frame).is_err() {
+            trace!("the recorder has stopped. Frame wasn't recorded");
+        }
+    }
+}
+
+struct OpenFile {
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+struct RecordingWriter {
+    directory: PathBuf,
+    prefix: String,
+    options: RecorderOptions,
+    current: Option<OpenFile>,
+    batch: Vec<u8>,
+    batched: usize,
+}
+
+impl RecordingWriter {
+    fn run(mut self, receiver: mpsc::Receiver<RecordedFrame>) {
+        loop {
+            match receiver.recv_timeout(self.options.flush_interval) {
+                Ok(frame) => {
+                    if let Err(err) = self.push(&frame) {
+                        error!("couldn't serialize recorded frame -> {}", err);
+                    }
+                    if self.batched >= self.options.batch_size {
+                        self.flush();
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => self.flush(),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    self.flush();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, frame: &RecordedFrame) -> serde_json::Result<()> {
+        serde_json::to_writer(&mut self.batch, frame)?;
+        self.batch.push(b'\n');
+        self.batched += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        if self.batched == 0 {
+            return;
+        }
+        if let Err(err) = self.write_batch() {
+            error!(
+                "lost {} recorded frames while writing to {} -> {}",
+                self.batched,
+                self.directory.display(),
+                err
+            );
+            // start over with a fresh file in case this one is broken
+            self.current = None;
+        }
+        self.batch.clear();
+        self.batched = 0;
+    }
+
+    fn write_batch(&mut self) -> io::Result<()> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.batch)?;
+        let compressed = encoder.finish()?;
+
+        let rotate = self.current.as_ref().is_none_or(|current| {
+            current.size >= self.options.max_file_size
+                || current.opened.elapsed() >= self.options.max_file_age
+        });
+        if rotate {
+            self.current = Some(self.open_file()?);
+        }
+        let current = self.current.as_mut().expect("a file was just opened");
+        current.file.write_all(&compressed)?;
+        current.file.flush()?;
+        current.size += compressed.len() as u64;
+        Ok(())
+    }
+
+    fn open_file(&self) -> io::Result<OpenFile> {
+        let name = format!(
+            "{}-{}.{}",
+            self.prefix,
+            Utc::now().format("%Y%m%dT%H%M%S%.9f"),
+            RECORDING_EXTENSION
+        );
+        let path = self.directory.join(name);
+        info!("recording websocket frames to {}", path.display());
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(OpenFile {
+            file,
+            size: 0,
+            opened: Instant::now(),
+        })
+    }
+}
+
+/// Read the frames from a recording file, or from every recording in a directory in the order
+/// they were written
+pub fn read_recording(
+    path: impl AsRef<Path>,
+) -> io::Result<impl Iterator<Item = io::Result<RecordedFrame>>> {
+    let path = path.as_ref();
+    let files = if path.is_dir() {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.ends_with(RECORDING_EXTENSION))
+            })
+            .collect();
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+    Ok(files.into_iter().flat_map(|path| {
+        let lines: Box<dyn Iterator<Item = io::Result<String>>> = match File::open(&path) {
+            Ok(file) => Box::new(BufReader::new(MultiGzDecoder::new(file)).lines()),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        };
+        lines.map(|line| {
+            serde_json::from_str(&line?)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        })
+    }))
+}
+
+/// How fast to replay a recording
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// With the same gaps between frames as when they were recorded
+    RealTime,
+    /// Real time sped up by a factor
+    Accelerated(f64),
+    /// As fast as the frames can be handled
+    Max,
+}
+
+impl ReplaySpeed {
+    /// How long after the replay starts a frame should be handled
+    pub(crate) fn offset(&self, since_first_frame: chrono::Duration) -> Option<Duration> {
+        let elapsed = since_first_frame.to_std().unwrap_or_default();
+        match self {
+            Self::RealTime => Some(elapsed),
+            Self::Accelerated(factor) if *factor > 0.0 => Some(elapsed.div_f64(*factor)),
+            Self::Accelerated(_) | Self::Max => None,
+        }
+    }
+}
+
+pub(crate) fn warn_unreadable(err: &io::Error) {
+    warn!("skipping unreadable recorded frame -> {}", err);
+}
+
+#[cfg(test)]
+mod recorder_tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("linnaeus-recorder-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn frames_are_recorded_and_read_back_in_order() {
+        let directory = temp_dir();
+        let recorder = Recorder::start(
+            &directory,
+            "kraken",
+            RecorderOptions::default()
+                .with_batch_size(2)
+                .with_max_file_size(1),
+        )
+        .expect("couldn't start recorder");
+        let frames: Vec<String> = (0..5)
+            .map(|i| format!("{{\"event\":\"heartbeat\",\"i\":{}}}", i))
+            .collect();
+        for frame in &frames {
+            recorder.record(frame);
+        }
+        drop(recorder);
+
+        // the writer thread flushes once every recorder has been dropped
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let read = loop {
+            let read: Vec<String> = read_recording(&directory)
+                .expect("couldn't read recording")
+                .filter_map(Result::ok)
+                .map(|frame| frame.frame().clone())
+                .collect();
+            if read.len() == frames.len() || Instant::now() > deadline {
+                break read;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(read, frames);
+        let files = std::fs::read_dir(&directory)
+            .expect("couldn't list recordings")
+            .count();
+        assert_eq!(files, 3);
+        std::fs::remove_dir_all(directory).expect("couldn't clean up recordings");
+    }
+
+    #[tokio::test]
+    async fn replayed_frames_reach_subscribers() -> anyhow::Result<()> {
+        use crate::config::WebsocketConfig;
+        use crate::subscription::SubscriptionOptions;
+        use crate::test_utils::load_test_json;
+        use crate::LinnaeusWebsocket;
+        use futures::StreamExt;
+
+        let directory = temp_dir();
+        std::fs::create_dir_all(&directory)?;
+        let ticker: serde_json::Value = serde_json::from_str(&load_test_json("public/ticker")?)?;
+        let status: serde_json::Value =
+            serde_json::from_str(&load_test_json("general/system_status")?)?;
+        let start = Utc::now();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        for (seconds, frame) in [(0, &status), (1, &ticker), (2, &ticker)] {
+            let frame = RecordedFrame::new(
+                start + chrono::Duration::seconds(seconds),
+                frame.to_string(),
+            );
+            serde_json::to_writer(&mut encoder, &frame)?;
+            encoder.write_all(b"\n")?;
+        }
+        std::fs::write(directory.join("kraken-1.jsonl.gz"), encoder.finish()?)?;
+
+        let lws = LinnaeusWebsocket::new_replay(WebsocketConfig::default());
+        let stream = lws
+            .subscribe_ticker(&["XBT/USD".to_string()], SubscriptionOptions::default())
+            .await?;
+        lws.replay(&directory, ReplaySpeed::Max).await?;
+        let tickers: Vec<_> = stream.take(2).collect().await;
+        assert_eq!(tickers.len(), 2);
+        assert_eq!(tickers[0].0, "XBT/USD");
+        assert!(lws.cancel_all().await.is_err());
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn replay_offsets() {
+        let second = chrono::Duration::seconds(1);
+        assert_eq!(
+            ReplaySpeed::RealTime.offset(second),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            ReplaySpeed::Accelerated(4.0).offset(second),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(ReplaySpeed::Max.offset(second), None);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::messages::Channel;
+use chrono::Utc;
+use dashmap::DashMap;
+use derive_getters::Getters;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceEventKind {
+    /// One or more messages between the last received sequence and this one were never received
+    Gap { expected: i64, received: i64 },
+    /// A message with a sequence that was already seen. The message is not forwarded to
+    /// subscribers
+    Duplicate { last: i64, received: i64 },
+}
+
+/// Emitted when a private channel receives a message out of sequence
+#[derive(Debug, Clone, Getters)]
+pub struct SequenceEvent {
+    channel: Channel,
+    kind: SequenceEventKind,
+    /// When the last in-sequence message was received. Anything after this may have been missed
+    last_received_at: Option<chrono::DateTime<Utc>>,
+}
+
+impl SequenceEvent {
+    /// Number of messages that were missed. 0 for duplicates
+    pub fn missed(&self) -> i64 {
+        match self.kind {
+            SequenceEventKind::Gap { expected, received } => received - expected,
+            SequenceEventKind::Duplicate { .. } => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChannelSequence {
+    last: i64,
+    received_at: chrono::DateTime<Utc>,
+}
+
+/// Tracks the sequence numbers of the private channels. Kraken numbers every message on
+/// ownTrades and openOrders starting from 1 for each new subscription.
+#[derive(Debug, Default)]
+pub(crate) struct SequenceTracker {
+    channels: DashMap<Channel, ChannelSequence, ahash::RandomState>,
+}
+
+impl SequenceTracker {
+    /// Record a sequence number. Returns an event if it wasn't the next one expected.
+    pub(crate) fn observe(&self, channel: &Channel, sequence: i64) -> Option<SequenceEvent> {
+        let now = Utc::now();
+        let mut entry = self.channels.entry(channel.clone()).or_insert(ChannelSequence {
+            last: 0,
+            received_at: now,
+        });
+        let previous = *entry;
+        let last_received_at = (previous.last > 0).then_some(previous.received_at);
+
+        if sequence <= previous.last {
+            return Some(SequenceEvent {
+                channel: channel.clone(),
+                kind: SequenceEventKind::Duplicate {
+                    last: previous.last,
+                    received: sequence,
+                },
+                last_received_at,
+            });
+        }
+
+        *entry = ChannelSequence {
+            last: sequence,
+            received_at: now,
+        };
+
+        let expected = previous.last + 1;
+        (sequence != expected).then(|| SequenceEvent {
+            channel: channel.clone(),
+            kind: SequenceEventKind::Gap {
+                expected,
+                received: sequence,
+            },
+            last_received_at,
+        })
+    }
+
+    /// Forget a single channel. Used when it's subscribed to again
+    pub(crate) fn reset_channel(&self, channel: &Channel) {
+        self.channels.remove(channel);
+    }
+
+    /// Forget everything. Sequences restart when the subscriptions are made again
+    pub(crate) fn reset(&self) {
+        self.channels.clear();
+    }
+}
+
+#[cfg(test)]
+mod sequence_tests {
+    use super::*;
+
+    #[test]
+    fn in_order_sequences_are_accepted() {
+        let tracker = SequenceTracker::default();
+        for sequence in 1..=5 {
+            assert!(tracker.observe(&Channel::OwnTrades, sequence).is_none());
+        }
+        // channels are tracked independently
+        assert!(tracker.observe(&Channel::OpenOrders, 1).is_none());
+    }
+
+    #[test]
+    fn gaps_are_detected() {
+        let tracker = SequenceTracker::default();
+        assert!(tracker.observe(&Channel::OpenOrders, 1).is_none());
+        let event = tracker
+            .observe(&Channel::OpenOrders, 4)
+            .expect("expected a gap");
+        assert_eq!(
+            event.kind(),
+            &SequenceEventKind::Gap {
+                expected: 2,
+                received: 4
+            }
+        );
+        assert_eq!(event.missed(), 2);
+        assert!(event.last_received_at().is_some());
+        // tracking continues from the new sequence
+        assert!(tracker.observe(&Channel::OpenOrders, 5).is_none());
+    }
+
+    #[test]
+    fn gap_on_first_message() {
+        let tracker = SequenceTracker::default();
+        let event = tracker
+            .observe(&Channel::OwnTrades, 3)
+            .expect("expected a gap");
+        assert_eq!(event.missed(), 2);
+        assert!(event.last_received_at().is_none());
+    }
+
+    #[test]
+    fn duplicates_are_detected() {
+        let tracker = SequenceTracker::default();
+        assert!(tracker.observe(&Channel::OwnTrades, 1).is_none());
+        assert!(tracker.observe(&Channel::OwnTrades, 2).is_none());
+        let event = tracker
+            .observe(&Channel::OwnTrades, 2)
+            .expect("expected a duplicate");
+        assert_eq!(
+            event.kind(),
+            &SequenceEventKind::Duplicate {
+                last: 2,
+                received: 2
+            }
+        );
+        assert!(tracker.observe(&Channel::OwnTrades, 3).is_none());
+    }
+
+    #[test]
+    fn reset_restarts_sequences() {
+        let tracker = SequenceTracker::default();
+        assert!(tracker.observe(&Channel::OwnTrades, 1).is_none());
+        assert!(tracker.observe(&Channel::OwnTrades, 2).is_none());
+        tracker.reset();
+        assert!(tracker.observe(&Channel::OwnTrades, 1).is_none());
+    }
+}
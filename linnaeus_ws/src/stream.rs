@@ -0,0 +1,242 @@
+use crate::messages::private_messages::{OpenOrders, OwnTrades};
+use crate::messages::public_messages::{Book, Spread, Ticker, Trades, OHLC};
+use crate::messages::{ChannelMessage, ChannelMessageWrapper, Pair};
+use crate::subscription::{recv_counted, Received, SubscriptionMetrics, SubscriptionReceiver};
+use futures::stream::{select_all, BoxStream};
+use futures::{Stream, StreamExt};
+use log::warn;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Pulls the typed payload out of a channel message. Returns None for messages that belong to a
+/// different channel.
+pub(crate) type Extractor<T, I> = fn(T) -> Option<I>;
+
+/// A typed stream of messages for a single subscription.
+///
+/// Messages that couldn't be delivered because the consumer fell too far behind are counted
+/// rather than ending the stream.
+pub struct ChannelStream<I> {
+    inner: BoxStream<'static, I>,
+    metrics: Arc<SubscriptionMetrics>,
+}
+
+impl<I> std::fmt::Debug for ChannelStream<I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChannelStream")
+            .field("dropped", &self.dropped())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I: Send + 'static> ChannelStream<I> {
+    pub(crate) fn from_receivers<T: Clone + Send + 'static>(
+        receivers: Vec<SubscriptionReceiver<T>>,
+        metrics: Arc<SubscriptionMetrics>,
+        extract: Extractor<T, I>,
+    ) -> Self {
+        let streams = receivers.into_iter().map(|receiver| {
+            let metrics = metrics.clone();
+            futures::stream::unfold(receiver, move |mut receiver| {
+                let metrics = metrics.clone();
+                async move {
+                    loop {
+                        match recv_counted(&mut receiver, &metrics).await {
+                            Received::Message(message) => match extract(*message) {
+                                Some(item) => return Some((item, receiver)),
+                                None => continue,
+                            },
+                            Received::Lagged(skipped) => {
+                                warn!("subscriber lagged behind and lost {} messages", skipped);
+                            }
+                            Received::Closed => return None,
+                        }
+                    }
+                }
+            })
+            .boxed()
+        });
+        Self {
+            inner: select_all(streams).boxed(),
+            metrics,
+        }
+    }
+}
+
+impl<I> ChannelStream<I> {
+    /// Number of messages that were lost because this stream wasn't polled fast enough
+    pub fn dropped(&self) -> u64 {
+        self.metrics.dropped()
+    }
+
+    /// Delivery and drop counters for this subscription. Remain valid after the stream is dropped
+    pub fn metrics(&self) -> Arc<SubscriptionMetrics> {
+        self.metrics.clone()
+    }
+}
+
+impl<I> Stream for ChannelStream<I> {
+    type Item = I;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+pub(crate) fn ticker(message: ChannelMessageWrapper) -> Option<(Pair, Ticker)> {
+    match message.into_parts() {
+        (ChannelMessage::Ticker(ticker), Some(pair)) => Some((pair, ticker)),
+        _ => None,
+    }
+}
+
+pub(crate) fn ohlc(message: ChannelMessageWrapper) -> Option<(Pair, OHLC)> {
+    match message.into_parts() {
+        (ChannelMessage::OHLC(ohlc), Some(pair)) => Some((pair, ohlc)),
+        _ => None,
+    }
+}
+
+pub(crate) fn trades(message: ChannelMessageWrapper) -> Option<(Pair, Trades)> {
+    match message.into_parts() {
+        (ChannelMessage::Trade(trades), Some(pair)) => Some((pair, trades)),
+        _ => None,
+    }
+}
+
+pub(crate) fn spread(message: ChannelMessageWrapper) -> Option<(Pair, Spread)> {
+    match message.into_parts() {
+        (ChannelMessage::Spread(spread), Some(pair)) => Some((pair, spread)),
+        _ => None,
+    }
+}
+
+pub(crate) fn book(message: ChannelMessageWrapper) -> Option<(Pair, Book)> {
+    match message.into_parts() {
+        (ChannelMessage::Book(book), Some(pair)) => Some((pair, book)),
+        _ => None,
+    }
+}
+
+pub(crate) fn own_trades(message: ChannelMessageWrapper) -> Option<OwnTrades> {
+    match message.into_parts() {
+        (ChannelMessage::OwnTrades(own_trades), _) => Some(own_trades),
+        _ => None,
+    }
+}
+
+pub(crate) fn open_orders(message: ChannelMessageWrapper) -> Option<OpenOrders> {
+    match message.into_parts() {
+        (ChannelMessage::OpenOrders(open_orders), _) => Some(open_orders),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::*;
+    use crate::messages::Message;
+    use crate::subscription::{self, BufferPolicy, Subscriber, SubscriptionOptions};
+    use crate::test_utils;
+
+    fn stream_with_options<I: Send + 'static>(
+        pairs: usize,
+        options: SubscriptionOptions,
+        extract: Extractor<ChannelMessageWrapper, I>,
+    ) -> (Vec<Subscriber<ChannelMessageWrapper>>, ChannelStream<I>) {
+        let (subscribers, receivers) = (0..pairs)
+            .map(|_| subscription::channel(&options))
+            .unzip();
+        let stream = ChannelStream::from_receivers(receivers, Default::default(), extract);
+        (subscribers, stream)
+    }
+
+    fn stream<I: Send + 'static>(
+        pairs: usize,
+        buffer_size: usize,
+        extract: Extractor<ChannelMessageWrapper, I>,
+    ) -> (Vec<Subscriber<ChannelMessageWrapper>>, ChannelStream<I>) {
+        stream_with_options(
+            pairs,
+            SubscriptionOptions::default().with_buffer_size(buffer_size),
+            extract,
+        )
+    }
+
+    fn load_channel_message(file: &str) -> ChannelMessageWrapper {
+        let j = test_utils::load_test_json(file).expect("couldn't load test json from file");
+        match serde_json::from_str(&j).expect("couldn't deserialize message") {
+            Message::ChannelMessage(message) => message,
+            Message::Event(_) => panic!("expected a channel message"),
+        }
+    }
+
+    #[tokio::test]
+    async fn ticker_stream_yields_pair_and_ticker() {
+        let (subscribers, mut stream) = stream(1, 10, ticker);
+        subscribers[0].try_deliver(&load_channel_message("public/ticker"));
+        // messages from other channels are skipped
+        subscribers[0].try_deliver(&load_channel_message("public/spread"));
+        drop(subscribers);
+
+        let (pair, _) = stream.next().await.expect("expected a ticker");
+        assert_eq!(pair, "XBT/USD");
+        assert!(stream.next().await.is_none());
+        assert_eq!(stream.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn lagged_messages_are_counted() {
+        let (subscribers, mut stream) = stream(1, 2, spread);
+        for _ in 0..5 {
+            subscribers[0].try_deliver(&load_channel_message("public/spread"));
+        }
+        drop(subscribers);
+
+        let mut received = 0;
+        while stream.next().await.is_some() {
+            received += 1;
+        }
+        assert_eq!(received, 2);
+        assert_eq!(stream.dropped(), 3);
+        assert_eq!(stream.metrics().delivered(), 2);
+    }
+
+    #[tokio::test]
+    async fn conflated_stream_yields_latest() {
+        let (subscribers, mut stream) = stream_with_options(
+            1,
+            SubscriptionOptions::default().with_policy(BufferPolicy::ConflateLatest),
+            spread,
+        );
+        subscribers[0].try_deliver(&load_channel_message("public/spread"));
+        subscribers[0].try_deliver(&load_channel_message("public/spread_b"));
+        drop(subscribers);
+
+        let (_, latest) = stream.next().await.expect("expected a spread");
+        assert_eq!(latest.price().to_string(), "16605.50000");
+        assert!(stream.next().await.is_none());
+        assert_eq!(stream.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn streams_from_multiple_pairs_are_merged() {
+        let (subscribers, stream) = stream(2, 10, spread);
+        subscribers[0].try_deliver(&load_channel_message("public/spread"));
+        subscribers[1].try_deliver(&load_channel_message("public/spread_b"));
+        drop(subscribers);
+
+        let items: Vec<(Pair, Spread)> = stream.collect().await;
+        assert_eq!(items.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn own_trades_stream() {
+        let (subscribers, mut stream) = stream(1, 10, own_trades);
+        subscribers[0].try_deliver(&load_channel_message("private/own_trades"));
+        drop(subscribers);
+        let trades = stream.next().await.expect("expected own trades");
+        assert!(!trades.is_empty());
+    }
+}
@@ -0,0 +1,487 @@
+use dashmap::DashMap;
+use derive_setters::Setters;
+use log::trace;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, Notify};
+
+/// What happens when a subscriber can't keep up with the messages for its subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferPolicy {
+    /// Keep the newest buffer_size messages. A subscriber that falls behind loses the oldest ones
+    DropOldest,
+    /// Bounded queue. When it's full the connection waits for the subscriber to catch up which
+    /// stalls every other subscription on the connection
+    Block,
+    /// Only keep the latest message. Suited to ticker and spread where only the current value
+    /// matters
+    ConflateLatest,
+}
+
+#[derive(Debug, Clone, Setters)]
+#[setters(prefix = "with_")]
+pub struct SubscriptionOptions {
+    /// Ignored when the policy is ConflateLatest
+    buffer_size: usize,
+    policy: BufferPolicy,
+}
+
+impl Default for SubscriptionOptions {
+    fn default() -> Self {
+        Self {
+            buffer_size: 100,
+            policy: BufferPolicy::DropOldest,
+        }
+    }
+}
+
+impl SubscriptionOptions {
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+    pub fn policy(&self) -> BufferPolicy {
+        self.policy
+    }
+}
+
+/// Counters shared by every pair of a subscription
+#[derive(Debug, Default)]
+pub struct SubscriptionMetrics {
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl SubscriptionMetrics {
+    /// Messages handed to the subscriber
+    pub fn delivered(&self) -> u64 {
+        self.delivered.load(Ordering::Relaxed)
+    }
+    /// Messages that were lost because the subscriber didn't keep up
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Holds only the most recent message
+#[derive(Debug)]
+struct ConflatedSlot<T> {
+    value: std::sync::Mutex<Option<T>>,
+    overwritten: AtomicU64,
+    notify: Notify,
+    sender_closed: AtomicBool,
+    receiver_closed: AtomicBool,
+}
+
+impl<T> Default for ConflatedSlot<T> {
+    fn default() -> Self {
+        Self {
+            value: Default::default(),
+            overwritten: Default::default(),
+            notify: Default::default(),
+            sender_closed: Default::default(),
+            receiver_closed: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ConflatedSender<T> {
+    slot: Arc<ConflatedSlot<T>>,
+}
+
+impl<T> Drop for ConflatedSender<T> {
+    fn drop(&mut self) {
+        self.slot.sender_closed.store(true, Ordering::SeqCst);
+        self.slot.notify.notify_one();
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ConflatedReceiver<T> {
+    slot: Arc<ConflatedSlot<T>>,
+}
+
+impl<T> Drop for ConflatedReceiver<T> {
+    fn drop(&mut self) {
+        self.slot.receiver_closed.store(true, Ordering::SeqCst);
+    }
+}
+
+fn conflated<T>() -> (ConflatedSender<T>, ConflatedReceiver<T>) {
+    let slot = Arc::new(ConflatedSlot::default());
+    (
+        ConflatedSender { slot: slot.clone() },
+        ConflatedReceiver { slot },
+    )
+}
+
+#[derive(Debug)]
+enum Sender<T> {
+    Broadcast(broadcast::Sender<T>),
+    Bounded(mpsc::Sender<T>),
+    Conflated(ConflatedSender<T>),
+}
+
+pub(crate) enum Delivery<T> {
+    Delivered,
+    /// The receiver is gone and the subscriber can be removed
+    Closed,
+    /// The bounded queue is full. The message must be sent with this sender once the lock on the
+    /// subscriber has been released
+    Full(mpsc::Sender<T>),
+}
+
+/// The sending half of a subscription for a single channel and pair
+#[derive(Debug)]
+pub(crate) struct Subscriber<T> {
+    sender: Sender<T>,
+}
+
+impl<T: Clone> Subscriber<T> {
+    pub(crate) fn try_deliver(&self, message: &T) -> Delivery<T> {
+        match &self.sender {
+            Sender::Broadcast(sender) => match sender.send(message.clone()) {
+                Ok(_) => Delivery::Delivered,
+                Err(_) => Delivery::Closed,
+            },
+            Sender::Bounded(sender) => match sender.try_send(message.clone()) {
+                Ok(_) => Delivery::Delivered,
+                Err(mpsc::error::TrySendError::Full(_)) => Delivery::Full(sender.clone()),
+                Err(mpsc::error::TrySendError::Closed(_)) => Delivery::Closed,
+            },
+            Sender::Conflated(sender) => {
+                let slot = &sender.slot;
+                if slot.receiver_closed.load(Ordering::SeqCst) {
+                    return Delivery::Closed;
+                }
+                let previous = slot
+                    .value
+                    .lock()
+                    .expect("conflated slot lock poisoned")
+                    .replace(message.clone());
+                if previous.is_some() {
+                    slot.overwritten.fetch_add(1, Ordering::SeqCst);
+                }
+                slot.notify.notify_one();
+                Delivery::Delivered
+            }
+        }
+    }
+}
+
+impl<T> Subscriber<T> {
+    /// true once the receiving half has been dropped
+    fn is_closed(&self) -> bool {
+        match &self.sender {
+            Sender::Broadcast(sender) => sender.receiver_count() == 0,
+            Sender::Bounded(sender) => sender.is_closed(),
+            Sender::Conflated(sender) => sender.slot.receiver_closed.load(Ordering::SeqCst),
+        }
+    }
+}
+
+pub(crate) enum Received<T> {
+    Message(Box<T>),
+    /// This many messages were lost since the last message was received
+    Lagged(u64),
+    Closed,
+}
+
+/// The receiving half of a subscription for a single channel and pair
+#[derive(Debug)]
+pub(crate) enum SubscriptionReceiver<T> {
+    Broadcast(broadcast::Receiver<T>),
+    Bounded(mpsc::Receiver<T>),
+    Conflated(ConflatedReceiver<T>),
+}
+
+impl<T: Clone> SubscriptionReceiver<T> {
+    pub(crate) async fn recv(&mut self) -> Received<T> {
+        match self {
+            SubscriptionReceiver::Broadcast(receiver) => match receiver.recv().await {
+                Ok(message) => Received::Message(Box::new(message)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => Received::Lagged(skipped),
+                Err(broadcast::error::RecvError::Closed) => Received::Closed,
+            },
+            SubscriptionReceiver::Bounded(receiver) => match receiver.recv().await {
+                Some(message) => Received::Message(Box::new(message)),
+                None => Received::Closed,
+            },
+            SubscriptionReceiver::Conflated(receiver) => {
+                let slot = &receiver.slot;
+                loop {
+                    let overwritten = slot.overwritten.swap(0, Ordering::SeqCst);
+                    if overwritten > 0 {
+                        return Received::Lagged(overwritten);
+                    }
+                    let value = slot
+                        .value
+                        .lock()
+                        .expect("conflated slot lock poisoned")
+                        .take();
+                    if let Some(message) = value {
+                        return Received::Message(Box::new(message));
+                    }
+                    if slot.sender_closed.load(Ordering::SeqCst) {
+                        return Received::Closed;
+                    }
+                    slot.notify.notified().await;
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn broadcast_channel<T: Clone>(
+    buffer_size: usize,
+) -> (Subscriber<T>, broadcast::Receiver<T>) {
+    let (sender, receiver) = broadcast::channel(buffer_size.max(1));
+    (
+        Subscriber {
+            sender: Sender::Broadcast(sender),
+        },
+        receiver,
+    )
+}
+
+/// Create both halves of a subscription
+pub(crate) fn channel<T: Clone>(
+    options: &SubscriptionOptions,
+) -> (Subscriber<T>, SubscriptionReceiver<T>) {
+    let buffer_size = options.buffer_size.max(1);
+    match options.policy {
+        BufferPolicy::DropOldest => {
+            let (subscriber, receiver) = broadcast_channel(buffer_size);
+            (subscriber, SubscriptionReceiver::Broadcast(receiver))
+        }
+        BufferPolicy::Block => {
+            let (sender, receiver) = mpsc::channel(buffer_size);
+            (
+                Subscriber {
+                    sender: Sender::Bounded(sender),
+                },
+                SubscriptionReceiver::Bounded(receiver),
+            )
+        }
+        BufferPolicy::ConflateLatest => {
+            let (sender, receiver) = conflated();
+            (
+                Subscriber {
+                    sender: Sender::Conflated(sender),
+                },
+                SubscriptionReceiver::Conflated(receiver),
+            )
+        }
+    }
+}
+
+/// Receive the next message, recording drops in the metrics
+pub(crate) async fn recv_counted<T: Clone>(
+    receiver: &mut SubscriptionReceiver<T>,
+    metrics: &SubscriptionMetrics,
+) -> Received<T> {
+    let received = receiver.recv().await;
+    match &received {
+        Received::Message(_) => {
+            metrics.delivered.fetch_add(1, Ordering::Relaxed);
+        }
+        Received::Lagged(skipped) => {
+            metrics.dropped.fetch_add(*skipped, Ordering::Relaxed);
+        }
+        Received::Closed => {}
+    }
+    received
+}
+
+/// Every subscriber on a connection keyed by channel and pair
+#[derive(Debug)]
+pub(crate) struct Subscribers<T> {
+    subscribers: DashMap<u64, Vec<Subscriber<T>>, ahash::RandomState>,
+}
+
+impl<T> Default for Subscribers<T> {
+    fn default() -> Self {
+        Self {
+            subscribers: Default::default(),
+        }
+    }
+}
+
+impl<T: Clone> Subscribers<T> {
+    pub(crate) fn add(&self, key: u64, subscriber: Subscriber<T>) {
+        self.subscribers.entry(key).or_default().push(subscriber);
+    }
+
+    /// Deliver a message to everything subscribed to the key. Subscribers that use the Block
+    /// policy are waited on, which applies backpressure to the whole connection
+    pub(crate) async fn deliver(&self, key: u64, message: &T) {
+        let mut blocked = Vec::new();
+        if let Some(mut subscribers) = self.subscribers.get_mut(&key) {
+            subscribers.retain(|subscriber| match subscriber.try_deliver(message) {
+                Delivery::Delivered => true,
+                Delivery::Closed => false,
+                Delivery::Full(sender) => {
+                    blocked.push(sender);
+                    true
+                }
+            });
+        }
+        for sender in blocked {
+            if sender.send(message.clone()).await.is_err() {
+                trace!("blocking subscriber was dropped while waiting for it");
+            }
+        }
+    }
+
+    /// Drop the subscribers of a key so that their receivers see the channel close
+    pub(crate) fn remove(&self, key: u64) {
+        self.subscribers.remove(&key);
+    }
+
+    /// Drop the subscribers of a key whose receivers are gone. Closed subscribers are otherwise
+    /// only noticed when a message is delivered to them
+    pub(crate) fn remove_closed(&self, key: u64) {
+        self.subscribers.remove_if_mut(&key, |_, subscribers| {
+            subscribers.retain(|subscriber| !subscriber.is_closed());
+            subscribers.is_empty()
+        });
+    }
+
+    /// Drop every subscriber so that receivers see the channel close
+    pub(crate) fn clear(&self) {
+        self.subscribers.clear();
+    }
+}
+
+#[cfg(test)]
+mod subscription_tests {
+    use super::*;
+    use crate::messages::{ChannelMessageWrapper, Message};
+    use crate::test_utils;
+
+    fn spread_message() -> ChannelMessageWrapper {
+        let j = test_utils::load_test_json("public/spread")
+            .expect("couldn't load test json from file");
+        match serde_json::from_str(&j).expect("couldn't deserialize message") {
+            Message::ChannelMessage(message) => message,
+            Message::Event(_) => panic!("expected a channel message"),
+        }
+    }
+
+    async fn drain(receiver: &mut SubscriptionReceiver<ChannelMessageWrapper>) -> (u64, u64) {
+        let (mut messages, mut lagged) = (0, 0);
+        loop {
+            match receiver.recv().await {
+                Received::Message(_) => messages += 1,
+                Received::Lagged(n) => lagged += n,
+                Received::Closed => return (messages, lagged),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_reports_lag() {
+        let options = SubscriptionOptions::default().with_buffer_size(2);
+        let (subscriber, mut receiver) = channel(&options);
+        for _ in 0..5 {
+            assert!(matches!(
+                subscriber.try_deliver(&spread_message()),
+                Delivery::Delivered
+            ));
+        }
+        drop(subscriber);
+        assert_eq!(drain(&mut receiver).await, (2, 3));
+    }
+
+    #[tokio::test]
+    async fn block_never_drops() {
+        let options = SubscriptionOptions::default()
+            .with_buffer_size(2)
+            .with_policy(BufferPolicy::Block);
+        let (subscriber, mut receiver) = channel(&options);
+        assert!(matches!(
+            subscriber.try_deliver(&spread_message()),
+            Delivery::Delivered
+        ));
+        assert!(matches!(
+            subscriber.try_deliver(&spread_message()),
+            Delivery::Delivered
+        ));
+        let Delivery::Full(sender) = subscriber.try_deliver(&spread_message()) else {
+            panic!("expected the queue to be full");
+        };
+        let blocked = tokio::spawn(async move { sender.send(spread_message()).await });
+        assert!(matches!(receiver.recv().await, Received::Message(_)));
+        blocked
+            .await
+            .expect("join failed")
+            .expect("send failed");
+        drop(subscriber);
+        assert_eq!(drain(&mut receiver).await, (2, 0));
+    }
+
+    #[tokio::test]
+    async fn conflate_keeps_latest() {
+        let options = SubscriptionOptions::default().with_policy(BufferPolicy::ConflateLatest);
+        let (subscriber, mut receiver) = channel(&options);
+        for _ in 0..3 {
+            subscriber.try_deliver(&spread_message());
+        }
+        drop(subscriber);
+        assert_eq!(drain(&mut receiver).await, (1, 2));
+    }
+
+    #[tokio::test]
+    async fn closed_receivers_are_detected() {
+        for policy in [
+            BufferPolicy::DropOldest,
+            BufferPolicy::Block,
+            BufferPolicy::ConflateLatest,
+        ] {
+            let options = SubscriptionOptions::default().with_policy(policy);
+            let (subscriber, receiver) = channel(&options);
+            drop(receiver);
+            assert!(matches!(
+                subscriber.try_deliver(&spread_message()),
+                Delivery::Closed
+            ));
+        }
+    }
+
+    #[test]
+    fn subscribers_without_receivers_are_removed() {
+        let subscribers = Subscribers::default();
+        let options = SubscriptionOptions::default();
+        let (subscriber, kept) = channel::<ChannelMessageWrapper>(&options);
+        subscribers.add(1, subscriber);
+        let (subscriber, dropped) = channel(&options);
+        subscribers.add(1, subscriber);
+
+        drop(dropped);
+        subscribers.remove_closed(1);
+        assert_eq!(
+            subscribers.subscribers.get(&1).map(|key| key.len()),
+            Some(1)
+        );
+        drop(kept);
+        subscribers.remove_closed(1);
+        assert!(subscribers.subscribers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn metrics_count_drops() {
+        let options = SubscriptionOptions::default().with_buffer_size(1);
+        let (subscriber, mut receiver) = channel(&options);
+        let metrics = SubscriptionMetrics::default();
+        for _ in 0..3 {
+            subscriber.try_deliver(&spread_message());
+        }
+        drop(subscriber);
+        while !matches!(
+            recv_counted(&mut receiver, &metrics).await,
+            Received::Closed
+        ) {}
+        assert_eq!(metrics.delivered(), 1);
+        assert_eq!(metrics.dropped(), 2);
+    }
+}
@@ -0,0 +1,404 @@
+use crate::messages::general_messages::Depth;
+use crate::messages::public_messages::{Book, PriceLevel};
+use crate::messages::Pair;
+use crate::v2::messages::public_messages::{
+    InstrumentPair, Level3, Level3Event, Level3Order, Side,
+};
+use crate::v2::messages::Update;
+use derive_getters::Getters;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use thiserror::Error;
+
+/// Kraken only includes the best 10 price levels on each side in the checksum
+const CHECKSUM_LEVELS: usize = 10;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error(
+    "level 3 book checksum mismatch. Kraken sent {expected} but the local book is {calculated}"
+)]
+pub struct ChecksumMismatch {
+    expected: u32,
+    calculated: u32,
+}
+
+/// An order resting in the book
+#[derive(Debug, Clone, Getters)]
+pub struct QueuedOrder {
+    order_id: String,
+    price: Decimal,
+    qty: Decimal,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<&Level3Order> for QueuedOrder {
+    fn from(order: &Level3Order) -> Self {
+        Self {
+            order_id: order.order_id().clone(),
+            price: *order.limit_price(),
+            qty: *order.order_qty(),
+            timestamp: *order.timestamp(),
+        }
+    }
+}
+
+type Queue = VecDeque<QueuedOrder>;
+
+/// Local copy of the level 3 book built from a v2 level3 subscription. Each price level holds its
+/// orders in queue priority.
+///
+/// Like [LocalBook](crate::book::LocalBook) the book starts out invalid and becomes valid when a
+/// snapshot is applied. Every message is checked against kraken's checksum. On a mismatch the
+/// book is invalidated and a new snapshot is needed.
+#[derive(Debug, Clone)]
+pub struct Level3Book {
+    symbol: Pair,
+    depth: Depth,
+    price_precision: u32,
+    qty_precision: u32,
+    asks: BTreeMap<Decimal, Queue>,
+    bids: BTreeMap<Decimal, Queue>,
+    /// where to find each order
+    orders: HashMap<String, (Side, Decimal)>,
+    valid: bool,
+}
+
+impl Level3Book {
+    /// The precisions are needed to calculate the checksum. They're available from the
+    /// instrument channel
+    pub fn new(symbol: Pair, depth: Depth, price_precision: u32, qty_precision: u32) -> Self {
+        Self {
+            symbol,
+            depth,
+            price_precision,
+            qty_precision,
+            asks: Default::default(),
+            bids: Default::default(),
+            orders: Default::default(),
+            valid: false,
+        }
+    }
+
+    pub fn for_pair(pair: &InstrumentPair, depth: Depth) -> Self {
+        Self::new(
+            pair.symbol().clone(),
+            depth,
+            *pair.price_precision(),
+            *pair.qty_precision(),
+        )
+    }
+
+    pub fn symbol(&self) -> &Pair {
+        &self.symbol
+    }
+
+    pub fn depth(&self) -> Depth {
+        self.depth
+    }
+
+    /// false until a snapshot has been applied and after the book has been invalidated
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    /// Mark the book as out of date and clear it
+    pub fn invalidate(&mut self) {
+        self.valid = false;
+        self.asks.clear();
+        self.bids.clear();
+        self.orders.clear();
+    }
+
+    /// Apply a snapshot or an update. Returns false if nothing was applied, either because the
+    /// book is waiting for a snapshot or the message was for another symbol
+    pub fn apply(&mut self, update: &Update<Level3>) -> Result<bool, ChecksumMismatch> {
+        let mut applied = false;
+        for level3 in update.data() {
+            if *level3.symbol() != self.symbol {
+                continue;
+            }
+            if update.is_snapshot() {
+                self.invalidate();
+                self.valid = true;
+            } else if !self.valid {
+                return Ok(false);
+            }
+            for order in level3.asks() {
+                self.apply_order(Side::Sell, order);
+            }
+            for order in level3.bids() {
+                self.apply_order(Side::Buy, order);
+            }
+            self.truncate();
+
+            let calculated = self.checksum();
+            if calculated != *level3.checksum() {
+                self.invalidate();
+                return Err(ChecksumMismatch {
+                    expected: *level3.checksum(),
+                    calculated,
+                });
+            }
+            applied = true;
+        }
+        Ok(applied)
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut BTreeMap<Decimal, Queue> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    fn apply_order(&mut self, side: Side, order: &Level3Order) {
+        // snapshots don't include an event
+        match order.event().unwrap_or(Level3Event::Add) {
+            Level3Event::Add => {
+                self.remove(order.order_id());
+                self.insert(side, order.into());
+            }
+            Level3Event::Modify => {
+                let queued = self.orders.get(order.order_id()).and_then(|(side, price)| {
+                    let side = match side {
+                        Side::Buy => &mut self.bids,
+                        Side::Sell => &mut self.asks,
+                    };
+                    side.get_mut(price)?
+                        .iter_mut()
+                        .find(|queued| queued.order_id == *order.order_id())
+                });
+                match queued {
+                    // an order keeps its place in the queue while the price doesn't change
+                    Some(queued) if queued.price == *order.limit_price() => {
+                        queued.qty = *order.order_qty();
+                        queued.timestamp = *order.timestamp();
+                    }
+                    _ => {
+                        self.remove(order.order_id());
+                        self.insert(side, order.into());
+                    }
+                }
+            }
+            Level3Event::Delete => self.remove(order.order_id()),
+        }
+    }
+
+    fn insert(&mut self, side: Side, order: QueuedOrder) {
+        self.orders
+            .insert(order.order_id.clone(), (side, order.price));
+        self.side_mut(side)
+            .entry(order.price)
+            .or_default()
+            .push_back(order);
+    }
+
+    fn remove(&mut self, order_id: &str) {
+        let Some((side, price)) = self.orders.remove(order_id) else {
+            return;
+        };
+        let levels = self.side_mut(side);
+        if let Some(queue) = levels.get_mut(&price) {
+            queue.retain(|order| order.order_id != order_id);
+            if queue.is_empty() {
+                levels.remove(&price);
+            }
+        }
+    }
+
+    /// Drop the price levels that are deeper than the subscription
+    fn truncate(&mut self) {
+        let depth = u16::from(self.depth) as usize;
+        while self.asks.len() > depth {
+            if let Some((_, queue)) = self.asks.pop_last() {
+                for order in queue {
+                    self.orders.remove(&order.order_id);
+                }
+            }
+        }
+        while self.bids.len() > depth {
+            if let Some((_, queue)) = self.bids.pop_first() {
+                for order in queue {
+                    self.orders.remove(&order.order_id);
+                }
+            }
+        }
+    }
+
+    /// Price levels with the lowest ask first. Orders are in queue priority
+    pub fn asks(&self) -> impl Iterator<Item = (&Decimal, &Queue)> {
+        self.asks.iter()
+    }
+
+    /// Price levels with the highest bid first. Orders are in queue priority
+    pub fn bids(&self) -> impl Iterator<Item = (&Decimal, &Queue)> {
+        self.bids.iter().rev()
+    }
+
+    pub fn order(&self, order_id: &str) -> Option<&QueuedOrder> {
+        let (side, price) = self.orders.get(order_id)?;
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels
+            .get(price)?
+            .iter()
+            .find(|order| order.order_id == order_id)
+    }
+
+    /// Number of orders ahead of this one at its price level
+    pub fn queue_position(&self, order_id: &str) -> Option<usize> {
+        let (side, price) = self.orders.get(order_id)?;
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels
+            .get(price)?
+            .iter()
+            .position(|order| order.order_id == order_id)
+    }
+
+    /// Aggregate the orders at each price level into a level 2 snapshot
+    pub fn to_l2(&self) -> Book {
+        fn level((price, queue): (&Decimal, &Queue)) -> PriceLevel {
+            let volume = queue.iter().map(|order| order.qty).sum();
+            let timestamp = queue
+                .iter()
+                .map(|order| order.timestamp)
+                .max()
+                .expect("empty price levels are removed");
+            PriceLevel::new(*price, volume, timestamp)
+        }
+        Book::new_snapshot(
+            self.asks().map(level).collect(),
+            self.bids().map(level).collect(),
+        )
+    }
+
+    /// CRC32 of every order in the top 10 price levels, asks first
+    pub fn checksum(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        let levels = self
+            .asks()
+            .take(CHECKSUM_LEVELS)
+            .chain(self.bids().take(CHECKSUM_LEVELS));
+        for (_, queue) in levels {
+            for order in queue {
+                hasher.update(checksum_field(&order.price, self.price_precision).as_bytes());
+                hasher.update(checksum_field(&order.qty, self.qty_precision).as_bytes());
+            }
+        }
+        hasher.finalize()
+    }
+}
+
+/// The value at full precision without the decimal point or leading zeros
+fn checksum_field(value: &Decimal, precision: u32) -> String {
+    format!("{:.*}", precision as usize, value)
+        .replace('.', "")
+        .trim_start_matches('0')
+        .to_string()
+}
+
+#[cfg(test)]
+mod level3_tests {
+    use super::*;
+    use crate::book::LocalBook;
+    use crate::test_utils;
+    use crate::v2::messages::{ChannelMessage, Message};
+    use crate::v2::stream;
+    use rust_decimal_macros::dec;
+
+    fn load_update(file: &str) -> Update<Level3> {
+        let j = test_utils::load_test_json(&format!("v2/{}", file))
+            .expect("couldn't load test json from file");
+        let message: Message = serde_json::from_str(&j).expect("couldn't deserialize message");
+        let Message::ChannelMessage(message) = message else {
+            panic!("expected a channel message");
+        };
+        stream::level3(message).expect("expected a level3 message")
+    }
+
+    /// The levels and checksum of the example in kraken's book checksum documentation, with one
+    /// order per level
+    fn book() -> Level3Book {
+        let mut book = Level3Book::new("BTC/USD".to_string(), Depth::Ten, 5, 8);
+        assert!(book
+            .apply(&load_update("level3_snapshot"))
+            .expect("checksum mismatch"));
+        book
+    }
+
+    #[test]
+    fn checksum_fields() {
+        assert_eq!(checksum_field(&dec!(0.5), 8), "50000000");
+        assert_eq!(checksum_field(&dec!(26492.5), 1), "264925");
+        assert_eq!(checksum_field(&dec!(2), 8), "200000000");
+    }
+
+    #[test]
+    fn snapshot_builds_queues() {
+        let book = book();
+        assert!(book.is_valid());
+        assert_eq!(book.checksum(), 974947235);
+        let (price, queue) = book.asks().next().unwrap();
+        assert_eq!(*price, dec!(0.05005));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(book.queue_position("OAS6MD-CAJNO-RCNDKN"), Some(0));
+        assert_eq!(*book.bids().next().unwrap().0, dec!(0.05));
+        assert_eq!(book.asks().count(), 10);
+        assert_eq!(book.bids().count(), 10);
+    }
+
+    #[test]
+    fn updates_are_ignored_until_snapshot() {
+        let mut book = Level3Book::new("BTC/USD".to_string(), Depth::Ten, 5, 8);
+        assert_eq!(book.apply(&load_update("level3_update")), Ok(false));
+        assert!(!book.is_valid());
+    }
+
+    #[test]
+    fn updates_add_and_delete_orders() {
+        let mut book = book();
+        assert!(book
+            .apply(&load_update("level3_update"))
+            .expect("checksum mismatch"));
+        assert!(book.order("ODIUHD-LK5QJ-RDU26P").is_none());
+        assert_eq!(*book.bids().next().unwrap().0, dec!(0.04995));
+        // joins the back of the best ask's queue
+        assert_eq!(book.queue_position("ODPNES-O3KZU-5Q7F64"), Some(1));
+        assert_eq!(
+            *book.order("ODPNES-O3KZU-5Q7F64").unwrap().qty(),
+            dec!(0.0000025)
+        );
+        assert_eq!(book.asks().count(), 10);
+    }
+
+    #[test]
+    fn checksum_mismatch_invalidates() {
+        let mut book = book();
+        let j = r#"{"channel":"level3","type":"update","data":[{"symbol":"BTC/USD","checksum":1,
+            "bids":[],"asks":[{"event":"delete","order_id":"OS3J35-LW7AA-DGWZTR",
+            "limit_price":0.05010,"order_qty":0.000005,"timestamp":"2020-02-28T15:58:09.210164Z"}]}]}"#;
+        let message: ChannelMessage = serde_json::from_str(j).unwrap();
+        let error = book
+            .apply(&stream::level3(message).unwrap())
+            .expect_err("expected a checksum mismatch");
+        assert_eq!(error.expected, 1);
+        assert!(!book.is_valid());
+    }
+
+    #[test]
+    fn l2_view_matches_local_book() {
+        let book = book();
+        let mut l2 = LocalBook::new("BTC/USD".to_string(), Depth::Ten);
+        assert!(l2.apply(&book.to_l2()));
+        let best_ask = l2.best_ask().unwrap();
+        assert_eq!(*best_ask.price(), dec!(0.05005));
+        assert_eq!(*best_ask.volume(), dec!(0.000005));
+        assert_eq!(*l2.best_bid().unwrap().volume(), dec!(0.000005));
+        assert_eq!(l2.spread(), Some(dec!(0.00005)));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::messages::general_messages::{Depth, Interval};
+use crate::messages::Pair;
+use crate::v2::messages::ChannelName;
+use derive_getters::Getters;
+use derive_setters::Setters;
+use display_json::{DebugAsJson, DisplayAsJsonPretty};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    Subscribe,
+    Unsubscribe,
+    Ping,
+    Pong,
+}
+
+/// A request sent to kraken. Kraken echoes the request id in its response
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Request<P> {
+    method: Method,
+    params: Option<P>,
+    req_id: u64,
+}
+
+impl<P> Request<P> {
+    pub fn new(method: Method, params: Option<P>, req_id: u64) -> Self {
+        Self {
+            method,
+            params,
+            req_id,
+        }
+    }
+}
+
+/// Parameters of a subscribe or unsubscribe request.
+///
+/// ```
+/// # use linnaeus_ws::v2::messages::ChannelName;
+/// # use linnaeus_ws::v2::messages::general_messages::SubscribeParams;
+/// let params = SubscribeParams::new(ChannelName::Ticker).with_symbol("BTC/USD".to_string());
+/// ```
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Setters, Clone)]
+#[setters(prefix = "with_", strip_option)]
+pub struct SubscribeParams {
+    #[setters(skip)]
+    channel: ChannelName,
+    #[setters(skip)]
+    symbol: Option<Vec<Pair>>,
+    depth: Option<Depth>,
+    interval: Option<Interval>,
+    snapshot: Option<bool>,
+    #[setters(skip)]
+    token: Option<String>,
+}
+
+impl SubscribeParams {
+    pub fn new(channel: ChannelName) -> Self {
+        Self {
+            channel,
+            symbol: None,
+            depth: None,
+            interval: None,
+            snapshot: None,
+            token: None,
+        }
+    }
+
+    pub fn with_symbol(mut self, symbol: Pair) -> Self {
+        self.symbol.get_or_insert_with(Vec::new).push(symbol);
+        self
+    }
+
+    pub(crate) fn with_token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// The same subscription for a single symbol, or for no symbol at all
+    pub(crate) fn for_symbol(&self, symbol: Option<&Pair>) -> Self {
+        Self {
+            symbol: symbol.map(|symbol| vec![symbol.clone()]),
+            token: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Kraken's response to a request
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Response {
+    method: Method,
+    req_id: Option<u64>,
+    /// Missing from pong responses
+    success: Option<bool>,
+    result: Option<serde_json::Value>,
+    error: Option<String>,
+    time_in: Option<chrono::DateTime<chrono::Utc>>,
+    time_out: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none() && self.success.unwrap_or(true)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemState {
+    Online,
+    CancelOnly,
+    Maintenance,
+    PostOnly,
+}
+
+/// Sent when the connection is opened and whenever the state of the exchange changes
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Status {
+    api_version: String,
+    connection_id: serde_json::Number,
+    system: SystemState,
+    version: String,
+}
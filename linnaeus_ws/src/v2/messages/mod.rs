@@ -0,0 +1,362 @@
+pub mod general_messages;
+pub mod private_messages;
+pub mod public_messages;
+
+use crate::messages::Pair;
+use derive_getters::Getters;
+use general_messages::{Response, Status};
+use private_messages::{BalanceUpdate, Execution};
+use public_messages::{Book, Instrument, Level3, Ohlc, Ticker, Trade};
+use serde::de::{DeserializeOwned, Error as DeError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::hash::{Hash, Hasher};
+use strum::Display as DisplayEnum;
+
+/// Any message kraken sends on a v2 connection
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Message {
+    Response(Response),
+    ChannelMessage(ChannelMessage),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, DisplayEnum)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ChannelName {
+    Book,
+    Ticker,
+    Ohlc,
+    Trade,
+    Executions,
+    Balances,
+    Level3,
+    Instrument,
+}
+
+impl ChannelName {
+    /// Private channels need a token and an authenticated connection
+    pub fn is_private(&self) -> bool {
+        matches!(self, Self::Executions | Self::Balances | Self::Level3)
+    }
+
+    pub(crate) fn generate_identifier(&self, symbol: Option<&Pair>) -> u64 {
+        let mut hasher = ahash::AHasher::default();
+        self.hash(&mut hasher);
+        symbol.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateType {
+    Snapshot,
+    Update,
+}
+
+#[derive(Debug, Clone)]
+pub enum ChannelData {
+    Status(Vec<Status>),
+    Heartbeat,
+    Ticker(Vec<Ticker>),
+    Book(Vec<Book>),
+    Ohlc(Vec<Ohlc>),
+    Trade(Vec<Trade>),
+    Executions(Vec<Execution>),
+    Balances(Vec<BalanceUpdate>),
+    Level3(Vec<Level3>),
+    Instrument(Vec<Instrument>),
+}
+
+#[derive(Debug, Clone, Getters, Deserialize)]
+#[serde(try_from = "RawChannelMessage")]
+pub struct ChannelMessage {
+    update_type: UpdateType,
+    /// Only sent on the private channels
+    sequence: Option<u64>,
+    data: ChannelData,
+}
+
+#[derive(Deserialize)]
+struct RawChannelMessage {
+    channel: String,
+    #[serde(rename = "type")]
+    update_type: Option<UpdateType>,
+    sequence: Option<u64>,
+    #[serde(default)]
+    data: Value,
+}
+
+impl TryFrom<RawChannelMessage> for ChannelMessage {
+    type Error = serde_json::Error;
+
+    fn try_from(raw: RawChannelMessage) -> Result<Self, Self::Error> {
+        fn parse<T: DeserializeOwned>(data: Value) -> Result<Vec<T>, serde_json::Error> {
+            serde_json::from_value(data)
+        }
+        let data = match raw.channel.as_str() {
+            "status" => ChannelData::Status(parse(raw.data)?),
+            "heartbeat" => ChannelData::Heartbeat,
+            "ticker" => ChannelData::Ticker(parse(raw.data)?),
+            "book" => ChannelData::Book(parse(raw.data)?),
+            "ohlc" => ChannelData::Ohlc(parse(raw.data)?),
+            "trade" => ChannelData::Trade(parse(raw.data)?),
+            "executions" => ChannelData::Executions(parse(raw.data)?),
+            "balances" => ChannelData::Balances(parse(raw.data)?),
+            "level3" => ChannelData::Level3(parse(raw.data)?),
+            // the only channel where data is an object rather than a list
+            "instrument" => ChannelData::Instrument(vec![serde_json::from_value(raw.data)?]),
+            other => {
+                return Err(serde_json::Error::custom(format!(
+                    "unknown channel {}",
+                    other
+                )))
+            }
+        };
+        Ok(Self {
+            update_type: raw.update_type.unwrap_or(UpdateType::Update),
+            sequence: raw.sequence,
+            data,
+        })
+    }
+}
+
+impl ChannelMessage {
+    /// None for status and heartbeat messages
+    pub fn channel(&self) -> Option<ChannelName> {
+        match &self.data {
+            ChannelData::Status(_) | ChannelData::Heartbeat => None,
+            ChannelData::Ticker(_) => Some(ChannelName::Ticker),
+            ChannelData::Book(_) => Some(ChannelName::Book),
+            ChannelData::Ohlc(_) => Some(ChannelName::Ohlc),
+            ChannelData::Trade(_) => Some(ChannelName::Trade),
+            ChannelData::Executions(_) => Some(ChannelName::Executions),
+            ChannelData::Balances(_) => Some(ChannelName::Balances),
+            ChannelData::Level3(_) => Some(ChannelName::Level3),
+            ChannelData::Instrument(_) => Some(ChannelName::Instrument),
+        }
+    }
+
+    /// The symbol of a market data message. Kraken sends one message per symbol
+    pub fn symbol(&self) -> Option<&Pair> {
+        match &self.data {
+            ChannelData::Ticker(data) => data.first().map(|d| d.symbol()),
+            ChannelData::Book(data) => data.first().map(|d| d.symbol()),
+            ChannelData::Ohlc(data) => data.first().map(|d| d.symbol()),
+            ChannelData::Trade(data) => data.first().map(|d| d.symbol()),
+            ChannelData::Level3(data) => data.first().map(|d| d.symbol()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_channel_identifier(&self) -> Option<u64> {
+        Some(self.channel()?.generate_identifier(self.symbol()))
+    }
+
+    /// Pull the typed payload out of the message. Returns None for messages from other channels
+    pub(crate) fn into_update<T>(
+        self,
+        extract: fn(ChannelData) -> Option<Vec<T>>,
+    ) -> Option<Update<T>> {
+        Some(Update {
+            update_type: self.update_type,
+            sequence: self.sequence,
+            data: extract(self.data)?,
+        })
+    }
+}
+
+/// The typed payload of a channel message
+#[derive(Debug, Clone, Getters)]
+pub struct Update<T> {
+    update_type: UpdateType,
+    sequence: Option<u64>,
+    data: Vec<T>,
+}
+
+impl<T> Update<T> {
+    pub fn is_snapshot(&self) -> bool {
+        self.update_type == UpdateType::Snapshot
+    }
+
+    pub fn into_data(self) -> Vec<T> {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod v2_message_tests {
+    use super::*;
+    use crate::test_utils;
+    use general_messages::{Method, SystemState};
+    use private_messages::ExecType;
+    use public_messages::Level3Event;
+    use rust_decimal_macros::dec;
+
+    fn load_message(file: &str) -> Message {
+        let j = test_utils::load_test_json(&format!("v2/{}", file))
+            .expect("couldn't load test json from file");
+        serde_json::from_str(&j).expect("couldn't deserialize message")
+    }
+
+    fn load_channel_message(file: &str) -> ChannelMessage {
+        match load_message(file) {
+            Message::ChannelMessage(message) => message,
+            Message::Response(response) => panic!("expected a channel message. Got {}", response),
+        }
+    }
+
+    #[test]
+    fn deserialize_responses() {
+        let Message::Response(response) = load_message("subscribe_response") else {
+            panic!("expected a response");
+        };
+        assert_eq!(*response.method(), Method::Subscribe);
+        assert_eq!(*response.req_id(), Some(7));
+        assert!(response.is_success());
+
+        let Message::Response(response) = load_message("subscribe_error") else {
+            panic!("expected a response");
+        };
+        assert!(!response.is_success());
+        assert_eq!(
+            response.error().as_deref(),
+            Some("Currency pair not supported")
+        );
+
+        let Message::Response(response) = load_message("pong") else {
+            panic!("expected a response");
+        };
+        assert_eq!(*response.method(), Method::Pong);
+        assert!(response.is_success());
+    }
+
+    #[test]
+    fn deserialize_status_and_heartbeat() {
+        let message = load_channel_message("status");
+        let ChannelData::Status(status) = message.data() else {
+            panic!("expected a status message");
+        };
+        assert_eq!(*status[0].system(), SystemState::Online);
+        assert!(message.channel().is_none());
+
+        let message = load_channel_message("heartbeat");
+        assert!(matches!(message.data(), ChannelData::Heartbeat));
+    }
+
+    #[test]
+    fn deserialize_market_data() {
+        let message = load_channel_message("ticker");
+        assert_eq!(message.channel(), Some(ChannelName::Ticker));
+        assert_eq!(message.symbol().map(String::as_str), Some("BTC/USD"));
+        assert_eq!(*message.update_type(), UpdateType::Snapshot);
+
+        let message = load_channel_message("book_snapshot");
+        let ChannelData::Book(book) = message.data() else {
+            panic!("expected a book message");
+        };
+        assert_eq!(book[0].bids().len(), 3);
+        assert_eq!(*book[0].asks()[0].price(), dec!(0.5666));
+        assert_eq!(*book[0].checksum(), 2439117997);
+
+        let message = load_channel_message("book_update");
+        assert_eq!(*message.update_type(), UpdateType::Update);
+        assert_eq!(message.symbol().map(String::as_str), Some("MATIC/USD"));
+
+        let message = load_channel_message("ohlc");
+        assert_eq!(message.channel(), Some(ChannelName::Ohlc));
+
+        let message = load_channel_message("trade");
+        let ChannelData::Trade(trades) = message.data() else {
+            panic!("expected a trade message");
+        };
+        assert_eq!(*trades[0].trade_id(), 4665906);
+
+        let message = load_channel_message("instrument");
+        let ChannelData::Instrument(instrument) = message.data() else {
+            panic!("expected an instrument message");
+        };
+        assert_eq!(instrument[0].pairs()[0].symbol(), "EUR/USD");
+    }
+
+    #[test]
+    fn deserialize_level3() {
+        let message = load_channel_message("level3_update");
+        let ChannelData::Level3(level3) = message.data() else {
+            panic!("expected a level3 message");
+        };
+        assert_eq!(*level3[0].bids()[0].event(), Some(Level3Event::Delete));
+        assert_eq!(message.channel(), Some(ChannelName::Level3));
+    }
+
+    #[test]
+    fn deserialize_private_messages() {
+        let message = load_channel_message("executions");
+        assert_eq!(*message.sequence(), Some(2));
+        let ChannelData::Executions(executions) = message.data() else {
+            panic!("expected an executions message");
+        };
+        assert_eq!(*executions[0].exec_type(), ExecType::Trade);
+        assert_eq!(*executions[0].last_qty(), Some(dec!(0.5)));
+
+        let message = load_channel_message("balances_snapshot");
+        let ChannelData::Balances(balances) = message.data() else {
+            panic!("expected a balances message");
+        };
+        assert!(matches!(balances[0], BalanceUpdate::Balance(_)));
+
+        let message = load_channel_message("balances_update");
+        let ChannelData::Balances(balances) = message.data() else {
+            panic!("expected a balances message");
+        };
+        assert!(matches!(balances[0], BalanceUpdate::Ledger(_)));
+    }
+
+    #[test]
+    fn subscriptions_are_routed_by_channel_and_symbol() {
+        let ticker = load_channel_message("ticker");
+        assert_eq!(
+            ticker.get_channel_identifier(),
+            Some(ChannelName::Ticker.generate_identifier(Some(&"BTC/USD".to_string())))
+        );
+        let executions = load_channel_message("executions");
+        assert_eq!(
+            executions.get_channel_identifier(),
+            Some(ChannelName::Executions.generate_identifier(None))
+        );
+        assert!(load_channel_message("heartbeat")
+            .get_channel_identifier()
+            .is_none());
+    }
+
+    #[test]
+    fn convert_to_core_types() {
+        use linnaeus_types as types;
+
+        let message = load_channel_message("ticker");
+        let ChannelData::Ticker(ticker) = message.data() else {
+            panic!("expected a ticker message");
+        };
+        let ticker = types::Ticker::from(&ticker[0]);
+        assert_eq!(*ticker.ask_volume(), dec!(4.71221498));
+        assert_eq!(*ticker.open(), dec!(26189.7));
+
+        let message = load_channel_message("ohlc");
+        let ChannelData::Ohlc(ohlc) = message.data() else {
+            panic!("expected an ohlc message");
+        };
+        let candle = types::Candle::from(&ohlc[0]);
+        assert_eq!(candle.time(), ohlc[0].interval_begin());
+        assert_eq!(*candle.trades(), 12);
+
+        let message = load_channel_message("trade");
+        let ChannelData::Trade(trades) = message.data() else {
+            panic!("expected a trade message");
+        };
+        let trade = types::Trade::from(&trades[0]);
+        assert_eq!(*trade.trade_id(), Some(4665906));
+        assert_eq!(*trade.volume(), *trades[0].qty());
+    }
+}
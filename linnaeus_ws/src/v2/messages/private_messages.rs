@@ -0,0 +1,109 @@
+use crate::messages::Pair;
+use crate::v2::messages::public_messages::Side;
+use derive_getters::Getters;
+use display_json::{DebugAsJson, DisplayAsJsonPretty};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecType {
+    PendingNew,
+    New,
+    Trade,
+    Filled,
+    IcebergRefill,
+    Canceled,
+    Expired,
+    Amended,
+    Restated,
+    Status,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    PendingNew,
+    New,
+    PartiallyFilled,
+    Filled,
+    Canceled,
+    Expired,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Fee {
+    asset: String,
+    qty: Decimal,
+}
+
+/// An order status change or a fill. Most fields are only present for some exec types
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Execution {
+    exec_type: ExecType,
+    order_id: String,
+    order_status: Option<OrderStatus>,
+    cl_ord_id: Option<String>,
+    order_userref: Option<i64>,
+    symbol: Option<Pair>,
+    side: Option<Side>,
+    order_type: Option<String>,
+    order_qty: Option<Decimal>,
+    limit_price: Option<Decimal>,
+    exec_id: Option<String>,
+    trade_id: Option<u64>,
+    last_qty: Option<Decimal>,
+    last_price: Option<Decimal>,
+    cost: Option<Decimal>,
+    cum_qty: Option<Decimal>,
+    cum_cost: Option<Decimal>,
+    avg_price: Option<Decimal>,
+    fees: Option<Vec<Fee>>,
+    liquidity_ind: Option<String>,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Wallet {
+    #[serde(rename = "type")]
+    wallet_type: String,
+    id: String,
+    balance: Decimal,
+}
+
+/// Balance of an asset from a balances snapshot
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Balance {
+    asset: String,
+    asset_class: String,
+    balance: Decimal,
+    wallets: Option<Vec<Wallet>>,
+}
+
+/// A ledger entry that changed a balance
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct LedgerEntry {
+    ledger_id: String,
+    ref_id: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "type")]
+    entry_type: String,
+    subtype: Option<String>,
+    category: Option<String>,
+    asset: String,
+    asset_class: String,
+    amount: Decimal,
+    fee: Decimal,
+    /// The balance after this entry was applied
+    balance: Decimal,
+    wallet_type: Option<String>,
+    wallet_id: Option<String>,
+}
+
+/// Snapshots of the balances channel list balances, updates list ledger entries
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Clone)]
+#[serde(untagged)]
+pub enum BalanceUpdate {
+    Ledger(LedgerEntry),
+    Balance(Balance),
+}
@@ -0,0 +1,214 @@
+use crate::messages::Pair;
+use derive_getters::Getters;
+use linnaeus_types as types;
+use display_json::{DebugAsJson, DisplayAsJsonPretty};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Ticker {
+    symbol: Pair,
+    bid: Decimal,
+    bid_qty: Decimal,
+    ask: Decimal,
+    ask_qty: Decimal,
+    last: Decimal,
+    volume: Decimal,
+    vwap: Decimal,
+    low: Decimal,
+    high: Decimal,
+    change: Decimal,
+    change_pct: Decimal,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct BookLevel {
+    price: Decimal,
+    qty: Decimal,
+}
+
+/// A snapshot or update of the level 2 book. A quantity of zero removes the level
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Book {
+    symbol: Pair,
+    bids: Vec<BookLevel>,
+    asks: Vec<BookLevel>,
+    /// CRC32 of the top 10 levels of the book after applying this message
+    checksum: u32,
+    timestamp: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Ohlc {
+    symbol: Pair,
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    close: Decimal,
+    trades: u64,
+    volume: Decimal,
+    vwap: Decimal,
+    interval_begin: chrono::DateTime<chrono::Utc>,
+    /// minutes
+    interval: u32,
+    timestamp: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeOrderType {
+    Limit,
+    Market,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Trade {
+    symbol: Pair,
+    side: Side,
+    price: Decimal,
+    qty: Decimal,
+    ord_type: TradeOrderType,
+    trade_id: u64,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Level3Event {
+    Add,
+    Modify,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Level3Order {
+    /// Only present on updates
+    event: Option<Level3Event>,
+    order_id: String,
+    limit_price: Decimal,
+    order_qty: Decimal,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// A snapshot or update of the individual orders in the book. Requires an authenticated
+/// connection
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Level3 {
+    symbol: Pair,
+    bids: Vec<Level3Order>,
+    asks: Vec<Level3Order>,
+    checksum: u32,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Asset {
+    id: String,
+    status: String,
+    precision: u32,
+    precision_display: u32,
+    borrowable: bool,
+    collateral_value: Decimal,
+    margin_rate: Option<Decimal>,
+}
+
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct InstrumentPair {
+    symbol: Pair,
+    base: String,
+    quote: String,
+    status: String,
+    qty_precision: u32,
+    qty_increment: Decimal,
+    price_precision: u32,
+    price_increment: Decimal,
+    cost_precision: u32,
+    marginable: bool,
+    has_index: bool,
+    cost_min: Decimal,
+    qty_min: Decimal,
+    tick_size: Option<Decimal>,
+}
+
+/// Reference data for every asset and pair
+#[derive(Serialize, Deserialize, DebugAsJson, DisplayAsJsonPretty, Getters, Clone)]
+pub struct Instrument {
+    assets: Vec<Asset>,
+    pairs: Vec<InstrumentPair>,
+}
+
+impl From<Side> for types::Side {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Buy => Self::Buy,
+            Side::Sell => Self::Sell,
+        }
+    }
+}
+
+impl From<TradeOrderType> for types::TradeType {
+    fn from(order_type: TradeOrderType) -> Self {
+        match order_type {
+            TradeOrderType::Market => Self::Market,
+            TradeOrderType::Limit => Self::Limit,
+        }
+    }
+}
+
+impl From<&Ticker> for types::Ticker {
+    fn from(ticker: &Ticker) -> Self {
+        Self::new(
+            ticker.bid,
+            ticker.bid_qty,
+            ticker.ask,
+            ticker.ask_qty,
+            ticker.last,
+            // kraken only sends the change over the last 24 hours
+            ticker.last - ticker.change,
+            ticker.high,
+            ticker.low,
+            ticker.volume,
+            ticker.vwap,
+        )
+    }
+}
+
+impl From<&BookLevel> for types::PriceLevel {
+    fn from(level: &BookLevel) -> Self {
+        Self::new(level.price, level.qty, None)
+    }
+}
+
+impl From<&Ohlc> for types::Candle {
+    fn from(ohlc: &Ohlc) -> Self {
+        Self::new(
+            ohlc.interval_begin,
+            ohlc.open,
+            ohlc.high,
+            ohlc.low,
+            ohlc.close,
+            ohlc.vwap,
+            ohlc.volume,
+            ohlc.trades,
+        )
+    }
+}
+
+impl From<&Trade> for types::Trade {
+    fn from(trade: &Trade) -> Self {
+        Self::new(
+            trade.price,
+            trade.qty,
+            trade.timestamp,
+            trade.side.into(),
+            trade.ord_type.into(),
+            Some(trade.trade_id),
+        )
+    }
+}
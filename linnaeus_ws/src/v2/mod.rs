@@ -0,0 +1,569 @@
+//! Client for version 2 of the kraken websocket API.
+//!
+//! Shares the connection, reconnect and subscription machinery with the v1 client but has its own
+//! message set. Pick the version when creating the connection.
+
+pub mod level3;
+pub mod messages;
+mod stream;
+
+use crate::auth::{TokenManager, TokenProvider};
+use crate::config::WebsocketConfig;
+use crate::connection::{self, Connection, Handshake, PendingRequests, Protocol};
+use crate::error::LinnaeusWebsocketError;
+use crate::liveness::ConnectionHealth;
+use crate::messages::general_messages::{Depth, Interval};
+use crate::messages::Pair;
+use crate::stream::{ChannelStream, Extractor};
+use crate::subscription::{self, Subscribers, SubscriptionOptions, SubscriptionReceiver};
+use dashmap::DashMap;
+use log::{error, info, trace};
+use messages::general_messages::{Method, Request, Response, Status, SubscribeParams, SystemState};
+use messages::private_messages::{BalanceUpdate, Execution};
+use messages::public_messages::{Book, Instrument, Level3, Ohlc, Ticker, Trade};
+use messages::{ChannelData, ChannelMessage, ChannelName, Message, Update};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+pub const PUBLIC_URL: &str = "wss://ws.kraken.com/v2";
+pub const AUTHENTICATED_URL: &str = "wss://ws-auth.kraken.com/v2";
+/// The level3 channel is only served by this endpoint. Connect to it with a token provider
+pub const LEVEL3_URL: &str = "wss://ws-l3.kraken.com/v2";
+
+#[derive(Debug)]
+pub struct LinnaeusWebsocketV2 {
+    connection: Connection,
+    subscriptions: Subscribers<ChannelMessage>,
+    /// subscribe requests that are replayed when the connection is re-established, one per
+    /// channel and symbol keyed like the subscribers
+    active_subscriptions: DashMap<u64, SubscribeParams, ahash::RandomState>,
+    pending_requests: PendingRequests<Response>,
+    status: Mutex<Status>,
+}
+
+impl LinnaeusWebsocketV2 {
+    pub async fn new(url: &str) -> Result<Arc<Self>, LinnaeusWebsocketError> {
+        Self::connect(url, WebsocketConfig::default(), None).await
+    }
+
+    pub async fn new_with_config(
+        url: &str,
+        config: WebsocketConfig,
+    ) -> Result<Arc<Self>, LinnaeusWebsocketError> {
+        Self::connect(url, config, None).await
+    }
+
+    /// Connect to the authenticated endpoint ([AUTHENTICATED_URL]). Tokens are fetched from the
+    /// provider when they are needed and renewed before they expire.
+    pub async fn new_authenticated(
+        url: &str,
+        token_provider: Arc<dyn TokenProvider>,
+    ) -> Result<Arc<Self>, LinnaeusWebsocketError> {
+        Self::new_authenticated_with_config(url, token_provider, WebsocketConfig::default()).await
+    }
+
+    pub async fn new_authenticated_with_config(
+        url: &str,
+        token_provider: Arc<dyn TokenProvider>,
+        config: WebsocketConfig,
+    ) -> Result<Arc<Self>, LinnaeusWebsocketError> {
+        Self::connect(url, config, Some(TokenManager::new(token_provider))).await
+    }
+
+    async fn connect(
+        url: &str,
+        config: WebsocketConfig,
+        token_manager: Option<TokenManager>,
+    ) -> Result<Arc<Self>, LinnaeusWebsocketError> {
+        let (connection, reader, status) =
+            Connection::open::<Self>(url, config, token_manager).await?;
+
+        let linnaeus_websocket = Arc::new(Self {
+            connection,
+            subscriptions: Default::default(),
+            active_subscriptions: Default::default(),
+            pending_requests: Default::default(),
+            status: Mutex::new(status),
+        });
+
+        connection::start(&linnaeus_websocket, reader).await;
+
+        Ok(linnaeus_websocket)
+    }
+
+    /// The most recent status kraken sent
+    pub fn status(&self) -> Status {
+        self.status
+            .lock()
+            .expect("status lock was poisoned")
+            .clone()
+    }
+
+    /// Watch the health of the connection
+    pub fn health(&self) -> tokio::sync::watch::Receiver<ConnectionHealth> {
+        self.connection.health()
+    }
+
+    pub fn config(&self) -> &WebsocketConfig {
+        self.connection.config()
+    }
+
+    /// true if this connection was created with a token provider
+    pub fn is_authenticated(&self) -> bool {
+        self.connection.is_authenticated()
+    }
+
+    pub async fn ping(&self) -> Result<Response, LinnaeusWebsocketError> {
+        let id = self.connection.next_id();
+        let request: Request<()> = Request::new(Method::Ping, None, id);
+        self.connection
+            .request(&self.pending_requests, id, &request)
+            .await
+    }
+
+    /// Private channels need a valid token
+    async fn authenticate_subscription(
+        &self,
+        params: SubscribeParams,
+    ) -> Result<SubscribeParams, LinnaeusWebsocketError> {
+        if !params.channel().is_private() {
+            return Ok(params);
+        }
+        let token = self.connection.token("private subscriptions").await?;
+        Ok(params.with_token(token))
+    }
+
+    /// Send the subscription and wait for kraken to accept it
+    async fn send_subscription(
+        &self,
+        params: SubscribeParams,
+    ) -> Result<(), LinnaeusWebsocketError> {
+        let id = self.connection.next_id();
+        let request = Request::new(
+            Method::Subscribe,
+            Some(self.authenticate_subscription(params.clone()).await?),
+            id,
+        );
+        let response = self
+            .connection
+            .request(&self.pending_requests, id, &request)
+            .await?;
+        if let Some(err) = rejection(&response) {
+            return Err(err);
+        }
+        for (key, params) in Self::subscription_keys(&params) {
+            // subscribing again to the same channel/symbol replaces the earlier request
+            self.active_subscriptions.insert(key, params);
+        }
+        Ok(())
+    }
+
+    /// Unsubscribe from every channel/symbol the subscription covers. Their receivers are closed
+    /// and they're no longer resubscribed after a reconnect
+    pub async fn unsubscribe(&self, params: SubscribeParams) -> Result<(), LinnaeusWebsocketError> {
+        let subscriptions = Self::subscription_keys(&params);
+        let id = self.connection.next_id();
+        let request = Request::new(
+            Method::Unsubscribe,
+            Some(self.authenticate_subscription(params).await?),
+            id,
+        );
+        let response = self
+            .connection
+            .request(&self.pending_requests, id, &request)
+            .await;
+        for (key, _) in subscriptions {
+            self.active_subscriptions.remove(&key);
+            self.subscriptions.remove(key);
+        }
+        rejection(&response?).map_or(Ok(()), Err)
+    }
+
+    async fn resubscribe(&self) -> Result<(), LinnaeusWebsocketError> {
+        let subscriptions: Vec<SubscribeParams> = self
+            .active_subscriptions
+            .iter()
+            .map(|sub| sub.value().clone())
+            .collect();
+        for params in subscriptions {
+            let params = self.authenticate_subscription(params).await?;
+            let request = Request::new(Method::Subscribe, Some(params), self.connection.next_id());
+            self.connection.send_json(&request).await?;
+        }
+        Ok(())
+    }
+
+    /// The key of every channel/symbol a subscription covers with the subscription for just that
+    /// channel/symbol
+    fn subscription_keys(params: &SubscribeParams) -> Vec<(u64, SubscribeParams)> {
+        let channel = params.channel();
+        match params.symbol() {
+            None => vec![(channel.generate_identifier(None), params.for_symbol(None))],
+            Some(symbols) => symbols
+                .iter()
+                .map(|symbol| {
+                    (
+                        channel.generate_identifier(Some(symbol)),
+                        params.for_symbol(Some(symbol)),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Send the subscription once its subscribers have been added to `keys`. If kraken doesn't
+    /// accept it the receivers are dropped and their subscribers removed
+    async fn subscribe_receivers<R>(
+        &self,
+        params: SubscribeParams,
+        keys: Vec<u64>,
+        receivers: Vec<R>,
+    ) -> Result<Vec<R>, LinnaeusWebsocketError> {
+        if let Err(err) = self.send_subscription(params).await {
+            drop(receivers);
+            for key in keys {
+                self.subscriptions.remove_closed(key);
+            }
+            return Err(err);
+        }
+        Ok(receivers)
+    }
+
+    pub async fn subscribe(
+        &self,
+        params: SubscribeParams,
+    ) -> Result<Vec<broadcast::Receiver<ChannelMessage>>, LinnaeusWebsocketError> {
+        let options = SubscriptionOptions::default();
+        let keys: Vec<u64> = Self::subscription_keys(&params)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        // subscribers are added first so that the snapshot isn't missed
+        let receivers = keys
+            .iter()
+            .map(|key| {
+                let (subscriber, receiver) = subscription::broadcast_channel(options.buffer_size());
+                self.subscriptions.add(*key, subscriber);
+                receiver
+            })
+            .collect();
+        self.subscribe_receivers(params, keys, receivers).await
+    }
+
+    async fn subscribe_with_options(
+        &self,
+        params: SubscribeParams,
+        options: &SubscriptionOptions,
+    ) -> Result<Vec<SubscriptionReceiver<ChannelMessage>>, LinnaeusWebsocketError> {
+        let keys: Vec<u64> = Self::subscription_keys(&params)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        let receivers = keys
+            .iter()
+            .map(|key| {
+                let (subscriber, receiver) = subscription::channel(options);
+                self.subscriptions.add(*key, subscriber);
+                receiver
+            })
+            .collect();
+        self.subscribe_receivers(params, keys, receivers).await
+    }
+
+    async fn subscribe_stream<I: Send + 'static>(
+        &self,
+        params: SubscribeParams,
+        symbols: &[Pair],
+        options: SubscriptionOptions,
+        extract: Extractor<ChannelMessage, I>,
+    ) -> Result<ChannelStream<I>, LinnaeusWebsocketError> {
+        let params = symbols
+            .iter()
+            .fold(params, |params, symbol| params.with_symbol(symbol.clone()));
+        let receivers = self.subscribe_with_options(params, &options).await?;
+        Ok(ChannelStream::from_receivers(
+            receivers,
+            Default::default(),
+            extract,
+        ))
+    }
+
+    pub async fn subscribe_ticker(
+        &self,
+        symbols: &[Pair],
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<Update<Ticker>>, LinnaeusWebsocketError> {
+        let params = SubscribeParams::new(ChannelName::Ticker);
+        self.subscribe_stream(params, symbols, options, stream::ticker)
+            .await
+    }
+
+    pub async fn subscribe_book(
+        &self,
+        symbols: &[Pair],
+        depth: Depth,
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<Update<Book>>, LinnaeusWebsocketError> {
+        let params = SubscribeParams::new(ChannelName::Book).with_depth(depth);
+        self.subscribe_stream(params, symbols, options, stream::book)
+            .await
+    }
+
+    pub async fn subscribe_ohlc(
+        &self,
+        symbols: &[Pair],
+        interval: Interval,
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<Update<Ohlc>>, LinnaeusWebsocketError> {
+        let params = SubscribeParams::new(ChannelName::Ohlc).with_interval(interval);
+        self.subscribe_stream(params, symbols, options, stream::ohlc)
+            .await
+    }
+
+    pub async fn subscribe_trade(
+        &self,
+        symbols: &[Pair],
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<Update<Trade>>, LinnaeusWebsocketError> {
+        let params = SubscribeParams::new(ChannelName::Trade);
+        self.subscribe_stream(params, symbols, options, stream::trade)
+            .await
+    }
+
+    pub async fn subscribe_instrument(
+        &self,
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<Update<Instrument>>, LinnaeusWebsocketError> {
+        let params = SubscribeParams::new(ChannelName::Instrument);
+        self.subscribe_stream(params, &[], options, stream::instrument)
+            .await
+    }
+
+    /// Requires an authenticated connection to [LEVEL3_URL]
+    pub async fn subscribe_level3(
+        &self,
+        symbols: &[Pair],
+        depth: Depth,
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<Update<Level3>>, LinnaeusWebsocketError> {
+        let params = SubscribeParams::new(ChannelName::Level3).with_depth(depth);
+        self.subscribe_stream(params, symbols, options, stream::level3)
+            .await
+    }
+
+    /// Requires an authenticated connection
+    pub async fn subscribe_executions(
+        &self,
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<Update<Execution>>, LinnaeusWebsocketError> {
+        let params = SubscribeParams::new(ChannelName::Executions);
+        self.subscribe_stream(params, &[], options, stream::executions)
+            .await
+    }
+
+    /// Requires an authenticated connection
+    pub async fn subscribe_balances(
+        &self,
+        options: SubscriptionOptions,
+    ) -> Result<ChannelStream<Update<BalanceUpdate>>, LinnaeusWebsocketError> {
+        let params = SubscribeParams::new(ChannelName::Balances);
+        self.subscribe_stream(params, &[], options, stream::balances)
+            .await
+    }
+
+    pub async fn shutdown(self) {
+        self.connection.shutdown().await
+    }
+}
+
+/// The error kraken gave for a request it didn't accept
+fn rejection(response: &Response) -> Option<LinnaeusWebsocketError> {
+    if response.is_success() {
+        return None;
+    }
+    Some(LinnaeusWebsocketError::Kraken(
+        response
+            .error()
+            .clone()
+            .unwrap_or_else(|| "no error message".to_string()),
+    ))
+}
+
+impl Protocol for LinnaeusWebsocketV2 {
+    type Status = Status;
+
+    fn handshake(message: &str) -> Handshake<Status> {
+        let Ok(Message::ChannelMessage(message)) = serde_json::from_str(message) else {
+            return Handshake::Offline;
+        };
+        match message.data() {
+            ChannelData::Heartbeat => Handshake::Waiting,
+            ChannelData::Status(status) => {
+                info!("got system status {:?}", status);
+                match status.first() {
+                    Some(status) if *status.system() == SystemState::Online => {
+                        Handshake::Online(status.clone())
+                    }
+                    _ => Handshake::Offline,
+                }
+            }
+            _ => Handshake::Offline,
+        }
+    }
+
+    fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    async fn handle_message(&self, message: String) {
+        let msg: Message = match serde_json::from_str(&message) {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("error while deserializing websocket message {}", e);
+                return;
+            }
+        };
+
+        match msg {
+            Message::Response(response) => match *response.req_id() {
+                Some(request_id) => self.pending_requests.complete(request_id, response),
+                None => trace!("got a response without a request id {}", response),
+            },
+            Message::ChannelMessage(channel_message) => {
+                if let ChannelData::Status(status) = channel_message.data() {
+                    if let Some(status) = status.first() {
+                        info!("got system status {:?}", status);
+                        *self.status.lock().expect("status lock was poisoned") = status.clone();
+                    }
+                    return;
+                }
+                let Some(key) = channel_message.get_channel_identifier() else {
+                    return;
+                };
+                self.subscriptions.deliver(key, &channel_message).await;
+            }
+        }
+    }
+
+    async fn restore(&self, status: Status) -> Result<(), LinnaeusWebsocketError> {
+        *self.status.lock().expect("status lock was poisoned") = status;
+        self.pending_requests.clear();
+        self.resubscribe().await
+    }
+
+    fn disconnected(&self) {
+        self.subscriptions.clear();
+        self.active_subscriptions.clear();
+        self.pending_requests.clear();
+    }
+
+    async fn ping_with_timeout(&self, timeout: Duration) -> bool {
+        match tokio::time::timeout(timeout, self.ping()).await {
+            Ok(Ok(_)) => true,
+            // the response was dropped because we're reconnecting for some other reason
+            Ok(Err(LinnaeusWebsocketError::ResponseDropped(_))) => true,
+            Ok(Err(_)) | Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod websocket_v2_tests {
+    use super::*;
+    use crate::test_utils::setup;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn handshake_waits_for_status() {
+        let heartbeat = crate::test_utils::load_test_json("v2/heartbeat").unwrap();
+        assert!(matches!(
+            LinnaeusWebsocketV2::handshake(&heartbeat),
+            Handshake::Waiting
+        ));
+        let status = crate::test_utils::load_test_json("v2/status").unwrap();
+        assert!(matches!(
+            LinnaeusWebsocketV2::handshake(&status),
+            Handshake::Online(_)
+        ));
+        let ticker = crate::test_utils::load_test_json("v2/ticker").unwrap();
+        assert!(matches!(
+            LinnaeusWebsocketV2::handshake(&ticker),
+            Handshake::Offline
+        ));
+    }
+
+    /// A client that isn't connected to kraken
+    fn offline() -> LinnaeusWebsocketV2 {
+        LinnaeusWebsocketV2 {
+            connection: Connection::replay(Default::default()),
+            subscriptions: Default::default(),
+            active_subscriptions: Default::default(),
+            pending_requests: Default::default(),
+            status: Mutex::new(
+                serde_json::from_value(serde_json::json!({
+                    "api_version": "v2",
+                    "connection_id": 1,
+                    "system": "online",
+                    "version": "2.0.0"
+                }))
+                .expect("couldn't deserialize status"),
+            ),
+        }
+    }
+
+    /// Accept the request with id `id` once it has been sent
+    async fn accept(lws: &LinnaeusWebsocketV2, id: u64, method: &str) {
+        while !lws.pending_requests.is_pending(id) {
+            tokio::task::yield_now().await;
+        }
+        let response = serde_json::json!({"method": method, "req_id": id, "success": true});
+        lws.handle_message(response.to_string()).await;
+    }
+
+    #[tokio::test]
+    async fn resubscribing_replaces_and_unsubscribing_removes() -> anyhow::Result<()> {
+        let lws = offline();
+        let params = || {
+            SubscribeParams::new(ChannelName::Ticker)
+                .with_symbol("BTC/USD".to_string())
+                .with_symbol("BTC/EUR".to_string())
+        };
+        let (subscribed, _) = tokio::join!(lws.subscribe(params()), accept(&lws, 0, "subscribe"));
+        subscribed?;
+        let (subscribed, _) = tokio::join!(lws.subscribe(params()), accept(&lws, 1, "subscribe"));
+        let mut receivers = subscribed?;
+        assert_eq!(lws.active_subscriptions.len(), 2);
+
+        let unsubscribe =
+            SubscribeParams::new(ChannelName::Ticker).with_symbol("BTC/USD".to_string());
+        let (unsubscribed, _) =
+            tokio::join!(lws.unsubscribe(unsubscribe), accept(&lws, 2, "unsubscribe"));
+        unsubscribed?;
+        assert_eq!(lws.active_subscriptions.len(), 1);
+        assert!(matches!(
+            receivers[0].try_recv(),
+            Err(broadcast::error::TryRecvError::Closed)
+        ));
+        assert!(matches!(
+            receivers[1].try_recv(),
+            Err(broadcast::error::TryRecvError::Empty)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscribe_ticker() -> anyhow::Result<()> {
+        setup();
+        let lws = LinnaeusWebsocketV2::new(PUBLIC_URL).await?;
+        let mut ticker = lws
+            .subscribe_ticker(&["BTC/USD".to_string()], Default::default())
+            .await?;
+        let update = tokio::time::timeout(Duration::from_secs(5), ticker.next())
+            .await?
+            .expect("ticker stream ended");
+        assert!(update.is_snapshot());
+        assert_eq!(update.data()[0].symbol(), "BTC/USD");
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use crate::v2::messages::private_messages::{BalanceUpdate, Execution};
+use crate::v2::messages::public_messages::{Book, Instrument, Level3, Ohlc, Ticker, Trade};
+use crate::v2::messages::{ChannelData, ChannelMessage, Update};
+
+pub(crate) fn ticker(message: ChannelMessage) -> Option<Update<Ticker>> {
+    message.into_update(|data| match data {
+        ChannelData::Ticker(ticker) => Some(ticker),
+        _ => None,
+    })
+}
+
+pub(crate) fn book(message: ChannelMessage) -> Option<Update<Book>> {
+    message.into_update(|data| match data {
+        ChannelData::Book(book) => Some(book),
+        _ => None,
+    })
+}
+
+pub(crate) fn ohlc(message: ChannelMessage) -> Option<Update<Ohlc>> {
+    message.into_update(|data| match data {
+        ChannelData::Ohlc(ohlc) => Some(ohlc),
+        _ => None,
+    })
+}
+
+pub(crate) fn trade(message: ChannelMessage) -> Option<Update<Trade>> {
+    message.into_update(|data| match data {
+        ChannelData::Trade(trade) => Some(trade),
+        _ => None,
+    })
+}
+
+pub(crate) fn executions(message: ChannelMessage) -> Option<Update<Execution>> {
+    message.into_update(|data| match data {
+        ChannelData::Executions(executions) => Some(executions),
+        _ => None,
+    })
+}
+
+pub(crate) fn balances(message: ChannelMessage) -> Option<Update<BalanceUpdate>> {
+    message.into_update(|data| match data {
+        ChannelData::Balances(balances) => Some(balances),
+        _ => None,
+    })
+}
+
+pub(crate) fn level3(message: ChannelMessage) -> Option<Update<Level3>> {
+    message.into_update(|data| match data {
+        ChannelData::Level3(level3) => Some(level3),
+        _ => None,
+    })
+}
+
+pub(crate) fn instrument(message: ChannelMessage) -> Option<Update<Instrument>> {
+    message.into_update(|data| match data {
+        ChannelData::Instrument(instrument) => Some(instrument),
+        _ => None,
+    })
+}